@@ -0,0 +1,256 @@
+//! Async versions of the blocking [`GamecubeController`] APIs.
+//!
+//! These don't depend on any particular executor, so a whole device can be written as a single task
+//! that `select!`s between console commands and other events.
+//!
+//! By default the futures ask to be polled again straight away while waiting.
+//! To let the executor sleep instead, route a PIO interrupt to the controller and call [`on_pio_interrupt`] from its handler:
+//!
+//! ```ignore
+//! controller.set_wake_interrupt(Some(PioIRQ::Irq0));
+//! unsafe { NVIC::unmask(Interrupt::PIO0_IRQ_0) };
+//!
+//! #[interrupt]
+//! fn PIO0_IRQ_0() {
+//!     joybus_pio::on_pio_interrupt::<PIO0>(PioIRQ::Irq0);
+//! }
+//! ```
+//!
+//! The delays before each response are taken from an [`AsyncDelay`], which is either a blocking [`Delay`]
+//! or any `embedded_hal_async::delay::DelayNs` wrapped in a [`HalDelay`], e.g. `HalDelay(embassy_time::Delay)`.
+
+use core::cell::RefCell;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::{Poll, Waker};
+
+use cortex_m::delay::Delay;
+use cortex_m::interrupt::Mutex;
+
+use crate::rp2040_hal::{
+    pio::{PIOExt, PioIRQ, StateMachineIndex},
+    Timer,
+};
+use crate::{
+    ConfigSource, FsmAction, GamecubeCommand, GamecubeController, GamecubeInput, JoybusPin,
+};
+
+/// The tasks waiting on each state machine's RX FIFO, PIO0 SM0 to SM3 then PIO1 SM0 to SM3.
+static WAKERS: Mutex<RefCell<[Option<Waker>; 8]>> = Mutex::new(RefCell::new([
+    None, None, None, None, None, None, None, None,
+]));
+
+/// Wake the tasks waiting on a controller using `irq` of PIO block `P`, see [`GamecubeController::set_wake_interrupt`].
+///
+/// Call this from the handler of the matching interrupt, e.g. `PIO0_IRQ_0` for `PIO0` and [`PioIRQ::Irq0`].
+pub fn on_pio_interrupt<P: PIOExt>(irq: PioIRQ) {
+    let irq = match irq {
+        PioIRQ::Irq0 => 0,
+        PioIRQ::Irq1 => 1,
+    };
+    let registers = crate::port::registers::<P>().sm_irq(irq);
+    let pending = registers.irq_ints().read().bits() & 0x0F;
+    let inte_clear = (registers.irq_inte().as_ptr() as usize | 0x3000) as *mut u32;
+    cortex_m::interrupt::free(|cs| {
+        let mut wakers = WAKERS.borrow(cs).borrow_mut();
+        let mut woken = 0;
+        for sm in (0..4).filter(|sm| pending & 1 << sm != 0) {
+            if let Some(waker) = wakers[P::id() * 4 + sm].take() {
+                waker.wake();
+                woken |= 1 << sm;
+            }
+        }
+        // the interrupt stays asserted until the FIFO is read, so leave it disabled until the task waits again.
+        // Anything else sharing the interrupt, e.g. a fast path or another controller's handler, is left alone.
+        // Safety: only the RX FIFO not empty bits of woken tasks are touched, which were enabled by their futures.
+        // They are cleared through the atomic clear alias of the register so nothing else in it can be lost.
+        unsafe { inte_clear.write_volatile(woken) };
+    });
+}
+
+/// A delay that async APIs can wait on between a command and its response.
+pub trait AsyncDelay {
+    fn delay_us(&mut self, us: u32) -> impl Future<Output = ()>;
+}
+
+/// Blocks, the delays before a response are only a few microseconds.
+impl AsyncDelay for Delay {
+    fn delay_us(&mut self, us: u32) -> impl Future<Output = ()> {
+        Delay::delay_us(self, us);
+        core::future::ready(())
+    }
+}
+
+/// Makes any `embedded_hal_async::delay::DelayNs` usable as an [`AsyncDelay`], so the async APIs aren't tied to one HAL or executor.
+///
+/// The delay must be accurate to about a microsecond, a tick rate of 1MHz or more, otherwise responses will be late.
+pub struct HalDelay<D>(pub D);
+
+impl<D: embedded_hal_async::delay::DelayNs> AsyncDelay for HalDelay<D> {
+    fn delay_us(&mut self, us: u32) -> impl Future<Output = ()> {
+        self.0.delay_us(us)
+    }
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource>
+    GamecubeController<P, I, S, C>
+{
+    /// Waits for the next command from the console.
+    ///
+    /// Probe, reset, origin and recalibrate commands are responded to before returning.
+    /// Unknown commands trigger a resync before returning.
+    /// When [`GamecubeCommand::Poll`] is returned the rest of the poll is still arriving,
+    /// sample inputs and then call [`GamecubeController::respond_to_poll`].
+    ///
+    /// A command left partially received, e.g. because the previous poll was never responded to,
+    /// is abandoned with a resync once its next byte doesn't arrive within [`crate::JoybusConfig::recv_timeout_us`].
+    pub async fn next_command(
+        &mut self,
+        timer: &Timer,
+        delay: &mut impl AsyncDelay,
+    ) -> GamecubeCommand {
+        loop {
+            let value = if self.fsm.is_idle() {
+                self.recv_async().await
+            } else {
+                let timeout_us = u32::try_from(self.config().recv_timeout_us).unwrap_or(u32::MAX);
+                match self.recv_async_timeout(delay, timeout_us).await {
+                    Some(value) => value,
+                    None => {
+                        let action = self.fsm.on_timeout();
+                        self.perform_async(action, timer, delay).await;
+                        continue;
+                    }
+                }
+            };
+            let command = self.fsm.is_idle().then(|| GamecubeCommand::from(value));
+            match self.fsm.on_byte(value) {
+                FsmAction::PollStarted => {
+                    #[cfg(debug_assertions)]
+                    self.start_poll_budget(timer);
+                    return GamecubeCommand::Poll;
+                }
+                action => {
+                    self.perform_async(action, timer, delay).await;
+                    if let Some(command) = command {
+                        return command;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Same as [`GamecubeController::respond_to_poll`] but awaits `delay` before responding.
+    ///
+    /// The rest of the poll arrives within a few microseconds, so it is received without yielding.
+    pub async fn respond_to_poll_async(
+        &mut self,
+        timer: &Timer,
+        delay: &mut impl AsyncDelay,
+        input: GamecubeInput,
+    ) {
+        #[cfg(debug_assertions)]
+        self.end_poll_budget(timer);
+        delay.delay_us(crate::budget::POLL_FINISH_DELAY_US).await;
+
+        match self.recv_poll_args(timer) {
+            Ok(_) => {
+                let report = self.create_report(&input);
+                delay.delay_us(self.config().reply_delay_us).await;
+                self.send(&report);
+                self.last_report = report;
+            }
+            Err(action) => self.perform_async(action, timer, delay).await,
+        }
+    }
+
+    async fn perform_async(
+        &mut self,
+        action: FsmAction,
+        timer: &Timer,
+        delay: &mut impl AsyncDelay,
+    ) {
+        if let Some(response) = self.handle_action(action, timer) {
+            delay.delay_us(self.config().reply_delay_us).await;
+            self.send(response.as_bytes());
+        }
+    }
+
+    /// Wake tasks waiting in [`GamecubeController::recv_async`] from `irq` of this controller's PIO block,
+    /// or ask to be polled again straight away if None.
+    ///
+    /// [`on_pio_interrupt`] must be called from the handler of the interrupt, see the [module docs](self).
+    pub fn set_wake_interrupt(&mut self, irq: Option<PioIRQ>) {
+        if let Some(previous) = self.wake_interrupt {
+            self.port.rx_mut().disable_rx_not_empty_interrupt(previous);
+        }
+        self.wake_interrupt = irq;
+    }
+
+    /// Same as [`GamecubeController::recv`] but yields while waiting and never times out.
+    pub async fn recv_async(&mut self) -> u8 {
+        poll_fn(|cx| match self.port.try_recv_byte() {
+            Some(value) => {
+                self.byte_received(value);
+                Poll::Ready(value)
+            }
+            None => {
+                match self.wake_interrupt {
+                    Some(irq) => {
+                        cortex_m::interrupt::free(|cs| {
+                            let mut wakers = WAKERS.borrow(cs).borrow_mut();
+                            let waker = &mut wakers[P::id() * 4 + S::id()];
+                            if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                                *waker = Some(cx.waker().clone());
+                            }
+                        });
+                        // if a byte arrived since the FIFO was checked the interrupt fires as soon as it is enabled
+                        self.port.rx_mut().enable_rx_not_empty_interrupt(irq);
+                    }
+                    None => cx.waker().wake_by_ref(),
+                }
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Same as [`GamecubeController::recv_async`] but gives up and returns None once `delay` has waited `timeout_us`.
+    ///
+    /// A blocking [`Delay`] waits out the whole timeout before the FIFO is checked, which still catches a byte that arrived meanwhile.
+    pub async fn recv_async_timeout(
+        &mut self,
+        delay: &mut impl AsyncDelay,
+        timeout_us: u32,
+    ) -> Option<u8> {
+        let mut recv = pin!(self.recv_async());
+        let mut timeout = pin!(delay.delay_us(timeout_us));
+        poll_fn(|cx| {
+            if let Poll::Ready(value) = recv.as_mut().poll(cx) {
+                return Poll::Ready(Some(value));
+            }
+            timeout.as_mut().poll(cx).map(|()| None)
+        })
+        .await
+    }
+
+    /// Same as [`GamecubeController::send`] but completes only once the stop bit has been transmitted.
+    pub async fn send_async(&mut self, values: &[u8]) {
+        self.send(values);
+        self.flush_async().await;
+    }
+
+    /// Same as [`GamecubeController::flush`] but yields while waiting.
+    /// This always asks to be polled again straight away, the stop bit is only a few microseconds away.
+    pub async fn flush_async(&mut self) {
+        poll_fn(|cx| {
+            if self.is_send_complete() {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
@@ -0,0 +1,133 @@
+//! On-device benchmarks of the hot path, printed over defmt so that performance can be compared across releases.
+//!
+//! Like [`crate::JitterProbe`] the cycle source is provided by the user since the RP2040 has no DWT cycle counter.
+//!
+//! ```ignore
+//! let bench = Bench::new(cycle_count);
+//! bench.run_port(&mut port);
+//! let mut controller = GamecubeController::try_new(port, &timer, &mut delay)?;
+//! bench.run_controller(&mut controller, &timer, &mut delay);
+//! ```
+
+use core::hint::black_box;
+
+use cortex_m::delay::Delay;
+
+use crate::rp2040_hal::{
+    pio::{PIOExt, StateMachineIndex},
+    Timer,
+};
+use crate::{GamecubeController, GamecubeInput, JitterProbe, JitterStats, JoybusPin, JoybusPort};
+
+pub struct Bench {
+    cycle_count: fn() -> u32,
+    iterations: u32,
+}
+
+impl Bench {
+    /// `cycle_count` must return an incrementing counter, wrapping at `u32::MAX`.
+    /// Each benchmark runs 1000 iterations.
+    pub const fn new(cycle_count: fn() -> u32) -> Bench {
+        Bench {
+            cycle_count,
+            iterations: 1000,
+        }
+    }
+
+    pub const fn with_iterations(mut self, iterations: u32) -> Bench {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Runs and prints every benchmark that only needs a [`JoybusPort`].
+    ///
+    /// This sends frames on the bus, so nothing should be connected.
+    pub fn run_port<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+        &self,
+        port: &mut JoybusPort<P, I, S>,
+    ) {
+        print("report encode", self.report_encode());
+        print("fifo fill", self.fifo_fill(port));
+    }
+
+    /// Runs and prints every benchmark that needs a console, which must be polling `controller`.
+    pub fn run_controller<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+        &self,
+        controller: &mut GamecubeController<P, I, S>,
+        timer: &Timer,
+        delay: &mut Delay,
+    ) {
+        print(
+            "command to response",
+            self.command_to_response(controller, timer, delay),
+        );
+    }
+
+    /// Cycles to encode a [`GamecubeInput`] as a poll report.
+    pub fn report_encode(&self) -> JitterStats {
+        let mut stats = JitterStats::new();
+        let mut input = GamecubeInput::NEUTRAL;
+        for i in 0..self.iterations {
+            // vary the input so the encoding can't be hoisted out of the loop
+            input.a = i & 1 != 0;
+            input.stick_x = i as u8;
+            let input = black_box(input);
+
+            let start = (self.cycle_count)();
+            black_box(input.create_report());
+            stats.record((self.cycle_count)().wrapping_sub(start));
+        }
+        stats
+    }
+
+    /// Cycles from calling [`JoybusPort::send_frame`] until the first byte of a poll report is in the TX FIFO,
+    /// including waiting for the previous frame to finish and restarting the state machine.
+    pub fn fifo_fill<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+        &self,
+        port: &mut JoybusPort<P, I, S>,
+    ) -> JitterStats {
+        let mut stats = JitterStats::new();
+        let report = GamecubeInput::NEUTRAL.create_report();
+        for _ in 0..self.iterations {
+            // don't include the previous frame still being on the wire
+            port.flush();
+
+            let start = (self.cycle_count)();
+            let mut end = start;
+            port.send_frame_then(&report, || end = (self.cycle_count)());
+            stats.record(end.wrapping_sub(start));
+        }
+        port.flush();
+        stats
+    }
+
+    /// Cycles from the final byte of a poll being read from the RX FIFO until the first response byte is in the TX FIFO,
+    /// measured with a [`JitterProbe`] over a run of polls answered with [`GamecubeController::poll_blocking`].
+    ///
+    /// Any probe already installed on `controller` is removed.
+    pub fn command_to_response<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+        &self,
+        controller: &mut GamecubeController<P, I, S>,
+        timer: &Timer,
+        delay: &mut Delay,
+    ) -> JitterStats {
+        controller.set_jitter_probe(Some(JitterProbe::new(self.cycle_count)));
+        for _ in 0..self.iterations {
+            controller.poll_blocking(timer, delay, || GamecubeInput::NEUTRAL);
+        }
+        let stats = controller.jitter_probe().unwrap().stats();
+        controller.set_jitter_probe(None);
+        stats
+    }
+}
+
+fn print(name: &str, stats: JitterStats) {
+    defmt::info!(
+        "joybus bench {=str}: min {=u32} max {=u32} mean {=u32} cycles over {=u32} samples",
+        name,
+        stats.min,
+        stats.max,
+        stats.mean().unwrap_or(0),
+        stats.samples
+    );
+}
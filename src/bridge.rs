@@ -0,0 +1,519 @@
+//! Adapters that poll a controller of one kind and present it to a console of another.
+//!
+//! [`GcToN64Bridge`] lets a gamecube controller be used on an N64 and [`N64ToGcBridge`] the reverse.
+//! Both answer the console from a cached input and take a mapping describing where each button goes,
+//! with [`StickRange`] converting between the two stick ranges.
+//!
+//! Each side of a bridge needs its own [`JoybusPort`](crate::JoybusPort), so one port must use PIO0 and the other PIO1.
+//!
+//! ```ignore
+//! let pio0 = JoybusPort::new(pins.gpio28.reconfigure(), pac.PIO0, &mut pac.RESETS, clocks)?;
+//! let pio1 = JoybusPort::new(pins.gpio29.reconfigure(), pac.PIO1, &mut pac.RESETS, clocks)?;
+//! let mut bridge = GcToN64Bridge::new(GamecubeHost::new(pio0), N64Controller::new(pio1));
+//! loop {
+//!     bridge.update(&timer, &mut delay);
+//! }
+//! ```
+
+use cortex_m::delay::Delay;
+
+use crate::n64::{N64Command, N64Controller, N64Host, N64Input};
+use crate::rp2040_hal::{
+    pio::{PIOExt, StateMachineIndex},
+    Timer,
+};
+use crate::{GamecubeController, GamecubeHost, GamecubeInput, JoybusPin};
+
+/// How long [`GcToN64Bridge::update`] waits for a command from the console.
+/// Slightly longer than a frame so a console that is running normally is never missed.
+pub const CONSOLE_TIMEOUT_US: u64 = 20_000;
+
+/// Scales a stick axis between the ranges of two controllers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StickRange {
+    /// How far from center the source stick reaches at its edge.
+    pub from_radius: u8,
+    /// How far from center the converted axis reaches when the source stick is at its edge.
+    /// The result is clamped to this so a source stick with extra range never goes past what the console expects.
+    pub to_radius: u8,
+}
+
+impl StickRange {
+    /// An OEM gamecube stick reaches roughly ±100 from center and an OEM N64 stick roughly ±80.
+    pub const GAMECUBE_TO_N64: StickRange = StickRange {
+        from_radius: 100,
+        to_radius: 80,
+    };
+
+    /// The inverse of [`StickRange::GAMECUBE_TO_N64`], expanding an N64 stick to fill the gamecube range.
+    pub const N64_TO_GAMECUBE: StickRange = StickRange {
+        from_radius: 80,
+        to_radius: 100,
+    };
+
+    /// Convert an axis given as an offset from its center.
+    pub const fn convert(&self, offset: i16) -> i16 {
+        if self.from_radius == 0 {
+            return 0;
+        }
+        let to_radius = self.to_radius as i16;
+        let scaled = (offset as i32 * to_radius as i32 / self.from_radius as i32) as i16;
+        if scaled > to_radius {
+            to_radius
+        } else if scaled < -to_radius {
+            -to_radius
+        } else {
+            scaled
+        }
+    }
+}
+
+/// An input on a gamecube controller that can be mapped to a button on another controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GcSource {
+    /// Never pressed.
+    None,
+    A,
+    B,
+    X,
+    Y,
+    Z,
+    Start,
+    L,
+    R,
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+    /// The C-stick pushed past [`GcToN64Mapping::cstick_threshold`] in a direction.
+    CStickUp,
+    CStickDown,
+    CStickLeft,
+    CStickRight,
+}
+
+impl GcSource {
+    /// Returns true if this input is pressed, with the C-stick measured relative to `origin`.
+    pub fn is_pressed(self, input: &GamecubeInput, origin: &GamecubeInput, threshold: u8) -> bool {
+        let cstick_x = input.cstick_x as i16 - origin.cstick_x as i16;
+        let cstick_y = input.cstick_y as i16 - origin.cstick_y as i16;
+        let threshold = threshold as i16;
+        match self {
+            GcSource::None => false,
+            GcSource::A => input.a,
+            GcSource::B => input.b,
+            GcSource::X => input.x,
+            GcSource::Y => input.y,
+            GcSource::Z => input.z,
+            GcSource::Start => input.start,
+            GcSource::L => input.l_digital,
+            GcSource::R => input.r_digital,
+            GcSource::DpadUp => input.dpad_up,
+            GcSource::DpadDown => input.dpad_down,
+            GcSource::DpadLeft => input.dpad_left,
+            GcSource::DpadRight => input.dpad_right,
+            GcSource::CStickUp => cstick_y > threshold,
+            GcSource::CStickDown => cstick_y < -threshold,
+            GcSource::CStickLeft => cstick_x < -threshold,
+            GcSource::CStickRight => cstick_x > threshold,
+        }
+    }
+}
+
+/// Which gamecube input drives each N64 button, and how the stick is converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcToN64Mapping {
+    pub a: GcSource,
+    pub b: GcSource,
+    pub z: GcSource,
+    pub start: GcSource,
+    pub dpad_up: GcSource,
+    pub dpad_down: GcSource,
+    pub dpad_left: GcSource,
+    pub dpad_right: GcSource,
+    pub l: GcSource,
+    pub r: GcSource,
+    pub c_up: GcSource,
+    pub c_down: GcSource,
+    pub c_left: GcSource,
+    pub c_right: GcSource,
+    /// How far from its origin the C-stick must be pushed for a `CStick*` source to count as pressed.
+    pub cstick_threshold: u8,
+    pub stick: StickRange,
+}
+
+impl GcToN64Mapping {
+    /// Buttons map to the gamecube button of the same name and the C buttons to the C-stick,
+    /// except that the N64's Z trigger is the gamecube's L and the N64's L is the gamecube's Z,
+    /// the same layout as the gamecube releases of N64 games.
+    pub const DEFAULT: GcToN64Mapping = GcToN64Mapping {
+        a: GcSource::A,
+        b: GcSource::B,
+        z: GcSource::L,
+        start: GcSource::Start,
+        dpad_up: GcSource::DpadUp,
+        dpad_down: GcSource::DpadDown,
+        dpad_left: GcSource::DpadLeft,
+        dpad_right: GcSource::DpadRight,
+        l: GcSource::Z,
+        r: GcSource::R,
+        c_up: GcSource::CStickUp,
+        c_down: GcSource::CStickDown,
+        c_left: GcSource::CStickLeft,
+        c_right: GcSource::CStickRight,
+        cstick_threshold: 40,
+        stick: StickRange::GAMECUBE_TO_N64,
+    };
+
+    /// Convert `input` from a controller whose neutral position is `origin`.
+    pub fn map(&self, input: &GamecubeInput, origin: &GamecubeInput) -> N64Input {
+        let pressed = |source: GcSource| source.is_pressed(input, origin, self.cstick_threshold);
+        let axis = |value: u8, center: u8| {
+            self.stick
+                .convert(value as i16 - center as i16)
+                .clamp(i8::MIN as i16, i8::MAX as i16) as i8
+        };
+        N64Input {
+            a: pressed(self.a),
+            b: pressed(self.b),
+            z: pressed(self.z),
+            start: pressed(self.start),
+            dpad_up: pressed(self.dpad_up),
+            dpad_down: pressed(self.dpad_down),
+            dpad_left: pressed(self.dpad_left),
+            dpad_right: pressed(self.dpad_right),
+            l: pressed(self.l),
+            r: pressed(self.r),
+            c_up: pressed(self.c_up),
+            c_down: pressed(self.c_down),
+            c_left: pressed(self.c_left),
+            c_right: pressed(self.c_right),
+            stick_x: axis(input.stick_x, origin.stick_x),
+            stick_y: axis(input.stick_y, origin.stick_y),
+        }
+    }
+}
+
+impl Default for GcToN64Mapping {
+    fn default() -> Self {
+        GcToN64Mapping::DEFAULT
+    }
+}
+
+/// Polls a gamecube controller and presents it to an N64 console.
+///
+/// The console is always answered with the most recent inputs and the controller is polled straight after,
+/// so the console sees inputs one poll old but never has to wait on the controller.
+/// If the controller stops responding the console sees a neutral controller until it comes back.
+pub struct GcToN64Bridge<
+    P1: PIOExt,
+    I1: JoybusPin<P1>,
+    S1: StateMachineIndex,
+    P2: PIOExt,
+    I2: JoybusPin<P2>,
+    S2: StateMachineIndex,
+> {
+    host: GamecubeHost<P1, I1, S1>,
+    device: N64Controller<P2, I2, S2>,
+    mapping: GcToN64Mapping,
+    origin: Option<GamecubeInput>,
+    input: N64Input,
+}
+
+impl<
+        P1: PIOExt,
+        I1: JoybusPin<P1>,
+        S1: StateMachineIndex,
+        P2: PIOExt,
+        I2: JoybusPin<P2>,
+        S2: StateMachineIndex,
+    > GcToN64Bridge<P1, I1, S1, P2, I2, S2>
+{
+    pub fn new(
+        host: GamecubeHost<P1, I1, S1>,
+        device: N64Controller<P2, I2, S2>,
+    ) -> GcToN64Bridge<P1, I1, S1, P2, I2, S2> {
+        GcToN64Bridge {
+            host,
+            device,
+            mapping: GcToN64Mapping::DEFAULT,
+            origin: None,
+            input: N64Input::NEUTRAL,
+        }
+    }
+
+    pub fn set_mapping(&mut self, mapping: GcToN64Mapping) {
+        self.mapping = mapping;
+    }
+
+    /// Returns true if the controller responded to the most recent poll.
+    pub fn is_connected(&self) -> bool {
+        self.origin.is_some()
+    }
+
+    /// Answer the next command from the console, then poll the controller if the command was a poll.
+    /// Returns the command answered, or None if the console sent nothing within [`CONSOLE_TIMEOUT_US`].
+    ///
+    /// Call this from the main loop continuously.
+    pub fn update(&mut self, timer: &Timer, delay: &mut Delay) -> Option<N64Command> {
+        let command = self
+            .device
+            .respond(timer, delay, &self.input, CONSOLE_TIMEOUT_US);
+        // With no console the controller is still polled so it is ready once the console starts.
+        if matches!(command, None | Some(N64Command::Poll)) {
+            self.update_controller(timer);
+        }
+        command
+    }
+
+    fn update_controller(&mut self, timer: &Timer) {
+        let origin = match self.origin {
+            Some(origin) => origin,
+            None => {
+                // A controller needs to be probed and have its origin read before it will respond to polls.
+                let Ok(origin) = self.host.probe(timer).and_then(|_| self.host.origin(timer))
+                else {
+                    return;
+                };
+                // The origin has the same layout as a mode 3 poll followed by 2 reserved bytes.
+                let origin = GamecubeInput::from_report(origin[..8].try_into().unwrap());
+                debug!("joybus: controller connected");
+                self.origin = Some(origin);
+                origin
+            }
+        };
+
+        match self.host.poll_input(timer, false) {
+            Ok(input) => self.input = self.mapping.map(&input, &origin),
+            Err(_err) => {
+                debug!("joybus: controller disconnected {:?}", _err);
+                self.origin = None;
+                self.input = N64Input::NEUTRAL;
+            }
+        }
+    }
+
+    /// Returns the [`GamecubeHost`] and [`N64Controller`] so they can be reused.
+    pub fn free(self) -> (GamecubeHost<P1, I1, S1>, N64Controller<P2, I2, S2>) {
+        (self.host, self.device)
+    }
+}
+
+/// A button on an N64 controller that can be mapped to a button on another controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum N64Source {
+    /// Never pressed.
+    None,
+    A,
+    B,
+    Z,
+    Start,
+    L,
+    R,
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+    CUp,
+    CDown,
+    CLeft,
+    CRight,
+}
+
+impl N64Source {
+    /// Returns true if this button is pressed.
+    pub fn is_pressed(self, input: &N64Input) -> bool {
+        match self {
+            N64Source::None => false,
+            N64Source::A => input.a,
+            N64Source::B => input.b,
+            N64Source::Z => input.z,
+            N64Source::Start => input.start,
+            N64Source::L => input.l,
+            N64Source::R => input.r,
+            N64Source::DpadUp => input.dpad_up,
+            N64Source::DpadDown => input.dpad_down,
+            N64Source::DpadLeft => input.dpad_left,
+            N64Source::DpadRight => input.dpad_right,
+            N64Source::CUp => input.c_up,
+            N64Source::CDown => input.c_down,
+            N64Source::CLeft => input.c_left,
+            N64Source::CRight => input.c_right,
+        }
+    }
+}
+
+/// Which N64 button drives each gamecube button, and how the stick is converted.
+///
+/// The `cstick_*` fields choose the buttons that push the C-stick fully in each direction,
+/// and pressing L or R also fully presses the matching analog trigger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct N64ToGcMapping {
+    pub a: N64Source,
+    pub b: N64Source,
+    pub x: N64Source,
+    pub y: N64Source,
+    pub z: N64Source,
+    pub start: N64Source,
+    pub dpad_up: N64Source,
+    pub dpad_down: N64Source,
+    pub dpad_left: N64Source,
+    pub dpad_right: N64Source,
+    pub l: N64Source,
+    pub r: N64Source,
+    pub cstick_up: N64Source,
+    pub cstick_down: N64Source,
+    pub cstick_left: N64Source,
+    pub cstick_right: N64Source,
+    pub stick: StickRange,
+}
+
+impl N64ToGcMapping {
+    /// The inverse of [`GcToN64Mapping::DEFAULT`], with the C buttons on the C-stick and nothing on X and Y.
+    pub const DEFAULT: N64ToGcMapping = N64ToGcMapping {
+        a: N64Source::A,
+        b: N64Source::B,
+        x: N64Source::None,
+        y: N64Source::None,
+        z: N64Source::L,
+        start: N64Source::Start,
+        dpad_up: N64Source::DpadUp,
+        dpad_down: N64Source::DpadDown,
+        dpad_left: N64Source::DpadLeft,
+        dpad_right: N64Source::DpadRight,
+        l: N64Source::Z,
+        r: N64Source::R,
+        cstick_up: N64Source::CUp,
+        cstick_down: N64Source::CDown,
+        cstick_left: N64Source::CLeft,
+        cstick_right: N64Source::CRight,
+        stick: StickRange::N64_TO_GAMECUBE,
+    };
+
+    /// Convert `input`, an N64 stick is already zeroed by the controller itself so no origin is needed.
+    pub fn map(&self, input: &N64Input) -> GamecubeInput {
+        let pressed = |source: N64Source| source.is_pressed(input);
+        let axis = |value: i8| (128 + self.stick.convert(value as i16)).clamp(0, 255) as u8;
+        let cstick_axis =
+            |negative: N64Source, positive: N64Source| match (pressed(negative), pressed(positive))
+            {
+                (true, false) => 0,
+                (false, true) => 255,
+                _ => 128,
+            };
+        let l = pressed(self.l);
+        let r = pressed(self.r);
+        GamecubeInput {
+            start: pressed(self.start),
+            a: pressed(self.a),
+            b: pressed(self.b),
+            x: pressed(self.x),
+            y: pressed(self.y),
+            z: pressed(self.z),
+            dpad_up: pressed(self.dpad_up),
+            dpad_down: pressed(self.dpad_down),
+            dpad_left: pressed(self.dpad_left),
+            dpad_right: pressed(self.dpad_right),
+            l_digital: l,
+            r_digital: r,
+            stick_x: axis(input.stick_x),
+            stick_y: axis(input.stick_y),
+            cstick_x: cstick_axis(self.cstick_left, self.cstick_right),
+            cstick_y: cstick_axis(self.cstick_down, self.cstick_up),
+            l_analog: if l { 255 } else { 0 },
+            r_analog: if r { 255 } else { 0 },
+        }
+    }
+}
+
+impl Default for N64ToGcMapping {
+    fn default() -> Self {
+        N64ToGcMapping::DEFAULT
+    }
+}
+
+/// Polls an N64 controller and presents it to a gamecube console.
+///
+/// Like [`GcToN64Bridge`] the console is answered with the most recent inputs and the controller is polled straight after.
+pub struct N64ToGcBridge<
+    P1: PIOExt,
+    I1: JoybusPin<P1>,
+    S1: StateMachineIndex,
+    P2: PIOExt,
+    I2: JoybusPin<P2>,
+    S2: StateMachineIndex,
+> {
+    host: N64Host<P1, I1, S1>,
+    device: GamecubeController<P2, I2, S2>,
+    mapping: N64ToGcMapping,
+    connected: bool,
+    input: GamecubeInput,
+}
+
+impl<
+        P1: PIOExt,
+        I1: JoybusPin<P1>,
+        S1: StateMachineIndex,
+        P2: PIOExt,
+        I2: JoybusPin<P2>,
+        S2: StateMachineIndex,
+    > N64ToGcBridge<P1, I1, S1, P2, I2, S2>
+{
+    /// `device` is a [`GamecubeController`] that has already completed its handshake with the console.
+    pub fn new(
+        host: N64Host<P1, I1, S1>,
+        device: GamecubeController<P2, I2, S2>,
+    ) -> N64ToGcBridge<P1, I1, S1, P2, I2, S2> {
+        N64ToGcBridge {
+            host,
+            device,
+            mapping: N64ToGcMapping::DEFAULT,
+            connected: false,
+            input: GamecubeInput::NEUTRAL,
+        }
+    }
+
+    pub fn set_mapping(&mut self, mapping: N64ToGcMapping) {
+        self.mapping = mapping;
+    }
+
+    /// Returns true if the controller responded to the most recent poll.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Wait for the next poll from the console, answer it, then poll the controller.
+    ///
+    /// Call this from the main loop continuously.
+    /// Like [`GamecubeController::wait_for_poll_start`] this blocks until the console polls.
+    pub fn update(&mut self, timer: &Timer, delay: &mut Delay) {
+        self.device.wait_for_poll_start(timer, delay);
+        self.device.respond_to_poll(timer, delay, self.input);
+        self.update_controller(timer);
+    }
+
+    fn update_controller(&mut self, timer: &Timer) {
+        if !self.connected {
+            // Resetting zeroes the stick at its current position, just like the N64 does when the controller is plugged in.
+            self.connected = self.host.reset(timer).is_ok();
+            if !self.connected {
+                return;
+            }
+            debug!("joybus: controller connected");
+        }
+
+        match self.host.poll(timer) {
+            Ok(input) => self.input = self.mapping.map(&input),
+            Err(_err) => {
+                debug!("joybus: controller disconnected {:?}", _err);
+                self.connected = false;
+                self.input = GamecubeInput::NEUTRAL;
+            }
+        }
+    }
+
+    /// Returns the [`N64Host`] and [`GamecubeController`] so they can be reused.
+    pub fn free(self) -> (N64Host<P1, I1, S1>, GamecubeController<P2, I2, S2>) {
+        (self.host, self.device)
+    }
+}
@@ -0,0 +1,42 @@
+//! Debug build checks that code running inside a response window returns in time.
+//!
+//! Anything that runs between a command arriving and its response starting delays the response,
+//! and a late response is a dropped input or, once the console gives up waiting, a disconnected controller.
+//! In debug builds the time taken is measured against these budgets and overruns are logged with `warn!`
+//! and counted in [`crate::ControllerStats::budget_overruns`], so slow LED or input sampling code is caught early.
+//! Release builds skip the measurement entirely.
+
+#[cfg(debug_assertions)]
+use crate::rp2040_hal::{timer::Instant, Timer};
+
+/// Microseconds [`crate::GamecubeController`] waits after the start of a poll before receiving the rest of it,
+/// so the response can be sent as soon as the final byte arrives.
+pub(crate) const POLL_FINISH_DELAY_US: u32 = 40;
+
+/// Bits of a poll command still to arrive once its first byte has been received: 2 argument bytes and the stop bit.
+const POLL_REMAINING_BITS: u64 = 17;
+
+/// Microseconds available between [`crate::GamecubeController::wait_for_poll_start`] returning and
+/// the response method being called, e.g. for sampling inputs, before the response goes out late.
+pub const POLL_BUDGET_US: u64 = POLL_REMAINING_BITS * 4 - POLL_FINISH_DELAY_US as u64;
+
+/// Microseconds a callback run after a command has been fully received may take, e.g. [`crate::ResetBehavior::Callback`].
+/// An OEM controller starts responding within a few microseconds of the stop bit.
+pub const CALLBACK_BUDGET_US: u64 = 5;
+
+/// Warn if more than `budget_us` has passed since `start`, returning whether it did.
+#[cfg(debug_assertions)]
+pub(crate) fn check(timer: &Timer, start: Instant, budget_us: u64, what: &str) -> bool {
+    let elapsed_us = timer
+        .get_counter()
+        .checked_duration_since(start)
+        .map_or(0, |elapsed| elapsed.ticks());
+    if elapsed_us <= budget_us {
+        return false;
+    }
+    warn!(
+        "joybus: {} took {}us, over its {}us budget so the response is late",
+        what, elapsed_us, budget_us
+    );
+    true
+}
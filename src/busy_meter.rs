@@ -0,0 +1,81 @@
+//! Measurement of how much CPU time the controller spends busy waiting on the bus.
+//!
+//! Like [`crate::JitterProbe`] the cycle source is provided by the user since the RP2040 has no DWT cycle counter.
+
+/// Cycles spent busy waiting compared to the total cycles elapsed, returned by [`BusyMeter::take`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyReport {
+    /// Cycles spent blocked in [`crate::GamecubeController::recv`], [`crate::GamecubeController::send`] and
+    /// [`crate::GamecubeController::flush`], including the methods built on top of them.
+    pub busy_cycles: u32,
+    /// Cycles elapsed since the meter was created or last taken.
+    pub elapsed_cycles: u32,
+}
+
+impl BusyReport {
+    /// Percentage of the elapsed time that was not spent busy waiting, and is available for other work.
+    pub fn headroom_percent(&self) -> u8 {
+        if self.elapsed_cycles == 0 {
+            return 100;
+        }
+        let busy = self.busy_cycles.min(self.elapsed_cycles) as u64;
+        (100 - busy * 100 / self.elapsed_cycles as u64) as u8
+    }
+}
+
+/// Counts busy waiting cycles for a [`crate::GamecubeController`], see [`crate::GamecubeController::set_busy_meter`].
+#[derive(Debug, Clone, Copy)]
+pub struct BusyMeter {
+    cycle_count: fn() -> u32,
+    since: u32,
+    busy_cycles: u32,
+    waiting_since: Option<u32>,
+}
+
+impl BusyMeter {
+    /// `cycle_count` must return an incrementing counter, wrapping at `u32::MAX`.
+    pub fn new(cycle_count: fn() -> u32) -> BusyMeter {
+        BusyMeter {
+            cycle_count,
+            since: cycle_count(),
+            busy_cycles: 0,
+            waiting_since: None,
+        }
+    }
+
+    /// Returns the cycles counted since the meter was created or last taken, and starts counting again.
+    ///
+    /// Must be called more often than `cycle_count` wraps, e.g. once per frame, for the result to be meaningful.
+    pub fn take(&mut self) -> BusyReport {
+        let now = (self.cycle_count)();
+        let mut busy_cycles = self.busy_cycles;
+        // split a wait that is in progress between the two reports
+        if let Some(start) = &mut self.waiting_since {
+            busy_cycles = busy_cycles.saturating_add(now.wrapping_sub(*start));
+            *start = now;
+        }
+        let report = BusyReport {
+            busy_cycles,
+            elapsed_cycles: now.wrapping_sub(self.since),
+        };
+        self.since = now;
+        self.busy_cycles = 0;
+        report
+    }
+
+    /// Start counting a wait, returns false if a wait is already being counted.
+    pub(crate) fn enter(&mut self) -> bool {
+        if self.waiting_since.is_some() {
+            return false;
+        }
+        self.waiting_since = Some((self.cycle_count)());
+        true
+    }
+
+    pub(crate) fn exit(&mut self) {
+        if let Some(start) = self.waiting_since.take() {
+            let cycles = (self.cycle_count)().wrapping_sub(start);
+            self.busy_cycles = self.busy_cycles.saturating_add(cycles);
+        }
+    }
+}
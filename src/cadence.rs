@@ -0,0 +1,136 @@
+//! Estimation of the console's frame rate and phase from when it polls.
+//!
+//! Most games poll once per video frame, so the poll cadence follows the console's refresh rate.
+//! This lets inputs be sampled right before the next poll instead of up to a frame early,
+//! which matters to rhythm game controllers, and lets other work be scheduled between polls.
+//!
+//! ```ignore
+//! if let Some(next_us) = controller.cadence().next_poll_us(timer.get_counter().ticks()) {
+//!     // wake up a little before the poll to sample inputs
+//!     sleep_until(next_us - 500);
+//! }
+//! ```
+
+/// Estimated periods are stored in 1/16ths of a microsecond so the average keeps its precision.
+const FRACTION_BITS: u32 = 4;
+
+/// Each new interval moves the estimate 1/16th of the way, enough to settle within a second of polls.
+const SMOOTHING_SHIFT: u32 = 4;
+
+/// Intervals used before the estimate is considered settled.
+const SETTLED_SAMPLES: u32 = 16;
+
+/// Intervals that are a multiple of the period up to this many frames are treated as skipped polls.
+const MAX_SKIPPED_FRAMES: u64 = 4;
+
+/// Consecutive intervals that don't fit the estimate before it is thrown away and restarted.
+const MAX_REJECTED: u32 = 8;
+
+/// Tracks the interval between polls, see [`crate::GamecubeController::cadence`].
+///
+/// Intervals that are a small multiple of the current estimate are taken to be skipped frames, e.g. during loading,
+/// and longer gaps are ignored.
+/// If the cadence changes, e.g. a game switching between 50Hz and 60Hz, the estimate restarts after a few polls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollCadence {
+    last_poll_us: Option<u64>,
+    /// In 1/16ths of a microsecond, 0 until the first interval is recorded.
+    period: u64,
+    samples: u32,
+    rejected: u32,
+}
+
+impl PollCadence {
+    pub const fn new() -> PollCadence {
+        PollCadence {
+            last_poll_us: None,
+            period: 0,
+            samples: 0,
+            rejected: 0,
+        }
+    }
+
+    /// Record that a poll arrived at `timestamp_us`, a microsecond timestamp such as `timer.get_counter().ticks()`.
+    pub fn record_poll(&mut self, timestamp_us: u64) {
+        let last_poll_us = self.last_poll_us.replace(timestamp_us);
+        let Some(interval) = last_poll_us.map(|last| timestamp_us.saturating_sub(last)) else {
+            return;
+        };
+        if interval == 0 {
+            return;
+        }
+        let interval = interval << FRACTION_BITS;
+
+        if self.period == 0 {
+            self.period = interval;
+            self.samples = 1;
+            return;
+        }
+
+        // round to the nearest whole number of frames
+        let frames = (interval + self.period / 2) / self.period;
+        if (1..=MAX_SKIPPED_FRAMES).contains(&frames) {
+            let interval = interval / frames;
+            // reject intervals more than 1/8th of a period from the estimate
+            if interval.abs_diff(self.period) <= self.period >> 3 {
+                self.period = (self.period << SMOOTHING_SHIFT) - self.period + interval;
+                self.period >>= SMOOTHING_SHIFT;
+                self.samples = self.samples.saturating_add(1);
+                self.rejected = 0;
+                return;
+            }
+        }
+
+        self.rejected += 1;
+        if self.rejected >= MAX_REJECTED {
+            debug!("joybus: poll cadence changed, restarting estimate");
+            self.period = interval;
+            self.samples = 1;
+            self.rejected = 0;
+        }
+    }
+
+    /// Forget everything recorded so far, e.g. after the console was disconnected.
+    pub fn reset(&mut self) {
+        *self = PollCadence::new();
+    }
+
+    /// Whether enough consistent polls have been recorded for the estimate to be trusted.
+    pub fn is_settled(&self) -> bool {
+        self.samples >= SETTLED_SAMPLES
+    }
+
+    /// The estimated interval between polls in microseconds, None until settled.
+    pub fn period_us(&self) -> Option<u32> {
+        self.is_settled()
+            .then_some((self.period >> FRACTION_BITS) as u32)
+    }
+
+    /// The estimated poll rate in millihertz, e.g. 59940 for 59.94Hz. None until settled.
+    pub fn rate_mhz(&self) -> Option<u32> {
+        self.is_settled()
+            .then(|| ((1_000_000_000u64 << FRACTION_BITS) / self.period) as u32)
+    }
+
+    /// Microseconds since the most recent expected poll at `now_us`, from 0 to [`PollCadence::period_us`].
+    /// Keeps counting across skipped polls. None until settled.
+    pub fn phase_us(&self, now_us: u64) -> Option<u32> {
+        let last_poll_us = self.last_poll_us.filter(|_| self.is_settled())?;
+        let elapsed = now_us.saturating_sub(last_poll_us) << FRACTION_BITS;
+        Some(((elapsed % self.period) >> FRACTION_BITS) as u32)
+    }
+
+    /// The timestamp at which the next poll after `now_us` is expected. None until settled.
+    pub fn next_poll_us(&self, now_us: u64) -> Option<u64> {
+        let last_poll_us = self.last_poll_us.filter(|_| self.is_settled())?;
+        let elapsed = now_us.saturating_sub(last_poll_us) << FRACTION_BITS;
+        let frames = elapsed / self.period + 1;
+        Some(last_poll_us + ((frames * self.period) >> FRACTION_BITS))
+    }
+}
+
+impl Default for PollCadence {
+    fn default() -> Self {
+        PollCadence::new()
+    }
+}
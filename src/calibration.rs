@@ -0,0 +1,644 @@
+//! Mapping raw ADC readings of sticks and triggers to a [`GamecubeInput`], and a wizard that captures the mapping.
+//!
+//! A [`Calibration`] holds the raw reading of each stick at center and at each of its 8 notches,
+//! and of each trigger fully released and fully pressed.
+//! Each stick is mapped one sector at a time, so the notches of a worn or uneven gate still land exactly on
+//! the ideal notch coordinates in [`IDEAL_NOTCHES`].
+//!
+//! [`CalibrationWizard`] walks the user through capturing a calibration, one [`WizardStep`] at a time,
+//! so every firmware doesn't reimplement the same flow:
+//!
+//! 1. Release everything, capturing the center of both sticks and the released triggers.
+//! 2. Hold the main stick into each notch in turn, starting right and going counter-clockwise.
+//! 3. The same for the c-stick.
+//! 4. Fully press both triggers.
+//!
+//! ```ignore
+//! let mut wizard = CalibrationWizard::new();
+//! wizard.set_on_progress(Some(|step| display.show(step)));
+//! let calibration = loop {
+//!     let raw = RawAnalog { stick: (adc.read(&mut x)?, adc.read(&mut y)?), ..};
+//!     if let Some(calibration) = wizard.update(&raw, a_button.is_low()?) {
+//!         break calibration;
+//!     }
+//! };
+//! calibration.save(&mut storage, CALIBRATION_OFFSET)?;
+//! let input = calibration.apply(&raw, &buttons);
+//! ```
+//!
+//! Readings drift with the supply voltage and temperature, so they can be corrected by a [`Compensation`]
+//! before they are captured by the wizard or mapped by a calibration.
+//! [`Ratiometric`] corrects for the supply, anything else is a function or a custom implementation:
+//!
+//! ```ignore
+//! let mut ratiometric = Ratiometric::new(NOMINAL_SUPPLY);
+//! ratiometric.set_reference(adc.read(&mut supply_divider)?);
+//! let input = calibration.apply(&ratiometric.compensate(raw), &buttons);
+//! ```
+
+use crate::GamecubeInput;
+
+/// The coordinates the notches of a stick are mapped to, as distances from center,
+/// starting right and going counter-clockwise, every notch the full 127 from center.
+pub const IDEAL_NOTCHES: [(i8, i8); 8] = [
+    (127, 0),
+    (90, 90),
+    (0, 127),
+    (-90, 90),
+    (-127, 0),
+    (-90, -90),
+    (0, -127),
+    (90, -90),
+];
+
+/// The raw readings of the analog inputs, e.g. from the RP2040's 12 bit ADC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RawAnalog {
+    pub stick: (u16, u16),
+    pub cstick: (u16, u16),
+    pub l: u16,
+    pub r: u16,
+}
+
+/// A correction applied to raw readings before they are calibrated, see the [module docs](self).
+///
+/// It is called for every sample, so must be fast enough to still respond to the poll in time.
+pub trait Compensation {
+    fn compensate(&mut self, raw: RawAnalog) -> RawAnalog;
+}
+
+/// Any function of the raw readings, e.g. subtracting a drift measured against a temperature sensor.
+impl<F: FnMut(RawAnalog) -> RawAnalog> Compensation for F {
+    fn compensate(&mut self, raw: RawAnalog) -> RawAnalog {
+        self(raw)
+    }
+}
+
+/// Scales readings by how far the ADC reference or the supply of the sticks and triggers is from nominal.
+///
+/// Potentiometers powered from the same supply as the ADC reference are already ratiometric, so this is for
+/// sensors powered from a separate supply, or an ADC with its own reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ratiometric {
+    nominal: u16,
+    reference: u16,
+}
+
+impl Ratiometric {
+    /// Correct readings to what they would be with the reference measured as `nominal`, e.g. while calibrating.
+    pub const fn new(nominal: u16) -> Ratiometric {
+        Ratiometric {
+            nominal,
+            reference: nominal,
+        }
+    }
+
+    /// Update the measured reference, ideally sampled alongside the readings it corrects.
+    /// A reference of 0 leaves readings unchanged.
+    pub fn set_reference(&mut self, reference: u16) {
+        self.reference = reference;
+    }
+
+    pub fn reference(&self) -> u16 {
+        self.reference
+    }
+}
+
+impl Compensation for Ratiometric {
+    fn compensate(&mut self, raw: RawAnalog) -> RawAnalog {
+        if self.reference == 0 {
+            return raw;
+        }
+        let scale = |value: u16| {
+            (value as u32 * self.nominal as u32 / self.reference as u32).min(u16::MAX as u32) as u16
+        };
+        RawAnalog {
+            stick: (scale(raw.stick.0), scale(raw.stick.1)),
+            cstick: (scale(raw.cstick.0), scale(raw.cstick.1)),
+            l: scale(raw.l),
+            r: scale(raw.r),
+        }
+    }
+}
+
+/// The raw readings of a stick that are mapped to center and to each of [`IDEAL_NOTCHES`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StickCalibration {
+    pub center: (u16, u16),
+    pub notches: [(u16, u16); 8],
+}
+
+impl StickCalibration {
+    /// A perfectly circular stick of `radius` around `center`, with its axes in the same direction as the gamecube's.
+    pub const fn circle(center: (u16, u16), radius: u16) -> StickCalibration {
+        let mut notches = [(0, 0); 8];
+        let mut i = 0;
+        while i < 8 {
+            let (x, y) = IDEAL_NOTCHES[i];
+            notches[i] = (
+                (center.0 as i32 + x as i32 * radius as i32 / 127) as u16,
+                (center.1 as i32 + y as i32 * radius as i32 / 127) as u16,
+            );
+            i += 1;
+        }
+        StickCalibration { center, notches }
+    }
+
+    /// Map a raw reading to the gamecube's x and y, where 128 is centered.
+    pub fn apply(&self, raw: (u16, u16)) -> (u8, u8) {
+        let relative = |point: (u16, u16)| {
+            (
+                point.0 as i64 - self.center.0 as i64,
+                point.1 as i64 - self.center.1 as i64,
+            )
+        };
+        let (x, y) = relative(raw);
+        for i in 0..8 {
+            let (ax, ay) = relative(self.notches[i]);
+            let (bx, by) = relative(self.notches[(i + 1) % 8]);
+            let det = ax * by - ay * bx;
+            if det == 0 {
+                continue;
+            }
+            // express the reading as s * notch a + t * notch b, which lies in this sector if neither is negative
+            let s = x * by - y * bx;
+            let t = ax * y - ay * x;
+            if s * det.signum() < 0 || t * det.signum() < 0 {
+                continue;
+            }
+            let (ideal_ax, ideal_ay) = IDEAL_NOTCHES[i];
+            let (ideal_bx, ideal_by) = IDEAL_NOTCHES[(i + 1) % 8];
+            let mapped_x = (s * ideal_ax as i64 + t * ideal_bx as i64) / det;
+            let mapped_y = (s * ideal_ay as i64 + t * ideal_by as i64) / det;
+            return (axis_from_offset(mapped_x), axis_from_offset(mapped_y));
+        }
+        // only reachable at center or with notches that all lie on one line
+        (128, 128)
+    }
+}
+
+/// The raw readings of a trigger fully released and fully pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TriggerCalibration {
+    pub released: u16,
+    pub pressed: u16,
+}
+
+impl TriggerCalibration {
+    /// Map a raw reading to the gamecube's analog value, 0 when released to 255 when pressed.
+    /// `pressed` may be lower than `released` for triggers whose reading falls as they are pressed.
+    pub fn apply(&self, raw: u16) -> u8 {
+        let range = self.pressed as i32 - self.released as i32;
+        if range == 0 {
+            return 0;
+        }
+        ((raw as i32 - self.released as i32) * 255 / range).clamp(0, 255) as u8
+    }
+}
+
+/// The length of [`Calibration::to_bytes`].
+pub const CALIBRATION_LEN: usize = 1 + 2 * (2 + 16) * 2 + 2 * 2 * 2;
+
+/// Bumped whenever the layout of [`Calibration::to_bytes`] changes, so old data is rejected instead of misread.
+const CALIBRATION_VERSION: u8 = 1;
+
+/// The mapping of every analog input, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    pub stick: StickCalibration,
+    pub cstick: StickCalibration,
+    pub l: TriggerCalibration,
+    pub r: TriggerCalibration,
+}
+
+impl Calibration {
+    /// A guess for sticks and triggers that use the full range of the RP2040's 12 bit ADC, to use until calibrated.
+    pub const DEFAULT: Calibration = Calibration {
+        stick: StickCalibration::circle((2048, 2048), 2047),
+        cstick: StickCalibration::circle((2048, 2048), 2047),
+        l: TriggerCalibration {
+            released: 0,
+            pressed: 4095,
+        },
+        r: TriggerCalibration {
+            released: 0,
+            pressed: 4095,
+        },
+    };
+
+    /// Map `raw` into the sticks and analog triggers of `input`, everything else is passed through unchanged.
+    pub fn apply(&self, raw: &RawAnalog, input: &GamecubeInput) -> GamecubeInput {
+        let mut output = *input;
+        (output.stick_x, output.stick_y) = self.stick.apply(raw.stick);
+        (output.cstick_x, output.cstick_y) = self.cstick.apply(raw.cstick);
+        output.l_analog = self.l.apply(raw.l);
+        output.r_analog = self.r.apply(raw.r);
+        output
+    }
+
+    /// Serialize for persisting, e.g. through a [`crate::storage::WearLevelled`].
+    pub fn to_bytes(&self) -> [u8; CALIBRATION_LEN] {
+        let mut bytes = [0; CALIBRATION_LEN];
+        bytes[0] = CALIBRATION_VERSION;
+        let values = self.values();
+        for (chunk, value) in bytes[1..].chunks_exact_mut(2).zip(values) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserialize what was written by [`Calibration::to_bytes`].
+    /// Returns None if `bytes` is from a different version or is invalid, e.g. erased flash.
+    pub fn from_bytes(bytes: &[u8; CALIBRATION_LEN]) -> Option<Calibration> {
+        if bytes[0] != CALIBRATION_VERSION {
+            return None;
+        }
+        let mut values = [0; (CALIBRATION_LEN - 1) / 2];
+        for (value, chunk) in values.iter_mut().zip(bytes[1..].chunks_exact(2)) {
+            *value = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        let stick = |values: &[u16]| {
+            let mut notches = [(0, 0); 8];
+            for (notch, pair) in notches.iter_mut().zip(values[2..].chunks_exact(2)) {
+                *notch = (pair[0], pair[1]);
+            }
+            StickCalibration {
+                center: (values[0], values[1]),
+                notches,
+            }
+        };
+        Some(Calibration {
+            stick: stick(&values[0..18]),
+            cstick: stick(&values[18..36]),
+            l: TriggerCalibration {
+                released: values[36],
+                pressed: values[37],
+            },
+            r: TriggerCalibration {
+                released: values[38],
+                pressed: values[39],
+            },
+        })
+    }
+
+    /// Load from `storage` at `offset`, falling back to [`Calibration::DEFAULT`] if nothing valid has been saved.
+    #[cfg(feature = "storage")]
+    pub fn load<S: crate::storage::ReadStorage>(
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<Calibration, S::Error> {
+        let mut bytes = [0; CALIBRATION_LEN];
+        storage.read(offset, &mut bytes)?;
+        Ok(Calibration::from_bytes(&bytes).unwrap_or_default())
+    }
+
+    /// Save to `storage` at `offset`, using [`CALIBRATION_LEN`] bytes.
+    #[cfg(feature = "storage")]
+    pub fn save<S: crate::storage::Storage>(
+        &self,
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<(), S::Error> {
+        storage.write(offset, &self.to_bytes())
+    }
+
+    /// Every raw reading in the order they are serialized.
+    fn values(&self) -> [u16; (CALIBRATION_LEN - 1) / 2] {
+        let mut values = [0; (CALIBRATION_LEN - 1) / 2];
+        let mut i = 0;
+        let mut push = |value: u16| {
+            values[i] = value;
+            i += 1;
+        };
+        for stick in [self.stick, self.cstick] {
+            push(stick.center.0);
+            push(stick.center.1);
+            for notch in stick.notches {
+                push(notch.0);
+                push(notch.1);
+            }
+        }
+        for trigger in [self.l, self.r] {
+            push(trigger.released);
+            push(trigger.pressed);
+        }
+        values
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration::DEFAULT
+    }
+}
+
+/// What the user is asked to do next by a [`CalibrationWizard`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WizardStep {
+    /// Release both sticks and both triggers, then confirm.
+    Center,
+    /// Hold the main stick into notch 0 to 7 of [`IDEAL_NOTCHES`], then confirm.
+    StickNotch(u8),
+    /// Hold the c-stick into notch 0 to 7 of [`IDEAL_NOTCHES`], then confirm.
+    CStickNotch(u8),
+    /// Fully press both triggers, then confirm.
+    TriggersPressed,
+    /// The calibration is complete.
+    Done,
+}
+
+impl WizardStep {
+    /// The number of steps before [`WizardStep::Done`].
+    pub const COUNT: u8 = 18;
+
+    /// How many steps have been completed, from 0 to [`WizardStep::COUNT`].
+    pub fn progress(self) -> u8 {
+        match self {
+            WizardStep::Center => 0,
+            WizardStep::StickNotch(notch) => 1 + notch,
+            WizardStep::CStickNotch(notch) => 9 + notch,
+            WizardStep::TriggersPressed => 17,
+            WizardStep::Done => WizardStep::COUNT,
+        }
+    }
+
+    fn next(self) -> WizardStep {
+        match self {
+            WizardStep::Center => WizardStep::StickNotch(0),
+            WizardStep::StickNotch(7) => WizardStep::CStickNotch(0),
+            WizardStep::StickNotch(notch) => WizardStep::StickNotch(notch + 1),
+            WizardStep::CStickNotch(7) => WizardStep::TriggersPressed,
+            WizardStep::CStickNotch(notch) => WizardStep::CStickNotch(notch + 1),
+            WizardStep::TriggersPressed | WizardStep::Done => WizardStep::Done,
+        }
+    }
+
+    fn previous(self) -> WizardStep {
+        match self {
+            WizardStep::Center | WizardStep::StickNotch(0) => WizardStep::Center,
+            WizardStep::StickNotch(notch) => WizardStep::StickNotch(notch - 1),
+            WizardStep::CStickNotch(0) => WizardStep::StickNotch(7),
+            WizardStep::CStickNotch(notch) => WizardStep::CStickNotch(notch - 1),
+            WizardStep::TriggersPressed => WizardStep::CStickNotch(7),
+            WizardStep::Done => WizardStep::TriggersPressed,
+        }
+    }
+}
+
+/// Captures a [`Calibration`] one step at a time, see the [module docs](self).
+#[derive(Debug, Clone, Copy)]
+pub struct CalibrationWizard {
+    step: WizardStep,
+    calibration: Calibration,
+    confirm_held: bool,
+    on_progress: Option<fn(WizardStep)>,
+}
+
+impl CalibrationWizard {
+    /// Start at [`WizardStep::Center`], from [`Calibration::DEFAULT`].
+    pub const fn new() -> CalibrationWizard {
+        CalibrationWizard {
+            step: WizardStep::Center,
+            calibration: Calibration::DEFAULT,
+            confirm_held: false,
+            on_progress: None,
+        }
+    }
+
+    /// Call the function with the new step whenever the wizard moves to another step,
+    /// e.g. to show the next instruction or flash an LED.
+    pub fn set_on_progress(&mut self, on_progress: Option<fn(WizardStep)>) {
+        self.on_progress = on_progress;
+    }
+
+    pub fn step(&self) -> WizardStep {
+        self.step
+    }
+
+    /// The calibration captured so far, steps not yet completed keep the values of [`Calibration::DEFAULT`].
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    /// Capture `raw` for the current step when `confirm` is first pressed, then move to the next step.
+    /// Call this for every sample, it returns the calibration once every step is complete.
+    pub fn update(&mut self, raw: &RawAnalog, confirm: bool) -> Option<Calibration> {
+        if confirm && !self.confirm_held {
+            self.capture(raw);
+            self.go_to(self.step.next());
+        }
+        self.confirm_held = confirm;
+        match self.step {
+            WizardStep::Done => Some(self.calibration),
+            _ => None,
+        }
+    }
+
+    /// Go back to redo the previous step.
+    pub fn back(&mut self) {
+        self.go_to(self.step.previous());
+    }
+
+    /// Start over from [`WizardStep::Center`].
+    pub fn restart(&mut self) {
+        self.calibration = Calibration::DEFAULT;
+        self.go_to(WizardStep::Center);
+    }
+
+    fn capture(&mut self, raw: &RawAnalog) {
+        let calibration = &mut self.calibration;
+        match self.step {
+            WizardStep::Center => {
+                calibration.stick.center = raw.stick;
+                calibration.cstick.center = raw.cstick;
+                calibration.l.released = raw.l;
+                calibration.r.released = raw.r;
+            }
+            WizardStep::StickNotch(notch) => calibration.stick.notches[notch as usize] = raw.stick,
+            WizardStep::CStickNotch(notch) => {
+                calibration.cstick.notches[notch as usize] = raw.cstick
+            }
+            WizardStep::TriggersPressed => {
+                calibration.l.pressed = raw.l;
+                calibration.r.pressed = raw.r;
+            }
+            WizardStep::Done => {}
+        }
+    }
+
+    fn go_to(&mut self, step: WizardStep) {
+        if step == self.step {
+            return;
+        }
+        debug!("joybus: calibration step {}", step.progress());
+        self.step = step;
+        if let Some(on_progress) = self.on_progress {
+            on_progress(step);
+        }
+    }
+}
+
+impl Default for CalibrationWizard {
+    fn default() -> Self {
+        CalibrationWizard::new()
+    }
+}
+
+fn axis_from_offset(offset: i64) -> u8 {
+    (offset + 128).clamp(0, 255) as u8
+}
+
+const _: () = assert!(WizardStep::COUNT as usize == 2 + 2 * IDEAL_NOTCHES.len());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn trigger() {
+        let trigger = TriggerCalibration {
+            released: 100,
+            pressed: 1100,
+        };
+        let mapped = [0, 100, 600, 1100, u16::MAX].map(|raw| trigger.apply(raw));
+        assert_eq!(mapped, [0, 0, 127, 255, 255]);
+
+        let falling = TriggerCalibration {
+            released: 4000,
+            pressed: 3000,
+        };
+        let mapped = [4095, 3500, 0].map(|raw| falling.apply(raw));
+        assert_eq!(mapped, [0, 127, 255]);
+
+        let empty = TriggerCalibration {
+            released: 2000,
+            pressed: 2000,
+        };
+        assert_eq!(empty.apply(4095), 0);
+    }
+
+    #[test]
+    fn stick() {
+        let stick = Calibration::DEFAULT.stick;
+        assert_eq!(stick.apply((2048, 2048)), (128, 128));
+        assert_eq!(stick.apply((4095, 2048)), (255, 128));
+        assert_eq!(stick.apply((1, 2048)), (1, 128));
+        assert_eq!(stick.apply((2048, 4095)), (128, 255));
+
+        // past the notches of a smaller stick saturates
+        let small = StickCalibration::circle((2048, 2048), 1000);
+        assert_eq!(small.apply((4095, 2048)), (255, 128));
+        assert_eq!(small.apply((0, 2048)), (0, 128));
+        assert_eq!(small.apply((2048, 0)), (128, 0));
+    }
+
+    #[test]
+    fn uneven_notches() {
+        let mut stick = StickCalibration::circle((2000, 2100), 1500);
+        stick.notches[1] = (3000, 3200);
+        stick.notches[2] = (2100, 3900);
+        for (notch, ideal) in stick.notches.into_iter().zip(IDEAL_NOTCHES) {
+            let expected = ((ideal.0 as i16 + 128) as u8, (ideal.1 as i16 + 128) as u8);
+            assert_eq!(stick.apply(notch), expected);
+        }
+        // all notches on one line
+        let flat = StickCalibration {
+            center: (2048, 2048),
+            notches: [(2048, 2048); 8],
+        };
+        assert_eq!(flat.apply((4095, 0)), (128, 128));
+    }
+
+    #[test]
+    fn ratiometric() {
+        let mut ratiometric = Ratiometric::new(3300);
+        let raw = RawAnalog {
+            stick: (3000, 1500),
+            cstick: (0, u16::MAX),
+            l: 300,
+            r: 30,
+        };
+        assert_eq!(ratiometric.compensate(raw), raw);
+        ratiometric.set_reference(3000);
+        assert_eq!(
+            ratiometric.compensate(raw),
+            RawAnalog {
+                stick: (3300, 1650),
+                cstick: (0, u16::MAX),
+                l: 330,
+                r: 33,
+            }
+        );
+        ratiometric.set_reference(0);
+        assert_eq!(ratiometric.compensate(raw), raw);
+    }
+
+    #[test]
+    fn bytes() {
+        let bytes = Calibration::DEFAULT.to_bytes();
+        assert_eq!(bytes[..5], [CALIBRATION_VERSION, 0x00, 0x08, 0x00, 0x08]);
+        assert_eq!(bytes[CALIBRATION_LEN - 4..], [0x00, 0x00, 0xFF, 0x0F]);
+        assert_eq!(Calibration::from_bytes(&bytes), Some(Calibration::DEFAULT));
+        assert_eq!(Calibration::from_bytes(&[0xFF; CALIBRATION_LEN]), None);
+    }
+
+    static STEPS: AtomicU32 = AtomicU32::new(0);
+
+    #[test]
+    fn wizard() {
+        let mut wizard = CalibrationWizard::new();
+        wizard.set_on_progress(Some(|_| {
+            STEPS.fetch_add(1, Ordering::Relaxed);
+        }));
+        let raw = |i: u16| RawAnalog {
+            stick: (i, 100 + i),
+            cstick: (200 + i, 300 + i),
+            l: 400 + i,
+            r: 500 + i,
+        };
+
+        // holding confirm only completes one step
+        assert_eq!(wizard.update(&raw(0), true), None);
+        assert_eq!(wizard.update(&raw(0), true), None);
+        assert_eq!(wizard.step(), WizardStep::StickNotch(0));
+        wizard.back();
+        assert_eq!(wizard.step(), WizardStep::Center);
+        wizard.back();
+        assert_eq!(wizard.step(), WizardStep::Center);
+
+        let mut calibration = None;
+        for i in 0..WizardStep::COUNT as u16 {
+            assert_eq!(wizard.step().progress() as u16, i);
+            assert_eq!(calibration, None);
+            wizard.update(&raw(i), false);
+            calibration = wizard.update(&raw(i), true);
+        }
+        assert_eq!(wizard.step(), WizardStep::Done);
+        let calibration = calibration.unwrap();
+        assert_eq!(calibration.stick.center, (0, 100));
+        assert_eq!(calibration.cstick.center, (200, 300));
+        assert_eq!(calibration.stick.notches[7], (8, 108));
+        assert_eq!(calibration.cstick.notches[0], (209, 309));
+        assert_eq!(
+            calibration.l,
+            TriggerCalibration {
+                released: 400,
+                pressed: 417
+            }
+        );
+        assert_eq!(
+            calibration.r,
+            TriggerCalibration {
+                released: 500,
+                pressed: 517
+            }
+        );
+        // 2 steps forward and back, then every step
+        assert_eq!(STEPS.load(Ordering::Relaxed), 2 + WizardStep::COUNT as u32);
+
+        wizard.restart();
+        assert_eq!(wizard.step(), WizardStep::Center);
+        assert_eq!(wizard.calibration(), &Calibration::DEFAULT);
+    }
+}
@@ -0,0 +1,211 @@
+//! Reconstructs the commands and responses in a captured byte stream, for analysis tooling.
+//!
+//! The stream is the bytes seen on the line in order with no frame boundaries,
+//! e.g. from a logic analyzer export or from decoding each frame with the `sim` module and concatenating them.
+//! Frames are split using the known length of each command and its response.
+
+use std::vec::Vec;
+
+use crate::report::PollReport;
+use crate::GamecubeCommand;
+
+/// A single command and its response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transaction {
+    Probe {
+        id: [u8; 3],
+    },
+    Reset {
+        id: [u8; 3],
+    },
+    Origin {
+        origin: [u8; 10],
+    },
+    Recalibrate {
+        origin: [u8; 10],
+    },
+    Poll {
+        mode: u8,
+        rumble: bool,
+        report: PollReport,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureError {
+    /// An opcode that isn't part of the gamecube protocol was found at the given offset.
+    /// The length of its frames is unknown so decoding can't continue.
+    UnknownCommand { offset: usize, command: u8 },
+    /// The stream ended partway through the transaction starting at the given offset.
+    Truncated { offset: usize },
+}
+
+/// Decode every transaction in `bytes`, stopping at the first error.
+pub fn decode_stream(bytes: &[u8]) -> Result<Vec<Transaction>, CaptureError> {
+    let mut transactions = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let (transaction, len) =
+            decode_transaction(&bytes[offset..]).map_err(|err| err.at(offset))?;
+        transactions.push(transaction);
+        offset += len;
+    }
+    Ok(transactions)
+}
+
+/// Decode the transaction at the start of `bytes`, returning it and the number of bytes it took up.
+/// Error offsets are relative to the start of `bytes`.
+pub fn decode_transaction(bytes: &[u8]) -> Result<(Transaction, usize), CaptureError> {
+    let Some(&opcode) = bytes.first() else {
+        return Err(CaptureError::Truncated { offset: 0 });
+    };
+    let command = GamecubeCommand::from(opcode);
+    let (command_len, response_len) = match command {
+        GamecubeCommand::Probe | GamecubeCommand::Reset => (1, 3),
+        GamecubeCommand::Origin | GamecubeCommand::Recalibrate => (1, 10),
+        GamecubeCommand::Poll => (3, 8),
+        GamecubeCommand::Unknown(command) => {
+            return Err(CaptureError::UnknownCommand { offset: 0, command })
+        }
+    };
+    let len = command_len + response_len;
+    if bytes.len() < len {
+        return Err(CaptureError::Truncated { offset: 0 });
+    }
+    let response = &bytes[command_len..len];
+
+    let transaction = match command {
+        GamecubeCommand::Probe => Transaction::Probe {
+            id: response.try_into().unwrap(),
+        },
+        GamecubeCommand::Reset => Transaction::Reset {
+            id: response.try_into().unwrap(),
+        },
+        GamecubeCommand::Origin => Transaction::Origin {
+            origin: response.try_into().unwrap(),
+        },
+        GamecubeCommand::Recalibrate => Transaction::Recalibrate {
+            origin: response.try_into().unwrap(),
+        },
+        GamecubeCommand::Poll => Transaction::Poll {
+            mode: bytes[1],
+            rumble: bytes[2] & 1 != 0,
+            report: PollReport::decode(bytes[1], response.try_into().unwrap()),
+        },
+        GamecubeCommand::Unknown(_) => unreachable!(),
+    };
+    Ok((transaction, len))
+}
+
+impl CaptureError {
+    fn at(self, base: usize) -> CaptureError {
+        match self {
+            CaptureError::UnknownCommand { offset, command } => CaptureError::UnknownCommand {
+                offset: base + offset,
+                command,
+            },
+            CaptureError::Truncated { offset } => CaptureError::Truncated {
+                offset: base + offset,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBE: [u8; 4] = [0x00, 0x09, 0x00, 0x03];
+    const ORIGIN: [u8; 11] = [0x41, 0x00, 0x80, 128, 128, 128, 128, 0x1F, 0x1F, 0x00, 0x00];
+    const POLL: [u8; 11] = [
+        0x40, 0x03, 0x01, 0x01, 0x80, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60,
+    ];
+
+    #[test]
+    fn stream() {
+        let mut bytes = Vec::new();
+        for frame in [
+            &PROBE[..],
+            &ORIGIN,
+            &POLL,
+            &[0xFF, 0x09, 0x00, 0x20],
+            &[0x42; 11],
+        ] {
+            bytes.extend_from_slice(frame);
+        }
+        let transactions = decode_stream(&bytes).unwrap();
+        assert_eq!(transactions.len(), 5);
+        assert_eq!(
+            transactions[0],
+            Transaction::Probe {
+                id: [0x09, 0x00, 0x03]
+            }
+        );
+        assert_eq!(
+            transactions[1],
+            Transaction::Origin {
+                origin: ORIGIN[1..].try_into().unwrap()
+            }
+        );
+        let Transaction::Poll {
+            mode: 3,
+            rumble: true,
+            report: PollReport::Mode3(report),
+        } = transactions[2]
+        else {
+            panic!("not a mode 3 poll: {:?}", transactions[2]);
+        };
+        assert!(report.buttons.a && !report.buttons.b);
+        assert_eq!(
+            [
+                report.stick_x,
+                report.stick_y,
+                report.cstick_x,
+                report.cstick_y,
+                report.l_analog,
+                report.r_analog
+            ],
+            [0x10, 0x20, 0x30, 0x40, 0x50, 0x60]
+        );
+        assert_eq!(
+            transactions[3],
+            Transaction::Reset {
+                id: [0x09, 0x00, 0x20]
+            }
+        );
+        assert_eq!(
+            transactions[4],
+            Transaction::Recalibrate { origin: [0x42; 10] }
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(decode_stream(&[]), Ok(Vec::new()));
+        assert_eq!(
+            decode_transaction(&[]),
+            Err(CaptureError::Truncated { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&PROBE);
+        bytes.extend_from_slice(&POLL[..10]);
+        assert_eq!(
+            decode_stream(&bytes),
+            Err(CaptureError::Truncated { offset: 4 })
+        );
+
+        bytes.truncate(4);
+        bytes.extend_from_slice(&[0x54, 0x00, 0x00]);
+        assert_eq!(
+            decode_stream(&bytes),
+            Err(CaptureError::UnknownCommand {
+                offset: 4,
+                command: 0x54
+            })
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Host mode scripts that talk to a controller the way consoles, games and adapters do,
+//! for regression testing changes to device mode against realistic hosts.
+//!
+//! Each [`CompatScript`] is a named list of [`ScriptStep`]s covering a pattern seen in the wild,
+//! such as switching poll modes between menus and gameplay or re-reading the origin when the controller asks.
+//! Pick one by name with [`find`], e.g. from a serial command, and run it with [`run`]:
+//!
+//! ```ignore
+//! let mut host = GamecubeHost::new(port);
+//! for script in compat::SCRIPTS {
+//!     let report = compat::run(script, &mut host, &timer);
+//!     assert!(report.passed(), "{}: {:?}", script.name, report);
+//! }
+//! ```
+//!
+//! The scripts reproduce the commands and their cadence, not the exact timing of any particular game.
+//! Custom scripts can be built from [`ScriptStep`]s in the same way as the ones in [`SCRIPTS`].
+
+use crate::protocol::BUTTONS2_ALWAYS_SET;
+use crate::rp2040_hal::{
+    fugit::MicrosDurationU64,
+    pio::{PIOExt, StateMachineIndex},
+    Timer,
+};
+use crate::{GamecubeHost, HostError, HostQuirks, JoybusPin, OriginRefresh};
+
+/// A frame of an NTSC game, which most games poll once per.
+pub const NTSC_FRAME_US: u64 = 16_683;
+
+/// A single part of a [`CompatScript`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptStep {
+    Probe,
+    Reset,
+    Origin,
+    Recalibrate,
+    /// Poll `count` times, `interval_us` apart, with [`GamecubeHost::poll_with_quirks`] configured with `quirks`,
+    /// so the quirks decide the poll mode and when the origin is read.
+    Polls {
+        count: u32,
+        interval_us: u64,
+        quirks: HostQuirks,
+        rumble: bool,
+    },
+    /// Leave the bus idle, e.g. like a game loading.
+    Idle {
+        us: u64,
+    },
+}
+
+/// A named sequence of [`ScriptStep`]s, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatScript {
+    pub name: &'static str,
+    pub steps: &'static [ScriptStep],
+}
+
+/// The quirks of a gamecube polling in `mode`.
+const fn gamecube_mode(mode: u8) -> HostQuirks {
+    HostQuirks {
+        poll_mode: mode,
+        ..HostQuirks::GAMECUBE
+    }
+}
+
+/// Polls once per NTSC frame like a gamecube game.
+const fn frames(count: u32, quirks: HostQuirks, rumble: bool) -> ScriptStep {
+    ScriptStep::Polls {
+        count,
+        interval_us: NTSC_FRAME_US,
+        quirks,
+        rumble,
+    }
+}
+
+/// The console booting into its menu: a reset, the origin, then polling every frame.
+pub const BOOT: CompatScript = CompatScript {
+    name: "boot",
+    steps: &[
+        ScriptStep::Reset,
+        ScriptStep::Origin,
+        frames(300, HostQuirks::GAMECUBE, false),
+    ],
+};
+
+/// A game that polls in mode 3 every frame and rumbles briefly, with a loading screen in the middle.
+pub const GAMEPLAY: CompatScript = CompatScript {
+    name: "gameplay",
+    steps: &[
+        ScriptStep::Probe,
+        ScriptStep::Origin,
+        frames(300, HostQuirks::GAMECUBE, false),
+        frames(30, HostQuirks::GAMECUBE, true),
+        frames(60, HostQuirks::GAMECUBE, false),
+        ScriptStep::Idle { us: 500_000 },
+        frames(300, HostQuirks::GAMECUBE, false),
+    ],
+};
+
+/// A game that switches poll mode between its menus and gameplay, going through every mode.
+pub const MODE_SWITCHING: CompatScript = CompatScript {
+    name: "mode-switching",
+    steps: &[
+        ScriptStep::Probe,
+        ScriptStep::Origin,
+        frames(60, gamecube_mode(3), false),
+        frames(60, gamecube_mode(0), false),
+        frames(60, gamecube_mode(1), false),
+        frames(60, gamecube_mode(2), false),
+        frames(60, gamecube_mode(4), false),
+        frames(60, gamecube_mode(5), false),
+        frames(60, gamecube_mode(6), false),
+        frames(60, gamecube_mode(7), false),
+        frames(60, gamecube_mode(3), false),
+    ],
+};
+
+/// A game that recalibrates from its options menu, then reads the origin on a fixed cadence rather than when asked.
+pub const ORIGIN_CADENCE: CompatScript = CompatScript {
+    name: "origin-cadence",
+    steps: &[
+        ScriptStep::Probe,
+        ScriptStep::Origin,
+        frames(120, HostQuirks::GAMECUBE, false),
+        ScriptStep::Recalibrate,
+        frames(
+            600,
+            HostQuirks {
+                origin_refresh: OriginRefresh::Every(60),
+                ..HostQuirks::GAMECUBE
+            },
+            false,
+        ),
+    ],
+};
+
+/// An adapter that polls once per 1ms USB frame and stops rumble without braking.
+pub const FAST_ADAPTER: CompatScript = CompatScript {
+    name: "fast-adapter",
+    steps: &[
+        ScriptStep::Probe,
+        ScriptStep::Origin,
+        ScriptStep::Polls {
+            count: 5_000,
+            interval_us: 1_000,
+            quirks: HostQuirks::WII_U_ADAPTER,
+            rumble: false,
+        },
+    ],
+};
+
+/// Every script in this module.
+pub const SCRIPTS: &[CompatScript] =
+    &[BOOT, GAMEPLAY, MODE_SWITCHING, ORIGIN_CADENCE, FAST_ADAPTER];
+
+/// Look up a script in [`SCRIPTS`] by its name.
+pub fn find(name: &str) -> Option<&'static CompatScript> {
+    SCRIPTS.iter().find(|script| script.name == name)
+}
+
+/// The result of [`run`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScriptReport {
+    /// Commands sent, not counting origins read by [`GamecubeHost::poll_with_quirks`].
+    pub commands: u32,
+    /// Commands that got no or a short response.
+    pub failures: u32,
+    /// The step and error of the first failure.
+    pub first_failure: Option<(usize, HostError)>,
+    /// Responses missing the bit in buttons2 that is always set.
+    pub malformed: u32,
+    /// The slowest response, see [`GamecubeHost::last_response_us`].
+    pub max_response_us: u64,
+}
+
+impl ScriptReport {
+    pub fn passed(&self) -> bool {
+        self.failures == 0 && self.malformed == 0
+    }
+}
+
+/// Run every step of `script` against the controller connected to `host`, blocking until it is done.
+///
+/// Failures don't stop the script, like a console it carries on with the next command.
+/// The host is left configured with the quirks of the last polls step.
+pub fn run<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    script: &CompatScript,
+    host: &mut GamecubeHost<P, I, S>,
+    timer: &Timer,
+) -> ScriptReport {
+    let mut report = ScriptReport::default();
+    for (i, step) in script.steps.iter().enumerate() {
+        match *step {
+            ScriptStep::Probe => {
+                report.record(i, host, |host| host.probe(timer).map(|_| None));
+            }
+            ScriptStep::Reset => {
+                report.record(i, host, |host| host.reset(timer).map(|_| None));
+            }
+            ScriptStep::Origin => {
+                report.record(i, host, |host| {
+                    host.origin(timer).map(|origin| Some(origin[1]))
+                });
+            }
+            ScriptStep::Recalibrate => {
+                report.record(i, host, |host| {
+                    host.recalibrate(timer).map(|origin| Some(origin[1]))
+                });
+            }
+            ScriptStep::Polls {
+                count,
+                interval_us,
+                quirks,
+                rumble,
+            } => {
+                host.set_quirks(quirks);
+                let mut next = timer.get_counter();
+                for _ in 0..count {
+                    while timer.get_counter() < next {}
+                    next += MicrosDurationU64::micros(interval_us);
+                    report.record(i, host, |host| {
+                        host.poll_with_quirks(timer, rumble)
+                            .map(|response| Some(response[1]))
+                    });
+                }
+            }
+            ScriptStep::Idle { us } => {
+                let until = timer.get_counter() + MicrosDurationU64::micros(us);
+                while timer.get_counter() < until {}
+            }
+        }
+    }
+    report
+}
+
+impl ScriptReport {
+    /// Run a single transaction for step `step`, which returns the buttons2 byte of its response if it has one.
+    fn record<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+        &mut self,
+        step: usize,
+        host: &mut GamecubeHost<P, I, S>,
+        transaction: impl FnOnce(&mut GamecubeHost<P, I, S>) -> Result<Option<u8>, HostError>,
+    ) {
+        self.commands = self.commands.saturating_add(1);
+        match transaction(host) {
+            Ok(buttons2) => {
+                let response_us = host.last_response_us().unwrap_or(0);
+                self.max_response_us = self.max_response_us.max(response_us);
+                if buttons2.is_some_and(|buttons2| buttons2 & BUTTONS2_ALWAYS_SET == 0) {
+                    self.malformed = self.malformed.saturating_add(1);
+                }
+            }
+            Err(error) => {
+                self.failures = self.failures.saturating_add(1);
+                self.first_failure.get_or_insert((step, error));
+            }
+        }
+    }
+}
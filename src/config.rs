@@ -0,0 +1,84 @@
+//! Tuning parameters of a [`crate::GamecubeController`], either set at runtime or fixed at compile time.
+//!
+//! A controller reads its parameters from a [`ConfigSource`].
+//! By default that is a [`JoybusConfig`] stored in the controller, which can be replaced at any time with
+//! [`crate::GamecubeController::set_config`].
+//! Firmware that never changes them can use [`FixedConfig`] instead, so every parameter is a constant folded into the code:
+//!
+//! ```ignore
+//! let controller =
+//!     GamecubeController::try_new_with_config(port, &timer, &mut delay, FixedConfig::<96, 3, 3>);
+//! ```
+//!
+//! A config kept in a `const` can be fixed the same way by implementing [`ConfigSource`] for a unit struct:
+//!
+//! ```ignore
+//! const CONFIG: JoybusConfig = JoybusConfig { reply_delay_us: 3, ..JoybusConfig::DEFAULT };
+//!
+//! struct Config;
+//! impl ConfigSource for Config {
+//!     fn config(&self) -> JoybusConfig {
+//!         CONFIG
+//!     }
+//! }
+//! ```
+
+use crate::RECV_TIMEOUT_US;
+
+/// The parameters of a [`crate::GamecubeController`], see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoybusConfig {
+    /// How long to wait for each byte after the first of a command in microseconds,
+    /// before abandoning it as cut short.
+    pub recv_timeout_us: u64,
+    /// Microseconds to wait after a command before responding, on top of the time the PIO program takes to turn the line around.
+    pub reply_delay_us: u32,
+    /// The poll mode inputs are laid out in when responding to polls, see [`crate::report`].
+    /// Nearly every game requests mode 3.
+    pub poll_mode: u8,
+}
+
+impl JoybusConfig {
+    /// The parameters used by [`crate::GamecubeController::try_new`].
+    pub const DEFAULT: JoybusConfig = JoybusConfig {
+        recv_timeout_us: RECV_TIMEOUT_US,
+        reply_delay_us: crate::protocol::REPLY_DELAY_US,
+        poll_mode: 3,
+    };
+}
+
+impl Default for JoybusConfig {
+    fn default() -> Self {
+        JoybusConfig::DEFAULT
+    }
+}
+
+/// Where a [`crate::GamecubeController`] reads its [`JoybusConfig`] from.
+pub trait ConfigSource {
+    fn config(&self) -> JoybusConfig;
+}
+
+/// Set at runtime, see [`crate::GamecubeController::set_config`].
+impl ConfigSource for JoybusConfig {
+    #[inline(always)]
+    fn config(&self) -> JoybusConfig {
+        *self
+    }
+}
+
+/// A [`JoybusConfig`] fixed at compile time, each parameter is the const generic of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FixedConfig<const RECV_TIMEOUT_US: u64, const REPLY_DELAY_US: u32, const POLL_MODE: u8>;
+
+impl<const RECV_TIMEOUT_US: u64, const REPLY_DELAY_US: u32, const POLL_MODE: u8> ConfigSource
+    for FixedConfig<RECV_TIMEOUT_US, REPLY_DELAY_US, POLL_MODE>
+{
+    #[inline(always)]
+    fn config(&self) -> JoybusConfig {
+        JoybusConfig {
+            recv_timeout_us: RECV_TIMEOUT_US,
+            reply_delay_us: REPLY_DELAY_US,
+            poll_mode: POLL_MODE,
+        }
+    }
+}
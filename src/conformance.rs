@@ -0,0 +1,129 @@
+//! A scripted battery of checks run against a real controller from host mode, for QA of controllers built with this crate.
+//!
+//! ```ignore
+//! let mut host = GamecubeHost::new(port);
+//! let report = joybus_pio::conformance::run(&mut host, &timer);
+//! assert!(report.passed(), "{:?}", report);
+//! ```
+
+use crate::protocol::{BUTTONS2_ALWAYS_SET, GAMECUBE_CONTROLLER_ID};
+use crate::rp2040_hal::{
+    pio::{PIOExt, StateMachineIndex},
+    Timer,
+};
+use crate::{GamecubeHost, HostError, JoybusPin};
+
+/// The slowest acceptable time from the end of a command until the first response byte is received.
+/// The byte itself takes 32us, which leaves OEM-like controllers with plenty of room to respond.
+pub const MAX_RESPONSE_US: u64 = 60;
+
+/// Why a single check failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The controller didn't respond properly.
+    Host(HostError),
+    /// The probe response doesn't identify a standard gamecube controller.
+    UnexpectedId([u8; 3]),
+    /// The bit in buttons2 that is always set was clear.
+    MissingAlwaysSetBit,
+    /// The origin reports a stick outside the middle half of its range, the controller was probably not calibrated.
+    OffCenter,
+    /// The response started later than [`MAX_RESPONSE_US`].
+    TooSlow { response_us: u64 },
+}
+
+/// The result of every check run by [`run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub probe: Result<[u8; 3], Failure>,
+    pub origin: Result<[u8; 10], Failure>,
+    /// Polls in each mode from 0 to 7 with rumble off.
+    pub poll_modes: [Result<[u8; 8], Failure>; 8],
+    pub rumble_on: Result<[u8; 8], Failure>,
+    pub rumble_off: Result<[u8; 8], Failure>,
+    /// The slowest response seen across all checks.
+    pub max_response_us: u64,
+}
+
+impl ConformanceReport {
+    /// Returns true if every check passed.
+    pub fn passed(&self) -> bool {
+        self.probe.is_ok()
+            && self.origin.is_ok()
+            && self.poll_modes.iter().all(Result::is_ok)
+            && self.rumble_on.is_ok()
+            && self.rumble_off.is_ok()
+    }
+}
+
+/// Run the full battery against the controller connected to `host`.
+///
+/// The controller is left with rumble off.
+pub fn run<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    host: &mut GamecubeHost<P, I, S>,
+    timer: &Timer,
+) -> ConformanceReport {
+    let mut max_response_us = 0;
+
+    let probe = check(host, &mut max_response_us, |host| host.probe(timer)).and_then(|id| {
+        if id[..2] == GAMECUBE_CONTROLLER_ID {
+            Ok(id)
+        } else {
+            Err(Failure::UnexpectedId(id))
+        }
+    });
+
+    let origin = check(host, &mut max_response_us, |host| host.origin(timer)).and_then(|origin| {
+        if origin[1] & BUTTONS2_ALWAYS_SET == 0 {
+            Err(Failure::MissingAlwaysSetBit)
+        } else if origin[2..6].iter().any(|axis| !(64..=192).contains(axis)) {
+            Err(Failure::OffCenter)
+        } else {
+            Ok(origin)
+        }
+    });
+
+    let mut poll = |host: &mut GamecubeHost<P, I, S>, mode: u8, rumble: bool| {
+        check(host, &mut max_response_us, |host| {
+            host.poll(timer, mode, rumble)
+        })
+        .and_then(|report| {
+            if report[1] & 0b1000_0000 == 0 {
+                Err(Failure::MissingAlwaysSetBit)
+            } else {
+                Ok(report)
+            }
+        })
+    };
+    let mut poll_modes = [Err(Failure::Host(HostError::Timeout)); 8];
+    for (mode, result) in poll_modes.iter_mut().enumerate() {
+        *result = poll(host, mode as u8, false);
+    }
+    let rumble_on = poll(host, 3, true);
+    let rumble_off = poll(host, 3, false);
+
+    ConformanceReport {
+        probe,
+        origin,
+        poll_modes,
+        rumble_on,
+        rumble_off,
+        max_response_us,
+    }
+}
+
+/// Run a single transaction and check its response time.
+fn check<T, P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    host: &mut GamecubeHost<P, I, S>,
+    max_response_us: &mut u64,
+    transaction: impl FnOnce(&mut GamecubeHost<P, I, S>) -> Result<T, HostError>,
+) -> Result<T, Failure> {
+    let response = transaction(host).map_err(Failure::Host)?;
+    let response_us = host.last_response_us().unwrap_or(0);
+    *max_response_us = (*max_response_us).max(response_us);
+    if response_us > MAX_RESPONSE_US {
+        Err(Failure::TooSlow { response_us })
+    } else {
+        Ok(response)
+    }
+}
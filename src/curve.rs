@@ -0,0 +1,373 @@
+//! Response curves and deadzones for analog sticks, applied to a [`GamecubeInput`] before it is encoded.
+//!
+//! Sticks read through an ADC rarely rest exactly at center or reach the edge of their range,
+//! and often feel too sensitive around center. Each stick is shaped by a [`StickShape`] in three steps:
+//!
+//! 1. Distances from center up to the inner deadzone are sent as centered, which hides resting noise.
+//! 2. The remaining distance is stretched so that the outer deadzone reaches full deflection.
+//! 3. The result is passed through a [`Curve`], keeping the direction of the stick.
+//!
+//! Deadzones are radial, so the shaped stick never leaves a circle of radius [`FULL_DEFLECTION`].
+//!
+//! ```ignore
+//! let mut shaping = Shaping::default();
+//! shaping.stick = StickShape { curve: Curve::Expo(40), inner_deadzone: 6, outer_deadzone: 10 };
+//! controller.respond_to_poll(&timer, &mut delay, shaping.apply(&input));
+//! ```
+//!
+//! Sticks prone to snapback should be filtered with [`crate::snapback`] before they are shaped.
+//!
+//! The shaping is saved alongside the [`crate::calibration`] through [`Shaping::save`].
+
+use crate::GamecubeInput;
+
+/// The distance from center of a fully deflected stick.
+pub const FULL_DEFLECTION: u8 = 127;
+
+/// The number of points of a [`Curve::Lut`], spaced 8 apart from a distance of 0 to 128.
+pub const CURVE_LUT_LEN: usize = 17;
+
+/// How the distance of a stick from center is mapped after the deadzones are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    /// The distance is sent unchanged.
+    Linear,
+    /// Blend from linear to cubic by this percentage, from 0 to 100,
+    /// the higher it is the finer the control near center.
+    Expo(u8),
+    /// The distance sent for each input distance of 0, 8, 16 up to 128, interpolated in between.
+    Lut([u8; CURVE_LUT_LEN]),
+}
+
+impl Curve {
+    /// Map `distance`, from 0 to [`FULL_DEFLECTION`].
+    pub const fn apply(&self, distance: u8) -> u8 {
+        let distance = distance as u32;
+        let full = FULL_DEFLECTION as u32;
+        let shaped = match self {
+            Curve::Linear => distance,
+            Curve::Expo(expo) => {
+                let expo = if *expo > 100 { 100 } else { *expo as u32 };
+                let cubic = distance * distance * distance / (full * full);
+                (distance * (100 - expo) + cubic * expo) / 100
+            }
+            Curve::Lut(points) => {
+                let index = (distance / 8) as usize;
+                let fraction = distance % 8;
+                let start = points[index] as u32;
+                let end = points[index + 1] as u32;
+                if end >= start {
+                    start + (end - start) * fraction / 8
+                } else {
+                    start - (start - end) * fraction / 8
+                }
+            }
+        };
+        if shaped > full {
+            FULL_DEFLECTION
+        } else {
+            shaped as u8
+        }
+    }
+}
+
+/// The deadzones and curve of one stick, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StickShape {
+    pub curve: Curve,
+    /// Distances from center up to this are sent as centered.
+    pub inner_deadzone: u8,
+    /// Distances within this of [`FULL_DEFLECTION`] are sent as fully deflected.
+    pub outer_deadzone: u8,
+}
+
+impl StickShape {
+    /// No deadzones and a linear curve, the stick is only limited to a circle.
+    pub const NONE: StickShape = StickShape {
+        curve: Curve::Linear,
+        inner_deadzone: 0,
+        outer_deadzone: 0,
+    };
+
+    /// Shape the stick at `x` and `y`, where 128 is centered.
+    pub const fn apply(&self, x: u8, y: u8) -> (u8, u8) {
+        let dx = x as i32 - 128;
+        let dy = y as i32 - 128;
+        let distance = isqrt((dx * dx + dy * dy) as u32) as i32;
+        let inner = self.inner_deadzone as i32;
+        if distance <= inner {
+            return (128, 128);
+        }
+
+        let full = FULL_DEFLECTION as i32;
+        let outer = full - self.outer_deadzone as i32;
+        let stretched = if distance >= outer {
+            full
+        } else {
+            (distance - inner) * full / (outer - inner)
+        };
+        let shaped = self.curve.apply(stretched as u8) as i32;
+        (
+            axis_from_offset(dx * shaped / distance),
+            axis_from_offset(dy * shaped / distance),
+        )
+    }
+
+    fn write_bytes(&self, bytes: &mut [u8; STICK_SHAPE_LEN]) {
+        match self.curve {
+            Curve::Linear => bytes[0] = 0,
+            Curve::Expo(expo) => {
+                bytes[0] = 1;
+                bytes[1] = expo;
+            }
+            Curve::Lut(points) => {
+                bytes[0] = 2;
+                bytes[1..1 + CURVE_LUT_LEN].copy_from_slice(&points);
+            }
+        }
+        bytes[1 + CURVE_LUT_LEN] = self.inner_deadzone;
+        bytes[2 + CURVE_LUT_LEN] = self.outer_deadzone;
+    }
+
+    fn read_bytes(bytes: &[u8; STICK_SHAPE_LEN]) -> Option<StickShape> {
+        let curve = match bytes[0] {
+            0 => Curve::Linear,
+            1 => Curve::Expo(bytes[1]),
+            2 => Curve::Lut(bytes[1..1 + CURVE_LUT_LEN].try_into().unwrap()),
+            _ => return None,
+        };
+        Some(StickShape {
+            curve,
+            inner_deadzone: bytes[1 + CURVE_LUT_LEN],
+            outer_deadzone: bytes[2 + CURVE_LUT_LEN],
+        })
+    }
+}
+
+impl Default for StickShape {
+    fn default() -> Self {
+        StickShape::NONE
+    }
+}
+
+const STICK_SHAPE_LEN: usize = 3 + CURVE_LUT_LEN;
+
+/// The length of [`Shaping::to_bytes`].
+pub const SHAPING_LEN: usize = 1 + STICK_SHAPE_LEN * 2;
+
+/// Bumped whenever the layout of [`Shaping::to_bytes`] changes, so old data is rejected instead of misread.
+const SHAPING_VERSION: u8 = 1;
+
+/// The shape of both sticks of a controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Shaping {
+    pub stick: StickShape,
+    pub cstick: StickShape,
+}
+
+impl Shaping {
+    /// Shape both sticks of `input`, everything else is passed through unchanged.
+    pub const fn apply(&self, input: &GamecubeInput) -> GamecubeInput {
+        let mut output = *input;
+        (output.stick_x, output.stick_y) = self.stick.apply(input.stick_x, input.stick_y);
+        (output.cstick_x, output.cstick_y) = self.cstick.apply(input.cstick_x, input.cstick_y);
+        output
+    }
+
+    /// Serialize for persisting, e.g. through a [`crate::storage::WearLevelled`].
+    pub fn to_bytes(&self) -> [u8; SHAPING_LEN] {
+        let mut bytes = [0; SHAPING_LEN];
+        bytes[0] = SHAPING_VERSION;
+        let mut stick = [0; STICK_SHAPE_LEN];
+        self.stick.write_bytes(&mut stick);
+        bytes[1..1 + STICK_SHAPE_LEN].copy_from_slice(&stick);
+        let mut cstick = [0; STICK_SHAPE_LEN];
+        self.cstick.write_bytes(&mut cstick);
+        bytes[1 + STICK_SHAPE_LEN..].copy_from_slice(&cstick);
+        bytes
+    }
+
+    /// Deserialize what was written by [`Shaping::to_bytes`].
+    /// Returns None if `bytes` is from a different version or is invalid, e.g. erased flash.
+    pub fn from_bytes(bytes: &[u8; SHAPING_LEN]) -> Option<Shaping> {
+        if bytes[0] != SHAPING_VERSION {
+            return None;
+        }
+        Some(Shaping {
+            stick: StickShape::read_bytes(bytes[1..1 + STICK_SHAPE_LEN].try_into().unwrap())?,
+            cstick: StickShape::read_bytes(bytes[1 + STICK_SHAPE_LEN..].try_into().unwrap())?,
+        })
+    }
+
+    /// Load from `storage` at `offset`, falling back to [`Shaping::default`] if nothing valid has been saved.
+    #[cfg(feature = "storage")]
+    pub fn load<S: crate::storage::ReadStorage>(
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<Shaping, S::Error> {
+        let mut bytes = [0; SHAPING_LEN];
+        storage.read(offset, &mut bytes)?;
+        Ok(Shaping::from_bytes(&bytes).unwrap_or_default())
+    }
+
+    /// Save to `storage` at `offset`, using [`SHAPING_LEN`] bytes.
+    /// Keep it next to the calibration so both are written by the same save.
+    #[cfg(feature = "storage")]
+    pub fn save<S: crate::storage::Storage>(
+        &self,
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<(), S::Error> {
+        storage.write(offset, &self.to_bytes())
+    }
+}
+
+const fn axis_from_offset(offset: i32) -> u8 {
+    let value = offset + 128;
+    if value < 0 {
+        0
+    } else if value > 255 {
+        255
+    } else {
+        value as u8
+    }
+}
+
+/// The square root of `value`, rounded down.
+const fn isqrt(value: u32) -> u32 {
+    let mut root = 0;
+    let mut bit = 1 << 30;
+    let mut value = value;
+    while bit > value {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if value >= root + bit {
+            value -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+// Check each step of the module docs.
+const _: () = {
+    let shape = StickShape {
+        curve: Curve::Linear,
+        inner_deadzone: 10,
+        outer_deadzone: 17,
+    };
+    // inside the inner deadzone
+    let (x, y) = shape.apply(136, 122);
+    assert!(x == 128 && y == 128);
+    // inside the outer deadzone
+    let (x, y) = shape.apply(238, 128);
+    assert!(x == 255 && y == 128);
+    // a corner is limited to the circle
+    let (x, y) = StickShape::NONE.apply(255, 255);
+    assert!(x == 218 && y == 218);
+    let (x, y) = StickShape::NONE.apply(28, 200);
+    assert!(x == 28 && y == 200);
+};
+const _: () = {
+    assert!(Curve::Expo(0).apply(64) == 64);
+    assert!(Curve::Expo(100).apply(64) == 16);
+    assert!(Curve::Expo(100).apply(FULL_DEFLECTION) == FULL_DEFLECTION);
+    let mut points = [0; CURVE_LUT_LEN];
+    let mut i = 0;
+    while i < CURVE_LUT_LEN {
+        points[i] = (i * 8) as u8;
+        i += 1;
+    }
+    assert!(Curve::Lut(points).apply(100) == 100);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isqrt_rounds_down() {
+        assert_eq!(isqrt(0), 0);
+        assert_eq!(isqrt(1), 1);
+        assert_eq!(isqrt(15), 3);
+        assert_eq!(isqrt(16), 4);
+        assert_eq!(isqrt(32_768), 181);
+        assert_eq!(isqrt(u32::MAX), 65_535);
+    }
+
+    #[test]
+    fn axis_saturates() {
+        assert_eq!(axis_from_offset(-129), 0);
+        assert_eq!(axis_from_offset(-128), 0);
+        assert_eq!(axis_from_offset(0), 128);
+        assert_eq!(axis_from_offset(127), 255);
+        assert_eq!(axis_from_offset(128), 255);
+    }
+
+    #[test]
+    fn extremes_stay_on_the_circle() {
+        assert_eq!(StickShape::NONE.apply(0, 0), (39, 39));
+        assert_eq!(StickShape::NONE.apply(0, 128), (1, 128));
+        assert_eq!(StickShape::NONE.apply(255, 128), (255, 128));
+        assert_eq!(StickShape::NONE.apply(128, 0), (128, 1));
+    }
+
+    #[test]
+    fn deadzones_covering_everything() {
+        let shape = StickShape {
+            curve: Curve::Linear,
+            inner_deadzone: 255,
+            outer_deadzone: 0,
+        };
+        assert_eq!(shape.apply(0, 0), (128, 128));
+        assert_eq!(shape.apply(255, 255), (128, 128));
+    }
+
+    #[test]
+    fn curves() {
+        // above 100 is treated as 100
+        assert_eq!(Curve::Expo(255).apply(64), Curve::Expo(100).apply(64));
+        assert_eq!(Curve::Expo(50).apply(64), 40);
+        assert_eq!(Curve::Expo(50).apply(0), 0);
+
+        let mut falling = [0; CURVE_LUT_LEN];
+        for (i, point) in falling.iter_mut().enumerate() {
+            *point = 255 - (i * 8) as u8;
+        }
+        // clamped to full deflection, then interpolated downwards between points
+        assert_eq!(Curve::Lut(falling).apply(0), FULL_DEFLECTION);
+        assert_eq!(Curve::Lut(falling).apply(124), 127);
+        assert_eq!(Curve::Lut(falling).apply(FULL_DEFLECTION), 127);
+        assert_eq!(Curve::Lut([0; CURVE_LUT_LEN]).apply(100), 0);
+    }
+
+    #[test]
+    fn bytes() {
+        let shaping = Shaping {
+            stick: StickShape {
+                curve: Curve::Expo(40),
+                inner_deadzone: 6,
+                outer_deadzone: 10,
+            },
+            cstick: StickShape {
+                curve: Curve::Lut([3; CURVE_LUT_LEN]),
+                inner_deadzone: 1,
+                outer_deadzone: 2,
+            },
+        };
+        let bytes = shaping.to_bytes();
+        assert_eq!(bytes[..4], [SHAPING_VERSION, 1, 40, 0]);
+        assert_eq!(bytes[2 + CURVE_LUT_LEN..1 + STICK_SHAPE_LEN], [6, 10]);
+        assert_eq!(Shaping::from_bytes(&bytes), Some(shaping));
+
+        // erased flash
+        assert_eq!(Shaping::from_bytes(&[0xFF; SHAPING_LEN]), None);
+        let mut bad_curve = bytes;
+        bad_curve[1] = 3;
+        assert_eq!(Shaping::from_bytes(&bad_curve), None);
+    }
+}
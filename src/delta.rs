@@ -0,0 +1,279 @@
+//! Differences between two [`GamecubeInput`]s and events for each change, e.g. for logging, macro triggers or waking from idle.
+//!
+//! [`GamecubeInput::diff`] compares two inputs directly.
+//! [`ChangeEvents`] keeps the previous input itself and calls a function for every button pressed or released
+//! and every analog value that crosses its threshold:
+//!
+//! ```ignore
+//! let mut events = ChangeEvents::new();
+//! events.set_threshold(Analog::L, Some(200));
+//! events.set_on_change(Some(|event| debug!("{:?}", event)));
+//! loop {
+//!     let input = read_input();
+//!     if !events.update(&input).is_empty() {
+//!         last_activity = timer.get_counter();
+//!     }
+//!     controller.respond_to_poll(&timer, &mut delay, input);
+//! }
+//! ```
+
+use crate::remap::Button;
+use crate::GamecubeInput;
+
+/// An analog value of a gamecube controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Analog {
+    StickX,
+    StickY,
+    CStickX,
+    CStickY,
+    L,
+    R,
+}
+
+impl Analog {
+    pub const ALL: [Analog; 6] = [
+        Analog::StickX,
+        Analog::StickY,
+        Analog::CStickX,
+        Analog::CStickY,
+        Analog::L,
+        Analog::R,
+    ];
+
+    pub fn value(self, input: &GamecubeInput) -> u8 {
+        match self {
+            Analog::StickX => input.stick_x,
+            Analog::StickY => input.stick_y,
+            Analog::CStickX => input.cstick_x,
+            Analog::CStickY => input.cstick_y,
+            Analog::L => input.l_analog,
+            Analog::R => input.r_analog,
+        }
+    }
+}
+
+/// What changed from one [`GamecubeInput`] to another, see [`GamecubeInput::diff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InputDelta {
+    /// Bits in the order of [`Button::ALL`].
+    pressed: u16,
+    /// Bits in the order of [`Button::ALL`].
+    released: u16,
+    /// Indexed in the order of [`Analog::ALL`].
+    analog: [i16; 6],
+}
+
+impl InputDelta {
+    /// Returns true if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.pressed == 0 && self.released == 0 && self.analog == [0; 6]
+    }
+
+    /// Returns true if `button` went from released to pressed.
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.pressed & 1 << button as u16 != 0
+    }
+
+    /// Returns true if `button` went from pressed to released.
+    pub fn is_released(&self, button: Button) -> bool {
+        self.released & 1 << button as u16 != 0
+    }
+
+    /// The buttons that went from released to pressed.
+    pub fn pressed(&self) -> impl Iterator<Item = Button> + '_ {
+        Button::ALL
+            .into_iter()
+            .filter(|button| self.is_pressed(*button))
+    }
+
+    /// The buttons that went from pressed to released.
+    pub fn released(&self) -> impl Iterator<Item = Button> + '_ {
+        Button::ALL
+            .into_iter()
+            .filter(|button| self.is_released(*button))
+    }
+
+    /// How much `analog` changed, the new value minus the old one.
+    pub fn analog(&self, analog: Analog) -> i16 {
+        self.analog[analog as usize]
+    }
+}
+
+impl GamecubeInput {
+    /// What changed going from `self` to `other`.
+    pub fn diff(&self, other: &GamecubeInput) -> InputDelta {
+        let mut delta = InputDelta::default();
+        for (i, button) in Button::ALL.into_iter().enumerate() {
+            match (button.is_pressed(self), button.is_pressed(other)) {
+                (false, true) => delta.pressed |= 1 << i,
+                (true, false) => delta.released |= 1 << i,
+                _ => {}
+            }
+        }
+        for (change, analog) in delta.analog.iter_mut().zip(Analog::ALL) {
+            *change = analog.value(other) as i16 - analog.value(self) as i16;
+        }
+        delta
+    }
+}
+
+/// A single change reported by [`ChangeEvents`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Pressed(Button),
+    Released(Button),
+    /// `analog` crossed its threshold, rising from below it to at or above it, or falling back below it.
+    Crossed {
+        analog: Analog,
+        rising: bool,
+    },
+}
+
+/// Reports the changes of each input from the previous one, see the [module docs](self).
+#[derive(Debug, Clone, Copy)]
+pub struct ChangeEvents {
+    previous: GamecubeInput,
+    /// Indexed in the order of [`Analog::ALL`].
+    thresholds: [Option<u8>; 6],
+    on_change: Option<fn(InputEvent)>,
+}
+
+impl ChangeEvents {
+    /// Start from [`GamecubeInput::NEUTRAL`] with no thresholds and no callback.
+    pub const fn new() -> ChangeEvents {
+        ChangeEvents {
+            previous: GamecubeInput::NEUTRAL,
+            thresholds: [None; 6],
+            on_change: None,
+        }
+    }
+
+    /// Report [`InputEvent::Crossed`] whenever `analog` crosses `threshold`, or never if None.
+    pub fn set_threshold(&mut self, analog: Analog, threshold: Option<u8>) {
+        self.thresholds[analog as usize] = threshold;
+    }
+
+    pub fn threshold(&self, analog: Analog) -> Option<u8> {
+        self.thresholds[analog as usize]
+    }
+
+    /// Call the function for every event, in the order of [`Button::ALL`] then [`Analog::ALL`].
+    pub fn set_on_change(&mut self, on_change: Option<fn(InputEvent)>) {
+        self.on_change = on_change;
+    }
+
+    /// The input passed to the most recent [`ChangeEvents::update`].
+    pub fn previous(&self) -> &GamecubeInput {
+        &self.previous
+    }
+
+    /// Compare `input` to the previous one, calling the change callback for each event, and return the difference.
+    pub fn update(&mut self, input: &GamecubeInput) -> InputDelta {
+        let delta = self.previous.diff(input);
+        if let Some(on_change) = self.on_change {
+            for button in delta.pressed() {
+                on_change(InputEvent::Pressed(button));
+            }
+            for button in delta.released() {
+                on_change(InputEvent::Released(button));
+            }
+            for (analog, threshold) in Analog::ALL.into_iter().zip(self.thresholds) {
+                let Some(threshold) = threshold else { continue };
+                let was_above = analog.value(&self.previous) >= threshold;
+                let is_above = analog.value(input) >= threshold;
+                if was_above != is_above {
+                    on_change(InputEvent::Crossed {
+                        analog,
+                        rising: is_above,
+                    });
+                }
+            }
+        }
+        self.previous = *input;
+        delta
+    }
+}
+
+impl Default for ChangeEvents {
+    fn default() -> Self {
+        ChangeEvents::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn diff() {
+        let from = GamecubeInput {
+            a: true,
+            z: true,
+            stick_x: 0,
+            l_analog: 255,
+            ..GamecubeInput::NEUTRAL
+        };
+        let to = GamecubeInput {
+            a: true,
+            b: true,
+            r_digital: true,
+            stick_x: 255,
+            ..GamecubeInput::NEUTRAL
+        };
+        let delta = from.diff(&to);
+        assert!(!delta.is_empty());
+        assert!(delta.pressed().eq([Button::B, Button::R]));
+        assert!(delta.released().eq([Button::Z]));
+        assert!(!delta.is_pressed(Button::A) && !delta.is_released(Button::A));
+        assert_eq!(delta.analog(Analog::StickX), 255);
+        assert_eq!(delta.analog(Analog::L), -255);
+        assert_eq!(delta.analog(Analog::StickY), 0);
+
+        assert!(to.diff(&to).is_empty());
+        assert_eq!(to.diff(&from).analog(Analog::StickX), -255);
+    }
+
+    /// Events seen by [`record`], one counter per kind.
+    static PRESSED: AtomicU32 = AtomicU32::new(0);
+    static RELEASED: AtomicU32 = AtomicU32::new(0);
+    static RISING: AtomicU32 = AtomicU32::new(0);
+    static FALLING: AtomicU32 = AtomicU32::new(0);
+
+    fn record(event: InputEvent) {
+        let counter = match event {
+            InputEvent::Pressed(_) => &PRESSED,
+            InputEvent::Released(_) => &RELEASED,
+            InputEvent::Crossed { rising: true, .. } => &RISING,
+            InputEvent::Crossed { rising: false, .. } => &FALLING,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn change_events() {
+        let mut events = ChangeEvents::new();
+        events.set_threshold(Analog::L, Some(200));
+        events.set_on_change(Some(record));
+
+        let inputs = [
+            (true, 199),
+            (true, 200),
+            (false, 255),
+            (false, 255),
+            (false, 0),
+        ];
+        for (x, l_analog) in inputs {
+            let input = GamecubeInput {
+                x,
+                l_analog,
+                ..GamecubeInput::NEUTRAL
+            };
+            events.update(&input);
+            assert_eq!(events.previous(), &input);
+        }
+        let counts = [&PRESSED, &RELEASED, &RISING, &FALLING].map(|c| c.load(Ordering::Relaxed));
+        assert_eq!(counts, [1, 1, 1, 1]);
+    }
+}
@@ -0,0 +1,94 @@
+//! Detects whether a gamecube or an N64 console is connected, for firmware that works on both with a single cable.
+//!
+//! Both consoles start by probing with the same 0x00 command, so the command alone can't tell them apart.
+//! A gamecube console sends its commands at 5us per bit where an N64 sends at 4us per bit,
+//! so the time taken to receive the first byte of a command decides it.
+//! Any command unique to one console decides it straight away.
+//!
+//! ```ignore
+//! match AutoDevice::detect(port, &timer, &mut delay, 1_000_000) {
+//!     Ok(AutoDevice::Gamecube(controller)) => run_gamecube(controller),
+//!     Ok(AutoDevice::N64(controller)) => run_n64(controller),
+//!     Err(port) => retry(port),
+//! }
+//! ```
+
+use cortex_m::delay::Delay;
+
+use crate::n64::N64Controller;
+use crate::protocol::{CMD_N64_POLL, CMD_PAK_READ, CMD_PAK_WRITE};
+use crate::rp2040_hal::{
+    pio::{PIOExt, StateMachineIndex},
+    Timer,
+};
+use crate::{GamecubeCommand, GamecubeController, JoybusPin, JoybusPort};
+
+/// A byte that takes longer than this from its first falling edge until it is received was sent at the gamecube's bit rate.
+/// An N64 byte takes around 30us and a gamecube byte around 37us.
+const GAMECUBE_BYTE_US: u64 = 33;
+
+/// Which console is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Gamecube,
+    N64,
+}
+
+/// Wait up to `timeout_us` for a command and work out which console sent it.
+///
+/// The command is consumed without a response, consoles retry unanswered probes so this only delays the handshake.
+/// Returns None if no command arrived.
+pub fn detect_protocol<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    port: &mut JoybusPort<P, I, S>,
+    timer: &Timer,
+    timeout_us: u64,
+) -> Option<Protocol> {
+    port.restart_for_read(timer);
+    let (command, duration_us) = port.recv_byte_timed(timer, timeout_us)?;
+    // don't leave the rest of the command in the FIFO
+    port.restart_for_read(timer);
+
+    let protocol = match (GamecubeCommand::from(command), command) {
+        (GamecubeCommand::Origin | GamecubeCommand::Recalibrate | GamecubeCommand::Poll, _) => {
+            Protocol::Gamecube
+        }
+        // N64 poll, pak read and pak write
+        (_, CMD_N64_POLL | CMD_PAK_READ | CMD_PAK_WRITE) => Protocol::N64,
+        _ if duration_us > GAMECUBE_BYTE_US => Protocol::Gamecube,
+        _ => Protocol::N64,
+    };
+    debug!(
+        "joybus: detected {:?} from command {} taking {}us",
+        protocol, command, duration_us
+    );
+    Some(protocol)
+}
+
+/// A device configured for whichever console was detected.
+// Only created once at startup, so the unused space in the N64 variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
+pub enum AutoDevice<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> {
+    Gamecube(GamecubeController<P, I, S>),
+    N64(N64Controller<P, I, S>),
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> AutoDevice<P, I, S> {
+    /// Detect the console with [`detect_protocol`] and set up the matching device.
+    /// A gamecube controller also completes its handshake, see [`GamecubeController::try_new`].
+    ///
+    /// Err contains the port if no console was detected or the gamecube handshake failed.
+    pub fn detect(
+        mut port: JoybusPort<P, I, S>,
+        timer: &Timer,
+        delay: &mut Delay,
+        timeout_us: u64,
+    ) -> Result<AutoDevice<P, I, S>, JoybusPort<P, I, S>> {
+        match detect_protocol(&mut port, timer, timeout_us) {
+            Some(Protocol::Gamecube) => {
+                GamecubeController::try_new(port, timer, delay).map(AutoDevice::Gamecube)
+            }
+            Some(Protocol::N64) => Ok(AutoDevice::N64(N64Controller::new(port))),
+            None => Err(port),
+        }
+    }
+}
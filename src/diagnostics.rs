@@ -0,0 +1,99 @@
+//! Reserved opcodes that let a console or adapter side tool check a controller's health without USB access.
+//!
+//! Diagnostics are off by default, unknown opcodes are normally resynced on like any other,
+//! and are enabled with [`crate::GamecubeController::set_diagnostics`].
+//! Each command is a single opcode byte, answered like a probe:
+//!
+//! | Opcode | Response |
+//! |--------|----------|
+//! | [`DIAGNOSTICS_VERSION`] | [`Diagnostics::firmware_version`] then the major, minor and patch version of this crate, 7 bytes |
+//! | [`DIAGNOSTICS_STATS`] | polls, probes, origins, resyncs and budget overruns from [`crate::ControllerStats`], each a u32 big endian, 20 bytes |
+//! | [`DIAGNOSTICS_CALIBRATION`] | the origin sent in response to origin commands, 10 bytes |
+//! | [`DIAGNOSTICS_LAST_REPORT`] | the most recent poll response, 8 bytes |
+
+use crate::rp2040_hal::pio::{PIOExt, StateMachineIndex};
+use crate::{ConfigSource, GamecubeController, JoybusPin, Response, MAX_RESPONSE_LEN};
+
+pub const DIAGNOSTICS_VERSION: u8 = 0xF0;
+pub const DIAGNOSTICS_STATS: u8 = 0xF1;
+pub const DIAGNOSTICS_CALIBRATION: u8 = 0xF2;
+pub const DIAGNOSTICS_LAST_REPORT: u8 = 0xF3;
+
+/// What a controller reports about itself in response to diagnostics commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// The version of the firmware, in whatever scheme it uses.
+    pub firmware_version: [u8; 4],
+}
+
+pub(crate) const fn is_diagnostics(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        DIAGNOSTICS_VERSION | DIAGNOSTICS_STATS | DIAGNOSTICS_CALIBRATION | DIAGNOSTICS_LAST_REPORT
+    )
+}
+
+const fn parse_version(digits: &str) -> u8 {
+    let digits = digits.as_bytes();
+    let mut value = 0u8;
+    let mut i = 0;
+    while i < digits.len() {
+        value = value * 10 + (digits[i] - b'0');
+        i += 1;
+    }
+    value
+}
+
+/// The version of this crate, as sent in response to [`DIAGNOSTICS_VERSION`].
+const CRATE_VERSION: [u8; 3] = [
+    parse_version(env!("CARGO_PKG_VERSION_MAJOR")),
+    parse_version(env!("CARGO_PKG_VERSION_MINOR")),
+    parse_version(env!("CARGO_PKG_VERSION_PATCH")),
+];
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource>
+    GamecubeController<P, I, S, C>
+{
+    /// Answer the diagnostics commands with `diagnostics`, or resync on them like any unknown command if None.
+    pub fn set_diagnostics(&mut self, diagnostics: Option<Diagnostics>) {
+        self.diagnostics = diagnostics;
+        self.fsm.set_diagnostics(diagnostics.is_some());
+    }
+
+    /// The response to a diagnostics `opcode`.
+    pub(crate) fn diagnostics_response(&self, opcode: u8) -> Option<Response> {
+        let diagnostics = self.diagnostics?;
+        let mut response = [0; MAX_RESPONSE_LEN];
+        let len = match opcode {
+            DIAGNOSTICS_VERSION => {
+                response[..4].copy_from_slice(&diagnostics.firmware_version);
+                response[4..7].copy_from_slice(&CRATE_VERSION);
+                7
+            }
+            DIAGNOSTICS_STATS => {
+                let stats = self.stats;
+                let counters = [
+                    stats.polls,
+                    stats.probes,
+                    stats.origins,
+                    stats.resyncs,
+                    stats.budget_overruns,
+                ];
+                for (bytes, counter) in response.chunks_exact_mut(4).zip(counters) {
+                    bytes.copy_from_slice(&counter.to_be_bytes());
+                }
+                20
+            }
+            DIAGNOSTICS_CALIBRATION => {
+                response[..10].copy_from_slice(&self.origin);
+                10
+            }
+            DIAGNOSTICS_LAST_REPORT => {
+                response[..8].copy_from_slice(&self.last_report);
+                8
+            }
+            _ => return None,
+        };
+        Some(Response::new(&response[..len]))
+    }
+}
@@ -0,0 +1,212 @@
+//! `ufmt` formatting of inputs, commands and stats, for printing protocol state over a serial console without defmt.
+//!
+//! `uDebug` prints every field like `core::fmt::Debug` does.
+//! `uDisplay` prints a single compact line, e.g. `A Z stick 128,200 c 128,128 L 0 R 0` for an input.
+//!
+//! ```ignore
+//! uwriteln!(serial, "{} after {}", input, controller.stats()).ok();
+//! ```
+
+use ufmt::{uDebug, uDisplay, uWrite, uwrite, Formatter};
+
+use crate::{ControllerStats, FsmAction, GamecubeCommand, GamecubeInput};
+
+/// Write the names of the held buttons, each followed by a space.
+fn write_buttons<W: uWrite + ?Sized>(
+    f: &mut Formatter<'_, W>,
+    buttons: &[(&str, bool)],
+) -> Result<(), W::Error> {
+    for (name, _) in buttons.iter().filter(|(_, held)| *held) {
+        uwrite!(f, "{} ", *name)?;
+    }
+    Ok(())
+}
+
+impl uDebug for GamecubeInput {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        f.debug_struct("GamecubeInput")?
+            .field("start", &self.start)?
+            .field("a", &self.a)?
+            .field("b", &self.b)?
+            .field("x", &self.x)?
+            .field("y", &self.y)?
+            .field("z", &self.z)?
+            .field("dpad_up", &self.dpad_up)?
+            .field("dpad_down", &self.dpad_down)?
+            .field("dpad_left", &self.dpad_left)?
+            .field("dpad_right", &self.dpad_right)?
+            .field("l_digital", &self.l_digital)?
+            .field("r_digital", &self.r_digital)?
+            .field("stick_x", &self.stick_x)?
+            .field("stick_y", &self.stick_y)?
+            .field("cstick_x", &self.cstick_x)?
+            .field("cstick_y", &self.cstick_y)?
+            .field("l_analog", &self.l_analog)?
+            .field("r_analog", &self.r_analog)?
+            .finish()
+    }
+}
+
+impl uDisplay for GamecubeInput {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        write_buttons(
+            f,
+            &[
+                ("START", self.start),
+                ("A", self.a),
+                ("B", self.b),
+                ("X", self.x),
+                ("Y", self.y),
+                ("Z", self.z),
+                ("UP", self.dpad_up),
+                ("DOWN", self.dpad_down),
+                ("LEFT", self.dpad_left),
+                ("RIGHT", self.dpad_right),
+                ("LD", self.l_digital),
+                ("RD", self.r_digital),
+            ],
+        )?;
+        uwrite!(
+            f,
+            "stick {},{} c {},{} L {} R {}",
+            self.stick_x,
+            self.stick_y,
+            self.cstick_x,
+            self.cstick_y,
+            self.l_analog,
+            self.r_analog
+        )
+    }
+}
+
+impl uDebug for GamecubeCommand {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        match self {
+            GamecubeCommand::Probe => f.write_str("Probe"),
+            GamecubeCommand::Poll => f.write_str("Poll"),
+            GamecubeCommand::Origin => f.write_str("Origin"),
+            GamecubeCommand::Recalibrate => f.write_str("Recalibrate"),
+            GamecubeCommand::Reset => f.write_str("Reset"),
+            GamecubeCommand::Unknown(opcode) => f.debug_tuple("Unknown")?.field(opcode)?.finish(),
+        }
+    }
+}
+
+impl uDisplay for GamecubeCommand {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        match self {
+            GamecubeCommand::Probe => f.write_str("probe"),
+            GamecubeCommand::Poll => f.write_str("poll"),
+            GamecubeCommand::Origin => f.write_str("origin"),
+            GamecubeCommand::Recalibrate => f.write_str("recalibrate"),
+            GamecubeCommand::Reset => f.write_str("reset"),
+            GamecubeCommand::Unknown(opcode) => uwrite!(f, "unknown {}", opcode),
+        }
+    }
+}
+
+impl uDebug for FsmAction {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        match self {
+            FsmAction::Wait => f.write_str("Wait"),
+            FsmAction::RespondId => f.write_str("RespondId"),
+            FsmAction::Reset => f.write_str("Reset"),
+            FsmAction::RespondOrigin => f.write_str("RespondOrigin"),
+            FsmAction::Recalibrate => f.write_str("Recalibrate"),
+            FsmAction::PollStarted => f.write_str("PollStarted"),
+            FsmAction::RespondPoll { mode, rumble } => f
+                .debug_struct("RespondPoll")?
+                .field("mode", mode)?
+                .field("rumble", rumble)?
+                .finish(),
+            FsmAction::RespondDiagnostics(opcode) => {
+                f.debug_tuple("RespondDiagnostics")?.field(opcode)?.finish()
+            }
+            FsmAction::UnknownCommand(opcode) => {
+                f.debug_tuple("UnknownCommand")?.field(opcode)?.finish()
+            }
+            FsmAction::Resync => f.write_str("Resync"),
+        }
+    }
+}
+
+impl uDebug for ControllerStats {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        f.debug_struct("ControllerStats")?
+            .field("polls", &self.polls)?
+            .field("probes", &self.probes)?
+            .field("origins", &self.origins)?
+            .field("resyncs", &self.resyncs)?
+            .field("last_command_us", &self.last_command_us)?
+            .field("last_poll_us", &self.last_poll_us)?
+            .field("budget_overruns", &self.budget_overruns)?
+            .field("tx_underruns", &self.tx_underruns)?
+            .field("unknown_commands", &self.unknown_commands)?
+            .finish()
+    }
+}
+
+impl uDisplay for ControllerStats {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        uwrite!(
+            f,
+            "polls {} probes {} origins {} resyncs {} overruns {} underruns {}",
+            self.polls,
+            self.probes,
+            self.origins,
+            self.resyncs,
+            self.budget_overruns,
+            self.tx_underruns
+        )
+    }
+}
+
+#[cfg(feature = "n64")]
+impl uDebug for crate::n64::N64Input {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        f.debug_struct("N64Input")?
+            .field("a", &self.a)?
+            .field("b", &self.b)?
+            .field("z", &self.z)?
+            .field("start", &self.start)?
+            .field("dpad_up", &self.dpad_up)?
+            .field("dpad_down", &self.dpad_down)?
+            .field("dpad_left", &self.dpad_left)?
+            .field("dpad_right", &self.dpad_right)?
+            .field("l", &self.l)?
+            .field("r", &self.r)?
+            .field("c_up", &self.c_up)?
+            .field("c_down", &self.c_down)?
+            .field("c_left", &self.c_left)?
+            .field("c_right", &self.c_right)?
+            .field("stick_x", &self.stick_x)?
+            .field("stick_y", &self.stick_y)?
+            .finish()
+    }
+}
+
+#[cfg(feature = "n64")]
+impl uDisplay for crate::n64::N64Input {
+    fn fmt<W: uWrite + ?Sized>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error> {
+        write_buttons(
+            f,
+            &[
+                ("START", self.start),
+                ("A", self.a),
+                ("B", self.b),
+                ("Z", self.z),
+                ("UP", self.dpad_up),
+                ("DOWN", self.dpad_down),
+                ("LEFT", self.dpad_left),
+                ("RIGHT", self.dpad_right),
+                ("L", self.l),
+                ("R", self.r),
+                ("CU", self.c_up),
+                ("CD", self.c_down),
+                ("CL", self.c_left),
+                ("CR", self.c_right),
+            ],
+        )?;
+        uwrite!(f, "stick {},{}", self.stick_x, self.stick_y)
+    }
+}
@@ -0,0 +1,293 @@
+//! Conversion between [`GamecubeInput`] and the text format of Dolphin's pipe input,
+//! so sessions recorded from a device can be replayed in the emulator and emulator scripts can drive a device.
+//!
+//! Dolphin reads newline separated commands from a named pipe and holds the resulting state until it is changed:
+//! * `PRESS <button>` and `RELEASE <button>`, where the button is one of
+//!   `A`, `B`, `X`, `Y`, `Z`, `START`, `L`, `R`, `D_UP`, `D_DOWN`, `D_LEFT` or `D_RIGHT`
+//! * `SET MAIN <x> <y>` and `SET C <x> <y>` with each axis from 0.0 to 1.0, centered at 0.5
+//! * `SET L <value>` and `SET R <value>` from 0.0 released to 1.0 fully pressed
+//!
+//! ```ignore
+//! let mut pipe = std::fs::OpenOptions::new().write(true).open("~/.dolphin-emu/Pipes/joybus")?;
+//! let mut previous = GamecubeInput::NEUTRAL;
+//! pipe.write_all(dolphin::to_commands(&previous).as_bytes())?;
+//! for input in recorded_inputs {
+//!     pipe.write_all(dolphin::changed_commands(&previous, &input).as_bytes())?;
+//!     previous = input;
+//! }
+//! ```
+
+use std::fmt::Write;
+use std::string::String;
+
+use crate::GamecubeInput;
+
+/// Why a line couldn't be applied by [`apply_command`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeError {
+    /// The line didn't start with `PRESS`, `RELEASE` or `SET`.
+    UnknownCommand,
+    /// The button, stick or trigger name wasn't recognized.
+    UnknownTarget,
+    /// A value was missing or wasn't a number.
+    InvalidValue,
+}
+
+/// Every button in the order [`to_commands`] writes them, paired with its state in `input`.
+fn buttons(input: &GamecubeInput) -> [(&'static str, bool); 12] {
+    [
+        ("A", input.a),
+        ("B", input.b),
+        ("X", input.x),
+        ("Y", input.y),
+        ("Z", input.z),
+        ("START", input.start),
+        ("L", input.l_digital),
+        ("R", input.r_digital),
+        ("D_UP", input.dpad_up),
+        ("D_DOWN", input.dpad_down),
+        ("D_LEFT", input.dpad_left),
+        ("D_RIGHT", input.dpad_right),
+    ]
+}
+
+fn button_mut<'a>(input: &'a mut GamecubeInput, name: &str) -> Option<&'a mut bool> {
+    Some(match name {
+        "A" => &mut input.a,
+        "B" => &mut input.b,
+        "X" => &mut input.x,
+        "Y" => &mut input.y,
+        "Z" => &mut input.z,
+        "START" => &mut input.start,
+        "L" => &mut input.l_digital,
+        "R" => &mut input.r_digital,
+        "D_UP" => &mut input.dpad_up,
+        "D_DOWN" => &mut input.dpad_down,
+        "D_LEFT" => &mut input.dpad_left,
+        "D_RIGHT" => &mut input.dpad_right,
+        _ => return None,
+    })
+}
+
+/// Stick axes map 0.0 and 1.0 to 1 and 255 so that 0.5 lands exactly on the center of 128.
+fn axis_to_pipe(value: u8) -> f32 {
+    (0.5 + (value as f32 - 128.0) / 254.0).clamp(0.0, 1.0)
+}
+
+fn axis_from_pipe(value: f32) -> u8 {
+    (128.0 + (value.clamp(0.0, 1.0) - 0.5) * 254.0).round() as u8
+}
+
+fn trigger_to_pipe(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+fn trigger_from_pipe(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Commands that set every button, stick and trigger of Dolphin's pipe input to match `input`.
+pub fn to_commands(input: &GamecubeInput) -> String {
+    let mut commands = String::new();
+    for (name, pressed) in buttons(input) {
+        write_button(&mut commands, name, pressed);
+    }
+    write_main(&mut commands, input);
+    write_c(&mut commands, input);
+    write_trigger(&mut commands, "L", input.l_analog);
+    write_trigger(&mut commands, "R", input.r_analog);
+    commands
+}
+
+/// Commands for only what changed from `previous` to `input`, for streaming a recording without resending everything.
+pub fn changed_commands(previous: &GamecubeInput, input: &GamecubeInput) -> String {
+    let mut commands = String::new();
+    for ((name, was_pressed), (_, pressed)) in buttons(previous).into_iter().zip(buttons(input)) {
+        if was_pressed != pressed {
+            write_button(&mut commands, name, pressed);
+        }
+    }
+    if (previous.stick_x, previous.stick_y) != (input.stick_x, input.stick_y) {
+        write_main(&mut commands, input);
+    }
+    if (previous.cstick_x, previous.cstick_y) != (input.cstick_x, input.cstick_y) {
+        write_c(&mut commands, input);
+    }
+    if previous.l_analog != input.l_analog {
+        write_trigger(&mut commands, "L", input.l_analog);
+    }
+    if previous.r_analog != input.r_analog {
+        write_trigger(&mut commands, "R", input.r_analog);
+    }
+    commands
+}
+
+fn write_button(commands: &mut String, name: &str, pressed: bool) {
+    let action = if pressed { "PRESS" } else { "RELEASE" };
+    writeln!(commands, "{action} {name}").unwrap();
+}
+
+fn write_main(commands: &mut String, input: &GamecubeInput) {
+    let (x, y) = (axis_to_pipe(input.stick_x), axis_to_pipe(input.stick_y));
+    writeln!(commands, "SET MAIN {x:.4} {y:.4}").unwrap();
+}
+
+fn write_c(commands: &mut String, input: &GamecubeInput) {
+    let (x, y) = (axis_to_pipe(input.cstick_x), axis_to_pipe(input.cstick_y));
+    writeln!(commands, "SET C {x:.4} {y:.4}").unwrap();
+}
+
+fn write_trigger(commands: &mut String, name: &str, value: u8) {
+    let value = trigger_to_pipe(value);
+    writeln!(commands, "SET {name} {value:.4}").unwrap();
+}
+
+/// Apply a single line of pipe input to `input`, e.g. from an emulator script driving a device.
+/// Values outside of 0.0 to 1.0 are clamped. Blank lines are ignored.
+pub fn apply_command(input: &mut GamecubeInput, line: &str) -> Result<(), PipeError> {
+    let mut words = line.split_whitespace();
+    let Some(command) = words.next() else {
+        return Ok(());
+    };
+    let target = words.next().ok_or(PipeError::UnknownTarget)?;
+    let mut value = || -> Result<f32, PipeError> {
+        words
+            .next()
+            .and_then(|word| word.parse().ok())
+            .ok_or(PipeError::InvalidValue)
+    };
+
+    match command {
+        "PRESS" | "RELEASE" => {
+            *button_mut(input, target).ok_or(PipeError::UnknownTarget)? = command == "PRESS";
+        }
+        "SET" => match target {
+            "MAIN" => {
+                let (x, y) = (value()?, value()?);
+                input.stick_x = axis_from_pipe(x);
+                input.stick_y = axis_from_pipe(y);
+            }
+            "C" => {
+                let (x, y) = (value()?, value()?);
+                input.cstick_x = axis_from_pipe(x);
+                input.cstick_y = axis_from_pipe(y);
+            }
+            "L" => input.l_analog = trigger_from_pipe(value()?),
+            "R" => input.r_analog = trigger_from_pipe(value()?),
+            _ => return Err(PipeError::UnknownTarget),
+        },
+        _ => return Err(PipeError::UnknownCommand),
+    }
+    Ok(())
+}
+
+/// Apply every line of `commands` to `input` in order, stopping at the first line that fails.
+/// The error contains the index of that line.
+pub fn apply_commands(input: &mut GamecubeInput, commands: &str) -> Result<(), (usize, PipeError)> {
+    for (i, line) in commands.lines().enumerate() {
+        apply_command(input, line).map_err(|err| (i, err))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neutral() {
+        assert_eq!(
+            to_commands(&GamecubeInput::NEUTRAL),
+            "RELEASE A\nRELEASE B\nRELEASE X\nRELEASE Y\nRELEASE Z\nRELEASE START\n\
+             RELEASE L\nRELEASE R\nRELEASE D_UP\nRELEASE D_DOWN\nRELEASE D_LEFT\nRELEASE D_RIGHT\n\
+             SET MAIN 0.5000 0.5000\nSET C 0.5000 0.5000\nSET L 0.0000\nSET R 0.0000\n"
+        );
+    }
+
+    #[test]
+    fn changed() {
+        let previous = GamecubeInput::NEUTRAL;
+        assert_eq!(changed_commands(&previous, &previous), "");
+        let input = GamecubeInput {
+            a: true,
+            stick_x: 255,
+            stick_y: 0,
+            r_analog: 255,
+            ..previous
+        };
+        assert_eq!(
+            changed_commands(&previous, &input),
+            "PRESS A\nSET MAIN 1.0000 0.0000\nSET R 1.0000\n"
+        );
+        assert_eq!(
+            changed_commands(&input, &previous),
+            "RELEASE A\nSET MAIN 0.5000 0.5000\nSET R 0.0000\n"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        // 0 has no pipe value of its own, it is the same as 1
+        for value in 1..=255 {
+            let expected = GamecubeInput {
+                dpad_left: value % 2 == 0,
+                stick_x: value,
+                stick_y: 255 - value + 1,
+                cstick_x: value,
+                cstick_y: 128,
+                l_analog: value,
+                r_analog: 255 - value,
+                ..GamecubeInput::NEUTRAL
+            };
+            let mut input = GamecubeInput::NEUTRAL;
+            apply_commands(&mut input, &to_commands(&expected)).unwrap();
+            assert_eq!(input, expected);
+        }
+    }
+
+    #[test]
+    fn clamped() {
+        let mut input = GamecubeInput::NEUTRAL;
+        apply_commands(&mut input, "SET MAIN -1 2.5\nSET L 7\n\nSET R -0.1").unwrap();
+        assert_eq!((input.stick_x, input.stick_y), (1, 255));
+        assert_eq!((input.l_analog, input.r_analog), (255, 0));
+    }
+
+    #[test]
+    fn errors() {
+        let mut input = GamecubeInput::NEUTRAL;
+        assert_eq!(apply_command(&mut input, ""), Ok(()));
+        assert_eq!(apply_command(&mut input, "   "), Ok(()));
+        assert_eq!(
+            apply_command(&mut input, "HOLD A"),
+            Err(PipeError::UnknownCommand)
+        );
+        assert_eq!(
+            apply_command(&mut input, "PRESS"),
+            Err(PipeError::UnknownTarget)
+        );
+        assert_eq!(
+            apply_command(&mut input, "PRESS Q"),
+            Err(PipeError::UnknownTarget)
+        );
+        assert_eq!(
+            apply_command(&mut input, "SET Q 1"),
+            Err(PipeError::UnknownTarget)
+        );
+        assert_eq!(
+            apply_command(&mut input, "SET MAIN 0.5"),
+            Err(PipeError::InvalidValue)
+        );
+        assert_eq!(
+            apply_command(&mut input, "SET L x"),
+            Err(PipeError::InvalidValue)
+        );
+        assert_eq!(input, GamecubeInput::NEUTRAL);
+
+        assert_eq!(
+            apply_commands(&mut input, "PRESS B\nPRESS Q\nPRESS X"),
+            Err((1, PipeError::UnknownTarget))
+        );
+        assert!(input.b && !input.x);
+    }
+}
@@ -0,0 +1,260 @@
+//! Answering polls entirely from an interrupt, with the report pushed into the TX FIFO by DMA.
+//!
+//! [`GamecubeController::into_fast_path`] returns a [`FastPath`] that does everything between a command arriving
+//! and its response going out inside the PIO interrupt handler:
+//!
+//! 1. The RX FIFO not empty interrupt, enabled with [`FastPath::enable_interrupt`], fires on every byte of a command.
+//! 2. [`FastPath::on_interrupt`] feeds the byte to the [`crate::ProtocolFsm`] and returns straight away until the command is complete,
+//!    so the handler never waits on the line.
+//! 3. Once the last byte of a poll is in, the report staged by the main loop is copied out of a [`ReportStaging`]
+//!    and a DMA channel feeds it to the TX FIFO, so the CPU is free again as soon as the transfer is started.
+//!
+//! The main loop only has to keep the staged report up to date, e.g. through an [`crate::InputCell`],
+//! and answer the occasional other command with [`FastPath::handle_pending`]:
+//!
+//! ```ignore
+//! static INPUTS: InputCell = InputCell::new();
+//! static mut BUFFER: [u32; FAST_PATH_BUFFER_LEN] = [0; FAST_PATH_BUFFER_LEN];
+//!
+//! let mut fast_path = controller.into_fast_path(dma.ch0, INPUTS.staging(), unsafe { &mut *addr_of_mut!(BUFFER) });
+//! fast_path.enable_interrupt(PioIRQ::Irq0);
+//! // move fast_path, the timer and a delay into the handler's state
+//!
+//! #[interrupt]
+//! fn PIO0_IRQ_0() {
+//!     fast_path.on_interrupt(&timer, &mut delay);
+//! }
+//!
+//! loop {
+//!     INPUTS.set(read_inputs());
+//!     // with the fast path shared with the handler, e.g. through a `Mutex<RefCell<_>>`
+//!     fast_path.handle_pending(&timer, &mut delay);
+//! }
+//! ```
+//!
+//! # Latency
+//!
+//! A poll response starts [`crate::JoybusConfig::reply_delay_us`] plus under 1us of CPU work after the stop bit of the poll,
+//! regardless of what the main loop is doing, provided that:
+//! * The PIO interrupt has the highest priority, or at least nothing of higher priority runs for longer than a byte, 32us.
+//! * Nothing disables interrupts for longer than a byte, or the start of the command is read late and the response with it.
+//!
+//! Other commands, such as probes and origins, and commands cut short by the console are left for [`FastPath::handle_pending`]
+//! to answer or resynchronise after outside the handler, since that can take up to [`crate::BUS_IDLE_GIVE_UP_US`].
+//! The interrupt stays disabled until then, so call it often enough that the console doesn't give up on a probe,
+//! from thread mode or from a lower priority interrupt.
+
+use cortex_m::delay::Delay;
+
+use crate::port::{irq_inte_alias, JoybusPort};
+use crate::rp2040_hal::{
+    dma::{Channel, ChannelIndex, SingleChannel, CH0},
+    gpio::bank0::Gpio28,
+    pac::PIO0,
+    pio::{PIOExt, PioIRQ, StateMachineIndex, SM0},
+    Timer,
+};
+use crate::{ConfigSource, FsmAction, GamecubeController, JoybusConfig, JoybusPin, ReportStaging};
+
+/// The length of the buffer DMA reads a poll response from, one word per byte of the report.
+pub const FAST_PATH_BUFFER_LEN: usize = 8;
+
+/// Answers commands from an interrupt handler, see the [module docs](self).
+pub struct FastPath<
+    P: PIOExt = PIO0,
+    I: JoybusPin<P> = Gpio28,
+    S: StateMachineIndex = SM0,
+    C: ConfigSource = JoybusConfig,
+    CH: ChannelIndex = CH0,
+> {
+    controller: GamecubeController<P, I, S, C>,
+    channel: Channel<CH>,
+    staging: &'static ReportStaging,
+    /// Read by DMA while a poll response is sent, so must stay in place and untouched until the transfer completes.
+    buffer: &'static mut [u32; FAST_PATH_BUFFER_LEN],
+    rumble: bool,
+    /// The interrupt enabled by [`FastPath::enable_interrupt`], disabled while an action is pending.
+    irq: Option<PioIRQ>,
+    /// An action left by the interrupt handler for [`FastPath::handle_pending`].
+    pending: Option<FsmAction>,
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource>
+    GamecubeController<P, I, S, C>
+{
+    /// Answer polls from an interrupt with reports from `staging`, sent by DMA on `channel` from `buffer`,
+    /// see [`crate::fast_path`].
+    pub fn into_fast_path<CH: ChannelIndex>(
+        mut self,
+        channel: Channel<CH>,
+        staging: &'static ReportStaging,
+        buffer: &'static mut [u32; FAST_PATH_BUFFER_LEN],
+    ) -> FastPath<P, I, S, C, CH> {
+        // the fast path never masks interrupts, so nothing would unmask any left over from a blocking wait
+        self.unmask_response_irqs();
+        FastPath {
+            controller: self,
+            channel,
+            staging,
+            buffer,
+            rumble: false,
+            irq: None,
+            pending: None,
+        }
+    }
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource, CH: ChannelIndex>
+    FastPath<P, I, S, C, CH>
+{
+    /// Enable the RX FIFO not empty interrupt of the controller's state machine on `irq`,
+    /// call [`FastPath::on_interrupt`] from its handler.
+    pub fn enable_interrupt(&mut self, irq: PioIRQ) {
+        self.irq = Some(irq);
+        // Safety: only this state machine's bit is set, through the atomic set alias so nothing else in the register can be lost.
+        unsafe { irq_inte_alias::<P>(irq, 0x2000).write_volatile(1 << S::id()) };
+    }
+
+    pub fn disable_interrupt(&self, irq: PioIRQ) {
+        // Safety: only this state machine's bit is cleared, through the atomic clear alias so nothing else in the register can be lost.
+        unsafe { irq_inte_alias::<P>(irq, 0x3000).write_volatile(1 << S::id()) };
+    }
+
+    /// Receive everything in the RX FIFO and respond if it completes a poll, returning what was done about it.
+    /// Returns None if no command was completed, e.g. because the rest of it is still on its way.
+    ///
+    /// Any other action, including a resync after a command cut short, is left for [`FastPath::handle_pending`]
+    /// with the interrupt disabled until then.
+    pub fn on_interrupt(&mut self, timer: &Timer, delay: &mut Delay) -> Option<FsmAction> {
+        let controller = &mut self.controller;
+        loop {
+            let byte = match controller.port.try_recv_entry()? {
+                Some(byte) => byte,
+                // a frame never ends inside a command, so the console stopped sending part way through
+                None if !controller.fsm.is_idle() => {
+                    let action = controller.fsm.on_timeout();
+                    self.defer(action);
+                    return Some(action);
+                }
+                None => continue,
+            };
+            match controller.fsm.on_byte(byte) {
+                FsmAction::Wait => {}
+                FsmAction::PollStarted => {
+                    #[cfg(debug_assertions)]
+                    controller.start_poll_budget(timer);
+                }
+                action @ FsmAction::RespondPoll { mode, rumble } => {
+                    #[cfg(debug_assertions)]
+                    controller.end_poll_budget(timer);
+                    controller.record_poll(timer, mode, rumble);
+                    self.rumble = rumble;
+                    delay.delay_us(controller.config().reply_delay_us);
+                    let report = self.staging.next_report();
+                    self.send_report(&report);
+                    self.controller.last_report = report;
+                    return Some(action);
+                }
+                action => {
+                    self.defer(action);
+                    return Some(action);
+                }
+            }
+        }
+    }
+
+    /// Carry out the action [`FastPath::on_interrupt`] left for outside the handler, if any,
+    /// then enable the interrupt again. Returns the action that was carried out.
+    pub fn handle_pending(&mut self, timer: &Timer, delay: &mut Delay) -> Option<FsmAction> {
+        let action = self.pending.take()?;
+        self.controller.perform(action, timer, delay);
+        if let Some(irq) = self.irq {
+            self.enable_interrupt(irq);
+        }
+        Some(action)
+    }
+
+    /// Returns true if [`FastPath::on_interrupt`] has left an action for [`FastPath::handle_pending`].
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Leave `action` for [`FastPath::handle_pending`], disabling the interrupt so the handler doesn't fire
+    /// again for the bytes that follow before it has been dealt with.
+    fn defer(&mut self, action: FsmAction) {
+        self.pending = Some(action);
+        if let Some(irq) = self.irq {
+            self.disable_interrupt(irq);
+        }
+    }
+
+    /// Whether the console asked for rumble in the most recent poll.
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    /// The report sent in response to the most recent poll.
+    pub fn last_report(&self) -> [u8; 8] {
+        self.controller.last_report()
+    }
+
+    /// Returns true once the most recent response including its stop bit has been transmitted.
+    pub fn is_send_complete(&self) -> bool {
+        !self.channel.ch().ch_ctrl_trig().read().busy().bit_is_set()
+            && self.controller.is_send_complete()
+    }
+
+    /// Wait for any response in progress to finish, then return the controller, DMA channel and buffer.
+    #[allow(clippy::type_complexity)]
+    pub fn free(
+        self,
+    ) -> (
+        GamecubeController<P, I, S, C>,
+        Channel<CH>,
+        &'static mut [u32; FAST_PATH_BUFFER_LEN],
+    ) {
+        while !self.is_send_complete() {}
+        (self.controller, self.channel, self.buffer)
+    }
+
+    /// Start sending `report` by DMA, returning as soon as the transfer is started.
+    fn send_report(&mut self, report: &[u8; 8]) {
+        let ch = self.channel.ch();
+        // the previous response is long finished by the time the console sends another command, but make sure
+        while ch.ch_ctrl_trig().read().busy().bit_is_set() {}
+        let port = &mut self.controller.port;
+        port.flush();
+        port.restart_for_write();
+
+        for (i, (word, value)) in self.buffer.iter_mut().zip(report).enumerate() {
+            *word = port.frame_word(*value, i == report.len() - 1);
+        }
+        #[cfg(feature = "flight-recorder")]
+        for word in self.buffer.iter() {
+            port.record_sent(*word);
+        }
+        // Safety: the buffer is 'static and isn't touched again until the transfer is complete,
+        // and the TX FIFO is a valid word sized write target that only this port writes to.
+        ch.ch_read_addr()
+            .write(|w| unsafe { w.bits(self.buffer.as_ptr() as u32) });
+        ch.ch_write_addr()
+            .write(|w| unsafe { w.bits(port.tx_fifo_address()) });
+        ch.ch_trans_count()
+            .write(|w| unsafe { w.bits(FAST_PATH_BUFFER_LEN as u32) });
+        ch.ch_ctrl_trig().write(|w| unsafe {
+            w.data_size()
+                .size_word()
+                .incr_read()
+                .set_bit()
+                .incr_write()
+                .clear_bit()
+                .treq_sel()
+                .bits(JoybusPort::<P, I, S>::tx_dreq())
+                // chaining to itself disables chaining
+                .chain_to()
+                .bits(CH::id())
+                .en()
+                .set_bit()
+        });
+    }
+}
@@ -0,0 +1,148 @@
+//! Deliberately misbehaving on the bus, for testing how console side software and [`crate::GamecubeHost`] cope with faulty devices.
+//!
+//! Faults are applied to every frame a [`crate::JoybusPort`] sends, so a port with faults enabled can be handed to
+//! any device or host type.
+//! This is only meant for testing, never enable it in firmware that is used to play.
+//!
+//! ```ignore
+//! let mut port = JoybusPort::new(pin, pac.PIO0, &mut pac.RESETS, clocks)?;
+//! port.set_fault_injector(Some(FaultInjector::new(
+//!     FaultConfig {
+//!         bit_flip_rate: FaultConfig::rate_per_thousand(10),
+//!         ..FaultConfig::NONE
+//!     },
+//!     0x1234_5678,
+//! )));
+//! let controller = GamecubeController::try_new(port, &timer, &mut delay)?;
+//! ```
+
+/// How often each kind of fault is injected into a sent frame.
+///
+/// Rates are out of 65536 frames, so 0 never injects the fault and 65535 almost always does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaultConfig {
+    /// Flip a single random bit of the frame.
+    pub bit_flip_rate: u16,
+    /// Wait [`FaultConfig::delay_cycles`] before starting the frame.
+    pub delay_rate: u16,
+    /// System clock cycles to wait when a delay is injected.
+    /// An OEM controller starts responding within a few microseconds and consoles give up after around 100us.
+    pub delay_cycles: u32,
+    /// End the frame without a stop bit.
+    pub drop_stop_bit_rate: u16,
+}
+
+impl FaultConfig {
+    /// Never inject anything.
+    pub const NONE: FaultConfig = FaultConfig {
+        bit_flip_rate: 0,
+        delay_rate: 0,
+        delay_cycles: 0,
+        drop_stop_bit_rate: 0,
+    };
+
+    /// Convert a rate out of 1000 frames into a rate out of 65536 frames, saturating at 1000.
+    pub const fn rate_per_thousand(per_thousand: u16) -> u16 {
+        let rate = per_thousand as u32 * 65536 / 1000;
+        if rate > u16::MAX as u32 {
+            u16::MAX
+        } else {
+            rate as u16
+        }
+    }
+}
+
+/// Counts of the faults injected so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub frames: u32,
+    pub bit_flips: u32,
+    pub delays: u32,
+    pub dropped_stop_bits: u32,
+}
+
+/// What to do to a single frame, decided by [`FaultInjector::next_frame`].
+pub(crate) struct FrameFaults {
+    /// Index of the bit to flip, counting from the most significant bit of the first byte.
+    pub flip_bit: Option<usize>,
+    pub delay_cycles: Option<u32>,
+    pub drop_stop_bit: bool,
+}
+
+impl FrameFaults {
+    /// The byte at `index` of the frame and whether it is followed by a stop bit, after injecting the faults.
+    pub fn apply(&self, index: usize, value: u8, stop: bool) -> (u8, bool) {
+        let value = match self.flip_bit {
+            Some(bit) if bit / 8 == index => value ^ (0x80 >> (bit % 8)),
+            _ => value,
+        };
+        (value, stop && !self.drop_stop_bit)
+    }
+}
+
+/// Decides which faults to inject into each frame, see [`crate::JoybusPort::set_fault_injector`].
+#[derive(Debug, Clone, Copy)]
+pub struct FaultInjector {
+    config: FaultConfig,
+    /// xorshift32 state, never 0.
+    state: u32,
+    stats: FaultStats,
+}
+
+impl FaultInjector {
+    /// `seed` makes a run reproducible, a seed of 0 is replaced with 1.
+    pub const fn new(config: FaultConfig, seed: u32) -> FaultInjector {
+        FaultInjector {
+            config,
+            state: if seed == 0 { 1 } else { seed },
+            stats: FaultStats {
+                frames: 0,
+                bit_flips: 0,
+                delays: 0,
+                dropped_stop_bits: 0,
+            },
+        }
+    }
+
+    pub fn set_config(&mut self, config: FaultConfig) {
+        self.config = config;
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        self.stats
+    }
+
+    pub(crate) fn next_frame(&mut self, frame_len: usize) -> FrameFaults {
+        self.stats.frames = self.stats.frames.saturating_add(1);
+        let flip_bit = self.roll(self.config.bit_flip_rate).then(|| {
+            self.stats.bit_flips = self.stats.bit_flips.saturating_add(1);
+            self.next_random() as usize % (frame_len * 8)
+        });
+        let delay_cycles = self.roll(self.config.delay_rate).then(|| {
+            self.stats.delays = self.stats.delays.saturating_add(1);
+            self.config.delay_cycles
+        });
+        let drop_stop_bit = self.roll(self.config.drop_stop_bit_rate);
+        if drop_stop_bit {
+            self.stats.dropped_stop_bits = self.stats.dropped_stop_bits.saturating_add(1);
+        }
+        FrameFaults {
+            flip_bit,
+            delay_cycles,
+            drop_stop_bit,
+        }
+    }
+
+    fn roll(&mut self, rate: u16) -> bool {
+        rate != 0 && (self.next_random() >> 16) < rate as u32
+    }
+
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+}
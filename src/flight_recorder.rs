@@ -0,0 +1,131 @@
+//! An always-on record of the most recent bytes a port moved through its FIFOs, for postmortem debugging.
+//!
+//! A [`FlightRecorder`] attached to a [`crate::JoybusPort`] with [`crate::JoybusPort::set_flight_recorder`]
+//! is handed every byte the port reads from its RX FIFO or writes to its TX FIFO, along with its direction
+//! and the low 32 bits of the microsecond timer, overwriting the oldest record once it is full.
+//! After a desync or a crash it holds the last [`FLIGHT_RECORDER_LEN`] bytes that went over the bus.
+//!
+//! ```ignore
+//! static mut RECORDER: FlightRecorder = FlightRecorder::new();
+//!
+//! port.set_flight_recorder(Some(unsafe { &mut *addr_of_mut!(RECORDER) }));
+//!
+//! #[panic_handler]
+//! fn panic(_: &core::panic::PanicInfo) -> ! {
+//!     // nothing else runs any more, so reading it behind the port's back is fine here
+//!     for record in unsafe { &*addr_of!(RECORDER) }.iter() {
+//!         // print the record, or leave it in RAM for a debugger to read
+//!     }
+//!     loop {}
+//! }
+//! ```
+//!
+//! # Why this isn't done by DMA
+//!
+//! Ideally a DMA channel would mirror the FIFOs into the buffer without involving the CPU,
+//! but the RP2040's PIO FIFOs can't be observed without popping them: a DMA read of the RX FIFO takes the byte away from the port,
+//! and a DMA write to the TX FIFO is the only copy of what is sent.
+//! So the port stores each record itself right where it already touches the FIFO, which is a handful of instructions per byte,
+//! around 1us per poll at 125MHz, and nothing at all when no recorder is attached.
+//! Bytes sent by DMA from [`crate::fast_path`] are recorded together when the transfer is started.
+
+use crate::rp2040_hal::pac::TIMER;
+
+/// How many records a [`FlightRecorder`] holds before overwriting the oldest.
+pub const FLIGHT_RECORDER_LEN: usize = 256;
+
+/// Something a port moved through one of its FIFOs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlightEvent {
+    /// A byte was read from the RX FIFO.
+    Received(u8),
+    /// The state machine marked the end of the frame being received.
+    ReceivedFrameEnd,
+    /// A byte was written to the TX FIFO, `stop` is set for the last byte of a frame.
+    Sent { byte: u8, stop: bool },
+}
+
+/// A single entry of a [`FlightRecorder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlightRecord {
+    /// The low 32 bits of the microsecond timer when the port moved the byte, which wraps every 71 minutes.
+    ///
+    /// This is when the CPU touched the FIFO, not when the byte was on the wire:
+    /// received bytes are read up to a few bytes late and sent bytes are queued ahead of the state machine.
+    pub timestamp_us: u32,
+    pub event: FlightEvent,
+}
+
+impl FlightRecord {
+    const EMPTY: FlightRecord = FlightRecord {
+        timestamp_us: 0,
+        event: FlightEvent::ReceivedFrameEnd,
+    };
+}
+
+/// A circular buffer of the most recent [`FlightRecord`]s, see the [module docs](self).
+pub struct FlightRecorder {
+    records: [FlightRecord; FLIGHT_RECORDER_LEN],
+    /// Where the next record goes, which is the oldest once full.
+    next: usize,
+    len: usize,
+    /// How many records have ever been stored, wrapping.
+    total: u32,
+}
+
+impl FlightRecorder {
+    pub const fn new() -> FlightRecorder {
+        FlightRecorder {
+            records: [FlightRecord::EMPTY; FLIGHT_RECORDER_LEN],
+            next: 0,
+            len: 0,
+            total: 0,
+        }
+    }
+
+    /// Store `event` timestamped with the current time, overwriting the oldest record if full.
+    #[inline]
+    pub fn record(&mut self, event: FlightEvent) {
+        // Safety: reading the raw timer is side effect free and doesn't latch the high word like TIMELR does.
+        let timestamp_us = unsafe { (*TIMER::ptr()).timerawl().read().bits() };
+        self.records[self.next] = FlightRecord {
+            timestamp_us,
+            event,
+        };
+        self.next = (self.next + 1) % FLIGHT_RECORDER_LEN;
+        self.len = (self.len + 1).min(FLIGHT_RECORDER_LEN);
+        self.total = self.total.wrapping_add(1);
+    }
+
+    /// How many records are held, at most [`FLIGHT_RECORDER_LEN`].
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many records have ever been stored, wrapping, which tells how many were overwritten.
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// The held records, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &FlightRecord> + '_ {
+        let start = self.next + FLIGHT_RECORDER_LEN - self.len;
+        (start..start + self.len).map(|i| &self.records[i % FLIGHT_RECORDER_LEN])
+    }
+
+    /// Forget every record, [`FlightRecorder::total`] keeps counting.
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+}
+
+impl Default for FlightRecorder {
+    fn default() -> Self {
+        FlightRecorder::new()
+    }
+}
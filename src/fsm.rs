@@ -0,0 +1,287 @@
+use crate::protocol::{
+    CMD_ORIGIN, CMD_POLL, CMD_PROBE, CMD_RECALIBRATE, CMD_RESET, POLL_COMMAND_LEN,
+};
+
+/// The device side command handling logic of the gamecube protocol, free of any IO or timing.
+///
+/// Feed it every byte received from the console with [`ProtocolFsm::on_byte`] and
+/// report receive timeouts with [`ProtocolFsm::on_timeout`].
+/// Each call returns the [`FsmAction`] the driver should perform next.
+/// [`crate::GamecubeController`] is a thin driver around this type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolFsm {
+    state: State,
+    /// Answer the diagnostics opcodes instead of resyncing on them.
+    diagnostics: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the first byte of a command.
+    Idle,
+    /// Received the poll opcode and `received` of its two argument bytes.
+    Poll {
+        args: [u8; POLL_COMMAND_LEN - 1],
+        received: usize,
+    },
+}
+
+/// What the driver of a [`ProtocolFsm`] should do next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsmAction {
+    /// Nothing to do, keep receiving bytes.
+    Wait,
+    /// A probe was received, respond with the device identifier.
+    RespondId,
+    /// A reset was received, reset any device state and then respond with the device identifier.
+    Reset,
+    /// An origin was received, respond with the origin.
+    RespondOrigin,
+    /// A recalibrate was received, capture a new origin from the current inputs and respond with it.
+    Recalibrate,
+    /// The first byte of a poll was received, the argument bytes are still on their way.
+    /// This is the point at which inputs should be sampled.
+    PollStarted,
+    /// The poll is complete, respond with the input report.
+    RespondPoll { mode: u8, rumble: bool },
+    /// A diagnostics command was received, respond with the requested report.
+    /// Only emitted once enabled with [`ProtocolFsm::set_diagnostics`], see [`crate::diagnostics`].
+    RespondDiagnostics(u8),
+    /// A command with an opcode that isn't handled above was received,
+    /// what happens next is up to [`crate::UnknownCommandBehavior`].
+    UnknownCommand(u8),
+    /// The bus is in an unknown state, wait for it to go idle and restart reading.
+    Resync,
+}
+
+impl ProtocolFsm {
+    pub const fn new() -> ProtocolFsm {
+        ProtocolFsm {
+            state: State::Idle,
+            diagnostics: false,
+        }
+    }
+
+    /// Answer the opcodes in [`crate::diagnostics`] with [`FsmAction::RespondDiagnostics`] instead of resyncing.
+    pub fn set_diagnostics(&mut self, enabled: bool) {
+        self.diagnostics = enabled;
+    }
+
+    /// Process a single byte received from the console.
+    pub fn on_byte(&mut self, byte: u8) -> FsmAction {
+        match self.state {
+            State::Idle => match GamecubeCommand::from(byte) {
+                GamecubeCommand::Probe => FsmAction::RespondId,
+                GamecubeCommand::Reset => FsmAction::Reset,
+                GamecubeCommand::Origin => FsmAction::RespondOrigin,
+                GamecubeCommand::Recalibrate => FsmAction::Recalibrate,
+                GamecubeCommand::Poll => {
+                    self.state = State::Poll {
+                        args: [0; POLL_COMMAND_LEN - 1],
+                        received: 0,
+                    };
+                    FsmAction::PollStarted
+                }
+                GamecubeCommand::Unknown(opcode)
+                    if self.diagnostics && crate::diagnostics::is_diagnostics(opcode) =>
+                {
+                    FsmAction::RespondDiagnostics(opcode)
+                }
+                GamecubeCommand::Unknown(opcode) => FsmAction::UnknownCommand(opcode),
+            },
+            State::Poll { mut args, received } => {
+                args[received] = byte;
+                if received + 1 < args.len() {
+                    self.state = State::Poll {
+                        args,
+                        received: received + 1,
+                    };
+                    FsmAction::Wait
+                } else {
+                    self.state = State::Idle;
+                    FsmAction::RespondPoll {
+                        mode: args[0],
+                        rumble: args[1] & 1 != 0,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Process a receive timeout, abandoning any partially received command.
+    pub fn on_timeout(&mut self) -> FsmAction {
+        self.state = State::Idle;
+        FsmAction::Resync
+    }
+
+    /// Returns true if no command is partially received.
+    pub fn is_idle(&self) -> bool {
+        self.state == State::Idle
+    }
+
+    /// Abandon any partially received command, e.g. because the state machine was restarted.
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+}
+
+impl Default for ProtocolFsm {
+    fn default() -> Self {
+        ProtocolFsm::new()
+    }
+}
+
+/// A command sent by the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamecubeCommand {
+    /// 0x00, asks for the device identifier.
+    Probe,
+    /// 0x40, asks for the current inputs.
+    Poll,
+    /// 0x41, asks for the origin (neutral stick and trigger positions).
+    Origin,
+    /// 0x42, asks the device to recalibrate and return the new origin.
+    Recalibrate,
+    /// 0xFF, resets the device and asks for the device identifier.
+    Reset,
+    /// Any other opcode.
+    Unknown(u8),
+}
+
+impl GamecubeCommand {
+    pub fn from(value: u8) -> Self {
+        match value {
+            CMD_PROBE => GamecubeCommand::Probe,
+            CMD_RESET => GamecubeCommand::Reset,
+            CMD_ORIGIN => GamecubeCommand::Origin,
+            CMD_RECALIBRATE => GamecubeCommand::Recalibrate,
+            CMD_POLL => GamecubeCommand::Poll,
+            value => GamecubeCommand::Unknown(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Feed every byte of `command` to `fsm`, returning the action for each.
+    fn feed<const N: usize>(fsm: &mut ProtocolFsm, command: [u8; N]) -> [FsmAction; N] {
+        command.map(|byte| fsm.on_byte(byte))
+    }
+
+    #[test]
+    fn single_byte_commands() {
+        let mut fsm = ProtocolFsm::new();
+        assert_eq!(fsm.on_byte(CMD_PROBE), FsmAction::RespondId);
+        assert!(fsm.is_idle());
+        assert_eq!(fsm.on_byte(CMD_RESET), FsmAction::Reset);
+        assert!(fsm.is_idle());
+        assert_eq!(fsm.on_byte(CMD_ORIGIN), FsmAction::RespondOrigin);
+        assert!(fsm.is_idle());
+        assert_eq!(fsm.on_byte(CMD_RECALIBRATE), FsmAction::Recalibrate);
+        assert!(fsm.is_idle());
+    }
+
+    #[test]
+    fn poll_every_mode() {
+        let mut fsm = ProtocolFsm::new();
+        for mode in 0..8 {
+            for (rumble_byte, rumble) in [(0x00, false), (0x01, true), (0x02, false), (0x03, true)]
+            {
+                assert_eq!(
+                    feed(&mut fsm, [CMD_POLL, mode, rumble_byte]),
+                    [
+                        FsmAction::PollStarted,
+                        FsmAction::Wait,
+                        FsmAction::RespondPoll { mode, rumble }
+                    ]
+                );
+                assert!(fsm.is_idle());
+            }
+        }
+    }
+
+    #[test]
+    fn poll_arguments_are_not_commands() {
+        let mut fsm = ProtocolFsm::new();
+        assert_eq!(
+            feed(&mut fsm, [CMD_POLL, CMD_RESET, CMD_PROBE]),
+            [
+                FsmAction::PollStarted,
+                FsmAction::Wait,
+                FsmAction::RespondPoll {
+                    mode: CMD_RESET,
+                    rumble: false
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_poll() {
+        let mut fsm = ProtocolFsm::new();
+        assert_eq!(
+            feed(&mut fsm, [CMD_POLL, 0x03]),
+            [FsmAction::PollStarted, FsmAction::Wait]
+        );
+        assert!(!fsm.is_idle());
+        assert_eq!(fsm.on_timeout(), FsmAction::Resync);
+        assert!(fsm.is_idle());
+        assert_eq!(fsm.on_byte(CMD_PROBE), FsmAction::RespondId);
+
+        fsm.on_byte(CMD_POLL);
+        fsm.reset();
+        assert!(fsm.is_idle());
+        assert_eq!(fsm.on_byte(CMD_ORIGIN), FsmAction::RespondOrigin);
+    }
+
+    #[test]
+    fn unknown_commands() {
+        let mut fsm = ProtocolFsm::new();
+        for opcode in 0..=u8::MAX {
+            let expected = match opcode {
+                CMD_PROBE => FsmAction::RespondId,
+                CMD_RESET => FsmAction::Reset,
+                CMD_ORIGIN => FsmAction::RespondOrigin,
+                CMD_RECALIBRATE => FsmAction::Recalibrate,
+                CMD_POLL => FsmAction::PollStarted,
+                opcode => FsmAction::UnknownCommand(opcode),
+            };
+            assert_eq!(fsm.on_byte(opcode), expected);
+            fsm.reset();
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut fsm = ProtocolFsm::new();
+        for opcode in 0..=u8::MAX {
+            if crate::diagnostics::is_diagnostics(opcode) {
+                assert_eq!(fsm.on_byte(opcode), FsmAction::UnknownCommand(opcode));
+                fsm.set_diagnostics(true);
+                assert_eq!(fsm.on_byte(opcode), FsmAction::RespondDiagnostics(opcode));
+                fsm.set_diagnostics(false);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn on_byte_never_panics(
+            stream in proptest::collection::vec(any::<u8>(), 0..512),
+            timeouts in proptest::collection::vec(any::<bool>(), 0..512),
+        ) {
+            let mut fsm = ProtocolFsm::new();
+            fsm.set_diagnostics(stream.first().is_some_and(|byte| byte & 1 != 0));
+            for (byte, timeout) in stream.into_iter().zip(timeouts.into_iter().chain(core::iter::repeat(false))) {
+                if timeout {
+                    prop_assert_eq!(fsm.on_timeout(), FsmAction::Resync);
+                    prop_assert!(fsm.is_idle());
+                }
+                fsm.on_byte(byte);
+            }
+        }
+    }
+}
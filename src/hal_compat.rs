@@ -0,0 +1,26 @@
+//! Papers over the differences between the rp2040-hal releases this crate can be built against.
+//! One is selected via the `hal-0_10` or `hal-0_12` feature.
+
+use pio::{SideSet, Wrap};
+
+#[cfg(all(feature = "hal-0_10", feature = "hal-0_12"))]
+compile_error!("The `hal-0_10` and `hal-0_12` features are mutually exclusive. Disable default features to use `hal-0_12`.");
+
+#[cfg(not(any(feature = "hal-0_10", feature = "hal-0_12")))]
+compile_error!(
+    "Enable either the `hal-0_10` or `hal-0_12` feature to select an rp2040-hal version."
+);
+
+pub(crate) type Program = pio::Program<{ pio::RP2040_MAX_PROGRAM_SIZE }>;
+
+/// pio 0.3 (used by rp2040-hal 0.12) added a PIO version to `Program`.
+pub(crate) fn program(code: &[u16], wrap: Wrap) -> Program {
+    Program {
+        code: code.iter().copied().collect(),
+        origin: Some(0),
+        wrap,
+        side_set: SideSet::default(),
+        #[cfg(feature = "hal-0_12")]
+        version: pio::PioVersion::V0,
+    }
+}
@@ -0,0 +1,153 @@
+//! On-target tests that run host mode against device mode over two pins wired together,
+//! for catching regressions in the PIO program and its timing that only show up on real hardware.
+//!
+//! The device side is a [`crate::GamecubeController`] turned into a [`crate::fast_path::FastPath`],
+//! answering polls from the PIO interrupt with whatever report is staged in a [`ReportStaging`],
+//! and everything else from a lower priority interrupt through [`crate::fast_path::FastPath::handle_pending`].
+//! The host side is a [`GamecubeHost`] on the other pin, which stages a report, polls for it,
+//! and checks that every byte arrived exactly as staged.
+//! [`crate::JoybusPort::new_pair`] puts both on a single PIO block, so only a jumper between the two pins is needed.
+//!
+//! Each check is a plain function returning the first [`Failure`], so it can be called from any on-target test runner,
+//! e.g. with `defmt-test`:
+//!
+//! ```ignore
+//! static STAGING: ReportStaging = ReportStaging::new();
+//!
+//! #[defmt_test::tests]
+//! mod tests {
+//!     #[init]
+//!     fn init() -> Rig {
+//!         let (device, host) = JoybusPort::new_pair(pins.gpio28, pins.gpio27, pac.PIO0, &mut pac.RESETS, clocks)?;
+//!         // turn `device` into a fast path answering from STAGING, shared by the PIO0_IRQ_0 handler
+//!         // and a lower priority one that it pends to handle anything other than a poll
+//!         Rig { host: GamecubeHost::new(host), timer }
+//!     }
+//!
+//!     #[test]
+//!     fn polls(rig: &mut Rig) {
+//!         joybus_pio::hil::polls(&mut rig.host, &rig.timer, &STAGING, 1000).unwrap();
+//!     }
+//! }
+//! ```
+
+use crate::conformance::MAX_RESPONSE_US;
+use crate::protocol::GAMECUBE_CONTROLLER_ID;
+use crate::rp2040_hal::{
+    fugit::MicrosDurationU64,
+    pio::{PIOExt, StateMachineIndex},
+    Timer,
+};
+use crate::{GamecubeHost, HostError, JoybusPin, ReportStaging};
+
+/// Why a check failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The device didn't respond properly.
+    Host(HostError),
+    /// The probe response doesn't identify a standard gamecube controller.
+    UnexpectedId([u8; 3]),
+    /// The poll response differs from the report staged for it.
+    Mismatch { staged: [u8; 8], received: [u8; 8] },
+    /// The response started later than [`MAX_RESPONSE_US`].
+    TooSlow { response_us: u64 },
+}
+
+impl From<HostError> for Failure {
+    fn from(error: HostError) -> Failure {
+        Failure::Host(error)
+    }
+}
+
+/// Probe the device and read its origin, like a console does when a controller is plugged in.
+pub fn handshake<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    host: &mut GamecubeHost<P, I, S>,
+    timer: &Timer,
+) -> Result<(), Failure> {
+    let id = host.probe(timer)?;
+    if id[..2] != GAMECUBE_CONTROLLER_ID {
+        return Err(Failure::UnexpectedId(id));
+    }
+    check_response_time(host)?;
+    host.origin(timer)?;
+    check_response_time(host)
+}
+
+/// Poll the device `count` times, cycling through every poll mode and toggling rumble,
+/// with a different staged report each time so that every bit of the response is exercised.
+pub fn polls<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    host: &mut GamecubeHost<P, I, S>,
+    timer: &Timer,
+    staging: &ReportStaging,
+    count: u32,
+) -> Result<(), Failure> {
+    let mut seed = 0x2545_F491;
+    for i in 0..count {
+        let staged = next_report(&mut seed);
+        staging.set_next_report(&staged);
+        let received = host.poll(timer, (i % 8) as u8, i % 2 == 1)?;
+        if received != staged {
+            return Err(Failure::Mismatch { staged, received });
+        }
+        check_response_time(host)?;
+    }
+    Ok(())
+}
+
+/// Poll the device `count` times, `interval_us` apart, for checking that nothing drifts or stalls over many
+/// back to back transactions, e.g. with an interval of 1000 like the fastest USB adapters.
+pub fn back_to_back<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    host: &mut GamecubeHost<P, I, S>,
+    timer: &Timer,
+    staging: &ReportStaging,
+    count: u32,
+    interval_us: u64,
+) -> Result<(), Failure> {
+    let mut seed = 0x9E37_79B9;
+    let mut next = timer.get_counter();
+    for _ in 0..count {
+        while timer.get_counter() < next {}
+        next += MicrosDurationU64::micros(interval_us);
+
+        let staged = next_report(&mut seed);
+        staging.set_next_report(&staged);
+        let received = host.poll(timer, 3, false)?;
+        if received != staged {
+            return Err(Failure::Mismatch { staged, received });
+        }
+        check_response_time(host)?;
+    }
+    Ok(())
+}
+
+/// Run every check, with the poll counts used by the test suite.
+pub fn run<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    host: &mut GamecubeHost<P, I, S>,
+    timer: &Timer,
+    staging: &ReportStaging,
+) -> Result<(), Failure> {
+    handshake(host, timer)?;
+    polls(host, timer, staging, 1_000)?;
+    back_to_back(host, timer, staging, 1_000, 1_000)
+}
+
+fn check_response_time<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    host: &GamecubeHost<P, I, S>,
+) -> Result<(), Failure> {
+    match host.last_response_us() {
+        Some(response_us) if response_us > MAX_RESPONSE_US => Err(Failure::TooSlow { response_us }),
+        _ => Ok(()),
+    }
+}
+
+/// A report of xorshift noise, so that runs are repeatable.
+fn next_report(seed: &mut u32) -> [u8; 8] {
+    let mut report = [0; 8];
+    for chunk in report.chunks_mut(4) {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        chunk.copy_from_slice(&seed.to_le_bytes());
+    }
+    report
+}
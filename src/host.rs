@@ -0,0 +1,378 @@
+//! The console side of the gamecube protocol, for polling a controller.
+
+use cortex_m::delay::Delay;
+
+use crate::protocol::{
+    BUTTONS1_ORIGIN_REQUEST, CMD_ORIGIN, CMD_POLL, CMD_PROBE, CMD_RECALIBRATE, CMD_RESET,
+};
+use crate::role::RoleTracker;
+use crate::rp2040_hal::{
+    gpio::bank0::Gpio28,
+    pac::PIO0,
+    pio::{PIOExt, StateMachineIndex, SM0},
+    Timer,
+};
+use crate::{
+    GamecubeInput, JoybusPin, JoybusPort, JoybusRole, RoleState, RoleStats, ServiceOutcome,
+    FRAME_GAP_US,
+};
+
+/// How long [`GamecubeHost`] and [`crate::n64::N64Host`] wait for a response to start once its command has been sent.
+/// OEM controllers respond within a few microseconds of the stop bit.
+pub const RESPONSE_TIMEOUT_US: u64 = 100;
+
+/// Why a [`GamecubeHost`] or [`crate::n64::N64Host`] command didn't get a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostError {
+    /// Nothing was received within [`RESPONSE_TIMEOUT_US`], the controller is probably unplugged.
+    Timeout,
+    /// The response ended after `received` bytes when `expected` were required.
+    ShortResponse { received: usize, expected: usize },
+}
+
+/// When [`GamecubeHost::poll_with_quirks`] asks the controller for its origin again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OriginRefresh {
+    /// Only ever use the origin read when the controller was connected.
+    Never,
+    /// Whenever the controller sets the origin request bit in its poll response.
+    WhenRequested,
+    /// Every `n` polls, regardless of what the controller asks for.
+    Every(u32),
+}
+
+/// Details of how different consoles and adapters talk to a controller,
+/// used by [`GamecubeHost::poll_with_quirks`] to reproduce real world controller behavior when testing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostQuirks {
+    /// The mode byte sent with polls, see [`crate::report`].
+    pub poll_mode: u8,
+    pub origin_refresh: OriginRefresh,
+    /// The rumble byte sent while rumble is off.
+    /// 0x00 just stops driving the motor while 0x02 actively brakes it, which the gamecube uses when stopping rumble.
+    pub rumble_off: u8,
+}
+
+impl HostQuirks {
+    /// A gamecube or Wii running a gamecube game.
+    pub const GAMECUBE: HostQuirks = HostQuirks {
+        poll_mode: 3,
+        origin_refresh: OriginRefresh::WhenRequested,
+        rumble_off: 0x02,
+    };
+
+    /// The official Wii U and Switch adapter.
+    pub const WII_U_ADAPTER: HostQuirks = HostQuirks {
+        poll_mode: 3,
+        origin_refresh: OriginRefresh::WhenRequested,
+        rumble_off: 0x00,
+    };
+}
+
+impl Default for HostQuirks {
+    fn default() -> Self {
+        HostQuirks::GAMECUBE
+    }
+}
+
+/// How many polls [`GamecubeHost`] brakes the motor for when rumble stops, before sending [`HostQuirks::rumble_off`].
+/// This is 50ms at one poll per frame, plenty for the motor to stop spinning.
+pub const RUMBLE_BRAKE_POLLS: u32 = 3;
+
+/// What the rumble motor of a controller polled by a [`GamecubeHost`] should do, see [`GamecubeHost::set_rumble`].
+///
+/// Stopping is sequenced the way a console does it: the polls right after the motor was running send 0x02 to brake it,
+/// then polls go back to the [`HostQuirks::rumble_off`] byte.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RumbleCommand {
+    /// Stop the motor, braking it first if it was running.
+    #[default]
+    Stop,
+    /// Run the motor until told otherwise.
+    Start,
+    /// Run the motor for the next `polls` polls, then stop as for [`RumbleCommand::Stop`].
+    Pulse { polls: u32 },
+}
+
+/// Acts as a console, sending commands to a gamecube controller over a [`JoybusPort`].
+pub struct GamecubeHost<P: PIOExt = PIO0, I: JoybusPin<P> = Gpio28, S: StateMachineIndex = SM0> {
+    port: JoybusPort<P, I, S>,
+    quirks: HostQuirks,
+    origin: Option<[u8; 10]>,
+    /// The origin has to be read again before the next poll, see [`GamecubeHost::is_origin_requested`].
+    origin_requested: bool,
+    /// The origin has been read since the last [`GamecubeHost::take_new_origin`].
+    new_origin: bool,
+    polls_since_origin: u32,
+    last_response_us: Option<u64>,
+    /// The rumble command sent by [`GamecubeHost::poll_scheduled`].
+    rumble: RumbleCommand,
+    /// The most recent poll asked for the motor to run.
+    motor_running: bool,
+    /// Polls left to brake the motor for.
+    brake_polls: u32,
+    /// The most recent response to [`JoybusRole::service`].
+    last_report: Option<[u8; 8]>,
+    role: RoleTracker,
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> GamecubeHost<P, I, S> {
+    pub fn new(port: JoybusPort<P, I, S>) -> GamecubeHost<P, I, S> {
+        GamecubeHost {
+            port,
+            quirks: HostQuirks::default(),
+            origin: None,
+            origin_requested: false,
+            new_origin: false,
+            polls_since_origin: 0,
+            last_response_us: None,
+            rumble: RumbleCommand::Stop,
+            motor_running: false,
+            brake_polls: 0,
+            last_report: None,
+            role: RoleTracker::new(),
+        }
+    }
+
+    /// Configure how [`GamecubeHost::poll_with_quirks`] behaves, see [`HostQuirks`].
+    pub fn set_quirks(&mut self, quirks: HostQuirks) {
+        self.quirks = quirks;
+    }
+
+    /// Microseconds from the end of the most recent command until the first byte of its response was received,
+    /// or None if there was no response.
+    /// This includes the 32us the byte takes on the wire, the controller's own response delay is the remainder.
+    pub fn last_response_us(&self) -> Option<u64> {
+        self.last_response_us
+    }
+
+    /// Set what [`GamecubeHost::poll_scheduled`] and [`JoybusRole::service`] ask the rumble motor to do from the next poll on.
+    pub fn set_rumble(&mut self, command: RumbleCommand) {
+        self.rumble = command;
+    }
+
+    /// The command set by [`GamecubeHost::set_rumble`], a [`RumbleCommand::Pulse`] counts down its polls.
+    pub fn rumble(&self) -> RumbleCommand {
+        self.rumble
+    }
+
+    /// The report read by the most recent [`JoybusRole::service`], None if it wasn't answered.
+    /// It is laid out according to the configured [`HostQuirks::poll_mode`].
+    pub fn last_report(&self) -> Option<[u8; 8]> {
+        self.last_report
+    }
+
+    /// The origin most recently read, by [`GamecubeHost::origin`], [`GamecubeHost::recalibrate`] or a poll that was due one.
+    pub fn last_origin(&self) -> Option<[u8; 10]> {
+        self.origin
+    }
+
+    /// The sticks and triggers of [`GamecubeHost::last_origin`], the neutral positions to measure inputs against.
+    pub fn origin_input(&self) -> Option<GamecubeInput> {
+        self.origin
+            .map(|origin| GamecubeInput::from_report(&origin[..8].try_into().unwrap()))
+    }
+
+    /// The sticks and triggers of the origin if it has been read since the last call, so an adapter can re-centre
+    /// the inputs it passes on whenever a controller sends a new origin, e.g. after the user recalibrates it.
+    pub fn take_new_origin(&mut self) -> Option<GamecubeInput> {
+        if !core::mem::take(&mut self.new_origin) {
+            return None;
+        }
+        self.origin_input()
+    }
+
+    /// Whether the next poll made by [`GamecubeHost::poll_with_quirks`] or [`GamecubeHost::poll_scheduled`]
+    /// reads the origin first, because the controller was probed or reset or, with [`OriginRefresh::WhenRequested`],
+    /// because its last poll response had [`crate::protocol::BUTTONS1_ORIGIN_REQUEST`] set.
+    pub fn is_origin_requested(&self) -> bool {
+        self.origin_requested || self.origin.is_none()
+    }
+
+    /// Returns the [`JoybusPort`] so it can be reused.
+    pub fn free(self) -> JoybusPort<P, I, S> {
+        self.port
+    }
+
+    /// Ask the controller for its device identifier.
+    /// Like a console, the origin is then read again before the next poll, see [`GamecubeHost::is_origin_requested`].
+    pub fn probe(&mut self, timer: &Timer) -> Result<[u8; 3], HostError> {
+        let id = self.transaction(timer, &[CMD_PROBE])?;
+        self.origin_requested = true;
+        Ok(id)
+    }
+
+    /// Reset the controller, it responds with its device identifier.
+    /// The origin is then read again before the next poll, the same as after a probe.
+    pub fn reset(&mut self, timer: &Timer) -> Result<[u8; 3], HostError> {
+        let id = self.transaction(timer, &[CMD_RESET])?;
+        self.origin_requested = true;
+        Ok(id)
+    }
+
+    /// Ask the controller for the neutral positions of its sticks and triggers.
+    pub fn origin(&mut self, timer: &Timer) -> Result<[u8; 10], HostError> {
+        let origin = self.transaction(timer, &[CMD_ORIGIN])?;
+        self.origin_read(origin);
+        Ok(origin)
+    }
+
+    /// Ask the controller to recalibrate, it responds with its new origin.
+    pub fn recalibrate(&mut self, timer: &Timer) -> Result<[u8; 10], HostError> {
+        let origin = self.transaction(timer, &[CMD_RECALIBRATE])?;
+        self.origin_read(origin);
+        Ok(origin)
+    }
+
+    fn origin_read(&mut self, origin: [u8; 10]) {
+        self.origin = Some(origin);
+        self.origin_requested = false;
+        self.new_origin = true;
+        self.polls_since_origin = 0;
+    }
+
+    /// Poll the controller's inputs, the layout of the response depends on `mode`, see [`crate::report`].
+    pub fn poll(&mut self, timer: &Timer, mode: u8, rumble: bool) -> Result<[u8; 8], HostError> {
+        self.transaction(timer, &[CMD_POLL, mode, rumble as u8])
+    }
+
+    /// Poll the controller in mode 3, the mode used by nearly every game.
+    pub fn poll_input(&mut self, timer: &Timer, rumble: bool) -> Result<GamecubeInput, HostError> {
+        self.poll(timer, 3, rumble)
+            .map(|report| GamecubeInput::from_report(&report))
+    }
+
+    /// Poll the controller the way the console or adapter configured with [`GamecubeHost::set_quirks`] does,
+    /// reading the origin first if it is due.
+    pub fn poll_with_quirks(&mut self, timer: &Timer, rumble: bool) -> Result<[u8; 8], HostError> {
+        let rumble = if rumble { 0x01 } else { self.quirks.rumble_off };
+        self.poll_with_rumble_byte(timer, rumble)
+    }
+
+    /// Same as [`GamecubeHost::poll_with_quirks`] but the rumble byte follows the command set by [`GamecubeHost::set_rumble`].
+    pub fn poll_scheduled(&mut self, timer: &Timer) -> Result<[u8; 8], HostError> {
+        let rumble = self.next_rumble_byte();
+        self.poll_with_rumble_byte(timer, rumble)
+    }
+
+    /// The rumble byte for the next poll, advancing the [`RumbleCommand`].
+    fn next_rumble_byte(&mut self) -> u8 {
+        let run = match self.rumble {
+            RumbleCommand::Stop => false,
+            RumbleCommand::Start => true,
+            RumbleCommand::Pulse { polls: 0 } => {
+                self.rumble = RumbleCommand::Stop;
+                false
+            }
+            RumbleCommand::Pulse { polls } => {
+                self.rumble = RumbleCommand::Pulse { polls: polls - 1 };
+                true
+            }
+        };
+        if run {
+            self.motor_running = true;
+            self.brake_polls = 0;
+            return 0x01;
+        }
+        if self.motor_running {
+            self.motor_running = false;
+            self.brake_polls = RUMBLE_BRAKE_POLLS;
+        }
+        if self.brake_polls > 0 {
+            self.brake_polls -= 1;
+            0x02
+        } else {
+            self.quirks.rumble_off
+        }
+    }
+
+    fn poll_with_rumble_byte(&mut self, timer: &Timer, rumble: u8) -> Result<[u8; 8], HostError> {
+        let origin_due = match self.quirks.origin_refresh {
+            OriginRefresh::Never | OriginRefresh::WhenRequested => self.is_origin_requested(),
+            OriginRefresh::Every(polls) => {
+                self.is_origin_requested() || self.polls_since_origin >= polls
+            }
+        };
+        if origin_due {
+            self.origin(timer)?;
+        }
+
+        let report = self.transaction(timer, &[CMD_POLL, self.quirks.poll_mode, rumble])?;
+        self.polls_since_origin = self.polls_since_origin.saturating_add(1);
+
+        if self.quirks.origin_refresh == OriginRefresh::WhenRequested
+            && report[0] & BUTTONS1_ORIGIN_REQUEST != 0
+        {
+            // read it before the next poll, like a console does
+            trace!("joybus: controller requested its origin be read again");
+            self.origin_requested = true;
+        }
+        Ok(report)
+    }
+
+    /// Send `command` and receive a response of exactly `N` bytes.
+    pub fn transaction<const N: usize>(
+        &mut self,
+        timer: &Timer,
+        command: &[u8],
+    ) -> Result<[u8; N], HostError> {
+        transaction(&mut self.port, timer, command, &mut self.last_response_us)
+    }
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> JoybusRole for GamecubeHost<P, I, S> {
+    fn service(&mut self, timer: &Timer, _delay: &mut Delay, _timeout_us: u64) -> ServiceOutcome {
+        self.last_report = self.poll_scheduled(timer).ok();
+        self.role.record(self.last_report.is_some())
+    }
+
+    fn state(&self) -> RoleState {
+        self.role.state
+    }
+
+    fn stats(&self) -> RoleStats {
+        self.role.stats
+    }
+}
+
+/// Send `command` as a console and receive a response of exactly `N` bytes,
+/// recording the time until the response started in `last_response_us`.
+pub(crate) fn transaction<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, const N: usize>(
+    port: &mut JoybusPort<P, I, S>,
+    timer: &Timer,
+    command: &[u8],
+    last_response_us: &mut Option<u64>,
+) -> Result<[u8; N], HostError> {
+    // The previous response may still be finishing its stop bit, don't talk over it.
+    port.restart_for_read(timer);
+
+    port.send_frame(command);
+    port.flush();
+
+    let sent = timer.get_counter();
+    *last_response_us = None;
+    let mut response = [0; N];
+    let Some((first, rest)) = response.split_first_mut() else {
+        return Ok(response);
+    };
+    *first = port
+        .recv_byte(timer, RESPONSE_TIMEOUT_US)
+        .ok_or(HostError::Timeout)?;
+    *last_response_us = Some(
+        timer
+            .get_counter()
+            .checked_duration_since(sent)
+            .unwrap()
+            .ticks(),
+    );
+
+    let received = 1 + port.recv_frame(timer, rest, FRAME_GAP_US).unwrap_or(0);
+    if received == N {
+        Ok(response)
+    } else {
+        Err(HostError::ShortResponse {
+            received,
+            expected: N,
+        })
+    }
+}
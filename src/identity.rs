@@ -0,0 +1,103 @@
+//! Ready made identities for the kinds of device a console can find on a controller port.
+//!
+//! A [`DeviceIdentity`] is what a [`crate::GamecubeController`] answers probes, resets and origin commands with.
+//! Apply one with [`crate::GamecubeController::set_identity`], e.g. to look like a WaveBird to a game that treats it differently:
+//!
+//! ```ignore
+//! controller.set_identity(&DevicePreset::WaveBird.identity());
+//! ```
+//!
+//! Only the identity changes, polls are still answered with gamecube controller reports.
+//! That is what the bongos and most third party pads send anyway, but a game that really expects a keyboard or a steering wheel
+//! will misread them, so those presets are for probing how a game or adapter reacts to the identifier.
+//! Use [`crate::keyboard`] to act as a working keyboard.
+
+use crate::protocol::{self, ID_RESPONSE_LEN, ORIGIN_RESPONSE_LEN};
+use crate::{ID_RESPONSE, ORIGIN_RESPONSE};
+
+/// The responses that identify a device to the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    /// The response to probe and reset: 2 identifier bytes and a status byte.
+    pub id: [u8; ID_RESPONSE_LEN],
+    /// The response to origin until a recalibrate or [`crate::GamecubeController::set_origin`] replaces it.
+    pub origin: [u8; ORIGIN_RESPONSE_LEN],
+}
+
+/// Status byte sent by controllers.
+const STATUS: u8 = ID_RESPONSE[2];
+
+/// A wired OEM controller, what [`crate::GamecubeController`] identifies as by default.
+pub const OEM_CONTROLLER: DeviceIdentity = DeviceIdentity {
+    id: ID_RESPONSE,
+    origin: ORIGIN_RESPONSE,
+};
+
+/// A WaveBird receiver with a controller paired.
+pub const WAVEBIRD: DeviceIdentity = DeviceIdentity {
+    id: [protocol::WAVEBIRD_ID[0], protocol::WAVEBIRD_ID[1], STATUS],
+    origin: ORIGIN_RESPONSE,
+};
+
+/// A pad without a rumble motor, as reported by several third party pads.
+pub const NO_RUMBLE_CONTROLLER: DeviceIdentity = DeviceIdentity {
+    id: [
+        protocol::NO_RUMBLE_CONTROLLER_ID[0],
+        protocol::NO_RUMBLE_CONTROLLER_ID[1],
+        STATUS,
+    ],
+    origin: ORIGIN_RESPONSE,
+};
+
+/// The DK bongos, which identify as a standard controller.
+pub const BONGOS: DeviceIdentity = OEM_CONTROLLER;
+
+/// A keyboard, see the [module docs](self) for why polls won't work.
+pub const KEYBOARD: DeviceIdentity = DeviceIdentity {
+    id: [protocol::KEYBOARD_ID[0], protocol::KEYBOARD_ID[1], 0],
+    origin: ORIGIN_RESPONSE,
+};
+
+/// A steering wheel such as the Logitech Speed Force, see the [module docs](self) for why polls won't work.
+pub const STEERING_WHEEL: DeviceIdentity = DeviceIdentity {
+    id: [
+        protocol::STEERING_WHEEL_ID[0],
+        protocol::STEERING_WHEEL_ID[1],
+        0,
+    ],
+    origin: ORIGIN_RESPONSE,
+};
+
+/// The identities in this module by name, e.g. for choosing one from a menu or a saved setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DevicePreset {
+    #[default]
+    Oem,
+    WaveBird,
+    NoRumble,
+    Bongos,
+    Keyboard,
+    SteeringWheel,
+}
+
+impl DevicePreset {
+    pub const ALL: [DevicePreset; 6] = [
+        DevicePreset::Oem,
+        DevicePreset::WaveBird,
+        DevicePreset::NoRumble,
+        DevicePreset::Bongos,
+        DevicePreset::Keyboard,
+        DevicePreset::SteeringWheel,
+    ];
+
+    pub const fn identity(self) -> DeviceIdentity {
+        match self {
+            DevicePreset::Oem => OEM_CONTROLLER,
+            DevicePreset::WaveBird => WAVEBIRD,
+            DevicePreset::NoRumble => NO_RUMBLE_CONTROLLER,
+            DevicePreset::Bongos => BONGOS,
+            DevicePreset::Keyboard => KEYBOARD,
+            DevicePreset::SteeringWheel => STEERING_WHEEL,
+        }
+    }
+}
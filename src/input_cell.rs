@@ -0,0 +1,118 @@
+use core::sync::atomic::{fence, AtomicU32, Ordering};
+
+use crate::GamecubeInput;
+
+/// Hands the latest [`GamecubeInput`] from the main loop over to an interrupt driven poll responder without locking.
+///
+/// The main loop calls [`InputCell::store`] whenever it has sampled new inputs and the interrupt handler calls
+/// [`InputCell::load`] or [`InputCell::load_report`] when it needs to answer a poll.
+/// Only a single context may call `store`, any number of contexts may call `load`.
+pub struct InputCell {
+    staging: ReportStaging,
+}
+
+impl InputCell {
+    pub const fn new() -> InputCell {
+        InputCell {
+            staging: ReportStaging::new(),
+        }
+    }
+
+    /// Publish a new input, replacing whatever was stored before.
+    ///
+    /// Must only ever be called from one context at a time.
+    pub fn store(&self, input: &GamecubeInput) {
+        self.staging.set_next_report(&input.create_report());
+    }
+
+    /// Returns the most recently stored input.
+    pub fn load(&self) -> GamecubeInput {
+        GamecubeInput::from_report(&self.load_report())
+    }
+
+    /// Returns the most recently stored input already encoded as a poll report.
+    /// This skips the encoding step which is useful when responding from an interrupt.
+    pub fn load_report(&self) -> [u8; 8] {
+        self.staging.next_report()
+    }
+
+    /// The underlying staging buffer, for passing to [`crate::GamecubeController::respond_to_poll_staged`].
+    pub fn staging(&self) -> &ReportStaging {
+        &self.staging
+    }
+}
+
+impl Default for InputCell {
+    fn default() -> Self {
+        InputCell::new()
+    }
+}
+
+/// A double buffered poll report that can be replaced from the main loop while an interrupt is sending it.
+///
+/// [`ReportStaging::set_next_report`] always writes the slot that readers are not currently directed to and then flips a sequence counter,
+/// so an interrupt that preempts it never has to wait for it to finish and never observes a half written report.
+/// A reader on the other core that races a write just retries.
+///
+/// Only a single context may call `set_next_report`, any number of contexts may call `next_report`.
+pub struct ReportStaging {
+    sequence: AtomicU32,
+    slots: [[AtomicU32; 2]; 2],
+}
+
+impl ReportStaging {
+    pub const fn new() -> ReportStaging {
+        let report = GamecubeInput::NEUTRAL.to_mode3().encode();
+        let low = u32::from_le_bytes([report[0], report[1], report[2], report[3]]);
+        let high = u32::from_le_bytes([report[4], report[5], report[6], report[7]]);
+        ReportStaging {
+            sequence: AtomicU32::new(0),
+            slots: [
+                [AtomicU32::new(low), AtomicU32::new(high)],
+                [AtomicU32::new(low), AtomicU32::new(high)],
+            ],
+        }
+    }
+
+    /// Stage the report to be sent in response to the next poll.
+    ///
+    /// Must only ever be called from one context at a time.
+    pub fn set_next_report(&self, report: &[u8; 8]) {
+        let next = self.sequence.load(Ordering::Relaxed).wrapping_add(1);
+        let slot = &self.slots[next as usize & 1];
+        // Make sure a reader that sees any of the stores below also sees the previous sequence update.
+        fence(Ordering::Release);
+        slot[0].store(
+            u32::from_le_bytes([report[0], report[1], report[2], report[3]]),
+            Ordering::Relaxed,
+        );
+        slot[1].store(
+            u32::from_le_bytes([report[4], report[5], report[6], report[7]]),
+            Ordering::Relaxed,
+        );
+        self.sequence.store(next, Ordering::Release);
+    }
+
+    /// Returns a copy of the most recently staged report.
+    pub fn next_report(&self) -> [u8; 8] {
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            let slot = &self.slots[sequence as usize & 1];
+            let low = slot[0].load(Ordering::Relaxed).to_le_bytes();
+            let high = slot[1].load(Ordering::Relaxed).to_le_bytes();
+            fence(Ordering::Acquire);
+            // If the sequence moved on, the writer may have started overwriting the slot we just read.
+            if self.sequence.load(Ordering::Relaxed) == sequence {
+                return [
+                    low[0], low[1], low[2], low[3], high[0], high[1], high[2], high[3],
+                ];
+            }
+        }
+    }
+}
+
+impl Default for ReportStaging {
+    fn default() -> Self {
+        ReportStaging::new()
+    }
+}
@@ -0,0 +1,51 @@
+use crate::GamecubeInput;
+
+/// Delays inputs by a number of polls, e.g. to practice with the latency of netplay.
+///
+/// Reports are stored already encoded, so answering a poll costs the same as without a delay.
+/// Up to `N` polls of delay can be configured at runtime with [`InputDelay::set_delay`].
+///
+/// Pass this to [`crate::GamecubeController::respond_to_poll_delayed`].
+pub struct InputDelay<const N: usize> {
+    reports: [[u8; 8]; N],
+    /// The slot the next report is written to, the report `k` polls ago is in the slot `k` before this.
+    head: usize,
+    delay: usize,
+}
+
+impl<const N: usize> InputDelay<N> {
+    /// Creates a delay line of `N` polls, initially filled with neutral inputs.
+    pub const fn new() -> InputDelay<N> {
+        InputDelay {
+            reports: [GamecubeInput::NEUTRAL.to_mode3().encode(); N],
+            head: 0,
+            delay: N,
+        }
+    }
+
+    /// Set how many polls inputs are delayed by, anything above `N` is treated as `N`.
+    pub fn set_delay(&mut self, polls: usize) {
+        self.delay = polls.min(N);
+    }
+
+    pub fn delay(&self) -> usize {
+        self.delay
+    }
+
+    /// Record the report for this poll and return the report to send in its place.
+    pub fn push_report(&mut self, report: [u8; 8]) -> [u8; 8] {
+        if self.delay == 0 {
+            return report;
+        }
+        let delayed = self.reports[(self.head + N - self.delay) % N];
+        self.reports[self.head] = report;
+        self.head = (self.head + 1) % N;
+        delayed
+    }
+}
+
+impl<const N: usize> Default for InputDelay<N> {
+    fn default() -> Self {
+        InputDelay::new()
+    }
+}
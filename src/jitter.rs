@@ -0,0 +1,104 @@
+//! Measurement of how long the controller takes to start responding to a command.
+//!
+//! The Cortex-M0+ in the RP2040 has no DWT cycle counter and SysTick is owned by [`cortex_m::delay::Delay`],
+//! so the cycle source is provided by the user, e.g. a free running PWM counter or SysTick when using a different delay implementation.
+//!
+//! Each sample is the number of cycles from the final command byte being read from the RX FIFO
+//! to the first response byte being written to the TX FIFO.
+//! This excludes the fixed time spent by the PIO program, so it is a measure of the jitter introduced by software.
+
+/// Minimum, maximum and mean of the recorded response times in cycles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JitterStats {
+    pub min: u32,
+    pub max: u32,
+    pub samples: u32,
+    total: u64,
+}
+
+impl JitterStats {
+    pub const fn new() -> JitterStats {
+        JitterStats {
+            min: u32::MAX,
+            max: 0,
+            samples: 0,
+            total: 0,
+        }
+    }
+
+    pub fn record(&mut self, cycles: u32) {
+        self.min = self.min.min(cycles);
+        self.max = self.max.max(cycles);
+        self.samples = self.samples.saturating_add(1);
+        self.total = self.total.saturating_add(cycles as u64);
+    }
+
+    /// Returns None if nothing has been recorded yet.
+    pub fn mean(&self) -> Option<u32> {
+        if self.samples == 0 {
+            None
+        } else {
+            Some((self.total / self.samples as u64) as u32)
+        }
+    }
+}
+
+impl Default for JitterStats {
+    fn default() -> Self {
+        JitterStats::new()
+    }
+}
+
+/// Records [`JitterStats`] for a [`crate::GamecubeController`], see [`crate::GamecubeController::set_jitter_probe`].
+#[derive(Debug, Clone, Copy)]
+pub struct JitterProbe {
+    cycle_count: fn() -> u32,
+    budget: Option<u32>,
+    command_end: Option<u32>,
+    stats: JitterStats,
+}
+
+impl JitterProbe {
+    /// `cycle_count` must return an incrementing counter, wrapping at `u32::MAX`.
+    pub const fn new(cycle_count: fn() -> u32) -> JitterProbe {
+        JitterProbe {
+            cycle_count,
+            budget: None,
+            command_end: None,
+            stats: JitterStats::new(),
+        }
+    }
+
+    /// Panic in debug builds if a response takes longer than `cycles` to start.
+    pub const fn with_budget(mut self, cycles: u32) -> JitterProbe {
+        self.budget = Some(cycles);
+        self
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    pub fn reset(&mut self) {
+        self.stats = JitterStats::new();
+    }
+
+    /// Called whenever a byte is read from the RX FIFO, only the last byte before a response is kept.
+    pub(crate) fn byte_received(&mut self) {
+        self.command_end = Some((self.cycle_count)());
+    }
+
+    /// Called once the first response byte is in the TX FIFO.
+    pub(crate) fn response_started(&mut self) {
+        if let Some(start) = self.command_end.take() {
+            let cycles = (self.cycle_count)().wrapping_sub(start);
+            if let Some(budget) = self.budget {
+                debug_assert!(
+                    cycles <= budget,
+                    "response took {cycles} cycles to start, the budget is {budget} cycles"
+                );
+            }
+            self.stats.record(cycles);
+        }
+    }
+}
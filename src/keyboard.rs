@@ -0,0 +1,361 @@
+//! The device side of the gamecube ASCII keyboard, as used by Phantasy Star Online.
+//!
+//! The keyboard reports up to 3 held keys per poll rather than key events,
+//! so [`TypingBuffer`] turns text into a sequence of reports with a release frame after each press.
+//! Key codes and the report layout match Dolphin's keyboard emulation.
+
+use cortex_m::delay::Delay;
+
+use crate::protocol::{
+    CMD_KEYBOARD_POLL, CMD_PROBE, CMD_RESET, ID_RESPONSE_LEN, KEYBOARD_ID, REPLY_DELAY_US,
+};
+use crate::role::RoleTracker;
+use crate::rp2040_hal::{
+    gpio::bank0::Gpio28,
+    pac::PIO0,
+    pio::{PIOExt, StateMachineIndex, SM0},
+    Timer,
+};
+use crate::{
+    JoybusPin, JoybusPort, JoybusRole, RoleState, RoleStats, ServiceOutcome, FRAME_GAP_US,
+};
+
+/// Response to the probe and reset commands.
+pub const KEYBOARD_ID_RESPONSE: [u8; ID_RESPONSE_LEN] = [KEYBOARD_ID[0], KEYBOARD_ID[1], 0x00];
+
+/// The maximum number of keys a single report can hold.
+pub const MAX_KEYS: usize = 3;
+
+pub const KEY_LEFT_SHIFT: u8 = 0x54;
+pub const KEY_BACKSPACE: u8 = 0x50;
+pub const KEY_TAB: u8 = 0x51;
+pub const KEY_SPACE: u8 = 0x59;
+pub const KEY_ENTER: u8 = 0x61;
+
+/// The key code for `c` and whether shift needs to be held, or None if `c` can't be typed.
+/// Only ASCII letters, digits and the punctuation that shares its position with a US layout are supported.
+pub const fn key_for_char(c: u8) -> Option<(u8, bool)> {
+    Some(match c {
+        b'a'..=b'z' => (0x10 + c - b'a', false),
+        b'A'..=b'Z' => (0x10 + c - b'A', true),
+        b'1'..=b'9' => (0x2A + c - b'1', false),
+        b'0' => (0x33, false),
+        b'-' => (0x34, false),
+        b',' => (0x3C, false),
+        b'.' => (0x3D, false),
+        b'?' => (0x3E, true),
+        b'/' => (0x3E, false),
+        b' ' => (KEY_SPACE, false),
+        b'\t' => (KEY_TAB, false),
+        b'\n' => (KEY_ENTER, false),
+        0x08 => (KEY_BACKSPACE, false),
+        _ => return None,
+    })
+}
+
+/// A command received from the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyboardCommand {
+    /// 0x00, asks for the device identifier.
+    Probe,
+    /// 0xFF, same as [`KeyboardCommand::Probe`].
+    Reset,
+    /// 0x54, asks for the held keys.
+    Poll,
+    Unknown(u8),
+}
+
+/// Queues text to be typed on a [`GamecubeKeyboard`], spreading it across as many polls as it takes.
+///
+/// Each press frame holds up to [`TypingBuffer::set_keys_per_frame`] keys and is followed by a frame with every key released,
+/// so repeated characters register as separate presses.
+/// Characters that need shift held are only grouped with other shifted characters.
+/// Bytes that [`key_for_char`] can't type are skipped.
+pub struct TypingBuffer<const N: usize> {
+    text: [u8; N],
+    /// Index of the next character to type.
+    head: usize,
+    len: usize,
+    keys_per_frame: usize,
+    /// The previous frame pressed keys, so the next frame releases them.
+    releasing: bool,
+}
+
+impl<const N: usize> TypingBuffer<N> {
+    pub const fn new() -> TypingBuffer<N> {
+        TypingBuffer {
+            text: [0; N],
+            head: 0,
+            len: 0,
+            keys_per_frame: 1,
+            releasing: false,
+        }
+    }
+
+    /// How many keys to press in a single frame, from 1 to [`MAX_KEYS`].
+    /// Shift counts as a key, but a shifted character is always allowed to use 2.
+    /// Defaults to 1, since some games read only one new key per poll.
+    pub fn set_keys_per_frame(&mut self, keys: usize) {
+        self.keys_per_frame = keys.clamp(1, MAX_KEYS);
+    }
+
+    /// Queue as much of `text` as fits, returning the number of bytes queued.
+    pub fn push_str(&mut self, text: &str) -> usize {
+        let count = text.len().min(N - self.len);
+        for byte in &text.as_bytes()[..count] {
+            self.text[(self.head + self.len) % N] = *byte;
+            self.len += 1;
+        }
+        count
+    }
+
+    /// Returns true once everything queued has been typed and released.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0 && !self.releasing
+    }
+
+    /// The keys to report for the next poll.
+    pub fn next_keys(&mut self) -> [u8; MAX_KEYS] {
+        let mut keys = [0; MAX_KEYS];
+        if self.releasing {
+            self.releasing = false;
+            return keys;
+        }
+
+        let mut count = 0;
+        let mut shift = None;
+        while self.len > 0 {
+            let Some((key, shifted)) = key_for_char(self.text[self.head]) else {
+                self.pop();
+                continue;
+            };
+            let needed = if shift.is_none() && shifted { 2 } else { 1 };
+            if count + needed > self.keys_per_frame.max(needed)
+                || shift.is_some_and(|shift| shift != shifted)
+                || keys[..count].contains(&key)
+            {
+                break;
+            }
+            if shift.is_none() && shifted {
+                keys[count] = KEY_LEFT_SHIFT;
+                count += 1;
+            }
+            shift = Some(shifted);
+            keys[count] = key;
+            count += 1;
+            self.pop();
+        }
+        self.releasing = count > 0;
+        keys
+    }
+
+    fn pop(&mut self) {
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+    }
+}
+
+impl<const N: usize> Default for TypingBuffer<N> {
+    fn default() -> Self {
+        TypingBuffer::new()
+    }
+}
+
+/// Acts as a gamecube keyboard, responding to commands from the console over a [`JoybusPort`].
+pub struct GamecubeKeyboard<P: PIOExt = PIO0, I: JoybusPin<P> = Gpio28, S: StateMachineIndex = SM0>
+{
+    port: JoybusPort<P, I, S>,
+    /// Incremented on every poll, the console uses it to tell new reports apart.
+    counter: u8,
+    /// The keys [`JoybusRole::service`] reports as held.
+    keys: [u8; MAX_KEYS],
+    role: RoleTracker,
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> GamecubeKeyboard<P, I, S> {
+    pub fn new(mut port: JoybusPort<P, I, S>) -> GamecubeKeyboard<P, I, S> {
+        port.jump(0);
+        GamecubeKeyboard {
+            port,
+            counter: 0,
+            keys: [0; MAX_KEYS],
+            role: RoleTracker::new(),
+        }
+    }
+
+    /// Returns the [`JoybusPort`] so it can be reused.
+    pub fn free(self) -> JoybusPort<P, I, S> {
+        self.port
+    }
+
+    /// Set the keys that [`JoybusRole::service`] reports as held, unused key slots should be 0.
+    pub fn set_keys(&mut self, keys: [u8; MAX_KEYS]) {
+        self.keys = keys;
+    }
+
+    /// Wait up to `timeout_us` microseconds for a command and respond to it, reporting `keys` as held if it is a poll.
+    /// Unused key slots should be 0.
+    /// Returns the command that was handled, or None if nothing arrived.
+    pub fn respond(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        keys: [u8; MAX_KEYS],
+        timeout_us: u64,
+    ) -> Option<KeyboardCommand> {
+        self.respond_with(timer, delay, timeout_us, || keys)
+    }
+
+    /// Same as [`GamecubeKeyboard::respond`] but the keys come from `typing`, which only advances when a poll is answered.
+    pub fn respond_typing<const N: usize>(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        typing: &mut TypingBuffer<N>,
+        timeout_us: u64,
+    ) -> Option<KeyboardCommand> {
+        self.respond_with(timer, delay, timeout_us, || typing.next_keys())
+    }
+
+    fn respond_with(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        timeout_us: u64,
+        keys: impl FnOnce() -> [u8; MAX_KEYS],
+    ) -> Option<KeyboardCommand> {
+        let command = match self.port.recv_byte(timer, timeout_us)? {
+            CMD_PROBE => KeyboardCommand::Probe,
+            CMD_RESET => KeyboardCommand::Reset,
+            CMD_KEYBOARD_POLL => KeyboardCommand::Poll,
+            other => KeyboardCommand::Unknown(other),
+        };
+        trace!("joybus: keyboard {:?}", command);
+
+        match command {
+            KeyboardCommand::Probe | KeyboardCommand::Reset => {
+                delay.delay_us(REPLY_DELAY_US);
+                self.port.send_frame(&KEYBOARD_ID_RESPONSE);
+            }
+            KeyboardCommand::Poll => {
+                // 2 argument bytes
+                if self.port.recv_frame(timer, &mut [0; 2], FRAME_GAP_US) != Some(2) {
+                    self.port.restart_for_read(timer);
+                    return Some(command);
+                }
+                #[cfg(debug_assertions)]
+                let start = timer.get_counter();
+                let [key0, key1, key2] = keys();
+                #[cfg(debug_assertions)]
+                crate::budget::check(timer, start, crate::CALLBACK_BUDGET_US, "keyboard keys");
+                let counter = self.counter;
+                self.counter = (self.counter + 1) & 0x0F;
+                let checksum = key0 ^ key1 ^ key2 ^ counter;
+                delay.delay_us(REPLY_DELAY_US);
+                self.port
+                    .send_frame(&[counter, 0, 0, 0, key0, key1, key2, checksum]);
+            }
+            KeyboardCommand::Unknown(_) => {
+                debug!("joybus: resyncing");
+                self.port.restart_for_read(timer);
+            }
+        }
+        Some(command)
+    }
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> JoybusRole for GamecubeKeyboard<P, I, S> {
+    fn service(&mut self, timer: &Timer, delay: &mut Delay, timeout_us: u64) -> ServiceOutcome {
+        let command = self.respond(timer, delay, self.keys, timeout_us);
+        self.role.record(command == Some(KeyboardCommand::Poll))
+    }
+
+    fn state(&self) -> RoleState {
+        self.role.state
+    }
+
+    fn stats(&self) -> RoleStats {
+        self.role.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Type everything queued in `typing`, returning the reports and how many were sent.
+    fn type_all<const N: usize, const M: usize>(
+        typing: &mut TypingBuffer<N>,
+    ) -> ([[u8; MAX_KEYS]; M], usize) {
+        let mut reports = [[0; MAX_KEYS]; M];
+        let mut count = 0;
+        while !typing.is_empty() {
+            reports[count] = typing.next_keys();
+            count += 1;
+        }
+        (reports, count)
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!(key_for_char(b'a'), Some((0x10, false)));
+        assert_eq!(key_for_char(b'Z'), Some((0x29, true)));
+        assert_eq!(key_for_char(b'1'), Some((0x2A, false)));
+        assert_eq!(key_for_char(b'9'), Some((0x32, false)));
+        assert_eq!(key_for_char(b'0'), Some((0x33, false)));
+        assert_eq!(key_for_char(b'?'), Some((0x3E, true)));
+        assert_eq!(key_for_char(0x08), Some((KEY_BACKSPACE, false)));
+        assert_eq!(key_for_char(0x7F), None);
+        assert_eq!(key_for_char(0xFF), None);
+    }
+
+    #[test]
+    fn one_key_per_frame() {
+        let mut typing = TypingBuffer::<8>::new();
+        assert!(typing.is_empty());
+        assert_eq!(typing.next_keys(), [0; MAX_KEYS]);
+
+        typing.push_str("hI");
+        let (reports, count) = type_all::<8, 8>(&mut typing);
+        assert_eq!(
+            reports[..count],
+            [[0x17, 0, 0], [0; 3], [KEY_LEFT_SHIFT, 0x18, 0], [0; 3]]
+        );
+    }
+
+    #[test]
+    fn grouping() {
+        let mut typing = TypingBuffer::<16>::new();
+        typing.set_keys_per_frame(usize::MAX);
+        // repeated keys and a change of shift start a new frame, unsupported bytes are skipped
+        typing.push_str("abcdd\u{7f}AB");
+        let (reports, count) = type_all::<16, 8>(&mut typing);
+        assert_eq!(
+            reports[..count],
+            [
+                [0x10, 0x11, 0x12],
+                [0; 3],
+                [0x13, 0, 0],
+                [0; 3],
+                [0x13, 0, 0],
+                [0; 3],
+                [KEY_LEFT_SHIFT, 0x10, 0x11],
+                [0; 3],
+            ]
+        );
+    }
+
+    #[test]
+    fn wrap_around() {
+        let mut typing = TypingBuffer::<4>::new();
+        assert_eq!(typing.push_str(""), 0);
+        assert_eq!(typing.push_str("abcd"), 4);
+        assert_eq!(typing.push_str("e"), 0);
+        typing.next_keys();
+        typing.next_keys();
+        assert_eq!(typing.push_str("efg"), 1);
+        let (reports, count) = type_all::<4, 8>(&mut typing);
+        let pressed = reports[..count].iter().step_by(2).map(|keys| keys[0]);
+        assert!(pressed.eq([0x11, 0x12, 0x13, 0x14]));
+    }
+}
@@ -0,0 +1,334 @@
+//! A mapping layer on top of [`Remap`] for one-handed and other accessibility controllers.
+//!
+//! A shift button switches between a base and an alternate [`Remap`], either while held or toggled on and off,
+//! so a few physical buttons can reach every button of the controller.
+//! Buttons can also be made to toggle, so a single press holds the button down until it is pressed again.
+//!
+//! The configuration is saved alongside other profiles through [`Layers::save`].
+//!
+//! ```ignore
+//! let mut alternate = Remap::IDENTITY;
+//! alternate.set_button(Button::A, Some(Button::X));
+//! alternate.set_button(Button::B, Some(Button::Y));
+//! let mut layers = Layers::new(Remap::IDENTITY, alternate);
+//! layers.set_shift(Some(Button::Z), ShiftMode::Hold);
+//! layers.set_toggle(Button::R, true);
+//! controller.respond_to_poll(&timer, &mut delay, layers.apply(&input));
+//! ```
+
+use crate::remap::{Button, Remap, REMAP_LEN};
+use crate::GamecubeInput;
+
+/// How the shift button selects the alternate layer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShiftMode {
+    /// The alternate layer is used while the shift button is held.
+    #[default]
+    Hold,
+    /// Each press of the shift button switches between the base and alternate layer.
+    Toggle,
+}
+
+/// The length of [`Layers::to_bytes`].
+pub const LAYERS_LEN: usize = 5 + REMAP_LEN * 2;
+
+/// Bumped whenever the layout of [`Layers::to_bytes`] changes, so old data is rejected instead of misread.
+const LAYERS_VERSION: u8 = 1;
+
+/// A base and alternate [`Remap`] selected by a shift button, plus buttons with toggle semantics.
+///
+/// This holds the state of the shift and toggled buttons, so [`Layers::apply`] must be called for every poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layers {
+    base: Remap,
+    alternate: Remap,
+    /// The physical button that selects the alternate layer, it is never sent to the console itself.
+    shift: Option<Button>,
+    shift_mode: ShiftMode,
+    /// Bits in the order of [`Button::ALL`] of the sent buttons that toggle instead of following what is held.
+    toggles: u16,
+    /// Whether the alternate layer is in use.
+    shifted: bool,
+    shift_held: bool,
+    /// Bits in the order of [`Button::ALL`] of the toggled buttons that are currently on.
+    toggled_on: u16,
+    /// Bits in the order of [`Button::ALL`] of the buttons that were pressed after mapping, for edge detection.
+    mapped_held: u16,
+}
+
+impl Layers {
+    /// Use `base` normally and `alternate` when shifted, without a shift button or toggled buttons until they are set.
+    pub const fn new(base: Remap, alternate: Remap) -> Layers {
+        Layers {
+            base,
+            alternate,
+            shift: None,
+            shift_mode: ShiftMode::Hold,
+            toggles: 0,
+            shifted: false,
+            shift_held: false,
+            toggled_on: 0,
+            mapped_held: 0,
+        }
+    }
+
+    /// Select the alternate layer with the physical button `shift`, or never select it if None.
+    pub fn set_shift(&mut self, shift: Option<Button>, mode: ShiftMode) {
+        self.shift = shift;
+        self.shift_mode = mode;
+        self.shifted = false;
+    }
+
+    pub fn shift(&self) -> Option<Button> {
+        self.shift
+    }
+
+    pub fn shift_mode(&self) -> ShiftMode {
+        self.shift_mode
+    }
+
+    /// Make the sent `button` toggle on each press instead of following what is held.
+    /// This applies after mapping, so it affects `button` however it is reached.
+    pub fn set_toggle(&mut self, button: Button, toggle: bool) {
+        let bit = 1 << button as u16;
+        if toggle {
+            self.toggles |= bit;
+        } else {
+            self.toggles &= !bit;
+            self.toggled_on &= !bit;
+        }
+    }
+
+    pub fn is_toggle(&self, button: Button) -> bool {
+        self.toggles & 1 << button as u16 != 0
+    }
+
+    pub fn set_base(&mut self, base: Remap) {
+        self.base = base;
+    }
+
+    pub fn base(&self) -> &Remap {
+        &self.base
+    }
+
+    pub fn set_alternate(&mut self, alternate: Remap) {
+        self.alternate = alternate;
+    }
+
+    pub fn alternate(&self) -> &Remap {
+        &self.alternate
+    }
+
+    /// Whether the alternate layer is currently in use.
+    pub fn is_shifted(&self) -> bool {
+        self.shifted
+    }
+
+    /// Return to the base layer and release every toggled button, e.g. when switching profiles.
+    pub fn reset(&mut self) {
+        self.shifted = false;
+        self.shift_held = false;
+        self.toggled_on = 0;
+        self.mapped_held = 0;
+    }
+
+    /// Map `input` through the current layer and apply the toggled buttons.
+    pub fn apply(&mut self, input: &GamecubeInput) -> GamecubeInput {
+        let mut input = *input;
+        if let Some(shift) = self.shift {
+            let held = shift.is_pressed(&input);
+            self.shifted = match self.shift_mode {
+                ShiftMode::Hold => held,
+                ShiftMode::Toggle => self.shifted ^ (held && !self.shift_held),
+            };
+            self.shift_held = held;
+            shift.set_pressed(&mut input, false);
+        }
+
+        let remap = if self.shifted {
+            &self.alternate
+        } else {
+            &self.base
+        };
+        let mut output = remap.apply(&input);
+
+        let mut held = 0;
+        for (i, button) in Button::ALL.into_iter().enumerate() {
+            if button.is_pressed(&output) {
+                held |= 1 << i;
+            }
+        }
+        self.toggled_on ^= held & !self.mapped_held & self.toggles;
+        self.mapped_held = held;
+        for (i, button) in Button::ALL.into_iter().enumerate() {
+            if self.toggles & 1 << i != 0 {
+                button.set_pressed(&mut output, self.toggled_on & 1 << i != 0);
+            }
+        }
+        output
+    }
+
+    /// Serialize the configuration for persisting, e.g. through a [`crate::storage::WearLevelled`].
+    /// Which layer is in use and which buttons are toggled on is not included.
+    pub fn to_bytes(&self) -> [u8; LAYERS_LEN] {
+        let mut bytes = [0; LAYERS_LEN];
+        bytes[0] = LAYERS_VERSION;
+        bytes[1] = self.shift.map(|shift| shift as u8).unwrap_or(0xFF);
+        bytes[2] = self.shift_mode as u8;
+        bytes[3..5].copy_from_slice(&self.toggles.to_le_bytes());
+        bytes[5..5 + REMAP_LEN].copy_from_slice(&self.base.to_bytes());
+        bytes[5 + REMAP_LEN..].copy_from_slice(&self.alternate.to_bytes());
+        bytes
+    }
+
+    /// Deserialize what was written by [`Layers::to_bytes`].
+    /// Returns None if `bytes` is from a different version or is invalid, e.g. erased flash.
+    pub fn from_bytes(bytes: &[u8; LAYERS_LEN]) -> Option<Layers> {
+        if bytes[0] != LAYERS_VERSION {
+            return None;
+        }
+        let shift = match bytes[1] {
+            0xFF => None,
+            index => Some(*Button::ALL.get(index as usize)?),
+        };
+        let shift_mode = match bytes[2] {
+            0 => ShiftMode::Hold,
+            1 => ShiftMode::Toggle,
+            _ => return None,
+        };
+        let toggles = u16::from_le_bytes([bytes[3], bytes[4]]);
+        if toggles >> Button::ALL.len() != 0 {
+            return None;
+        }
+        let base = Remap::from_bytes(bytes[5..5 + REMAP_LEN].try_into().unwrap())?;
+        let alternate = Remap::from_bytes(bytes[5 + REMAP_LEN..].try_into().unwrap())?;
+
+        let mut layers = Layers::new(base, alternate);
+        layers.set_shift(shift, shift_mode);
+        layers.toggles = toggles;
+        Some(layers)
+    }
+
+    /// Load from `storage` at `offset`, falling back to [`Layers::default`] if nothing valid has been saved.
+    #[cfg(feature = "storage")]
+    pub fn load<S: crate::storage::ReadStorage>(
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<Layers, S::Error> {
+        let mut bytes = [0; LAYERS_LEN];
+        storage.read(offset, &mut bytes)?;
+        Ok(Layers::from_bytes(&bytes).unwrap_or_default())
+    }
+
+    /// Save to `storage` at `offset`, using [`LAYERS_LEN`] bytes.
+    #[cfg(feature = "storage")]
+    pub fn save<S: crate::storage::Storage>(
+        &self,
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<(), S::Error> {
+        storage.write(offset, &self.to_bytes())
+    }
+}
+
+impl Default for Layers {
+    /// Both layers are [`Remap::IDENTITY`] with no shift button, so inputs pass through unchanged.
+    fn default() -> Self {
+        Layers::new(Remap::IDENTITY, Remap::IDENTITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(buttons: &[Button]) -> GamecubeInput {
+        let mut input = GamecubeInput::NEUTRAL;
+        for button in buttons {
+            button.set_pressed(&mut input, true);
+        }
+        input
+    }
+
+    fn a_to_x() -> Layers {
+        let mut alternate = Remap::IDENTITY;
+        alternate.set_button(Button::A, Some(Button::X));
+        Layers::new(Remap::IDENTITY, alternate)
+    }
+
+    #[test]
+    fn shift_hold() {
+        let mut layers = a_to_x();
+        layers.set_shift(Some(Button::Z), ShiftMode::Hold);
+        assert_eq!(layers.apply(&held(&[Button::A])), held(&[Button::A]));
+        // the shift button itself is never sent
+        assert_eq!(
+            layers.apply(&held(&[Button::Z, Button::A])),
+            held(&[Button::X])
+        );
+        assert!(layers.is_shifted());
+        assert_eq!(layers.apply(&held(&[Button::A])), held(&[Button::A]));
+    }
+
+    #[test]
+    fn shift_toggle() {
+        let mut layers = a_to_x();
+        layers.set_shift(Some(Button::Z), ShiftMode::Toggle);
+        let outputs = [
+            held(&[Button::Z]),
+            held(&[Button::Z, Button::A]),
+            held(&[Button::A]),
+            held(&[Button::Z]),
+            held(&[Button::A]),
+        ]
+        .map(|input| layers.apply(&input));
+        assert_eq!(
+            outputs,
+            [
+                held(&[]),
+                held(&[Button::X]),
+                held(&[Button::X]),
+                held(&[]),
+                held(&[Button::A]),
+            ]
+        );
+    }
+
+    #[test]
+    fn toggled_buttons() {
+        let mut layers = Layers::default();
+        layers.set_toggle(Button::R, true);
+        let outputs = [&[Button::R][..], &[Button::R], &[], &[Button::R], &[]]
+            .map(|buttons| layers.apply(&held(buttons)).r_digital);
+        assert_eq!(outputs, [true, true, true, false, false]);
+
+        layers.apply(&held(&[Button::R]));
+        layers.reset();
+        assert!(!layers.apply(&held(&[])).r_digital);
+        layers.set_toggle(Button::R, false);
+        assert!(layers.apply(&held(&[Button::R])).r_digital);
+    }
+
+    #[test]
+    fn bytes() {
+        let mut layers = a_to_x();
+        layers.set_shift(Some(Button::Z), ShiftMode::Toggle);
+        layers.set_toggle(Button::R, true);
+        let bytes = layers.to_bytes();
+        assert_eq!(bytes[..5], [LAYERS_VERSION, 5, 1, 0x00, 0x08]);
+        assert_eq!(Layers::from_bytes(&bytes), Some(layers));
+        assert_eq!(
+            Layers::default().to_bytes()[..5],
+            [LAYERS_VERSION, 0xFF, 0, 0, 0]
+        );
+
+        // erased flash
+        assert_eq!(Layers::from_bytes(&[0xFF; LAYERS_LEN]), None);
+        let mut bad_mode = bytes;
+        bad_mode[2] = 2;
+        assert_eq!(Layers::from_bytes(&bad_mode), None);
+        let mut bad_toggles = bytes;
+        bad_toggles[4] = 0x10;
+        assert_eq!(Layers::from_bytes(&bad_toggles), None);
+    }
+}
@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 //! An implementation of the controller side of the joybus protocol for gamecube for the RP2040 chip via its PIO functionality.
 //!
@@ -7,369 +7,1386 @@
 //! For understanding how the inner protocol works consider
 //! [this excellent writeup on the GC controller protocol](https://jefflongo.dev/posts/gc-controller-reverse-engineering-part-1)
 
+#[cfg(all(feature = "hal-0_10", not(feature = "hal-0_12")))]
+extern crate pio_0_2 as pio;
+#[cfg(feature = "hal-0_12")]
+extern crate pio_0_3 as pio;
+#[cfg(all(feature = "hal-0_10", not(feature = "hal-0_12")))]
+extern crate rp2040_hal_0_10 as rp2040_hal;
+#[cfg(feature = "hal-0_12")]
+extern crate rp2040_hal_0_12 as rp2040_hal;
+
+use core::sync::atomic::{AtomicBool, Ordering};
 use cortex_m::delay::Delay;
 use embedded_hal::digital::InputPin;
-use pio::{Instruction, InstructionOperands, Program, ProgramWithDefines, SideSet, Wrap};
 use rp2040_hal::{
-    clocks::Clock,
-    clocks::ClocksManager,
-    gpio::{bank0::Gpio28, FunctionNull, FunctionPio0, Pin, PullDown},
-    pac::{PIO0, RESETS},
-    pio::{PIOExt, Running, Rx, ShiftDirection, StateMachine, Tx, SM0},
+    fugit::MicrosDurationU64,
+    gpio::bank0::Gpio28,
+    pac::PIO0,
+    pio::{PIOExt, StateMachineIndex, SM0},
+    timer::Instant,
     Timer,
 };
 
-/// A wrapper around the PIO types from the rp2040 HAL required for low level communication over the joybus protocol.
-pub struct JoybusPio {
-    data_pin: Pin<Gpio28, FunctionPio0, PullDown>,
-    tx: Tx<(PIO0, SM0)>,
-    rx: Rx<(PIO0, SM0)>,
-    sm: StateMachine<(PIO0, SM0), Running>,
+#[macro_use]
+mod logging;
+
+#[cfg(feature = "async")]
+mod asynch;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(feature = "bridge")]
+pub mod bridge;
+mod budget;
+#[cfg(feature = "busy-meter")]
+mod busy_meter;
+mod cadence;
+pub mod calibration;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "host")]
+pub mod compat;
+pub mod config;
+#[cfg(feature = "host")]
+pub mod conformance;
+pub mod curve;
+pub mod delta;
+#[cfg(feature = "detect")]
+pub mod detect;
+pub mod diagnostics;
+#[cfg(feature = "ufmt")]
+mod display;
+#[cfg(feature = "std")]
+pub mod dolphin;
+pub mod fast_path;
+#[cfg(feature = "fault-injection")]
+pub mod fault;
+#[cfg(feature = "flight-recorder")]
+pub mod flight_recorder;
+mod fsm;
+mod hal_compat;
+#[cfg(feature = "hil-test")]
+pub mod hil;
+#[cfg(feature = "host")]
+mod host;
+pub mod identity;
+mod input_cell;
+mod input_delay;
+#[cfg(feature = "jitter")]
+mod jitter;
+#[cfg(feature = "keyboard")]
+pub mod keyboard;
+pub mod layers;
+pub mod melee;
+pub mod modifiers;
+#[cfg(feature = "n64")]
+pub mod n64;
+#[cfg(feature = "n64")]
+pub mod pak;
+#[cfg(feature = "park")]
+pub mod park;
+mod pin_config;
+mod port;
+mod power;
+pub mod profile;
+pub mod protocol;
+#[cfg(feature = "recording")]
+pub mod recording;
+pub mod remap;
+#[cfg(feature = "replay")]
+pub mod replay;
+pub mod report;
+pub mod resources;
+mod response_alignment;
+mod response_mask;
+mod role;
+#[cfg(feature = "rtt")]
+pub mod rtt;
+#[cfg(feature = "host")]
+pub mod rumble_loopback;
+pub mod sampler;
+pub mod sanitize;
+pub mod scheduler;
+#[cfg(feature = "std")]
+pub mod sim;
+pub mod snapback;
+#[cfg(feature = "host")]
+pub mod soak;
+pub mod split;
+#[cfg(feature = "storage")]
+pub mod storage;
+mod strobe;
+pub mod test_vectors;
+pub mod text_entry;
+mod timing;
+#[cfg(feature = "transfer")]
+pub mod transfer;
+#[cfg(feature = "n64")]
+pub mod transfer_pak;
+pub mod travel;
+pub mod triggers;
+#[cfg(feature = "usb")]
+pub mod usb;
+
+#[cfg(feature = "async")]
+pub use asynch::{on_pio_interrupt, AsyncDelay, HalDelay};
+pub use budget::{CALLBACK_BUDGET_US, POLL_BUDGET_US};
+#[cfg(feature = "busy-meter")]
+pub use busy_meter::{BusyMeter, BusyReport};
+pub use cadence::PollCadence;
+pub use config::{ConfigSource, FixedConfig, JoybusConfig};
+use diagnostics::Diagnostics;
+#[cfg(feature = "fault-injection")]
+pub use fault::{FaultConfig, FaultInjector, FaultStats};
+#[cfg(feature = "flight-recorder")]
+pub use flight_recorder::{FlightEvent, FlightRecord, FlightRecorder, FLIGHT_RECORDER_LEN};
+pub use fsm::{FsmAction, GamecubeCommand, ProtocolFsm};
+#[cfg(feature = "host")]
+pub use host::{
+    GamecubeHost, HostError, HostQuirks, OriginRefresh, RumbleCommand, RESPONSE_TIMEOUT_US,
+    RUMBLE_BRAKE_POLLS,
+};
+pub use identity::{DeviceIdentity, DevicePreset};
+pub use input_cell::{InputCell, ReportStaging};
+pub use input_delay::InputDelay;
+#[cfg(feature = "jitter")]
+pub use jitter::{JitterProbe, JitterStats};
+pub use pin_config::PinConfig;
+pub use port::{
+    JoybusPin, JoybusPort, JoybusPortPair, BUS_IDLE_GIVE_UP_US, BUS_IDLE_US, FRAME_END_IRQ,
+    FRAME_GAP_US, IDLE_TIMEOUT_STEP_NS, MAX_IDLE_TIMEOUT_US, PROGRAM, PROGRAM_LEN,
+};
+pub use power::{PowerEvent, PowerSense};
+use report::{decode_analog, encode_analog, AnalogValues, Buttons, PollReportMode3};
+pub use response_alignment::ResponseAlignment;
+use response_mask::MaskedIrqs;
+pub use response_mask::ResponseMask;
+use role::RoleTracker;
+pub use role::{JoybusRole, RoleState, RoleStats, ServiceOutcome};
+#[cfg(feature = "rtt")]
+pub use rtt::{RttTrace, TraceEvent};
+pub use strobe::PollStrobe;
+pub use timing::{
+    checked_clock_divisor, clock_divisor, ClockError, BITRATE, CYCLES_PER_BIT, MIN_SYSTEM_CLOCK_HZ,
+    T1, T2, T3,
+};
+
+#[deprecated(note = "renamed to JoybusPort")]
+pub type JoybusPio = JoybusPort;
+
+/// A wrapper around [`JoybusPort`] providing a high level interface for acting as a gamecube controller.
+///
+/// Like [`JoybusPort`] this defaults to PIO0, GPIO28 and SM0.
+/// Its tuning parameters default to a [`JoybusConfig`] that can be changed at runtime, see [`config`].
+pub struct GamecubeController<
+    P: PIOExt = PIO0,
+    I: JoybusPin<P> = Gpio28,
+    S: StateMachineIndex = SM0,
+    C: ConfigSource = JoybusConfig,
+> {
+    port: JoybusPort<P, I, S>,
+    config: C,
+    fsm: ProtocolFsm,
+    identity: DeviceIdentity,
+    origin: [u8; 10],
+    reset_behavior: ResetBehavior,
+    unknown_command_behavior: UnknownCommandBehavior,
+    status: StatusFlags,
+    /// The most recent poll response, used as the current inputs when recalibrating.
+    last_report: [u8; 8],
+    stats: ControllerStats,
+    idle_handler: Option<IdleHandler>,
+    /// When the most recent poll was answered, or when waiting started if there hasn't been one.
+    last_poll: Option<Instant>,
+    /// The idle handler has been called since the most recent poll.
+    idle: bool,
+    cadence: PollCadence,
+    strobe: Option<PollStrobe>,
+    response_mask: Option<ResponseMask>,
+    /// The interrupts masked by `response_mask` for the response in progress.
+    masked_irqs: MaskedIrqs,
+    response_alignment: Option<ResponseAlignment>,
+    /// The report [`JoybusRole::service`] responds to polls with.
+    next_report: [u8; 8],
+    role: RoleTracker,
+    diagnostics: Option<Diagnostics>,
+    /// When the poll being responded to started, to check the caller's code against [`POLL_BUDGET_US`].
+    #[cfg(debug_assertions)]
+    poll_started: Option<Instant>,
+    #[cfg(feature = "jitter")]
+    jitter: Option<JitterProbe>,
+    #[cfg(feature = "busy-meter")]
+    busy_meter: Option<BusyMeter>,
+    #[cfg(feature = "rtt")]
+    rtt: Option<RttTrace>,
+    #[cfg(feature = "async")]
+    wake_interrupt: Option<rp2040_hal::pio::PioIRQ>,
 }
 
-impl JoybusPio {
-    pub fn new(
-        data_pin: Pin<Gpio28, FunctionNull, PullDown>,
-        pio0: PIO0,
-        resets: &mut RESETS,
-        clocks: ClocksManager,
-    ) -> JoybusPio {
-        let data_pin: Pin<_, FunctionPio0, PullDown> = data_pin.into_function();
-        let data_pin_num = data_pin.id().num;
-
-        //     let program = pio_proc::pio_asm!(
-        //         "
-        // .define public T1 10
-        // .define public T2 20
-        // .define public T3 10
-
-        // ; Autopush with 8 bit ISR threshold
-        // public read:
-        //     set pindirs 0                   ; Set pin to input
-        // read_loop:
-        //     wait 0 pin 0 [T1 + T2 / 2 - 1]  ; Wait for falling edge, then wait until halfway through the 2uS which represents the bit value
-        //     in pins, 1                      ; Read bit value
-        //     wait 1 pin 0                    ; Done reading, so make sure we wait for the line to go high again before restarting the loop
-        //     jmp read_loop
-
-        // ; 9 bit OSR threshold, no autopull because it interferes with !osre
-        // public write:
-        //     set pindirs 1           ; Set pin to output
-        // write_loop:
-        //     set pins, 1             ; Set line high for at least 1uS to end pulse
-        //     pull ifempty block      ; Fetch next byte into OSR if we are done with the current one
-        //     out x, 1                ; Get bit
-        //     jmp !osre write_bit     ; If we aren't on the 9th bit, just write the bit
-        //     jmp x!=y write_stop_bit ; If we are on the 9th bit and it's a 1 that indicates stop bit so write it
-        //     pull ifempty block      ; If we are on the 9th bit and it's a 0 then we should skip to the next byte
-        //     out x, 1                ; Get first bit of the next byte
-        //     jmp write_bit_fast      ; Write it, skipping some of the delays because we spent so much time checking the 9th bit
-        // write_bit:
-        //     nop [3]
-        // write_bit_fast:
-        //     nop [T3 - 9]
-        //     set pins, 0 [T1 - 1]    ; Pulse always starts with low for 1uS
-        //     mov pins, x [T2 - 2]    ; Set line according to bit value for 2uS
-        //     jmp write_loop
-        // write_stop_bit:
-        //     nop [T3 - 6]
-        //     set pins, 0 [T1 - 1]
-        //     set pins, 1 [T2 - 2]
-        //     jmp read
-        // "
-        //     );
-
-        // pio proc macro is broken with cargo bin deps nightly feature.
-        // work around this by manually creating program.
-        let raw_program: [u16; 32] = [
-            //     .wrap_target
-            0xe080, //  0: set    pindirs, 0
-            0x3320, //  1: wait   0 pin, 0               [19]
-            0x4001, //  2: in     pins, 1
-            0x20a0, //  3: wait   1 pin, 0
-            0x0001, //  4: jmp    1
-            0xe081, //  5: set    pindirs, 1
-            0xe001, //  6: set    pins, 1
-            0x80e0, //  7: pull   ifempty block
-            0x6021, //  8: out    x, 1
-            0x00ee, //  9: jmp    !osre, 14
-            0x00b3, // 10: jmp    x != y, 19
-            0x80e0, // 11: pull   ifempty block
-            0x6021, // 12: out    x, 1
-            0x000f, // 13: jmp    15
-            0xa342, // 14: nop                           [3]
-            0xa142, // 15: nop                           [1]
-            0xe900, // 16: set    pins, 0                [9]
-            0xb201, // 17: mov    pins, x                [18]
-            0x0006, // 18: jmp    6
-            0xa442, // 19: nop                           [4]
-            0xe900, // 20: set    pins, 0                [9]
-            0xf201, // 21: set    pins, 1                [18]
-            0x0000, // 22: jmp    0
-            //     .wrap
-            0x0000, // padding
-            0x0000, // padding
-            0x0000, // padding
-            0x0000, // padding
-            0x0000, // padding
-            0x0000, // padding
-            0x0000, // padding
-            0x0000, // padding
-            0x0000, // padding
-        ];
+/// Counts of the commands handled by a [`GamecubeController`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ControllerStats {
+    /// Polls that were responded to.
+    pub polls: u32,
+    /// Probe and reset commands.
+    pub probes: u32,
+    /// Origin and recalibrate commands.
+    pub origins: u32,
+    /// Times the state machine was restarted because of an unknown command or a timeout.
+    pub resyncs: u32,
+    /// [`Timer`] microsecond timestamp of when the most recent command was received, including polls.
+    pub last_command_us: Option<u64>,
+    /// [`Timer`] microsecond timestamp of when the most recent poll was received, right before it was answered.
+    pub last_poll_us: Option<u64>,
+    /// Times a response was late because code run inside the response window exceeded its budget,
+    /// see [`POLL_BUDGET_US`] and [`CALLBACK_BUDGET_US`]. Only counted in debug builds.
+    pub budget_overruns: u32,
+    /// Responses abandoned part way through because the TX FIFO ran dry, see [`JoybusPort::send_frame`].
+    pub tx_underruns: u32,
+    /// Commands with an opcode the controller doesn't know, see [`UnknownCommandBehavior`].
+    pub unknown_commands: u32,
+}
 
-        let program = ProgramWithDefines {
-            program: Program {
-                code: raw_program.into(),
-                origin: Some(0),
-                wrap: Wrap {
-                    source: 22,
-                    target: 0,
-                },
-                side_set: SideSet::default(),
-            },
-            public_defines: (),
+/// Returned by [`GamecubeController::poll_blocking`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PollOutcome {
+    /// The poll mode requested by the console, see [`report`].
+    pub mode: u8,
+    /// Whether the console wants the rumble motor on.
+    pub rumble: bool,
+    /// Microseconds from calling `poll_blocking` until the response was queued.
+    pub waited_us: u64,
+    /// Counters as of this poll.
+    pub stats: ControllerStats,
+}
+
+/// The status flags [`GamecubeController`] sends in the button bytes of every poll response,
+/// see [`GamecubeController::set_status_flags`].
+///
+/// OEM controllers only ever send [`StatusFlags::use_origin`], but some games check the others,
+/// e.g. reading the origin again when [`StatusFlags::origin_request`] is set.
+/// [`crate::fast_path`] sends its staged reports as they are, apply the flags with [`StatusFlags::apply`] before staging them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusFlags {
+    /// Ask the console to read the origin again, see [`protocol::BUTTONS1_ORIGIN_REQUEST`].
+    pub origin_request: bool,
+    /// See [`protocol::BUTTONS1_ERROR_LATCH`].
+    pub error_latch: bool,
+    /// See [`protocol::BUTTONS1_ERROR`].
+    pub error: bool,
+    /// Tell the console the origin is in use, see [`protocol::BUTTONS2_ALWAYS_SET`].
+    /// Some consoles and adapters ignore responses without it.
+    pub use_origin: bool,
+}
+
+impl StatusFlags {
+    /// The flags sent by OEM controllers.
+    pub const OEM: StatusFlags = StatusFlags {
+        origin_request: false,
+        error_latch: false,
+        error: false,
+        use_origin: true,
+    };
+
+    /// `report` with the status flags of its button bytes replaced by these.
+    pub const fn apply(&self, mut report: [u8; 8]) -> [u8; 8] {
+        let mut buttons1 = report[0] & !protocol::BUTTONS1_STATUS_MASK;
+        if self.origin_request {
+            buttons1 |= protocol::BUTTONS1_ORIGIN_REQUEST;
+        }
+        if self.error_latch {
+            buttons1 |= protocol::BUTTONS1_ERROR_LATCH;
+        }
+        if self.error {
+            buttons1 |= protocol::BUTTONS1_ERROR;
+        }
+        report[0] = buttons1;
+        report[1] = if self.use_origin {
+            report[1] | protocol::BUTTONS2_ALWAYS_SET
+        } else {
+            report[1] & !protocol::BUTTONS2_ALWAYS_SET
+        };
+        report
+    }
+}
+
+impl Default for StatusFlags {
+    fn default() -> Self {
+        StatusFlags::OEM
+    }
+}
+
+/// What [`GamecubeController`] does when the console sends a reset (0xFF) command.
+/// In every case the controller then responds with its identifier, just like for a probe.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum ResetBehavior {
+    /// Treat reset exactly like a probe.
+    #[default]
+    Probe,
+    /// Restore the default origin, undoing [`GamecubeController::set_origin`].
+    RestoreDefaultOrigin,
+    /// Call the function, e.g. to clear rumble or flag that calibration should be rerun.
+    /// It runs inside the response window so must return within a couple of microseconds,
+    /// anything slow should be deferred to the main loop.
+    Callback(fn()),
+}
+
+/// What [`GamecubeController`] does when the console sends a command it doesn't know,
+/// see [`GamecubeController::set_unknown_command_behavior`].
+#[derive(Debug, Clone, Copy, Default)]
+pub enum UnknownCommandBehavior {
+    /// Wait for the bus to go idle and restart reading, discarding any argument bytes of the command.
+    #[default]
+    Resync,
+    /// Carry on reading straight away without responding.
+    /// Any argument bytes are then read as commands of their own, so this only suits commands without arguments.
+    Ignore,
+    /// Call the function with the opcode, then resync.
+    /// It runs inside the response window so must return within a couple of microseconds.
+    Callback(fn(u8)),
+    /// Receive `args` more bytes, then call `handler` with the whole command, opcode first.
+    /// The handler fills in the response and returns its length, or 0 to resync without responding.
+    /// It runs inside the response window so must return within a couple of microseconds.
+    ///
+    /// `args` is capped at [`MAX_COMMAND_LEN`] - 1. Receiving them blocks,
+    /// even in a [`crate::scheduler::ScheduledController`].
+    Respond {
+        args: usize,
+        handler: fn(&[u8], &mut [u8; MAX_RESPONSE_LEN]) -> usize,
+    },
+}
+
+/// Called by [`GamecubeController::wait_for_poll_start`] when the console hasn't polled for a while,
+/// see [`GamecubeController::set_idle_handler`].
+#[derive(Debug, Clone, Copy)]
+pub struct IdleHandler {
+    /// How long without a poll before `callback` is called.
+    pub timeout_us: u64,
+    /// Called once per idle period, e.g. to dim LEDs or stop rumble.
+    /// It is called again only after another poll has been answered and the console goes idle again.
+    pub callback: fn() -> IdleAction,
+}
+
+/// What [`GamecubeController`] does after calling an [`IdleHandler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleAction {
+    /// Keep waiting for the next poll.
+    Wait,
+    /// The console was probably unplugged, restart the state machine and restore the default origin,
+    /// so the controller is ready for a console to probe it like it was just plugged in.
+    Reprobe,
+}
+
+/// How long to wait for each byte after the first of a command by default, see [`JoybusConfig::recv_timeout_us`].
+/// The bytes of a command follow each other with no gap, so a few byte times is plenty.
+pub(crate) const RECV_TIMEOUT_US: u64 = 3 * protocol::BYTE_US;
+
+/// How long to wait for the console to start a command, before giving up on a handshake
+/// or resyncing in case the state machine is out of step with the line.
+pub(crate) const COMMAND_TIMEOUT_US: u64 = 2_000_000;
+
+/// How often [`GamecubeController::wait_for_poll_start_until`] checks its cancel flag while the bus is idle.
+pub const CANCEL_CHECK_INTERVAL_US: u64 = 100;
+
+/// Response to probe and reset: standard controller.
+const ID_RESPONSE: [u8; protocol::ID_RESPONSE_LEN] = [
+    protocol::GAMECUBE_CONTROLLER_ID[0],
+    protocol::GAMECUBE_CONTROLLER_ID[1],
+    3,
+];
+
+/// Default response to origin until a recalibrate or [`GamecubeController::set_origin`] replaces it.
+/// Set perfect deadzone, we have no analog sticks.
+/// Apparently gc adapter ignores this though and uses the first poll response instead.
+const ORIGIN_RESPONSE: [u8; protocol::ORIGIN_RESPONSE_LEN] = [
+    0,                             // butons1
+    protocol::BUTTONS2_ALWAYS_SET, // butons2
+    128,                           // stick x
+    128,                           // stick y
+    128,                           // cstick x
+    128,                           // cstick y
+    0,                             // left trigger
+    0,                             // right trigger
+    0,                             // reserved
+    0,                             // reserved
+];
+
+const _: () = assert!(ORIGIN_RESPONSE[1] & protocol::BUTTONS2_ALWAYS_SET != 0);
+
+/// The longest response to a command other than a poll, the diagnostics stats report.
+/// Also the longest response an [`UnknownCommandBehavior::Respond`] handler can send.
+pub const MAX_RESPONSE_LEN: usize = 20;
+
+/// The longest command an [`UnknownCommandBehavior::Respond`] handler can receive, including the opcode.
+pub const MAX_COMMAND_LEN: usize = 8;
+
+/// A response to a command other than a poll, returned by [`GamecubeController::handle_action`].
+pub(crate) struct Response {
+    bytes: [u8; MAX_RESPONSE_LEN],
+    len: usize,
+}
+
+impl Response {
+    pub(crate) fn new(bytes: &[u8]) -> Response {
+        let mut response = Response {
+            bytes: [0; MAX_RESPONSE_LEN],
+            len: bytes.len(),
         };
+        response.bytes[..bytes.len()].copy_from_slice(bytes);
+        response
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// How [`GamecubeController::try_new_with_retry`] retries the initial handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of commands to wait for before giving up.
+    pub attempts: u32,
+    /// How long to wait for each command in microseconds.
+    pub timeout_us: u64,
+    /// Delay before the first retry in microseconds, doubled for each subsequent retry.
+    pub backoff_us: u32,
+    /// Upper limit for the doubling of `backoff_us`.
+    pub max_backoff_us: u32,
+}
+
+impl RetryPolicy {
+    /// A single attempt with the same timeout as [`GamecubeController::try_new`].
+    pub const SINGLE: RetryPolicy = RetryPolicy {
+        attempts: 1,
+        timeout_us: COMMAND_TIMEOUT_US,
+        backoff_us: 0,
+        max_backoff_us: 0,
+    };
+}
 
-        let (mut pio, sm0, _, _, _) = pio0.split(resets);
-        let installed = pio
-        .install(&program.program)
-        .unwrap()
-        // TODO: do we need this or does rp2040_hal derive it for us?
-        //.set_wrap()
-        ;
-
-        // TODO: this math is a direct port from joybus-pio.
-        //       but with the non-deprecated clock_divisor_fixed_point method the math looks weird but is still equivalent.
-        //       If I can print the values with a debugger I could probably understand it well enough to simplify.
-        let bitrate = 250000;
-        let cycles_per_bit = 10 + 20 + 10;
-        let divisor = clocks.system_clock.freq().to_Hz() as f32 / (cycles_per_bit * bitrate) as f32;
-
-        let (sm, rx, tx) = rp2040_hal::pio::PIOBuilder::from_installed_program(installed)
-            .out_pins(data_pin_num, 1)
-            .set_pins(data_pin_num, 1)
-            .in_pin_base(data_pin_num)
-            // out shift
-            .out_shift_direction(ShiftDirection::Left)
-            .autopull(false)
-            .pull_threshold(9)
-            // in shift
-            .in_shift_direction(ShiftDirection::Left)
-            .autopush(true)
-            .push_threshold(8)
-            .clock_divisor_fixed_point(divisor as u16, (divisor * 256.0) as u8)
-            .build(sm0);
-        let sm = sm.start();
-
-        JoybusPio {
-            tx,
-            rx,
-            sm,
-            data_pin,
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 5,
+            timeout_us: 100_000,
+            backoff_us: 1_000,
+            max_backoff_us: 100_000,
         }
     }
 }
 
-/// A wrapper around [`JoybusPio`] providing a high level interface for acting as a gamecube controller.
-pub struct GamecubeController {
-    pio: JoybusPio,
+/// What was heard on the bus during a failed handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heard {
+    /// No command was received at all, the console is probably off or not connected.
+    Nothing,
+    /// A command that is not part of the gamecube protocol was received,
+    /// the console probably speaks a different joybus protocol. Contains the opcode.
+    UnknownCommand(u8),
+}
+
+/// Why [`GamecubeController::wait_for_poll_start_until`] returned without a poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The deadline passed without a poll arriving.
+    DeadlineExceeded,
+    /// The cancel flag was set.
+    Cancelled,
+    /// The console was powered off.
+    PowerLost,
 }
 
-impl GamecubeController {
+/// Returned by [`GamecubeController::try_new_with_retry`] when the handshake never succeeded.
+pub struct HandshakeError<P: PIOExt = PIO0, I: JoybusPin<P> = Gpio28, S: StateMachineIndex = SM0> {
+    /// The JoybusPort which can be reused.
+    pub port: JoybusPort<P, I, S>,
+    /// How many attempts were made.
+    pub attempts: u32,
+    /// The most informative thing that was heard on the bus across all attempts.
+    pub heard: Heard,
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> GamecubeController<P, I, S> {
     /// Initializes a connection with a gamecube protocol compatible device and
     /// returns a [`GamecubeController`] instance to interact with this connection.
     /// If Err is returned the device is not compatible with the gamecube protocol.
-    /// Err will contain the JoybusPio which can be reused.
+    /// Err will contain the JoybusPort which can be reused.
     pub fn try_new(
-        mut pio: JoybusPio,
+        port: JoybusPort<P, I, S>,
         timer: &Timer,
         delay: &mut Delay,
-    ) -> Result<GamecubeController, JoybusPio> {
-        pio.sm.exec_instruction(Instruction {
-            operands: InstructionOperands::JMP {
-                condition: pio::JmpCondition::Always,
-                address: 0,
-            },
-            delay: 0,
-            side_set: None,
-        });
+    ) -> Result<GamecubeController<P, I, S>, JoybusPort<P, I, S>> {
+        GamecubeController::try_new_with_config(port, timer, delay, JoybusConfig::DEFAULT)
+    }
 
-        let mut controller = GamecubeController { pio };
+    /// Same as [`GamecubeController::try_new`] but retries according to `policy` before giving up.
+    ///
+    /// Unlike `try_new`, receiving an unrecognized command is treated as a failed attempt.
+    /// The returned error describes what was heard on the bus and contains the JoybusPort which can be reused.
+    pub fn try_new_with_retry(
+        port: JoybusPort<P, I, S>,
+        timer: &Timer,
+        delay: &mut Delay,
+        policy: RetryPolicy,
+    ) -> Result<GamecubeController<P, I, S>, HandshakeError<P, I, S>> {
+        GamecubeController::try_new_with_retry_and_config(
+            port,
+            timer,
+            delay,
+            policy,
+            JoybusConfig::DEFAULT,
+        )
+    }
+
+    /// Replace the tuning parameters, see [`config`].
+    pub fn set_config(&mut self, config: JoybusConfig) {
+        self.config = config;
+    }
+}
 
-        match controller.recv(timer).map(GamecubeCommand::from) {
-            Some(GamecubeCommand::Reset) | Some(GamecubeCommand::Probe) => {
-                delay.delay_us(4);
-                controller.send(&[9, 0, 3]);
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource>
+    GamecubeController<P, I, S, C>
+{
+    /// Same as [`GamecubeController::try_new`] but with the tuning parameters from `config`, see [`config`].
+    pub fn try_new_with_config(
+        port: JoybusPort<P, I, S>,
+        timer: &Timer,
+        delay: &mut Delay,
+        config: C,
+    ) -> Result<Self, JoybusPort<P, I, S>> {
+        let mut controller = GamecubeController::from_port(port, config);
+
+        match controller.handshake_attempt(timer, delay, COMMAND_TIMEOUT_US) {
+            Ok(()) | Err(Heard::UnknownCommand(_)) => Ok(controller),
+            Err(Heard::Nothing) => Err(controller.port),
+        }
+    }
+
+    /// Same as [`GamecubeController::try_new_with_retry`] but with the tuning parameters from `config`, see [`config`].
+    pub fn try_new_with_retry_and_config(
+        port: JoybusPort<P, I, S>,
+        timer: &Timer,
+        delay: &mut Delay,
+        policy: RetryPolicy,
+        config: C,
+    ) -> Result<Self, HandshakeError<P, I, S>> {
+        let mut controller = GamecubeController::from_port(port, config);
+
+        let mut heard = Heard::Nothing;
+        let mut backoff_us = policy.backoff_us;
+        for attempt in 0..policy.attempts {
+            if attempt > 0 {
+                delay.delay_us(backoff_us);
+                backoff_us = backoff_us.saturating_mul(2).min(policy.max_backoff_us);
             }
-            Some(GamecubeCommand::Recalibrate) | Some(GamecubeCommand::Origin) => {
-                delay.delay_us(4);
-                // set perfect deadzone, we have no analog sticks
-                // Apparently gc adapter ignores this though and uses the first poll response instead.
-                controller.send(&[
-                    0,           // butons1
-                    0b1000_0000, // butons2
-                    128,         // stick x
-                    128,         // stick y
-                    128,         // cstick x
-                    128,         // cstick y
-                    0,           // left trigger
-                    0,           // right trigger
-                    0,           // reserved
-                    0,           // reserved
-                ]);
+
+            match controller.handshake_attempt(timer, delay, policy.timeout_us) {
+                Ok(()) => return Ok(controller),
+                // keep the most informative result
+                Err(Heard::Nothing) => {}
+                Err(unknown) => heard = unknown,
             }
-            Some(GamecubeCommand::Poll) => {
-                let report = [
-                    0,           // butons1
-                    0b1000_0000, // butons2
-                    128,         // stick x
-                    128,         // stick y
-                    128,         // cstick x
-                    128,         // cstick y
-                    0,           // left trigger
-                    0,           // right trigger
-                ];
-                controller.respond_to_poll_raw(timer, delay, &report);
+            debug!("joybus: handshake attempt {} failed", attempt + 1);
+        }
+
+        warn!(
+            "joybus: handshake failed after {} attempts, heard {:?}",
+            policy.attempts, heard
+        );
+        Err(HandshakeError {
+            port: controller.port,
+            attempts: policy.attempts,
+            heard,
+        })
+    }
+
+    fn from_port(mut port: JoybusPort<P, I, S>, config: C) -> GamecubeController<P, I, S, C> {
+        port.jump(0);
+        let neutral_report =
+            GamecubeInput::NEUTRAL.create_report_for_mode(config.config().poll_mode);
+
+        GamecubeController {
+            port,
+            config,
+            fsm: ProtocolFsm::new(),
+            identity: identity::OEM_CONTROLLER,
+            origin: ORIGIN_RESPONSE,
+            reset_behavior: ResetBehavior::Probe,
+            unknown_command_behavior: UnknownCommandBehavior::Resync,
+            status: StatusFlags::OEM,
+            last_report: neutral_report,
+            stats: ControllerStats::default(),
+            idle_handler: None,
+            last_poll: None,
+            idle: false,
+            cadence: PollCadence::new(),
+            strobe: None,
+            response_mask: None,
+            masked_irqs: MaskedIrqs::default(),
+            response_alignment: None,
+            next_report: neutral_report,
+            role: RoleTracker::new(),
+            diagnostics: None,
+            #[cfg(debug_assertions)]
+            poll_started: None,
+            #[cfg(feature = "jitter")]
+            jitter: None,
+            #[cfg(feature = "busy-meter")]
+            busy_meter: None,
+            #[cfg(feature = "rtt")]
+            rtt: None,
+            #[cfg(feature = "async")]
+            wake_interrupt: None,
+        }
+    }
+
+    /// Waits for a single command and responds to it if it's one we understand.
+    fn handshake_attempt(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        timeout_us: u64,
+    ) -> Result<(), Heard> {
+        match self.recv_timeout(timer, timeout_us) {
+            Some(value) => match self.fsm.on_byte(value) {
+                FsmAction::PollStarted => {
+                    self.mask_response_irqs();
+                    #[cfg(debug_assertions)]
+                    self.start_poll_budget(timer);
+                    let report = self.create_report(&GamecubeInput::NEUTRAL);
+                    self.respond_to_poll_raw(timer, delay, &report);
+                    Ok(())
+                }
+                action @ FsmAction::UnknownCommand(_) => {
+                    self.perform(action, timer, delay);
+                    Err(Heard::UnknownCommand(value))
+                }
+                action => {
+                    self.perform(action, timer, delay);
+                    Ok(())
+                }
+            },
+            None => Err(Heard::Nothing),
+        }
+    }
+
+    /// Waits for the next poll, handling any other commands along the way.
+    ///
+    /// If an [`IdleHandler`] is set it is called when no poll has been answered for its timeout.
+    ///
+    /// Once this returns the interrupts of any [`ResponseMask`] stay masked until the poll is responded to,
+    /// or until the next call to this or [`GamecubeController::wait_for_poll_start_until`] if it never is.
+    pub fn wait_for_poll_start(&mut self, timer: &Timer, delay: &mut Delay) {
+        self.unmask_response_irqs();
+        while let Some(handler) = self.idle_handler.filter(|_| !self.idle) {
+            let since = *self.last_poll.get_or_insert(timer.get_counter());
+            let deadline = since + MicrosDurationU64::micros(handler.timeout_us);
+            if self
+                .wait_for_poll_start_until(timer, delay, deadline, None)
+                .is_ok()
+            {
+                return;
             }
-            Some(GamecubeCommand::Unknown) => {
-                delay.delay_us(130);
-                controller.restart_sm_for_read();
+
+            debug!("joybus: console idle");
+            self.idle = true;
+            if (handler.callback)() == IdleAction::Reprobe {
+                self.origin = self.identity.origin;
+                self.restart_sm_for_read(timer);
             }
-            None => return Err(controller.pio),
         }
 
-        Ok(controller)
+        loop {
+            let action = match self.recv_command_byte(timer) {
+                Some(value) => self.fsm.on_byte(value),
+                None => self.fsm.on_timeout(),
+            };
+            match action {
+                FsmAction::PollStarted => {
+                    self.mask_response_irqs();
+                    #[cfg(debug_assertions)]
+                    self.start_poll_budget(timer);
+                    return;
+                }
+                action => self.perform(action, timer, delay),
+            }
+        }
     }
 
-    pub fn wait_for_poll_start(&mut self, timer: &Timer, delay: &mut Delay) {
+    /// Same as [`GamecubeController::wait_for_poll_start`] but gives up once `deadline` passes
+    /// or `cancel` is set, e.g. from an interrupt handler.
+    /// This allows the caller to go to sleep or re-probe when the console stops polling.
+    ///
+    /// `cancel` is checked at least every [`CANCEL_CHECK_INTERVAL_US`].
+    /// Interrupts are only masked when this returns Ok, see [`GamecubeController::wait_for_poll_start`].
+    pub fn wait_for_poll_start_until(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        deadline: Instant,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<(), WaitError> {
+        // close the window of a poll the caller didn't respond to
+        self.unmask_response_irqs();
         loop {
-            match self.recv(timer).map(GamecubeCommand::from) {
-                Some(GamecubeCommand::Reset) | Some(GamecubeCommand::Probe) => {
-                    delay.delay_us(4);
-                    self.send(&[9, 0, 3]);
+            if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                return Err(WaitError::Cancelled);
+            }
+            let remaining = deadline
+                .checked_duration_since(timer.get_counter())
+                .ok_or(WaitError::DeadlineExceeded)?;
+
+            let timeout_us = remaining.ticks().min(CANCEL_CHECK_INTERVAL_US);
+            let action = match self.recv_timeout(timer, timeout_us) {
+                Some(value) => self.fsm.on_byte(value),
+                // nothing is happening on the bus, go check the deadline and cancel flag again
+                None if self.fsm.is_idle() => continue,
+                None => self.fsm.on_timeout(),
+            };
+            match action {
+                FsmAction::PollStarted => {
+                    self.mask_response_irqs();
+                    #[cfg(debug_assertions)]
+                    self.start_poll_budget(timer);
+                    return Ok(());
                 }
-                Some(GamecubeCommand::Recalibrate) | Some(GamecubeCommand::Origin) => {
-                    delay.delay_us(4);
-                    // set perfect deadzone, we have no analog sticks
-                    // Apparently gc adapter ignores this though and uses the first poll response instead.
-                    self.send(&[
-                        0,   // butons1
-                        1,   // butons2
-                        128, // stick x
-                        128, // stick y
-                        128, // cstick x
-                        128, // cstick y
-                        0,   // left trigger
-                        0,   // right trigger
-                        0,   // reserved
-                        0,   // reserved
-                    ]);
+                action => self.perform(action, timer, delay),
+            }
+        }
+    }
+
+    /// Same as [`GamecubeController::wait_for_poll_start`] but gated on console power as reported by `power`.
+    ///
+    /// Returns [`WaitError::PowerLost`] when the console powers off.
+    /// While the console is off this blocks until it powers back on, at which point the state machine is restarted
+    /// and `capture_origin` is called to sample the neutral inputs, just like an OEM controller does when plugged in.
+    pub fn wait_for_poll_start_powered<V: InputPin>(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        power: &mut PowerSense<V>,
+        mut capture_origin: impl FnMut() -> GamecubeInput,
+    ) -> Result<(), WaitError> {
+        loop {
+            match power.update() {
+                Some(PowerEvent::PoweredOff) => {
+                    debug!("joybus: console powered off");
+                    return Err(WaitError::PowerLost);
                 }
-                Some(GamecubeCommand::Poll) => {
-                    return;
+                Some(PowerEvent::PoweredOn) => {
+                    debug!("joybus: console powered on");
+                    self.restart_sm_for_read(timer);
+                    self.set_origin(&capture_origin());
                 }
-                Some(GamecubeCommand::Unknown) | None => {
-                    delay.delay_us(130);
-                    self.restart_sm_for_read();
+                None => {}
+            }
+            if !power.is_powered() {
+                continue;
+            }
+
+            let deadline =
+                timer.get_counter() + MicrosDurationU64::micros(CANCEL_CHECK_INTERVAL_US);
+            match self.wait_for_poll_start_until(timer, delay, deadline, None) {
+                Ok(()) => return Ok(()),
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// Call `handler` when the console stops polling, or None to never call it.
+    pub fn set_idle_handler(&mut self, handler: Option<IdleHandler>) {
+        self.idle_handler = handler;
+    }
+
+    /// Toggle a pin every time a poll is answered, or None to stop.
+    /// The pin toggles once the full poll command has been received, right before the response is sent.
+    pub fn set_poll_strobe(&mut self, strobe: Option<PollStrobe>) {
+        self.strobe = strobe;
+    }
+
+    /// Hold off the interrupts in `mask`, e.g. [`ResponseMask::USB`], while a response is on its way out, or None to never mask any.
+    /// See [`ResponseMask`] for exactly when.
+    pub fn set_response_mask(&mut self, mask: Option<ResponseMask>) {
+        self.unmask_response_irqs();
+        self.response_mask = mask;
+    }
+
+    /// Start every response a fixed number of cycles after the command it answers, or None to respond as soon as possible.
+    /// See [`ResponseAlignment`].
+    pub fn set_response_alignment(&mut self, alignment: Option<ResponseAlignment>) {
+        self.response_alignment = alignment;
+    }
+
+    /// The alignment set by [`GamecubeController::set_response_alignment`], for reading its counts.
+    pub fn response_alignment(&self) -> Option<&ResponseAlignment> {
+        self.response_alignment.as_ref()
+    }
+
+    /// Configure what happens when the console sends a reset command, see [`ResetBehavior`].
+    pub fn set_reset_behavior(&mut self, behavior: ResetBehavior) {
+        self.reset_behavior = behavior;
+    }
+
+    /// Configure what happens when the console sends a command the controller doesn't know, see [`UnknownCommandBehavior`].
+    /// This doesn't apply to the diagnostics opcodes once [`GamecubeController::set_diagnostics`] enables them.
+    pub fn set_unknown_command_behavior(&mut self, behavior: UnknownCommandBehavior) {
+        self.unknown_command_behavior = behavior;
+    }
+
+    /// Identify as `identity` to the console from the next probe on, e.g. a [`DevicePreset`], see [`crate::identity`].
+    /// Its origin replaces the current one and becomes the default origin restored by resets and re-probes.
+    pub fn set_identity(&mut self, identity: &DeviceIdentity) {
+        self.identity = *identity;
+        self.origin = identity.origin;
+    }
+
+    /// The identity set by [`GamecubeController::set_identity`], [`crate::identity::OEM_CONTROLLER`] by default.
+    pub fn identity(&self) -> &DeviceIdentity {
+        &self.identity
+    }
+
+    /// Send `flags` in every poll response from now on, [`StatusFlags::OEM`] by default.
+    /// This doesn't apply to reports passed to [`GamecubeController::respond_to_poll_raw`].
+    pub fn set_status_flags(&mut self, flags: StatusFlags) {
+        self.status = flags;
+    }
+
+    pub fn status_flags(&self) -> StatusFlags {
+        self.status
+    }
+
+    /// Set [`StatusFlags::origin_request`] until the console next reads the origin,
+    /// e.g. after [`GamecubeController::set_origin`] or a reset so that the console picks up the new origin.
+    pub fn request_origin(&mut self) {
+        self.status.origin_request = true;
+    }
+
+    /// Use the sticks and triggers of `input` as the origin sent in response to origin commands.
+    /// Buttons in `input` are ignored.
+    pub fn set_origin(&mut self, input: &GamecubeInput) {
+        self.origin = [
+            0,                             // butons1
+            protocol::BUTTONS2_ALWAYS_SET, // butons2
+            input.stick_x,
+            input.stick_y,
+            input.cstick_x,
+            input.cstick_y,
+            input.l_analog,
+            input.r_analog,
+            0, // reserved
+            0, // reserved
+        ];
+    }
+
+    /// Carry out the IO for an action emitted by the [`ProtocolFsm`].
+    fn perform(&mut self, action: FsmAction, timer: &Timer, delay: &mut Delay) {
+        if let Some(response) = self.handle_action(action, timer) {
+            delay.delay_us(self.config().reply_delay_us);
+            self.send(response.as_bytes());
+        }
+    }
+
+    /// Update the controller for an action emitted by the [`ProtocolFsm`],
+    /// returning the response to send once the console is ready for it.
+    pub(crate) fn handle_action(&mut self, action: FsmAction, timer: &Timer) -> Option<Response> {
+        trace!("joybus: {:?}", action);
+        if !matches!(action, FsmAction::Wait | FsmAction::Resync) {
+            self.stats.last_command_us = Some(timer.get_counter().ticks());
+        }
+        match action {
+            FsmAction::RespondId => {
+                self.stats.probes += 1;
+                Some(Response::new(&self.identity.id))
+            }
+            FsmAction::Reset => {
+                self.stats.probes += 1;
+                match self.reset_behavior {
+                    ResetBehavior::Probe => {}
+                    ResetBehavior::RestoreDefaultOrigin => self.origin = self.identity.origin,
+                    ResetBehavior::Callback(callback) => {
+                        #[cfg(debug_assertions)]
+                        let start = timer.get_counter();
+                        callback();
+                        #[cfg(debug_assertions)]
+                        self.check_budget(timer, start, CALLBACK_BUDGET_US, "reset callback");
+                    }
                 }
+                Some(Response::new(&self.identity.id))
             }
+            FsmAction::RespondOrigin => {
+                self.stats.origins += 1;
+                self.status.origin_request = false;
+                Some(Response::new(&self.origin))
+            }
+            FsmAction::Recalibrate => {
+                self.stats.origins += 1;
+                self.status.origin_request = false;
+                // Like an OEM controller, treat whatever the sticks and triggers are doing right now as neutral.
+                self.set_origin(&GamecubeInput::from_report_for_mode(
+                    self.config().poll_mode,
+                    &self.last_report,
+                ));
+                Some(Response::new(&self.origin))
+            }
+            FsmAction::RespondDiagnostics(opcode) => self.diagnostics_response(opcode),
+            FsmAction::UnknownCommand(opcode) => self.unknown_command(opcode, timer),
+            FsmAction::Resync => {
+                debug!("joybus: resyncing");
+                self.stats.resyncs += 1;
+                self.restart_sm_for_read(timer);
+                None
+            }
+            // Poll responses need a report so are handled by the caller.
+            FsmAction::Wait | FsmAction::PollStarted | FsmAction::RespondPoll { .. } => None,
         }
     }
 
-    pub fn restart_sm_for_read(&mut self) {
-        self.pio.sm.clear_fifos(); // TODO: this should probably occur inside the restart
-        self.pio.sm.restart();
+    /// Handle a command the [`ProtocolFsm`] doesn't know as configured by [`UnknownCommandBehavior`],
+    /// returning the response to send if there is one.
+    fn unknown_command(&mut self, opcode: u8, timer: &Timer) -> Option<Response> {
+        debug!("joybus: unknown command {}", opcode);
+        self.stats.unknown_commands = self.stats.unknown_commands.saturating_add(1);
+        match self.unknown_command_behavior {
+            UnknownCommandBehavior::Resync => {}
+            UnknownCommandBehavior::Ignore => return None,
+            UnknownCommandBehavior::Callback(callback) => {
+                #[cfg(debug_assertions)]
+                let start = timer.get_counter();
+                callback(opcode);
+                #[cfg(debug_assertions)]
+                self.check_budget(timer, start, CALLBACK_BUDGET_US, "unknown command callback");
+            }
+            UnknownCommandBehavior::Respond { args, handler } => {
+                let mut command = [0; MAX_COMMAND_LEN];
+                command[0] = opcode;
+                let len = 1 + args.min(MAX_COMMAND_LEN - 1);
+                let mut received = 1;
+                while received < len {
+                    let Some(value) = self.recv_timeout(timer, FRAME_GAP_US) else {
+                        break;
+                    };
+                    command[received] = value;
+                    received += 1;
+                }
+                if received == len {
+                    let mut response = [0; MAX_RESPONSE_LEN];
+                    #[cfg(debug_assertions)]
+                    let start = timer.get_counter();
+                    let response_len =
+                        handler(&command[..len], &mut response).min(MAX_RESPONSE_LEN);
+                    #[cfg(debug_assertions)]
+                    self.check_budget(timer, start, CALLBACK_BUDGET_US, "unknown command handler");
+                    if response_len > 0 {
+                        return Some(Response::new(&response[..response_len]));
+                    }
+                }
+            }
+        }
+        self.handle_action(FsmAction::Resync, timer)
+    }
+
+    /// Restart the state machine into the read routine, discarding any partially received command.
+    /// See [`JoybusPort::restart_for_read`].
+    pub fn restart_sm_for_read(&mut self, timer: &Timer) {
+        self.fsm.reset();
+        self.port.restart_for_read(timer);
     }
 
     pub fn restart_sm_for_write(&mut self) {
-        self.pio.sm.clear_fifos(); // TODO: this should probably occur inside the restart
-        self.pio.sm.restart();
-        self.pio.sm.exec_instruction(Instruction {
-            operands: InstructionOperands::JMP {
-                condition: pio::JmpCondition::Always,
-                address: 5,
-            },
-            delay: 0,
-            side_set: None,
-        });
+        self.port.restart_for_write();
     }
 
     pub fn respond_to_poll(&mut self, timer: &Timer, delay: &mut Delay, input: GamecubeInput) {
-        self.respond_to_poll_raw(timer, delay, &input.create_report());
+        let report = self.create_report(&input);
+        self.respond_to_poll_raw(timer, delay, &report);
     }
 
-    pub fn respond_to_poll_raw(&mut self, timer: &Timer, delay: &mut Delay, report: &[u8]) {
-        delay.delay_us(40);
-
-        self.recv(timer);
-        self.recv(timer);
-        delay.delay_us(4);
+    /// Respond to a poll with whatever report is staged at the moment the response starts.
+    /// The report is copied out of `staging` once right before sending, so it is safe for the main loop to call
+    /// [`ReportStaging::set_next_report`] while this is running in an interrupt.
+    pub fn respond_to_poll_staged(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        staging: &ReportStaging,
+    ) {
+        if self.finish_poll_command(timer, delay).is_some() {
+            let report = staging.next_report();
+            self.send(&report);
+            self.last_report = report;
+        }
+    }
 
-        self.send(report);
+    /// Respond to a poll with the input from [`InputDelay::delay`] polls ago, and record `input` to be sent later.
+    pub fn respond_to_poll_delayed<const N: usize>(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        input_delay: &mut InputDelay<N>,
+        input: GamecubeInput,
+    ) {
+        let report = self.create_report(&input);
+        if self.finish_poll_command(timer, delay).is_some() {
+            let report = input_delay.push_report(report);
+            self.send(&report);
+            self.last_report = report;
+        }
     }
 
-    pub fn recv(&mut self, timer: &Timer) -> Option<u8> {
-        let instant = timer.get_counter();
+    pub fn respond_to_poll_raw(&mut self, timer: &Timer, delay: &mut Delay, report: &[u8]) {
+        if self.finish_poll_command(timer, delay).is_some() {
+            self.send(report);
+            if let Ok(report) = report.try_into() {
+                self.last_report = report;
+            }
+        }
+    }
 
+    /// Waits for the next poll, handling any other commands along the way, then responds with the input returned by `sample_input`.
+    ///
+    /// This combines [`GamecubeController::wait_for_poll_start`] and [`GamecubeController::respond_to_poll`] for superloop firmware.
+    /// `sample_input` is called as soon as the poll starts, so inputs are as fresh as possible.
+    /// If you already have an input just pass `|| input`.
+    pub fn poll_blocking(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        mut sample_input: impl FnMut() -> GamecubeInput,
+    ) -> PollOutcome {
+        let start = timer.get_counter();
         loop {
-            match self.pio.rx.read() {
-                Some(value) => return Some(value as u8),
-                None => {
-                    if timer
+            self.wait_for_poll_start(timer, delay);
+            let report = self.create_report(&sample_input());
+            if let Some((mode, rumble)) = self.finish_poll_command(timer, delay) {
+                self.send(&report);
+                self.last_report = report;
+                return PollOutcome {
+                    mode,
+                    rumble,
+                    waited_us: timer
                         .get_counter()
-                        .checked_duration_since(instant)
+                        .checked_duration_since(start)
                         .unwrap()
-                        .ticks()
-                        // TODO: high value used for testing
-                        > 2000000
-                    {
-                        return None;
-                    }
+                        .ticks(),
+                    stats: self.stats,
+                };
+            }
+        }
+    }
+
+    /// The report sent in response to the most recent poll.
+    pub fn last_report(&self) -> [u8; 8] {
+        self.last_report
+    }
+
+    /// Counters of everything this controller has handled so far.
+    pub fn stats(&self) -> ControllerStats {
+        self.stats
+    }
+
+    /// The console's estimated poll rate and phase, updated every time a poll is answered.
+    pub fn cadence(&self) -> &PollCadence {
+        &self.cadence
+    }
+
+    /// Start measuring response times with `probe`, or stop measuring if None.
+    #[cfg(feature = "jitter")]
+    pub fn set_jitter_probe(&mut self, probe: Option<JitterProbe>) {
+        self.jitter = probe;
+    }
+
+    /// The probe set by [`GamecubeController::set_jitter_probe`], for reading or resetting its stats.
+    #[cfg(feature = "jitter")]
+    pub fn jitter_probe(&mut self) -> Option<&mut JitterProbe> {
+        self.jitter.as_mut()
+    }
+
+    /// Start counting the cycles spent busy waiting on the bus with `meter`, or stop counting if None.
+    #[cfg(feature = "busy-meter")]
+    pub fn set_busy_meter(&mut self, meter: Option<BusyMeter>) {
+        self.busy_meter = meter;
+    }
+
+    /// The meter set by [`GamecubeController::set_busy_meter`], for taking its counts.
+    #[cfg(feature = "busy-meter")]
+    pub fn busy_meter(&mut self) -> Option<&mut BusyMeter> {
+        self.busy_meter.as_mut()
+    }
+
+    /// Note when a poll started, for [`GamecubeController::finish_poll_command`] to check against [`POLL_BUDGET_US`].
+    #[cfg(debug_assertions)]
+    pub(crate) fn start_poll_budget(&mut self, timer: &Timer) {
+        self.poll_started = Some(timer.get_counter());
+    }
+
+    /// Count and warn about an overrun if more than `budget_us` has passed since `start`.
+    #[cfg(debug_assertions)]
+    fn check_budget(&mut self, timer: &Timer, start: Instant, budget_us: u64, what: &str) {
+        if budget::check(timer, start, budget_us, what) {
+            self.stats.budget_overruns = self.stats.budget_overruns.saturating_add(1);
+        }
+    }
+
+    /// Stream timing events to `trace`, or stop streaming if None.
+    #[cfg(feature = "rtt")]
+    pub fn set_rtt_trace(&mut self, trace: Option<RttTrace>) {
+        self.rtt = trace;
+    }
+
+    /// The trace set by [`GamecubeController::set_rtt_trace`], for reading how many events were dropped.
+    #[cfg(feature = "rtt")]
+    pub fn rtt_trace(&mut self) -> Option<&mut RttTrace> {
+        self.rtt.as_mut()
+    }
+
+    /// Run `wait`, counting the time it takes as busy if a [`BusyMeter`] is set.
+    /// Nested calls are only counted once.
+    #[inline(always)]
+    fn busy_wait<T>(&mut self, wait: impl FnOnce(&mut Self) -> T) -> T {
+        #[cfg(feature = "busy-meter")]
+        if self.busy_meter.as_mut().is_some_and(|meter| meter.enter()) {
+            let result = wait(self);
+            if let Some(meter) = &mut self.busy_meter {
+                meter.exit();
+            }
+            return result;
+        }
+        wait(self)
+    }
+
+    /// Receive the rest of a poll command after [`GamecubeController::wait_for_poll_start`] returned.
+    /// Returns the poll mode and rumble state if the poll completed and the response should now be sent.
+    fn finish_poll_command(&mut self, timer: &Timer, delay: &mut Delay) -> Option<(u8, bool)> {
+        #[cfg(debug_assertions)]
+        self.end_poll_budget(timer);
+        delay.delay_us(budget::POLL_FINISH_DELAY_US);
+
+        match self.recv_poll_args(timer) {
+            Ok(poll) => {
+                delay.delay_us(self.config().reply_delay_us);
+                Some(poll)
+            }
+            Err(action) => {
+                self.unmask_response_irqs();
+                self.perform(action, timer, delay);
+                None
+            }
+        }
+    }
+
+    /// Mask the interrupts of the [`ResponseMask`], if there is one, until [`GamecubeController::unmask_response_irqs`].
+    fn mask_response_irqs(&mut self) {
+        if let Some(mask) = &self.response_mask {
+            self.masked_irqs.add(mask);
+        }
+    }
+
+    fn unmask_response_irqs(&mut self) {
+        self.masked_irqs.restore();
+    }
+
+    /// Check the time since the poll started against [`POLL_BUDGET_US`].
+    #[cfg(debug_assertions)]
+    pub(crate) fn end_poll_budget(&mut self, timer: &Timer) {
+        if let Some(start) = self.poll_started.take() {
+            self.check_budget(
+                timer,
+                start,
+                POLL_BUDGET_US,
+                "code run after the poll started",
+            );
+        }
+    }
+
+    /// Receive the argument bytes of a poll, returning its mode and rumble state.
+    /// If something other than the rest of the poll happens, returns the action to perform instead.
+    pub(crate) fn recv_poll_args(&mut self, timer: &Timer) -> Result<(u8, bool), FsmAction> {
+        loop {
+            let action = match self.recv(timer) {
+                Some(value) => self.fsm.on_byte(value),
+                None => self.fsm.on_timeout(),
+            };
+            match action {
+                FsmAction::Wait | FsmAction::PollStarted => {}
+                FsmAction::RespondPoll { mode, rumble } => {
+                    self.record_poll(timer, mode, rumble);
+                    return Ok((mode, rumble));
                 }
+                action => return Err(action),
             }
         }
     }
 
-    pub fn send(&mut self, values: &[u8]) {
-        // wait for line to be high
-        while self.pio.data_pin.as_input().is_low().unwrap() {}
+    /// Update the stats, strobe and cadence for a poll that is about to be responded to.
+    pub(crate) fn record_poll(&mut self, timer: &Timer, mode: u8, rumble: bool) {
+        trace!("joybus: poll mode {} rumble {}", mode, rumble);
+        self.stats.polls += 1;
+        if let Some(strobe) = &self.strobe {
+            strobe.toggle();
+        }
+        let now = timer.get_counter();
+        self.stats.last_command_us = Some(now.ticks());
+        self.stats.last_poll_us = Some(now.ticks());
+        self.cadence.record_poll(now.ticks());
+        self.last_poll = Some(now);
+        self.idle = false;
+    }
 
-        self.restart_sm_for_write();
+    pub fn recv(&mut self, timer: &Timer) -> Option<u8> {
+        self.recv_timeout(timer, self.config().recv_timeout_us)
+    }
 
-        for (i, value) in values.iter().enumerate() {
-            let stop = if i == values.len() - 1 { 1 } else { 0 };
-            let word = ((*value as u32) << 24) | ((stop as u32) << 23);
+    /// Receive the next byte of a command, or wait up to [`COMMAND_TIMEOUT_US`] for a new one to start.
+    fn recv_command_byte(&mut self, timer: &Timer) -> Option<u8> {
+        if self.fsm.is_idle() {
+            self.recv_timeout(timer, COMMAND_TIMEOUT_US)
+        } else {
+            self.recv(timer)
+        }
+    }
+
+    /// Receive a single byte, returning None if nothing arrives within `timeout_us` microseconds.
+    pub fn recv_timeout(&mut self, timer: &Timer, timeout_us: u64) -> Option<u8> {
+        self.busy_wait(|this| this.recv_timeout_inner(timer, timeout_us))
+    }
+
+    fn recv_timeout_inner(&mut self, timer: &Timer, timeout_us: u64) -> Option<u8> {
+        let value = self.port.recv_byte(timer, timeout_us)?;
+        self.byte_received(value);
+        Some(value)
+    }
 
-            while self.pio.tx.is_full() {}
-            self.pio.tx.write(word);
+    /// Receive a single byte without a [`Timer`], returning None once the line has been idle
+    /// for the timeout set by [`GamecubeController::set_idle_timeout`], see [`JoybusPort::recv_byte_until_idle`].
+    pub fn recv_until_idle(&mut self) -> Option<u8> {
+        self.busy_wait(|this| {
+            let value = this.port.recv_byte_until_idle()?;
+            this.byte_received(value);
+            Some(value)
+        })
+    }
+
+    /// Set the timeout counted by the state machine for [`GamecubeController::recv_until_idle`], see [`JoybusPort::set_idle_timeout`].
+    pub fn set_idle_timeout(&mut self, timeout_us: Option<u32>) {
+        self.port.set_idle_timeout(timeout_us);
+    }
+
+    fn byte_received(&mut self, value: u8) {
+        if let Some(alignment) = &mut self.response_alignment {
+            alignment.byte_received();
+        }
+        #[cfg(feature = "jitter")]
+        if let Some(jitter) = &mut self.jitter {
+            jitter.byte_received();
         }
+        #[cfg(feature = "rtt")]
+        if let Some(rtt) = &mut self.rtt {
+            rtt.event(TraceEvent::ByteReceived, value);
+        }
+        #[cfg(not(feature = "rtt"))]
+        let _ = value;
     }
-}
 
-enum GamecubeCommand {
-    Probe = 0x00,
-    Poll = 0x40,
-    Origin = 0x41,
-    Recalibrate = 0x42,
-    Reset = 0xFF,
-    Unknown,
+    /// Queue `values` for transmission, the last byte is followed by a stop bit.
+    ///
+    /// This returns as soon as the last byte is in the TX FIFO, which is well before it is on the wire.
+    /// Use [`GamecubeController::flush`] to wait for the stop bit to finish.
+    ///
+    /// If the TX FIFO underruns the response is abandoned and the controller goes back to waiting for a command,
+    /// counted in [`ControllerStats::tx_underruns`].
+    pub fn send(&mut self, values: &[u8]) {
+        self.mask_response_irqs();
+        self.busy_wait(|this| {
+            if let Some(alignment) = &mut this.response_alignment {
+                alignment.wait();
+            }
+            #[cfg(feature = "jitter")]
+            let jitter = &mut this.jitter;
+            #[cfg(feature = "rtt")]
+            let rtt = &mut this.rtt;
+            let sent = this.port.send_frame_then(values, || {
+                #[cfg(feature = "jitter")]
+                if let Some(jitter) = jitter {
+                    jitter.response_started();
+                }
+                #[cfg(feature = "rtt")]
+                if let Some(rtt) = rtt {
+                    rtt.event(
+                        TraceEvent::ResponseStarted,
+                        values.first().copied().unwrap_or(0),
+                    );
+                }
+            });
+            if !sent {
+                // the console saw a broken response, so start over listening for its next command
+                this.stats.tx_underruns = this.stats.tx_underruns.saturating_add(1);
+                this.fsm.reset();
+            } else {
+                #[cfg(feature = "rtt")]
+                if let Some(rtt) = &mut this.rtt {
+                    rtt.event(TraceEvent::ResponseQueued, values.len() as u8);
+                }
+            }
+        });
+        self.unmask_response_irqs();
+    }
+
+    /// Returns true once everything queued by [`GamecubeController::send`] including the stop bit has been transmitted.
+    pub fn is_send_complete(&self) -> bool {
+        self.port.is_send_complete()
+    }
+
+    /// Blocks until everything queued by [`GamecubeController::send`] including the stop bit has been transmitted.
+    pub fn flush(&mut self) {
+        self.busy_wait(|this| this.port.flush())
+    }
+
+    /// Set the input that [`JoybusRole::service`] responds to polls with.
+    pub fn set_input(&mut self, input: &GamecubeInput) {
+        self.next_report = self.create_report(input);
+    }
+
+    /// The tuning parameters in use, see [`config`].
+    #[inline(always)]
+    pub fn config(&self) -> JoybusConfig {
+        self.config.config()
+    }
+
+    /// `input` laid out in the configured [`JoybusConfig::poll_mode`].
+    pub(crate) fn create_report(&self, input: &GamecubeInput) -> [u8; 8] {
+        self.status
+            .apply(input.create_report_for_mode(self.config().poll_mode))
+    }
 }
 
-impl GamecubeCommand {
-    fn from(value: u8) -> Self {
-        match value {
-            0x00 => GamecubeCommand::Probe,
-            0xFF => GamecubeCommand::Reset,
-            0x41 => GamecubeCommand::Origin,
-            0x42 => GamecubeCommand::Recalibrate,
-            0x40 => GamecubeCommand::Poll,
-            _ => GamecubeCommand::Unknown,
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource> JoybusRole
+    for GamecubeController<P, I, S, C>
+{
+    fn service(&mut self, timer: &Timer, delay: &mut Delay, timeout_us: u64) -> ServiceOutcome {
+        let deadline = timer.get_counter() + MicrosDurationU64::micros(timeout_us);
+        let polled = self
+            .wait_for_poll_start_until(timer, delay, deadline, None)
+            .is_ok()
+            && self.finish_poll_command(timer, delay).is_some();
+        if polled {
+            let report = self.status.apply(self.next_report);
+            self.send(&report);
+            self.last_report = report;
         }
+        self.role.record(polled)
+    }
+
+    fn state(&self) -> RoleState {
+        self.role.state
+    }
+
+    fn stats(&self) -> RoleStats {
+        self.role.stats
     }
 }
 
 /// Specify the button and stick inputs to be provided to a gamecube compatible device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct GamecubeInput {
     pub start: bool,
     pub a: bool,
@@ -392,34 +1409,235 @@ pub struct GamecubeInput {
 }
 
 impl GamecubeInput {
-    fn create_report(&self) -> [u8; 8] {
-        #[rustfmt::skip]
-        let buttons1 =
-              if self.a     { 0b0000_0001 } else { 0 }
-            | if self.b     { 0b0000_0010 } else { 0 }
-            | if self.x     { 0b0000_0100 } else { 0 }
-            | if self.y     { 0b0000_1000 } else { 0 }
-            | if self.start { 0b0001_0000 } else { 0 };
-
-        #[rustfmt::skip]
-        let buttons2 = 0b1000_0000
-            | if self.dpad_left  { 0b0000_0001 } else { 0 }
-            | if self.dpad_right { 0b0000_0010 } else { 0 }
-            | if self.dpad_down  { 0b0000_0100 } else { 0 }
-            | if self.dpad_up    { 0b0000_1000 } else { 0 }
-            | if self.z          { 0b0001_0000 } else { 0 }
-            | if self.r_digital  { 0b0010_0000 } else { 0 }
-            | if self.l_digital  { 0b0100_0000 } else { 0 };
+    /// All buttons released, sticks centered and triggers fully released.
+    pub const NEUTRAL: GamecubeInput = GamecubeInput {
+        start: false,
+        a: false,
+        b: false,
+        x: false,
+        y: false,
+        z: false,
+        dpad_up: false,
+        dpad_down: false,
+        dpad_left: false,
+        dpad_right: false,
+        l_digital: false,
+        r_digital: false,
+        stick_x: 128,
+        stick_y: 128,
+        cstick_x: 128,
+        cstick_y: 128,
+        l_analog: 0,
+        r_analog: 0,
+    };
+
+    /// Decode a poll response report, the inverse of what is sent by [`GamecubeController::respond_to_poll`].
+    pub fn from_report(report: &[u8; 8]) -> GamecubeInput {
+        GamecubeInput::from_mode3(&PollReportMode3::decode(report))
+    }
+
+    /// The buttons pressed in this input.
+    pub const fn buttons(&self) -> Buttons {
+        Buttons {
+            start: self.start,
+            a: self.a,
+            b: self.b,
+            x: self.x,
+            y: self.y,
+            z: self.z,
+            dpad_up: self.dpad_up,
+            dpad_down: self.dpad_down,
+            dpad_left: self.dpad_left,
+            dpad_right: self.dpad_right,
+            l_digital: self.l_digital,
+            r_digital: self.r_digital,
+        }
+    }
 
+    /// This input with every button set to its state in `buttons`.
+    pub const fn with_buttons(self, buttons: Buttons) -> GamecubeInput {
+        GamecubeInput {
+            start: buttons.start,
+            a: buttons.a,
+            b: buttons.b,
+            x: buttons.x,
+            y: buttons.y,
+            z: buttons.z,
+            dpad_up: buttons.dpad_up,
+            dpad_down: buttons.dpad_down,
+            dpad_left: buttons.dpad_left,
+            dpad_right: buttons.dpad_right,
+            l_digital: buttons.l_digital,
+            r_digital: buttons.r_digital,
+            ..self
+        }
+    }
+
+    /// This input with the buttons set in `mask` replaced by their state in `values`, see [`Buttons::overridden`].
+    pub const fn override_buttons(self, mask: &Buttons, values: &Buttons) -> GamecubeInput {
+        self.with_buttons(self.buttons().overridden(mask, values))
+    }
+
+    pub const fn with_stick(self, x: u8, y: u8) -> GamecubeInput {
+        GamecubeInput {
+            stick_x: x,
+            stick_y: y,
+            ..self
+        }
+    }
+
+    pub const fn with_cstick(self, x: u8, y: u8) -> GamecubeInput {
+        GamecubeInput {
+            cstick_x: x,
+            cstick_y: y,
+            ..self
+        }
+    }
+
+    pub const fn with_triggers(self, l: u8, r: u8) -> GamecubeInput {
+        GamecubeInput {
+            l_analog: l,
+            r_analog: r,
+            ..self
+        }
+    }
+
+    /// Combine two layered input sources, e.g. physical buttons and a macro.
+    ///
+    /// A button is pressed if it is pressed in either input.
+    /// Each stick axis takes whichever value is further from center and each trigger whichever is pressed further,
+    /// so a source that leaves an input neutral never masks the other source.
+    pub const fn merge(&self, other: &GamecubeInput) -> GamecubeInput {
+        const fn furthest_from_center(a: u8, b: u8) -> u8 {
+            if a.abs_diff(128) >= b.abs_diff(128) {
+                a
+            } else {
+                b
+            }
+        }
+        const fn max(a: u8, b: u8) -> u8 {
+            if a >= b {
+                a
+            } else {
+                b
+            }
+        }
+        GamecubeInput {
+            stick_x: furthest_from_center(self.stick_x, other.stick_x),
+            stick_y: furthest_from_center(self.stick_y, other.stick_y),
+            cstick_x: furthest_from_center(self.cstick_x, other.cstick_x),
+            cstick_y: furthest_from_center(self.cstick_y, other.cstick_y),
+            l_analog: max(self.l_analog, other.l_analog),
+            r_analog: max(self.r_analog, other.r_analog),
+            ..self.with_buttons(self.buttons().union(&other.buttons()))
+        }
+    }
+
+    /// This input as a mode 3 poll response, the format used by [`GamecubeController::respond_to_poll`].
+    pub const fn to_mode3(&self) -> PollReportMode3 {
+        PollReportMode3 {
+            buttons: self.buttons(),
+            stick_x: self.stick_x,
+            stick_y: self.stick_y,
+            cstick_x: self.cstick_x,
+            cstick_y: self.cstick_y,
+            l_analog: self.l_analog,
+            r_analog: self.r_analog,
+        }
+    }
+
+    pub const fn from_mode3(report: &PollReportMode3) -> GamecubeInput {
+        let buttons = report.buttons;
+        GamecubeInput {
+            start: buttons.start,
+            a: buttons.a,
+            b: buttons.b,
+            x: buttons.x,
+            y: buttons.y,
+            z: buttons.z,
+            dpad_up: buttons.dpad_up,
+            dpad_down: buttons.dpad_down,
+            dpad_left: buttons.dpad_left,
+            dpad_right: buttons.dpad_right,
+            l_digital: buttons.l_digital,
+            r_digital: buttons.r_digital,
+            stick_x: report.stick_x,
+            stick_y: report.stick_y,
+            cstick_x: report.cstick_x,
+            cstick_y: report.cstick_y,
+            l_analog: report.l_analog,
+            r_analog: report.r_analog,
+        }
+    }
+
+    pub(crate) fn create_report(&self) -> [u8; 8] {
+        self.to_mode3().encode()
+    }
+
+    /// This input as a poll response for poll `mode`, see [`report`].
+    pub(crate) const fn create_report_for_mode(&self, mode: u8) -> [u8; 8] {
+        let [buttons1, buttons2] = self.buttons().encode();
+        let analog = encode_analog(
+            mode,
+            &AnalogValues {
+                cstick_x: self.cstick_x,
+                cstick_y: self.cstick_y,
+                l_analog: self.l_analog,
+                r_analog: self.r_analog,
+                a_analog: 0,
+                b_analog: 0,
+            },
+        );
         [
             buttons1,
             buttons2,
             self.stick_x,
             self.stick_y,
-            self.cstick_x,
-            self.cstick_y,
-            self.l_analog,
-            self.r_analog,
+            analog[0],
+            analog[1],
+            analog[2],
+            analog[3],
         ]
     }
+
+    /// Decode a poll response sent for poll `mode`, the inverse of [`GamecubeInput::create_report_for_mode`].
+    pub(crate) const fn from_report_for_mode(mode: u8, report: &[u8; 8]) -> GamecubeInput {
+        let analog = decode_analog(mode, &[report[4], report[5], report[6], report[7]]);
+        GamecubeInput {
+            stick_x: report[2],
+            stick_y: report[3],
+            cstick_x: analog.cstick_x,
+            cstick_y: analog.cstick_y,
+            l_analog: analog.l_analog,
+            r_analog: analog.r_analog,
+            ..GamecubeInput::NEUTRAL.with_buttons(Buttons::decode([report[0], report[1]]))
+        }
+    }
 }
+
+// Mode 3 reports from the configurable layout must match the fixed one used everywhere else.
+const _: () = {
+    let mut input = GamecubeInput::NEUTRAL;
+    input.a = true;
+    input.cstick_x = 200;
+    input.r_analog = 35;
+    let report = input.create_report_for_mode(3);
+    let expected = input.to_mode3().encode();
+    let mut i = 0;
+    while i < report.len() {
+        assert!(report[i] == expected[i]);
+        i += 1;
+    }
+    let decoded = GamecubeInput::from_report_for_mode(3, &report);
+    assert!(decoded.a && decoded.cstick_x == 200 && decoded.r_analog == 35);
+};
+
+// The default status flags leave the button bytes of every report exactly as they were.
+const _: () = {
+    let mut input = GamecubeInput::NEUTRAL;
+    input.a = true;
+    input.z = true;
+    let report = input.create_report_for_mode(3);
+    let applied = StatusFlags::OEM.apply(report);
+    assert!(applied[0] == report[0] && applied[1] == report[1]);
+};
@@ -0,0 +1,37 @@
+//! Internal logging macros.
+//!
+//! With the `log` feature these forward to the [`log`](https://docs.rs/log) crate,
+//! otherwise they compile to nothing while still type checking their arguments.
+
+macro_rules! trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::trace!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = ::core::format_args!($($arg)*);
+        }
+    }};
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::debug!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = ::core::format_args!($($arg)*);
+        }
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        ::log::warn!($($arg)*);
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = ::core::format_args!($($arg)*);
+        }
+    }};
+}
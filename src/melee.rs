@@ -0,0 +1,138 @@
+//! Helpers for producing the exact stick coordinates Melee sees.
+//!
+//! Melee reads each stick axis as an offset from center in steps of 1/80th, so a full deflection is 80 units,
+//! and scales any coordinate outside of the unit circle back onto it.
+//! That rescaling produces values between the steps, so coordinates meant to be exact must stay inside the circle.
+//! These helpers convert between raw report values and units and keep coordinates on the grid and inside the circle.
+//!
+//! ```ignore
+//! // 0.6625 tilt right
+//! input.stick_x = melee::raw_from_units(melee::units_from_fraction(6625));
+//! // the furthest legal point up and right
+//! let (x, y) = melee::clamp_to_circle(80, 80);
+//! input.cstick_x = melee::raw_from_units(x);
+//! input.cstick_y = melee::raw_from_units(y);
+//! ```
+
+/// Units in a full deflection of a single axis, the radius of the unit circle.
+pub const FULL_DEFLECTION: i8 = 80;
+
+/// The raw report value of a centered axis.
+const CENTER: i16 = 128;
+
+/// The offset of `raw` from center in units, clamped to a full deflection.
+pub const fn units_from_raw(raw: u8) -> i8 {
+    clamp_units(raw as i16 - CENTER)
+}
+
+/// The raw report value for `units` from center, clamped to a full deflection.
+pub const fn raw_from_units(units: i8) -> u8 {
+    (CENTER + clamp_units(units as i16) as i16) as u8
+}
+
+/// The nearest step to `fraction` of a full deflection in 1/10000ths, e.g. 6625 for 0.6625, clamped to a full deflection.
+/// Uses the same scale as [`crate::modifiers::SCALE_ONE`].
+pub const fn units_from_fraction(fraction: i16) -> i8 {
+    let scaled = fraction as i32 * FULL_DEFLECTION as i32;
+    let rounded = if scaled < 0 {
+        (scaled - 5_000) / 10_000
+    } else {
+        (scaled + 5_000) / 10_000
+    };
+    clamp_units(rounded as i16)
+}
+
+const fn clamp_units(units: i16) -> i8 {
+    let max = FULL_DEFLECTION as i16;
+    if units > max {
+        FULL_DEFLECTION
+    } else if units < -max {
+        -FULL_DEFLECTION
+    } else {
+        units as i8
+    }
+}
+
+/// Whether a coordinate in units is inside the unit circle, so Melee won't rescale it.
+pub const fn is_in_circle(x: i8, y: i8) -> bool {
+    let (x, y) = (x as i32, y as i32);
+    x * x + y * y <= FULL_DEFLECTION as i32 * FULL_DEFLECTION as i32
+}
+
+/// Pull a coordinate in units that is outside of the unit circle back in, keeping it on the grid and close to its angle.
+/// Coordinates already inside the circle are returned unchanged.
+pub fn clamp_to_circle(x: i8, y: i8) -> (i8, i8) {
+    if is_in_circle(x, y) {
+        return (x, y);
+    }
+    let (x, y) = (x as i32, y as i32);
+    let magnitude = (x * x + y * y).isqrt();
+    let mut x = x * FULL_DEFLECTION as i32 / magnitude;
+    let mut y = y * FULL_DEFLECTION as i32 / magnitude;
+    // the truncated magnitude can leave the point just outside, step the larger axis in until it fits
+    while !is_in_circle(x as i8, y as i8) {
+        if x.abs() >= y.abs() {
+            x -= x.signum();
+        } else {
+            y -= y.signum();
+        }
+    }
+    (x as i8, y as i8)
+}
+
+/// Snap a raw stick position to the grid and inside the unit circle, returning the raw values to send.
+pub fn quantize_stick(x: u8, y: u8) -> (u8, u8) {
+    let (x, y) = clamp_to_circle(units_from_raw(x), units_from_raw(y));
+    (raw_from_units(x), raw_from_units(y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_conversions() {
+        assert_eq!(units_from_raw(128), 0);
+        assert_eq!(units_from_raw(200), 72);
+        assert_eq!(units_from_raw(255), 80);
+        assert_eq!(units_from_raw(0), -80);
+        assert_eq!(raw_from_units(0), 128);
+        assert_eq!(raw_from_units(80), 208);
+        assert_eq!(raw_from_units(i8::MAX), 208);
+        assert_eq!(raw_from_units(i8::MIN), 48);
+    }
+
+    #[test]
+    fn fractions() {
+        assert_eq!(units_from_fraction(6625), 53);
+        assert_eq!(units_from_fraction(-6625), -53);
+        assert_eq!(units_from_fraction(10_000), 80);
+        assert_eq!(units_from_fraction(0), 0);
+        // rounded to the nearest step
+        assert_eq!(units_from_fraction(62), 0);
+        assert_eq!(units_from_fraction(63), 1);
+        assert_eq!(units_from_fraction(-63), -1);
+        assert_eq!(units_from_fraction(i16::MAX), 80);
+        assert_eq!(units_from_fraction(i16::MIN), -80);
+    }
+
+    #[test]
+    fn circle() {
+        assert!(is_in_circle(80, 0));
+        assert!(is_in_circle(56, 56));
+        assert!(!is_in_circle(57, 57));
+        assert_eq!(clamp_to_circle(56, -56), (56, -56));
+        assert_eq!(clamp_to_circle(80, 80), (56, 56));
+        assert_eq!(clamp_to_circle(-80, 80), (-56, 56));
+        assert_eq!(clamp_to_circle(80, 10), (79, 10));
+        assert_eq!(clamp_to_circle(i8::MIN, i8::MIN), (-56, -56));
+    }
+
+    #[test]
+    fn quantize() {
+        assert_eq!(quantize_stick(128, 128), (128, 128));
+        assert_eq!(quantize_stick(255, 255), (184, 184));
+        assert_eq!(quantize_stick(0, 0), (72, 72));
+        assert_eq!(quantize_stick(255, 128), (208, 128));
+    }
+}
@@ -0,0 +1,156 @@
+//! Modifier buttons that scale or snap the sticks and triggers, as used by box style controllers.
+//!
+//! Each modifier is a slot with an action, and the firmware reports which slots are held on every poll,
+//! so modifiers can be any physical button, including ones that have no gamecube equivalent.
+//! Modifiers are applied in slot order before the input is encoded.
+//! Sticks driven by buttons can be given a travel time with [`crate::travel`] after the modifiers are applied.
+//!
+//! ```ignore
+//! let modifiers = Modifiers::new([
+//!     // Melee tilt: 0.6625 of a full 80 unit deflection
+//!     ModifierAction::ScaleStick { x: 6625, y: 6625 },
+//!     // Melee lightshield
+//!     ModifierAction::TriggerL(49),
+//! ]);
+//! let input = modifiers.apply(&input, &[mod_x.is_low()?, light_shield.is_low()?]);
+//! controller.respond_to_poll(&timer, &mut delay, input);
+//! ```
+
+use crate::GamecubeInput;
+
+/// Scale factors are in 1/10000ths, so 10000 leaves the axis unchanged.
+pub const SCALE_ONE: u16 = 10_000;
+
+/// What a modifier does to the input while held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModifierAction {
+    /// Scale the main stick's distance from center on each axis, in 1/10000ths, e.g. 6625 for 0.6625.
+    ScaleStick { x: u16, y: u16 },
+    /// Same as [`ModifierAction::ScaleStick`] for the c-stick.
+    ScaleCStick { x: u16, y: u16 },
+    /// Move every deflected main stick axis to exactly this distance from center, keeping its direction.
+    /// Axes at center are left alone, so this gives fixed angles for the diagonals.
+    SnapStick { x: u8, y: u8 },
+    /// Same as [`ModifierAction::SnapStick`] for the c-stick.
+    SnapCStick { x: u8, y: u8 },
+    /// Press the left trigger to at least this analog value without pressing its digital button, e.g. for lightshield.
+    TriggerL(u8),
+    /// Same as [`ModifierAction::TriggerL`] for the right trigger.
+    TriggerR(u8),
+}
+
+/// `N` modifier slots, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers<const N: usize> {
+    actions: [ModifierAction; N],
+}
+
+impl<const N: usize> Modifiers<N> {
+    pub const fn new(actions: [ModifierAction; N]) -> Modifiers<N> {
+        Modifiers { actions }
+    }
+
+    pub fn set_action(&mut self, slot: usize, action: ModifierAction) {
+        self.actions[slot] = action;
+    }
+
+    pub fn action(&self, slot: usize) -> ModifierAction {
+        self.actions[slot]
+    }
+
+    /// Apply the action of every slot that is held in `held` to `input`.
+    pub fn apply(&self, input: &GamecubeInput, held: &[bool; N]) -> GamecubeInput {
+        let mut output = *input;
+        for (action, _) in self.actions.iter().zip(held).filter(|(_, held)| **held) {
+            match *action {
+                ModifierAction::ScaleStick { x, y } => {
+                    output.stick_x = scale_axis(output.stick_x, x);
+                    output.stick_y = scale_axis(output.stick_y, y);
+                }
+                ModifierAction::ScaleCStick { x, y } => {
+                    output.cstick_x = scale_axis(output.cstick_x, x);
+                    output.cstick_y = scale_axis(output.cstick_y, y);
+                }
+                ModifierAction::SnapStick { x, y } => {
+                    output.stick_x = snap_axis(output.stick_x, x);
+                    output.stick_y = snap_axis(output.stick_y, y);
+                }
+                ModifierAction::SnapCStick { x, y } => {
+                    output.cstick_x = snap_axis(output.cstick_x, x);
+                    output.cstick_y = snap_axis(output.cstick_y, y);
+                }
+                ModifierAction::TriggerL(value) => output.l_analog = output.l_analog.max(value),
+                ModifierAction::TriggerR(value) => output.r_analog = output.r_analog.max(value),
+            }
+        }
+        output
+    }
+}
+
+/// Scale the distance of `value` from the center of 128, rounding to the nearest step.
+fn scale_axis(value: u8, scale: u16) -> u8 {
+    let offset = value as i32 - 128;
+    let scaled =
+        (offset * scale as i32 + offset.signum() * (SCALE_ONE as i32 / 2)) / SCALE_ONE as i32;
+    (scaled + 128).clamp(0, 255) as u8
+}
+
+/// Move `value` to `distance` from center on the same side, leaving it alone if it is centered.
+fn snap_axis(value: u8, distance: u8) -> u8 {
+    let offset = value as i32 - 128;
+    (offset.signum() * distance as i32 + 128).clamp(0, 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale() {
+        assert_eq!(scale_axis(255, 6625), 212);
+        assert_eq!(scale_axis(0, 6625), 43);
+        assert_eq!(scale_axis(128, 6625), 128);
+        assert_eq!(scale_axis(200, SCALE_ONE), 200);
+        assert_eq!(scale_axis(255, 0), 128);
+        // scaling up saturates at the ends of the axis
+        assert_eq!(scale_axis(255, u16::MAX), 255);
+        assert_eq!(scale_axis(0, u16::MAX), 0);
+    }
+
+    #[test]
+    fn snap() {
+        assert_eq!(snap_axis(200, 57), 185);
+        assert_eq!(snap_axis(10, 57), 71);
+        assert_eq!(snap_axis(128, 57), 128);
+        assert_eq!(snap_axis(129, 255), 255);
+        assert_eq!(snap_axis(127, 255), 0);
+    }
+
+    #[test]
+    fn slots() {
+        let modifiers = Modifiers::new([
+            ModifierAction::ScaleStick { x: 5000, y: 5000 },
+            ModifierAction::SnapStick { x: 40, y: 40 },
+            ModifierAction::TriggerL(49),
+            ModifierAction::ScaleCStick { x: 0, y: 0 },
+        ]);
+        let input = GamecubeInput {
+            stick_x: 255,
+            stick_y: 128,
+            cstick_x: 0,
+            l_analog: 100,
+            ..GamecubeInput::NEUTRAL
+        };
+        assert_eq!(modifiers.apply(&input, &[false; 4]), input);
+
+        // applied in slot order, so the snap wins over the scale
+        let output = modifiers.apply(&input, &[true, true, true, true]);
+        assert_eq!((output.stick_x, output.stick_y), (168, 128));
+        assert_eq!(output.cstick_x, 128);
+        // a trigger is only ever pressed further
+        assert_eq!(output.l_analog, 100);
+        assert!(!output.l_digital);
+        let output = modifiers.apply(&GamecubeInput::NEUTRAL, &[false, false, true, false]);
+        assert_eq!(output.l_analog, 49);
+    }
+}
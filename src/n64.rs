@@ -0,0 +1,507 @@
+//! The N64 protocol, which shares its physical layer with the gamecube.
+//!
+//! [`N64Controller`] emulates a controller, bare by default or with an emulated accessory inserted, see [`crate::pak`].
+//! [`N64Host`] acts as the console for polling a controller, it requires the `host` feature.
+
+use cortex_m::delay::Delay;
+
+#[cfg(feature = "host")]
+use crate::host::transaction;
+use crate::pak::{block_address, data_crc, NoPak, Pak, PAK_INSERTED, PAK_REMOVED};
+use crate::protocol::{
+    CMD_N64_POLL, CMD_PAK_READ, CMD_PAK_WRITE, CMD_PROBE, CMD_RESET, ID_RESPONSE_LEN,
+    N64_BUTTONS1_A, N64_BUTTONS1_B, N64_BUTTONS1_DPAD_DOWN, N64_BUTTONS1_DPAD_LEFT,
+    N64_BUTTONS1_DPAD_RIGHT, N64_BUTTONS1_DPAD_UP, N64_BUTTONS1_START, N64_BUTTONS1_Z,
+    N64_BUTTONS2_C_DOWN, N64_BUTTONS2_C_LEFT, N64_BUTTONS2_C_RIGHT, N64_BUTTONS2_C_UP,
+    N64_BUTTONS2_L, N64_BUTTONS2_R, N64_BUTTONS2_RESET, N64_CONTROLLER_ID, N64_POLL_RESPONSE_LEN,
+    PAK_BLOCK_LEN, PAK_READ_RESPONSE_LEN, REPLY_DELAY_US,
+};
+use crate::role::RoleTracker;
+use crate::rp2040_hal::{
+    gpio::bank0::Gpio28,
+    pac::PIO0,
+    pio::{PIOExt, StateMachineIndex, SM0},
+    Timer,
+};
+#[cfg(feature = "host")]
+use crate::HostError;
+use crate::{
+    JoybusPin, JoybusPort, JoybusRole, RoleState, RoleStats, ServiceOutcome, FRAME_GAP_US,
+};
+
+/// Response to the info and reset commands: a standard N64 controller with no pak inserted.
+/// The last byte is the pak status, see [`crate::pak`].
+pub const N64_ID_RESPONSE: [u8; ID_RESPONSE_LEN] =
+    [N64_CONTROLLER_ID[0], N64_CONTROLLER_ID[1], PAK_REMOVED];
+
+/// A command received from an N64 console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum N64Command {
+    /// 0x00, asks for the device identifier and pak status.
+    Info,
+    /// 0xFF, same as [`N64Command::Info`] but also resets the controller, re-zeroing its stick.
+    Reset,
+    /// 0x01, asks for the current inputs.
+    Poll,
+    /// 0x02, reads 32 bytes from the pak. Ignored if there is no pak.
+    PakRead,
+    /// 0x03, writes 32 bytes to the pak. Ignored if there is no pak.
+    PakWrite,
+    Unknown(u8),
+}
+
+/// The furthest from center the console reads an N64 stick axis.
+/// OEM sticks reach roughly ±80 and games aren't made to handle anything past this.
+pub const STICK_MAX: i8 = 85;
+
+/// Convert a gamecube stick axis, 0 to 255 centered on 128, to an N64 axis clamped to ±[`STICK_MAX`].
+///
+/// The offset from center is kept as is, the `bridge` feature scales between the reach of the two sticks instead.
+pub const fn axis_from_gamecube(value: u8) -> i8 {
+    clamp_axis(value as i16 - 128)
+}
+
+/// Convert an N64 stick axis to a gamecube stick axis, 0 to 255 centered on 128.
+pub const fn axis_to_gamecube(value: i8) -> u8 {
+    (value as i16 + 128) as u8
+}
+
+/// Clamp `value` to ±[`STICK_MAX`].
+pub const fn clamp_axis(value: i16) -> i8 {
+    if value > STICK_MAX as i16 {
+        STICK_MAX
+    } else if value < -(STICK_MAX as i16) {
+        -STICK_MAX
+    } else {
+        value as i8
+    }
+}
+
+/// Specify the button and stick inputs to be provided to an N64 console.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct N64Input {
+    pub a: bool,
+    pub b: bool,
+    pub z: bool,
+    pub start: bool,
+    pub dpad_up: bool,
+    pub dpad_down: bool,
+    pub dpad_left: bool,
+    pub dpad_right: bool,
+    pub l: bool,
+    pub r: bool,
+    pub c_up: bool,
+    pub c_down: bool,
+    pub c_left: bool,
+    pub c_right: bool,
+    /// Positive is right, an OEM stick reaches roughly ±80.
+    /// Sent as is, use [`N64Input::with_stick`] to keep it within ±[`STICK_MAX`].
+    pub stick_x: i8,
+    /// Positive is up, an OEM stick reaches roughly ±80.
+    /// Sent as is, use [`N64Input::with_stick`] to keep it within ±[`STICK_MAX`].
+    pub stick_y: i8,
+}
+
+impl N64Input {
+    /// All buttons released and the stick centered.
+    pub const NEUTRAL: N64Input = N64Input {
+        a: false,
+        b: false,
+        z: false,
+        start: false,
+        dpad_up: false,
+        dpad_down: false,
+        dpad_left: false,
+        dpad_right: false,
+        l: false,
+        r: false,
+        c_up: false,
+        c_down: false,
+        c_left: false,
+        c_right: false,
+        stick_x: 0,
+        stick_y: 0,
+    };
+
+    /// Returns a copy with the stick set to `x` and `y`, each clamped to ±[`STICK_MAX`].
+    pub const fn with_stick(self, x: i8, y: i8) -> N64Input {
+        N64Input {
+            stick_x: clamp_axis(x as i16),
+            stick_y: clamp_axis(y as i16),
+            ..self
+        }
+    }
+
+    /// Returns a copy with the stick set from gamecube coordinates, see [`axis_from_gamecube`].
+    pub const fn with_gamecube_stick(self, x: u8, y: u8) -> N64Input {
+        N64Input {
+            stick_x: axis_from_gamecube(x),
+            stick_y: axis_from_gamecube(y),
+            ..self
+        }
+    }
+
+    /// The stick in gamecube coordinates, see [`axis_to_gamecube`].
+    pub const fn gamecube_stick(&self) -> (u8, u8) {
+        (
+            axis_to_gamecube(self.stick_x),
+            axis_to_gamecube(self.stick_y),
+        )
+    }
+
+    /// Returns true if L, R and Start are all held, the combo an OEM controller re-zeroes its stick on.
+    pub const fn is_reset_combo(&self) -> bool {
+        self.l && self.r && self.start
+    }
+
+    /// Encode as a response to [`N64Command::Poll`].
+    ///
+    /// Like an OEM controller, while [`N64Input::is_reset_combo`] holds Start is not sent and the reset bit is set in its place.
+    pub const fn encode(&self) -> [u8; N64_POLL_RESPONSE_LEN] {
+        let reset = self.is_reset_combo();
+        #[rustfmt::skip]
+        let buttons1 =
+              if self.a               { N64_BUTTONS1_A } else { 0 }
+            | if self.b               { N64_BUTTONS1_B } else { 0 }
+            | if self.z               { N64_BUTTONS1_Z } else { 0 }
+            | if self.start && !reset { N64_BUTTONS1_START } else { 0 }
+            | if self.dpad_up         { N64_BUTTONS1_DPAD_UP } else { 0 }
+            | if self.dpad_down       { N64_BUTTONS1_DPAD_DOWN } else { 0 }
+            | if self.dpad_left       { N64_BUTTONS1_DPAD_LEFT } else { 0 }
+            | if self.dpad_right      { N64_BUTTONS1_DPAD_RIGHT } else { 0 };
+
+        #[rustfmt::skip]
+        let buttons2 =
+              if reset        { N64_BUTTONS2_RESET } else { 0 }
+            | if self.l       { N64_BUTTONS2_L } else { 0 }
+            | if self.r       { N64_BUTTONS2_R } else { 0 }
+            | if self.c_up    { N64_BUTTONS2_C_UP } else { 0 }
+            | if self.c_down  { N64_BUTTONS2_C_DOWN } else { 0 }
+            | if self.c_left  { N64_BUTTONS2_C_LEFT } else { 0 }
+            | if self.c_right { N64_BUTTONS2_C_RIGHT } else { 0 };
+
+        [buttons1, buttons2, self.stick_x as u8, self.stick_y as u8]
+    }
+
+    /// Decode a response to [`N64Command::Poll`], the inverse of [`N64Input::encode`].
+    /// The reset bit is decoded as Start.
+    pub const fn decode(report: &[u8; N64_POLL_RESPONSE_LEN]) -> N64Input {
+        let [buttons1, buttons2, stick_x, stick_y] = *report;
+        N64Input {
+            a: buttons1 & N64_BUTTONS1_A != 0,
+            b: buttons1 & N64_BUTTONS1_B != 0,
+            z: buttons1 & N64_BUTTONS1_Z != 0,
+            start: buttons1 & N64_BUTTONS1_START != 0 || buttons2 & N64_BUTTONS2_RESET != 0,
+            dpad_up: buttons1 & N64_BUTTONS1_DPAD_UP != 0,
+            dpad_down: buttons1 & N64_BUTTONS1_DPAD_DOWN != 0,
+            dpad_left: buttons1 & N64_BUTTONS1_DPAD_LEFT != 0,
+            dpad_right: buttons1 & N64_BUTTONS1_DPAD_RIGHT != 0,
+            l: buttons2 & N64_BUTTONS2_L != 0,
+            r: buttons2 & N64_BUTTONS2_R != 0,
+            c_up: buttons2 & N64_BUTTONS2_C_UP != 0,
+            c_down: buttons2 & N64_BUTTONS2_C_DOWN != 0,
+            c_left: buttons2 & N64_BUTTONS2_C_LEFT != 0,
+            c_right: buttons2 & N64_BUTTONS2_C_RIGHT != 0,
+            stick_x: stick_x as i8,
+            stick_y: stick_y as i8,
+        }
+    }
+}
+
+const _: () = {
+    let combo = N64Input::NEUTRAL.with_gamecube_stick(255, 0);
+    assert!(combo.stick_x == STICK_MAX && combo.stick_y == -STICK_MAX);
+    let combo = N64Input {
+        l: true,
+        r: true,
+        start: true,
+        ..combo
+    };
+    let report = combo.encode();
+    assert!(report[0] & N64_BUTTONS1_START == 0 && report[1] & N64_BUTTONS2_RESET != 0);
+    assert!(N64Input::decode(&report).is_reset_combo());
+};
+
+/// Acts as an N64 controller, responding to commands from an N64 console over a [`JoybusPort`].
+///
+/// The slot holds a pak of type `K`, which defaults to [`NoPak`] for a bare controller.
+///
+/// Like an OEM controller the stick is sent relative to an origin, which is re-zeroed to wherever the stick is
+/// whenever the console sends [`N64Command::Reset`] or the player holds L, R and Start, see [`N64Controller::stick_origin`].
+pub struct N64Controller<
+    P: PIOExt = PIO0,
+    I: JoybusPin<P> = Gpio28,
+    S: StateMachineIndex = SM0,
+    K: Pak = NoPak,
+> {
+    port: JoybusPort<P, I, S>,
+    /// The input [`JoybusRole::service`] responds to polls with.
+    input: N64Input,
+    role: RoleTracker,
+    pak: Option<K>,
+    /// A pak was swapped for another since the previous info response.
+    pak_swapped: bool,
+    stick_origin: (i8, i8),
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> N64Controller<P, I, S> {
+    pub fn new(port: JoybusPort<P, I, S>) -> N64Controller<P, I, S> {
+        N64Controller::new_with_pak(port, None)
+    }
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, K: Pak> N64Controller<P, I, S, K> {
+    /// Same as [`N64Controller::new`] but with `pak` inserted from the start.
+    pub fn new_with_pak(
+        mut port: JoybusPort<P, I, S>,
+        pak: Option<K>,
+    ) -> N64Controller<P, I, S, K> {
+        port.jump(0);
+        N64Controller {
+            port,
+            input: N64Input::NEUTRAL,
+            role: RoleTracker::new(),
+            pak,
+            pak_swapped: false,
+            stick_origin: (0, 0),
+        }
+    }
+
+    /// Returns the [`JoybusPort`] so it can be reused.
+    pub fn free(self) -> JoybusPort<P, I, S> {
+        self.port
+    }
+
+    /// Insert `pak` into the slot, returning the pak it replaced.
+    ///
+    /// Replacing a pak is reported to the console as a removal followed by an insertion,
+    /// so the game initializes the new pak instead of assuming it is the old one.
+    pub fn insert_pak(&mut self, pak: K) -> Option<K> {
+        let previous = self.pak.replace(pak);
+        self.pak_swapped = previous.is_some();
+        previous
+    }
+
+    /// Empty the slot, returning the pak that was inserted.
+    pub fn remove_pak(&mut self) -> Option<K> {
+        self.pak_swapped = false;
+        self.pak.take()
+    }
+
+    /// The inserted pak, for reading or changing its contents between commands.
+    pub fn pak(&mut self) -> Option<&mut K> {
+        self.pak.as_mut()
+    }
+
+    /// The status byte sent in the next info response, see [`crate::pak`].
+    pub fn pak_status(&self) -> u8 {
+        if self.pak.is_some() && !self.pak_swapped {
+            PAK_INSERTED
+        } else {
+            PAK_REMOVED
+        }
+    }
+
+    /// Set the input that [`JoybusRole::service`] responds to polls with.
+    pub fn set_input(&mut self, input: &N64Input) {
+        self.input = *input;
+    }
+
+    /// The stick position of the input that is sent as center.
+    ///
+    /// This starts at (0, 0) and is re-zeroed to the input's stick position by [`N64Command::Reset`]
+    /// and by every poll answered while [`N64Input::is_reset_combo`] holds.
+    pub fn stick_origin(&self) -> (i8, i8) {
+        self.stick_origin
+    }
+
+    pub fn set_stick_origin(&mut self, origin: (i8, i8)) {
+        self.stick_origin = origin;
+    }
+
+    /// `input` with the stick made relative to the origin, re-zeroing the origin first if the reset combo is held.
+    fn zero_stick(&mut self, input: &N64Input) -> N64Input {
+        if input.is_reset_combo() {
+            self.stick_origin = (input.stick_x, input.stick_y);
+        }
+        let (origin_x, origin_y) = self.stick_origin;
+        N64Input {
+            stick_x: clamp_axis(input.stick_x as i16 - origin_x as i16),
+            stick_y: clamp_axis(input.stick_y as i16 - origin_y as i16),
+            ..*input
+        }
+    }
+
+    /// Wait up to `timeout_us` microseconds for a command and respond to it, using `input` if it is a poll.
+    /// Returns the command that was handled, or None if nothing arrived.
+    ///
+    /// A console polls once per frame, so the next command can be expected roughly 16ms after a poll.
+    pub fn respond(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        input: &N64Input,
+        timeout_us: u64,
+    ) -> Option<N64Command> {
+        let command = match self.port.recv_byte(timer, timeout_us)? {
+            CMD_PROBE => N64Command::Info,
+            CMD_RESET => N64Command::Reset,
+            CMD_N64_POLL => N64Command::Poll,
+            CMD_PAK_READ => N64Command::PakRead,
+            CMD_PAK_WRITE => N64Command::PakWrite,
+            other => N64Command::Unknown(other),
+        };
+        trace!("joybus: n64 {:?}", command);
+
+        match command {
+            N64Command::Info | N64Command::Reset => {
+                if command == N64Command::Reset {
+                    self.stick_origin = (input.stick_x, input.stick_y);
+                }
+                let status = self.pak_status();
+                self.pak_swapped = false;
+                delay.delay_us(REPLY_DELAY_US);
+                self.port
+                    .send_frame(&[N64_ID_RESPONSE[0], N64_ID_RESPONSE[1], status]);
+            }
+            N64Command::Poll => {
+                delay.delay_us(REPLY_DELAY_US);
+                let input = self.zero_stick(input);
+                self.port.send_frame(&input.encode());
+            }
+            N64Command::PakRead => {
+                let mut address = [0; 2];
+                let received = self.port.recv_frame(timer, &mut address, FRAME_GAP_US);
+                match &mut self.pak {
+                    Some(pak) if received == Some(address.len()) => {
+                        let mut data = [0; PAK_BLOCK_LEN];
+                        pak.read(block_address(address), &mut data);
+                        let mut response = [0; PAK_READ_RESPONSE_LEN];
+                        response[..PAK_BLOCK_LEN].copy_from_slice(&data);
+                        response[PAK_BLOCK_LEN] = data_crc(&data);
+                        delay.delay_us(REPLY_DELAY_US);
+                        self.port.send_frame(&response);
+                    }
+                    // there is no pak to respond with
+                    _ => self.port.restart_for_read(timer),
+                }
+            }
+            N64Command::PakWrite => {
+                let mut frame = [0; 2 + PAK_BLOCK_LEN];
+                let received = self.port.recv_frame(timer, &mut frame, FRAME_GAP_US);
+                match &mut self.pak {
+                    Some(pak) if received == Some(frame.len()) => {
+                        let data: &[u8; PAK_BLOCK_LEN] = frame[2..].try_into().unwrap();
+                        pak.write(block_address([frame[0], frame[1]]), data);
+                        delay.delay_us(REPLY_DELAY_US);
+                        self.port.send_frame(&[data_crc(data)]);
+                    }
+                    // there is no pak to respond with
+                    _ => self.port.restart_for_read(timer),
+                }
+            }
+            N64Command::Unknown(_) => {
+                debug!("joybus: resyncing");
+                self.port.restart_for_read(timer);
+            }
+        }
+        Some(command)
+    }
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, K: Pak> JoybusRole
+    for N64Controller<P, I, S, K>
+{
+    fn service(&mut self, timer: &Timer, delay: &mut Delay, timeout_us: u64) -> ServiceOutcome {
+        let input = self.input;
+        let command = self.respond(timer, delay, &input, timeout_us);
+        self.role.record(command == Some(N64Command::Poll))
+    }
+
+    fn state(&self) -> RoleState {
+        self.role.state
+    }
+
+    fn stats(&self) -> RoleStats {
+        self.role.stats
+    }
+}
+
+/// Acts as an N64 console, sending commands to an N64 controller over a [`JoybusPort`].
+#[cfg(feature = "host")]
+pub struct N64Host<P: PIOExt = PIO0, I: JoybusPin<P> = Gpio28, S: StateMachineIndex = SM0> {
+    port: JoybusPort<P, I, S>,
+    last_response_us: Option<u64>,
+    /// The most recent response to [`JoybusRole::service`].
+    last_input: Option<N64Input>,
+    role: RoleTracker,
+}
+
+#[cfg(feature = "host")]
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> N64Host<P, I, S> {
+    pub fn new(port: JoybusPort<P, I, S>) -> N64Host<P, I, S> {
+        N64Host {
+            port,
+            last_response_us: None,
+            last_input: None,
+            role: RoleTracker::new(),
+        }
+    }
+
+    /// Returns the [`JoybusPort`] so it can be reused.
+    pub fn free(self) -> JoybusPort<P, I, S> {
+        self.port
+    }
+
+    /// Microseconds from the end of the most recent command until the first byte of its response was received,
+    /// or None if there was no response.
+    pub fn last_response_us(&self) -> Option<u64> {
+        self.last_response_us
+    }
+
+    /// The input read by the most recent [`JoybusRole::service`], None if it wasn't answered.
+    pub fn last_input(&self) -> Option<N64Input> {
+        self.last_input
+    }
+
+    /// Ask the controller for its device identifier and pak status.
+    pub fn info(&mut self, timer: &Timer) -> Result<[u8; 3], HostError> {
+        self.transaction(timer, &[CMD_PROBE])
+    }
+
+    /// Reset the controller, which also zeroes its stick. It responds with its device identifier and pak status.
+    pub fn reset(&mut self, timer: &Timer) -> Result<[u8; 3], HostError> {
+        self.transaction(timer, &[CMD_RESET])
+    }
+
+    /// Poll the controller's inputs.
+    pub fn poll(&mut self, timer: &Timer) -> Result<N64Input, HostError> {
+        self.transaction(timer, &[CMD_N64_POLL])
+            .map(|report| N64Input::decode(&report))
+    }
+
+    /// Send `command` and receive a response of exactly `N` bytes.
+    pub fn transaction<const N: usize>(
+        &mut self,
+        timer: &Timer,
+        command: &[u8],
+    ) -> Result<[u8; N], HostError> {
+        transaction(&mut self.port, timer, command, &mut self.last_response_us)
+    }
+}
+
+#[cfg(feature = "host")]
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> JoybusRole for N64Host<P, I, S> {
+    fn service(&mut self, timer: &Timer, _delay: &mut Delay, _timeout_us: u64) -> ServiceOutcome {
+        self.last_input = self.poll(timer).ok();
+        self.role.record(self.last_input.is_some())
+    }
+
+    fn state(&self) -> RoleState {
+        self.role.state
+    }
+
+    fn stats(&self) -> RoleStats {
+        self.role.stats
+    }
+}
@@ -0,0 +1,77 @@
+//! Accessories plugged into the slot of an N64 controller, emulated by implementing [`Pak`].
+//!
+//! A pak is inserted into an [`crate::n64::N64Controller`] with [`crate::n64::N64Controller::insert_pak`]
+//! and can be swapped or removed at any time, just like a physical one.
+//! The console reads and writes a pak in blocks of [`PAK_BLOCK_LEN`] bytes at addresses aligned to the block size.
+//!
+//! The info response reports the state of the slot in its status byte:
+//!
+//! | Status | Meaning |
+//! |--------|---------|
+//! | [`PAK_INSERTED`] | A pak is inserted. |
+//! | [`PAK_REMOVED`] | No pak is inserted, or the pak was swapped since the previous info response. |
+//!
+//! Games check the status every few frames and initialize a pak again after seeing it removed,
+//! so a swap is reported as removed once before the new pak is reported as inserted.
+//!
+//! A Transfer Pak is provided by [`crate::transfer_pak`].
+
+/// The number of bytes read or written by a single pak command.
+pub use crate::protocol::PAK_BLOCK_LEN;
+
+/// Status bit set while a pak is inserted.
+pub const PAK_INSERTED: u8 = 0x01;
+
+/// Status bit set while no pak is inserted, and once after a pak is swapped.
+pub const PAK_REMOVED: u8 = 0x02;
+
+/// An accessory emulated by an [`crate::n64::N64Controller`].
+///
+/// Both methods are called between receiving a command and responding to it, so must return within a few microseconds.
+pub trait Pak {
+    /// Fill `data` with the block at `address`, which is a multiple of [`PAK_BLOCK_LEN`].
+    fn read(&mut self, address: u16, data: &mut [u8; PAK_BLOCK_LEN]);
+
+    /// Write `data` to the block at `address`, which is a multiple of [`PAK_BLOCK_LEN`].
+    fn write(&mut self, address: u16, data: &[u8; PAK_BLOCK_LEN]);
+}
+
+/// A slot that is always empty, the default for an [`crate::n64::N64Controller`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NoPak;
+
+impl Pak for NoPak {
+    fn read(&mut self, _address: u16, data: &mut [u8; PAK_BLOCK_LEN]) {
+        *data = [0; PAK_BLOCK_LEN];
+    }
+
+    fn write(&mut self, _address: u16, _data: &[u8; PAK_BLOCK_LEN]) {}
+}
+
+/// The CRC sent after the data of a pak read and in response to a pak write, which the console checks.
+pub const fn data_crc(data: &[u8; PAK_BLOCK_LEN]) -> u8 {
+    let mut crc = 0u8;
+    let mut i = 0;
+    // the data is followed by a zero byte to flush the CRC
+    while i <= PAK_BLOCK_LEN {
+        let byte = if i < PAK_BLOCK_LEN { data[i] } else { 0 };
+        let mut bit = 8;
+        while bit > 0 {
+            bit -= 1;
+            let xor = if crc & 0x80 != 0 { 0x85 } else { 0 };
+            crc = (crc << 1) | ((byte >> bit) & 1);
+            crc ^= xor;
+        }
+        i += 1;
+    }
+    crc
+}
+
+/// The address a pak command refers to, without the address CRC in its low 5 bits.
+pub(crate) const fn block_address(bytes: [u8; 2]) -> u16 {
+    u16::from_be_bytes(bytes) & !(PAK_BLOCK_LEN as u16 - 1)
+}
+
+// The CRCs a rumble pak responds with when the console turns the motor off and on.
+const _: () = assert!(data_crc(&[0x00; PAK_BLOCK_LEN]) == 0x00);
+const _: () = assert!(data_crc(&[0x01; PAK_BLOCK_LEN]) == 0xEB);
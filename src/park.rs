@@ -0,0 +1,72 @@
+//! Releasing the data line when the firmware crashes.
+//!
+//! If the firmware panics or hard faults while a frame is being sent, the state machine keeps driving the line
+//! at whatever level it was at, which can hold the console's data line low until the board is unplugged.
+//! [`park`] stops every joybus state machine and floats every data pin so the console sees the controller as unplugged instead.
+//!
+//! Call it from the panic handler and the HardFault handler:
+//!
+//! ```ignore
+//! #[panic_handler]
+//! fn panic(_: &core::panic::PanicInfo) -> ! {
+//!     joybus_pio::park::park();
+//!     loop {}
+//! }
+//!
+//! #[exception]
+//! unsafe fn HardFault(_: &ExceptionFrame) -> ! {
+//!     joybus_pio::park::park();
+//!     loop {}
+//! }
+//! ```
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::rp2040_hal::pac::{IO_BANK0, PIO0, PIO1};
+
+/// Bits 0 to 29 are the data pins of every [`crate::JoybusPort`] created so far,
+/// bits 30 and 31 are unused.
+static PINS: AtomicU32 = AtomicU32::new(0);
+
+/// Bits 0 to 3 are the state machines of PIO0 running a port, bits 4 to 7 are the state machines of PIO1.
+static STATE_MACHINES: AtomicU32 = AtomicU32::new(0);
+
+/// Called by [`crate::JoybusPort`] once its state machine is running.
+pub(crate) fn register(pin: u8, pio: usize, sm: usize) {
+    // the M0+ has no atomic read-modify-write
+    cortex_m::interrupt::free(|_| {
+        let pins = PINS.load(Ordering::Relaxed);
+        PINS.store(pins | 1 << pin, Ordering::Relaxed);
+        let state_machines = STATE_MACHINES.load(Ordering::Relaxed);
+        STATE_MACHINES.store(state_machines | 1 << (pio * 4 + sm), Ordering::Relaxed);
+    });
+}
+
+/// Stop the state machine of every [`crate::JoybusPort`] and float their data pins.
+///
+/// This only touches the registers of the ports and doesn't take ownership of anything,
+/// so it is safe to call at any time, but the ports won't work again until the chip is reset.
+pub fn park() {
+    let pins = PINS.load(Ordering::Relaxed);
+    let state_machines = STATE_MACHINES.load(Ordering::Relaxed);
+    // Safety: only the state machines owned by a port and the GPIO_CTRL registers of their data pins are touched,
+    // and it doesn't matter what they were doing since they won't be used again.
+    unsafe {
+        for (i, pio) in [PIO0::ptr(), PIO1::ptr()].into_iter().enumerate() {
+            let mask = (state_machines >> (i * 4)) as u8 & 0x0F;
+            if mask != 0 {
+                (*pio)
+                    .ctrl()
+                    .modify(|r, w| w.sm_enable().bits(r.sm_enable().bits() & !mask));
+            }
+        }
+        for pin in 0..30 {
+            if pins & 1 << pin != 0 {
+                (*IO_BANK0::ptr())
+                    .gpio(pin)
+                    .gpio_ctrl()
+                    .modify(|_, w| w.oeover().disable());
+            }
+        }
+    }
+}
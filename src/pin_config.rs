@@ -0,0 +1,28 @@
+use crate::rp2040_hal::gpio::{OutputDriveStrength, OutputSlewRate};
+
+/// Electrical settings of the data pin's pad, applied with [`crate::JoybusPort::set_pin_config`].
+///
+/// The defaults match the RP2040 reset values, which work well for a short trace to the controller port.
+/// Long cables or passthrough boards may need a faster slew rate or stronger drive to get clean edges,
+/// while the schmitt trigger helps reject noise on slow rising edges since the line is only pulled up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinConfig {
+    pub slew_rate: OutputSlewRate,
+    pub drive_strength: OutputDriveStrength,
+    /// Enables hysteresis on the input.
+    pub schmitt_trigger: bool,
+}
+
+impl PinConfig {
+    pub const RESET: PinConfig = PinConfig {
+        slew_rate: OutputSlewRate::Slow,
+        drive_strength: OutputDriveStrength::FourMilliAmps,
+        schmitt_trigger: true,
+    };
+}
+
+impl Default for PinConfig {
+    fn default() -> Self {
+        PinConfig::RESET
+    }
+}
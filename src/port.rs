@@ -0,0 +1,941 @@
+use embedded_hal::digital::InputPin;
+use pio::{Instruction, InstructionOperands, Wrap};
+
+use crate::rp2040_hal::{
+    clocks::Clock,
+    clocks::ClocksManager,
+    gpio::{bank0::Gpio28, FunctionNull, Pin, PinId, PullDown, ValidFunction},
+    pac::{pio0::RegisterBlock, PIO0, PIO1, RESETS},
+    pio::{
+        InstalledProgram, PIOBuilder, PIOExt, PioIRQ, Running, Rx, ShiftDirection, StateMachine,
+        StateMachineIndex, Tx, UninitStateMachine, SM0, SM1,
+    },
+    Timer,
+};
+#[cfg(feature = "fault-injection")]
+use crate::FaultInjector;
+use crate::{checked_clock_divisor, hal_compat, ClockError, PinConfig};
+#[cfg(feature = "flight-recorder")]
+use crate::{FlightEvent, FlightRecorder};
+
+/// Address of the `write` entry point in the PIO program, everything before it is the `read` routine.
+const WRITE_ADDRESS: u8 = 13;
+
+/// How long the line must stay high before [`JoybusPort::restart_for_read`] considers the bus idle.
+/// Within a frame the line is never high for longer than the 3us of a 1 bit.
+pub const BUS_IDLE_US: u64 = 12;
+
+/// How long [`JoybusPort::restart_for_read`] waits for the bus to go idle before restarting anyway.
+pub const BUS_IDLE_GIVE_UP_US: u64 = 1_000;
+
+/// How long [`JoybusPort::recv_frame`] waits for the next byte before considering the frame complete,
+/// in case the end of the frame isn't signalled by the state machine.
+/// This is a byte on the wire plus some margin.
+pub const FRAME_GAP_US: u64 = crate::protocol::BYTE_US + 8;
+
+// pio proc macro is broken with cargo bin deps nightly feature.
+// work around this by manually assembling the program.
+//     let program = pio_proc::pio_asm!(
+//         "
+// .define public T1 10
+// .define public T2 20
+// .define public T3 10
+
+// ; Autopush with 8 bit ISR threshold, jmp pin is the data pin
+// public read:
+//     set pindirs 0                   ; Set pin to input
+// read_loop:
+//     mov x, ~osr                     ; Idle timeout, OSR holds what the stop bit of the last frame sent left behind, 0 is ~14 minutes
+// idle_loop:
+//     jmp pin still_high
+//     jmp read_bit [T1 + T2 / 2 - 3]  ; Line went low, wait until halfway through the 2uS which represents the bit value
+// still_high:
+//     jmp x-- idle_loop
+//     mov isr, ~null                  ; Frame is over or timed out, replace the stop bit with an end of frame marker
+//     push noblock                    ; Mark the end of the frame in the RX FIFO after its final byte
+//     irq nowait 0 rel                ; Tell the CPU the frame is over
+//     jmp read_loop
+// read_bit:
+//     in pins, 1                      ; Read bit value
+//     wait 1 pin 0                    ; Done reading, so make sure we wait for the line to go high again
+//     set x, 31                       ; Within a frame the line is never high for more than 3uS, count to ~6uS
+//     jmp idle_loop
+
+// ; 9 bit OSR threshold, no autopull because it interferes with !osre
+// public write:
+//     set pindirs 1           ; Set pin to output
+// write_loop:
+//     set pins, 1             ; Set line high for at least 1uS to end pulse
+//     pull ifempty block      ; Fetch next byte into OSR if we are done with the current one
+//     out x, 1                ; Get bit
+//     jmp !osre write_bit     ; If we aren't on the 9th bit, just write the bit
+//     jmp x!=y write_stop_bit ; If we are on the 9th bit and it's a 1 that indicates stop bit so write it
+//     pull ifempty block      ; If we are on the 9th bit and it's a 0 then we should skip to the next byte
+//     out x, 1                ; Get first bit of the next byte
+//     jmp write_bit_fast      ; Write it, skipping some of the delays because we spent so much time checking the 9th bit
+// write_bit:
+//     nop [3]
+// write_bit_fast:
+//     nop [T3 - 9]
+//     set pins, 0 [T1 - 1]    ; Pulse always starts with low for 1uS
+//     mov pins, x [T2 - 2]    ; Set line according to bit value for 2uS
+//     jmp write_loop
+// write_stop_bit:
+//     nop [T3 - 6]
+//     set pins, 0 [T1 - 1]
+//     set pins, 1 [T2 - 2]
+//     jmp read
+// "
+//     );
+
+/// The joybus PIO program exactly as it is installed, starting at address 0.
+/// `read` starts at 0 and `write` at 13.
+pub const PROGRAM: &[u16] = &[
+    //     .wrap_target
+    0xe080, //  0: set    pindirs, 0
+    0xa02f, //  1: mov    x, ~osr
+    0x00c4, //  2: jmp    pin, 4
+    0x1109, //  3: jmp    9                      [17]
+    0x0042, //  4: jmp    x--, 2
+    0xa0cb, //  5: mov    isr, ~null
+    0x8000, //  6: push   noblock
+    0xc010, //  7: irq    nowait 0 rel
+    0x0001, //  8: jmp    1
+    0x4001, //  9: in     pins, 1
+    0x20a0, // 10: wait   1 pin, 0
+    0xe03f, // 11: set    x, 31
+    0x0002, // 12: jmp    2
+    0xe081, // 13: set    pindirs, 1
+    0xe001, // 14: set    pins, 1
+    0x80e0, // 15: pull   ifempty block
+    0x6021, // 16: out    x, 1
+    0x00f6, // 17: jmp    !osre, 22
+    0x00bb, // 18: jmp    x != y, 27
+    0x80e0, // 19: pull   ifempty block
+    0x6021, // 20: out    x, 1
+    0x0017, // 21: jmp    23
+    0xa342, // 22: nop                           [3]
+    0xa142, // 23: nop                           [1]
+    0xe900, // 24: set    pins, 0                [9]
+    0xb201, // 25: mov    pins, x                [18]
+    0x000e, // 26: jmp    14
+    0xa442, // 27: nop                           [4]
+    0xe900, // 28: set    pins, 0                [9]
+    0xf201, // 29: set    pins, 1                [18]
+    0x0000, // 30: jmp    0
+            //     .wrap
+];
+
+/// The number of instructions in [`PROGRAM`], out of the 32 available in a PIO block.
+pub const PROGRAM_LEN: usize = PROGRAM.len();
+
+/// Pushed into the RX FIFO by [`PROGRAM`] after the final byte of every received frame.
+/// A received byte only ever sets the low 8 bits so the two can't be confused.
+pub(crate) const FRAME_END_MARKER: u32 = u32::MAX;
+
+/// The PIO IRQ flag raised by [`PROGRAM`] once the line has been idle for around 6us after receiving a frame,
+/// relative to the state machine index so SM0 raises flag 0 and SM1 raises flag 1. See [`JoybusPort::take_frame_end`].
+pub const FRAME_END_IRQ: u8 = 0;
+
+/// The resolution of [`JoybusPort::set_idle_timeout`] in nanoseconds.
+///
+/// Each pass of the program's idle loop takes 2 PIO cycles, 200ns, and the timeout counts passes in steps of 512.
+pub const IDLE_TIMEOUT_STEP_NS: u32 = 102_400;
+
+/// The longest [`JoybusPort::set_idle_timeout`], which is also how often a port with no timeout set
+/// reports an idle bus as an empty frame, around 14 minutes.
+pub const MAX_IDLE_TIMEOUT_US: u32 =
+    ((IDLE_TIMEOUT_STEPS as u64 * IDLE_TIMEOUT_STEP_NS as u64) / 1000) as u32;
+
+/// The number of idle timeout steps, the stop word of a frame has 23 spare bits to carry them in.
+const IDLE_TIMEOUT_STEPS: u32 = 1 << 23;
+
+const _: () = assert!(
+    IDLE_TIMEOUT_STEP_NS as u64 * (crate::CYCLES_PER_BIT * crate::BITRATE) as u64
+        == 512 * 2 * 1_000_000_000
+);
+
+/// A pin that can be driven by PIO block `P`, which is every bank 0 pin.
+pub trait JoybusPin<P: PIOExt>: PinId + ValidFunction<P::PinFunction> {}
+
+impl<P: PIOExt, I: PinId + ValidFunction<P::PinFunction>> JoybusPin<P> for I {}
+
+/// A wrapper around the PIO types from the rp2040 HAL required for low level communication over the joybus protocol.
+///
+/// This only deals in frames of bytes followed by a stop bit and has no knowledge of the commands they contain,
+/// so it can be used for either end of the bus.
+///
+/// A port created with [`JoybusPort::new`] takes over a whole PIO block, so up to two can exist at once,
+/// e.g. one for each side of a bridge.
+/// [`JoybusPort::new_pair`] instead runs two ports on SM0 and SM1 of a single PIO block.
+/// The type parameters default to PIO0, GPIO28, the pin used by most boards, and SM0.
+pub struct JoybusPort<P: PIOExt = PIO0, I: JoybusPin<P> = Gpio28, S: StateMachineIndex = SM0> {
+    data_pin: Pin<I, P::PinFunction, PullDown>,
+    tx: Tx<(P, S)>,
+    rx: Rx<(P, S)>,
+    sm: StateMachine<(P, S), Running>,
+    registers: &'static RegisterBlock,
+    tx_underruns: u32,
+    /// The low 23 bits of every stop word, which the program turns into its idle timeout, see [`JoybusPort::set_idle_timeout`].
+    idle_timeout: u32,
+    /// Whether a byte has been read from the RX FIFO since the last end of frame marker.
+    in_frame: bool,
+    #[cfg(feature = "fault-injection")]
+    fault_injector: Option<FaultInjector>,
+    #[cfg(feature = "flight-recorder")]
+    flight_recorder: Option<&'static mut FlightRecorder>,
+}
+
+/// The low word of the system timer in microseconds, for bounding waits in code that isn't handed a [`Timer`].
+#[cfg(feature = "fault-injection")]
+fn timer_us() -> u32 {
+    // Safety: reading the raw counter has no side effects.
+    unsafe {
+        (*crate::rp2040_hal::pac::TIMER::ptr())
+            .timerawl()
+            .read()
+            .bits()
+    }
+}
+
+// Safety: `registers` is a fixed MMIO block that the port only touches the bits of its own state machine and pin in,
+// the same as the HAL types it holds, which are all Send.
+unsafe impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> Send for JoybusPort<P, I, S> {}
+
+/// The registers of PIO block `P`, for code that has to reach them without owning a [`JoybusPort`].
+pub(crate) fn registers<P: PIOExt>() -> &'static RegisterBlock {
+    let pio = if P::id() == 0 {
+        PIO0::ptr()
+    } else {
+        PIO1::ptr()
+    };
+    // Safety: the registers of a PIO block are at a fixed address for the lifetime of the program.
+    unsafe { &*pio }
+}
+
+/// The interrupt enable register for `irq` of PIO block `P`, through the atomic access alias at `offset`,
+/// 0x2000 to set bits or 0x3000 to clear them without disturbing the others.
+pub(crate) fn irq_inte_alias<P: PIOExt>(irq: PioIRQ, offset: usize) -> *mut u32 {
+    let irq = match irq {
+        PioIRQ::Irq0 => 0,
+        PioIRQ::Irq1 => 1,
+    };
+    let inte = registers::<P>().sm_irq(irq).irq_inte().as_ptr();
+    (inte as usize | offset) as *mut u32
+}
+
+/// The TX FIFO entry that makes [`PROGRAM`] write `value`, followed by a stop bit if `stop` is set.
+pub(crate) const fn tx_word(value: u8, stop: bool) -> u32 {
+    ((value as u32) << 24) | ((stop as u32) << 23)
+}
+
+/// Whether state machine `sm` has stalled on a blocking pull from an empty TX FIFO since its flag was last cleared,
+/// going by the value of the FDEBUG register.
+pub(crate) const fn tx_stalled(fdebug: u32, sm: usize) -> bool {
+    fdebug & tx_stall_flag(sm) != 0
+}
+
+/// The sticky FDEBUG.TXSTALL flag of state machine `sm`, cleared by writing 1 to it.
+const fn tx_stall_flag(sm: usize) -> u32 {
+    1 << (24 + sm)
+}
+
+/// Two ports sharing a PIO block on SM0 and SM1, returned by [`JoybusPort::new_pair`].
+pub type JoybusPortPair<P, I, I2> = (JoybusPort<P, I>, JoybusPort<P, I2, SM1>);
+
+impl<P: PIOExt, I: JoybusPin<P>> JoybusPort<P, I> {
+    /// Installs the joybus program into `pio` and starts it on SM0.
+    /// Returns an error if the system clock can't produce the joybus bit timing.
+    pub fn new(
+        data_pin: Pin<I, FunctionNull, PullDown>,
+        pio: P,
+        resets: &mut RESETS,
+        clocks: ClocksManager,
+    ) -> Result<JoybusPort<P, I>, ClockError> {
+        JoybusPort::new_with_builder(data_pin, pio, resets, clocks, |builder| builder)
+    }
+
+    /// Same as [`JoybusPort::new`] but `configure` is given the fully configured [`PIOBuilder`] right before the state machine is built.
+    /// This allows experimenting with alternative divisors, shift configuration or pin bases without forking the constructor.
+    ///
+    /// Changing the configuration can easily break the protocol, there is no validation of the result.
+    pub fn new_with_builder(
+        data_pin: Pin<I, FunctionNull, PullDown>,
+        pio: P,
+        resets: &mut RESETS,
+        clocks: ClocksManager,
+        configure: impl FnOnce(PIOBuilder<P>) -> PIOBuilder<P>,
+    ) -> Result<JoybusPort<P, I>, ClockError> {
+        let divisor = checked_clock_divisor(clocks.system_clock.freq().to_Hz())?;
+
+        // Safety: the registers of a PIO block are at a fixed address for the lifetime of the program,
+        // and the port owns the block so nothing else will touch them.
+        let registers = unsafe { &*(&*pio as *const RegisterBlock) };
+
+        let (mut pio, sm0, _, _, _) = pio.split(resets);
+        let installed = pio.install(&program()).unwrap();
+
+        Ok(JoybusPort::build(
+            data_pin, installed, sm0, registers, divisor, configure,
+        ))
+    }
+
+    /// Installs the joybus program into `pio` once and starts a port on SM0 using `data_pin` and another on SM1 using `second_pin`.
+    ///
+    /// This lets a device and a host run at the same time on a single PIO block,
+    /// e.g. for a passthrough that injects inputs or a bridge, leaving the other PIO block free.
+    /// Returns an error if the system clock can't produce the joybus bit timing.
+    pub fn new_pair<I2: JoybusPin<P>>(
+        data_pin: Pin<I, FunctionNull, PullDown>,
+        second_pin: Pin<I2, FunctionNull, PullDown>,
+        pio: P,
+        resets: &mut RESETS,
+        clocks: ClocksManager,
+    ) -> Result<JoybusPortPair<P, I, I2>, ClockError> {
+        let divisor = checked_clock_divisor(clocks.system_clock.freq().to_Hz())?;
+
+        // Safety: the registers of a PIO block are at a fixed address for the lifetime of the program.
+        // The two ports only touch the state machine, FIFOs, IRQ flag and pin bits that belong to them.
+        let registers = unsafe { &*(&*pio as *const RegisterBlock) };
+
+        let (mut pio, sm0, sm1, _, _) = pio.split(resets);
+        let installed = pio.install(&program()).unwrap();
+        // Safety: the program is never uninstalled, so it stays valid for both state machines.
+        let shared = unsafe { installed.share() };
+
+        Ok((
+            JoybusPort::build(data_pin, installed, sm0, registers, divisor, |builder| {
+                builder
+            }),
+            JoybusPort::build(second_pin, shared, sm1, registers, divisor, |builder| {
+                builder
+            }),
+        ))
+    }
+}
+
+fn program() -> hal_compat::Program {
+    hal_compat::program(
+        PROGRAM,
+        Wrap {
+            source: PROGRAM_LEN as u8 - 1,
+            target: 0,
+        },
+    )
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> JoybusPort<P, I, S> {
+    /// Configure `sm` to run the already installed program on `data_pin` and start it.
+    fn build(
+        data_pin: Pin<I, FunctionNull, PullDown>,
+        installed: InstalledProgram<P>,
+        sm: UninitStateMachine<(P, S)>,
+        registers: &'static RegisterBlock,
+        (divisor_int, divisor_frac): (u16, u8),
+        configure: impl FnOnce(PIOBuilder<P>) -> PIOBuilder<P>,
+    ) -> JoybusPort<P, I, S> {
+        let data_pin: Pin<_, P::PinFunction, PullDown> = data_pin.into_function();
+        let data_pin_num = data_pin.id().num;
+
+        let builder = PIOBuilder::from_installed_program(installed)
+            .out_pins(data_pin_num, 1)
+            .set_pins(data_pin_num, 1)
+            .in_pin_base(data_pin_num)
+            .jmp_pin(data_pin_num)
+            // out shift
+            .out_shift_direction(ShiftDirection::Left)
+            .autopull(false)
+            .pull_threshold(9)
+            // in shift
+            .in_shift_direction(ShiftDirection::Left)
+            .autopush(true)
+            .push_threshold(8)
+            .clock_divisor_fixed_point(divisor_int, divisor_frac);
+        let (sm, rx, tx) = configure(builder).build(sm);
+        let sm = sm.start();
+        #[cfg(feature = "park")]
+        crate::park::register(data_pin_num, P::id(), S::id());
+
+        JoybusPort {
+            tx,
+            rx,
+            sm,
+            data_pin,
+            registers,
+            tx_underruns: 0,
+            idle_timeout: 0,
+            in_frame: false,
+            #[cfg(feature = "fault-injection")]
+            fault_injector: None,
+            #[cfg(feature = "flight-recorder")]
+            flight_recorder: None,
+        }
+    }
+
+    /// Configure the electrical settings of the data pin, see [`PinConfig`].
+    /// This can be called at any time, e.g. to tune the settings while observing the line on a scope.
+    pub fn set_pin_config(&mut self, config: &PinConfig) {
+        self.data_pin.set_slew_rate(config.slew_rate);
+        self.data_pin.set_drive_strength(config.drive_strength);
+        self.data_pin.set_schmitt_enabled(config.schmitt_trigger);
+    }
+
+    /// Bypass the 2 flip-flop input synchronizer between the data pin and the PIO block, which reduces sampling latency by 2 system clock cycles.
+    ///
+    /// This is only useful when fine tuning the sample point, e.g. with a large clock divisor where a single PIO cycle is coarse.
+    /// Without the synchronizer the PIO can sample the line while it is transitioning and read a metastable value,
+    /// so only enable this if the edges are clean and the sample point is well clear of them.
+    pub fn set_input_sync_bypass(&mut self, bypass: bool) {
+        let mask = 1 << self.data_pin.id().num;
+        // Safety: we only touch the bit for our own pin.
+        self.registers.input_sync_bypass().modify(|r, w| unsafe {
+            if bypass {
+                w.bits(r.bits() | mask)
+            } else {
+                w.bits(r.bits() & !mask)
+            }
+        });
+    }
+
+    /// Start injecting faults into every frame sent, or stop if None. See [`crate::fault`].
+    #[cfg(feature = "fault-injection")]
+    pub fn set_fault_injector(&mut self, injector: Option<FaultInjector>) {
+        self.fault_injector = injector;
+    }
+
+    /// The injector set by [`JoybusPort::set_fault_injector`], for reading its stats.
+    #[cfg(feature = "fault-injection")]
+    pub fn fault_injector(&mut self) -> Option<&mut FaultInjector> {
+        self.fault_injector.as_mut()
+    }
+
+    /// Start recording every byte moved through the FIFOs into `recorder`, or stop if None.
+    /// See [`crate::flight_recorder`].
+    #[cfg(feature = "flight-recorder")]
+    pub fn set_flight_recorder(&mut self, recorder: Option<&'static mut FlightRecorder>) {
+        self.flight_recorder = recorder;
+    }
+
+    /// The recorder set by [`JoybusPort::set_flight_recorder`].
+    #[cfg(feature = "flight-recorder")]
+    pub fn flight_recorder(&mut self) -> Option<&mut FlightRecorder> {
+        self.flight_recorder.as_deref_mut()
+    }
+
+    /// Hand `event` to the flight recorder if there is one.
+    #[cfg(feature = "flight-recorder")]
+    #[inline]
+    fn record(&mut self, event: FlightEvent) {
+        if let Some(recorder) = &mut self.flight_recorder {
+            recorder.record(event);
+        }
+    }
+
+    /// Record `word` made by [`tx_word`] as sent, for words that reach the TX FIFO by DMA.
+    #[cfg(feature = "flight-recorder")]
+    #[inline]
+    pub(crate) fn record_sent(&mut self, word: u32) {
+        self.record(FlightEvent::Sent {
+            byte: (word >> 24) as u8,
+            stop: word & (1 << 23) != 0,
+        });
+    }
+
+    /// The TX FIFO entry for `value` of a frame, see [`tx_word`].
+    /// The stop word also carries the idle timeout, which is left in the OSR once the stop bit is written.
+    pub(crate) fn frame_word(&self, value: u8, stop: bool) -> u32 {
+        if stop {
+            tx_word(value, stop) | self.idle_timeout
+        } else {
+            tx_word(value, stop)
+        }
+    }
+
+    /// Write `word` made by [`tx_word`] to the TX FIFO, which must have room for it.
+    #[inline]
+    fn write_tx(&mut self, word: u32) {
+        #[cfg(feature = "flight-recorder")]
+        self.record_sent(word);
+        self.tx.write(word);
+    }
+
+    /// Read the next entry of the RX FIFO, a byte or [`FRAME_END_MARKER`].
+    #[inline]
+    fn read_rx(&mut self) -> Option<u32> {
+        let value = self.rx.read()?;
+        self.in_frame = value != FRAME_END_MARKER;
+        #[cfg(feature = "flight-recorder")]
+        self.record(match value {
+            FRAME_END_MARKER => FlightEvent::ReceivedFrameEnd,
+            value => FlightEvent::Received(value as u8),
+        });
+        Some(value)
+    }
+
+    /// The RX FIFO, e.g. for setting up DMA or interrupts that this crate doesn't provide.
+    ///
+    /// Each entry is a received byte in the low 8 bits, or all ones marking the end of a received frame.
+    /// Reading from it takes bytes away from the rest of the port.
+    pub fn rx_mut(&mut self) -> &mut Rx<(P, S)> {
+        &mut self.rx
+    }
+
+    /// The TX FIFO, e.g. for setting up DMA or interrupts that this crate doesn't provide.
+    ///
+    /// The write routine, entered with [`JoybusPort::restart_for_write`], expects each entry to be a byte in the top 8 bits
+    /// followed by bit 23 set on the final byte of the frame, as written by [`JoybusPort::send_frame`].
+    pub fn tx_mut(&mut self) -> &mut Tx<(P, S)> {
+        &mut self.tx
+    }
+
+    /// The state machine running [`PROGRAM`], e.g. for setting up DMA or interrupts that this crate doesn't provide.
+    ///
+    /// Stopping it or changing its configuration can easily break the protocol.
+    pub fn sm_mut(&mut self) -> &mut StateMachine<(P, S), Running> {
+        &mut self.sm
+    }
+
+    /// Returns true if the state machine has seen a received frame end since the last call, clearing the flag.
+    ///
+    /// The program raises [`FRAME_END_IRQ`] once the line has stayed high for around 6us after a bit,
+    /// which never happens within a frame, and discards the stop bit that was shifted in.
+    /// The same happens in order with the received bytes inside the RX FIFO, which is what [`JoybusPort::recv_frame`] relies on,
+    /// the flag is for waking up or polling from elsewhere, e.g. an interrupt handler.
+    /// It is also raised without a frame once the line has been idle for the timeout set by [`JoybusPort::set_idle_timeout`].
+    pub fn take_frame_end(&mut self) -> bool {
+        let mask = 1 << ((FRAME_END_IRQ as usize + S::id()) % 4);
+        if self.registers.irq().read().bits() & mask == 0 {
+            return false;
+        }
+        // Safety: writing 1 clears only our own flag.
+        self.registers.irq().write(|w| unsafe { w.bits(mask) });
+        true
+    }
+
+    /// Receive a single byte, returning None if nothing arrives within `timeout_us` microseconds.
+    pub fn recv_byte(&mut self, timer: &Timer, timeout_us: u64) -> Option<u8> {
+        let instant = timer.get_counter();
+
+        loop {
+            match self.try_recv_byte() {
+                Some(value) => return Some(value),
+                None => {
+                    if timer
+                        .get_counter()
+                        .checked_duration_since(instant)
+                        .unwrap()
+                        .ticks()
+                        > timeout_us
+                    {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Same as [`JoybusPort::recv_byte`] but also returns the microseconds from the falling edge that started the byte
+    /// until it was received, which reveals the bit rate of the sender.
+    /// The state machine must be at the start of a frame, e.g. just after [`JoybusPort::restart_for_read`].
+    #[cfg(feature = "detect")]
+    pub(crate) fn recv_byte_timed(&mut self, timer: &Timer, timeout_us: u64) -> Option<(u8, u64)> {
+        let start = timer.get_counter();
+        while self.data_pin.as_input().is_high().unwrap() {
+            if timer
+                .get_counter()
+                .checked_duration_since(start)
+                .unwrap()
+                .ticks()
+                > timeout_us
+            {
+                return None;
+            }
+        }
+        let edge = timer.get_counter();
+        let value = self.recv_byte(timer, FRAME_GAP_US)?;
+        let duration = timer
+            .get_counter()
+            .checked_duration_since(edge)
+            .unwrap()
+            .ticks();
+        Some((value, duration))
+    }
+
+    /// Returns the next received byte if there is one, without waiting.
+    /// Frame boundaries are skipped over, use [`JoybusPort::recv_frame`] to receive whole frames.
+    pub fn try_recv_byte(&mut self) -> Option<u8> {
+        loop {
+            match self.read_rx()? {
+                FRAME_END_MARKER => {}
+                value => return Some(value as u8),
+            }
+        }
+    }
+
+    /// Returns the next entry of the RX FIFO if there is one, without waiting:
+    /// a received byte, or None for the end of a frame.
+    pub(crate) fn try_recv_entry(&mut self) -> Option<Option<u8>> {
+        self.read_rx()
+            .map(|value| (value != FRAME_END_MARKER).then_some(value as u8))
+    }
+
+    /// Receive a single byte like [`JoybusPort::recv_byte`] but without a [`Timer`],
+    /// returning None once the state machine reports the line idle for the timeout set by [`JoybusPort::set_idle_timeout`].
+    ///
+    /// The timeout is counted by the state machine from the end of the last frame, whether sent or received,
+    /// so this returns None straight away if the line has already been idle for that long.
+    /// Without a timeout set this can wait for up to [`MAX_IDLE_TIMEOUT_US`].
+    pub fn recv_byte_until_idle(&mut self) -> Option<u8> {
+        loop {
+            let in_frame = self.in_frame;
+            match self.read_rx() {
+                // the end of a frame that has bytes is normal, only an empty one means the line stayed idle
+                Some(FRAME_END_MARKER) if !in_frame => return None,
+                Some(FRAME_END_MARKER) | None => {}
+                Some(value) => return Some(value as u8),
+            }
+        }
+    }
+
+    /// Have the state machine report the line idle after `timeout_us` microseconds without a frame, or stop if None.
+    /// This is what [`JoybusPort::recv_byte_until_idle`] times out on, so minimal builds can receive without a [`Timer`].
+    ///
+    /// The timeout is rounded up to a multiple of [`IDLE_TIMEOUT_STEP_NS`] and capped at [`MAX_IDLE_TIMEOUT_US`].
+    /// It is carried to the state machine by the stop bit of every frame sent and by [`JoybusPort::restart_for_read`],
+    /// so it takes effect after whichever of those happens next.
+    ///
+    /// While the line stays idle the state machine reports it again every `timeout_us`, pushing an empty frame into the RX FIFO
+    /// and raising [`FRAME_END_IRQ`], so keep reading the port while a short timeout is set, or set None again.
+    pub fn set_idle_timeout(&mut self, timeout_us: Option<u32>) {
+        let steps = match timeout_us {
+            Some(timeout_us) => (timeout_us as u64 * 1000)
+                .div_ceil(IDLE_TIMEOUT_STEP_NS as u64)
+                .clamp(1, IDLE_TIMEOUT_STEPS as u64) as u32,
+            None => IDLE_TIMEOUT_STEPS,
+        };
+        // the state machine counts down from the inverse of what the stop bit leaves in the OSR
+        self.idle_timeout = IDLE_TIMEOUT_STEPS - steps;
+    }
+
+    /// Receive a frame into `buffer`, returning the number of bytes received
+    /// or None if the frame doesn't start within `timeout_us` microseconds.
+    ///
+    /// The frame ends once `buffer` is full or the state machine marks the end of the frame,
+    /// which it does after the line has been idle for around 6us, so frames of any length are received exactly.
+    /// If neither happens because no byte arrives for [`FRAME_GAP_US`], e.g. the marker was dropped because the RX FIFO was full,
+    /// the state machine is restarted with [`JoybusPort::restart_for_read`] to discard the stop bit.
+    /// When the buffer fills up first, this returns straight away so that a response can be sent with as little delay as possible,
+    /// the stop bit is then discarded by the next [`JoybusPort::send_frame`] or [`JoybusPort::restart_for_read`].
+    pub fn recv_frame(
+        &mut self,
+        timer: &Timer,
+        buffer: &mut [u8],
+        timeout_us: u64,
+    ) -> Option<usize> {
+        if buffer.is_empty() {
+            return Some(0);
+        }
+        buffer[0] = self.recv_byte(timer, timeout_us)?;
+        for (i, byte) in buffer.iter_mut().enumerate().skip(1) {
+            match self.recv_frame_byte(timer) {
+                Some(value) => *byte = value,
+                None => return Some(i),
+            }
+        }
+        Some(buffer.len())
+    }
+
+    /// Receive the next byte of the frame being received, or None once the frame has ended.
+    fn recv_frame_byte(&mut self, timer: &Timer) -> Option<u8> {
+        let instant = timer.get_counter();
+        loop {
+            match self.read_rx() {
+                Some(FRAME_END_MARKER) => return None,
+                Some(value) => return Some(value as u8),
+                None => {}
+            }
+            if timer
+                .get_counter()
+                .checked_duration_since(instant)
+                .unwrap()
+                .ticks()
+                > FRAME_GAP_US
+            {
+                self.restart_for_read(timer);
+                return None;
+            }
+        }
+    }
+
+    /// Queue `values` for transmission as a single frame, the last byte is followed by a stop bit.
+    /// Does nothing if `values` is empty.
+    ///
+    /// This returns as soon as the last byte is in the TX FIFO, which is well before it is on the wire.
+    /// Use [`JoybusPort::flush`] to wait for the stop bit to finish.
+    ///
+    /// If the TX FIFO runs dry before the last byte is queued, e.g. because an interrupt stalled the CPU,
+    /// the write routine would hold the line high waiting for the next byte, stretching the frame into something the other end can't read.
+    /// Instead the frame is abandoned: the line is released, the state machine returns to the read routine
+    /// and the event is counted in [`JoybusPort::tx_underruns`].
+    pub fn send_frame(&mut self, values: &[u8]) {
+        self.send_frame_then(values, || {});
+    }
+
+    /// Same as [`JoybusPort::send_frame`] but calls `first_byte_queued` as soon as the first byte is in the TX FIFO.
+    /// Returns false if the frame was abandoned because the TX FIFO underran.
+    pub(crate) fn send_frame_then(
+        &mut self,
+        values: &[u8],
+        first_byte_queued: impl FnOnce(),
+    ) -> bool {
+        if values.is_empty() {
+            return true;
+        }
+
+        #[cfg(feature = "fault-injection")]
+        let faults = self
+            .fault_injector
+            .as_mut()
+            .map(|injector| injector.next_frame(values.len()));
+        #[cfg(feature = "fault-injection")]
+        if let Some(cycles) = faults.as_ref().and_then(|faults| faults.delay_cycles) {
+            cortex_m::asm::delay(cycles);
+        }
+
+        // make sure we don't restart the SM in the middle of a previous transmission
+        self.flush();
+
+        // wait for line to be high
+        while self.data_pin.as_input().is_low().unwrap() {}
+
+        self.restart_for_write();
+
+        let mut first_byte_queued = Some(first_byte_queued);
+        for (i, value) in values.iter().enumerate() {
+            let (value, stop) = (*value, i == values.len() - 1);
+            #[cfg(feature = "fault-injection")]
+            let (value, stop) = faults
+                .as_ref()
+                .map_or((value, stop), |faults| faults.apply(i, value, stop));
+            let word = self.frame_word(value, stop);
+
+            if i == 0 {
+                self.write_first_tx(word);
+            } else {
+                if self.underran(i, values.len()) {
+                    return false;
+                }
+                while self.tx.is_full() {}
+                self.write_tx(word);
+            }
+
+            if let Some(callback) = first_byte_queued.take() {
+                callback();
+            }
+        }
+
+        #[cfg(feature = "fault-injection")]
+        if faults.is_some_and(|faults| faults.drop_stop_bit) {
+            // Without a stop bit the write routine stalls waiting for another byte with the line high,
+            // so return to the read routine once the final byte is out.
+            // The final byte is out within a byte time of the FIFO draining, give up waiting after the whole frame.
+            let start = timer_us();
+            let limit = (values.len() as u32 + 1) * crate::protocol::BYTE_US as u32;
+            while !self.tx_stalled() && timer_us().wrapping_sub(start) < limit {}
+            self.restart_at(0);
+        }
+        true
+    }
+
+    /// How many frames [`JoybusPort::send_frame`] has abandoned because the TX FIFO ran dry mid frame.
+    pub fn tx_underruns(&self) -> u32 {
+        self.tx_underruns
+    }
+
+    /// Start the write routine for a frame fed by [`JoybusPort::queue_frame`], unless the line is still held low
+    /// by the end of the previous frame. Returns true if the frame was started.
+    pub(crate) fn try_start_frame(&mut self) -> bool {
+        if self.data_pin.as_input().is_low().unwrap() {
+            return false;
+        }
+        self.restart_for_write();
+        true
+    }
+
+    /// Queue as many of `values` after the first `queued` as fit in the TX FIFO without blocking,
+    /// returning how many are queued in total, or None if the frame was abandoned because the TX FIFO underran.
+    ///
+    /// The frame must be started with [`JoybusPort::try_start_frame`] before the first call,
+    /// and this must then be called again before the FIFO runs dry until every byte is queued.
+    pub(crate) fn queue_frame(&mut self, values: &[u8], mut queued: usize) -> Option<usize> {
+        while queued < values.len() && !self.tx.is_full() {
+            let word = self.frame_word(values[queued], queued == values.len() - 1);
+            if queued == 0 {
+                self.write_first_tx(word);
+            } else if self.underran(queued, values.len()) {
+                return None;
+            } else {
+                self.write_tx(word);
+            }
+            queued += 1;
+        }
+        Some(queued)
+    }
+
+    /// Queue the first byte of a frame started with [`JoybusPort::restart_for_write`].
+    ///
+    /// The write routine stalls on the empty TX FIFO as soon as it is restarted, so once it has pulled the first byte
+    /// its TX stall flag is cleared, leaving the flag set only by an underrun later in the frame.
+    fn write_first_tx(&mut self, word: u32) {
+        cortex_m::interrupt::free(|_| {
+            self.write_tx(word);
+            // the pull completes within a few cycles, and the next one is a byte time away
+            while !self.tx.is_empty() {}
+            self.registers
+                .fdebug()
+                .write(|w| unsafe { w.bits(tx_stall_flag(S::id())) });
+        });
+    }
+
+    /// Whether the write routine has stalled on an empty TX FIFO since the first byte of the frame was queued.
+    fn tx_stalled(&self) -> bool {
+        tx_stalled(self.registers.fdebug().read().bits(), S::id())
+    }
+
+    /// Check for the TX FIFO running dry after `queued` of `len` bytes and abandon the frame if it has.
+    ///
+    /// The write routine only stalls between bytes once it has shifted out everything queued,
+    /// by then the line has been held high for longer than a bit and the frame is lost.
+    fn underran(&mut self, queued: usize, len: usize) -> bool {
+        if !self.tx_stalled() {
+            return false;
+        }
+        self.restart_at(0);
+        self.tx_underruns = self.tx_underruns.saturating_add(1);
+        warn!(
+            "joybus: TX FIFO underran after {} of {} bytes, frame abandoned",
+            queued, len
+        );
+        true
+    }
+
+    /// The address of this port's TX FIFO, for DMA to write words made by [`tx_word`] to.
+    pub(crate) fn tx_fifo_address(&self) -> u32 {
+        self.registers.txf(S::id()).as_ptr() as u32
+    }
+
+    /// The DREQ that paces DMA writes to this port's TX FIFO.
+    pub(crate) fn tx_dreq() -> u8 {
+        // PIO0's TX FIFOs are DREQs 0 to 3 and PIO1's are 8 to 11
+        (P::id() * 8 + S::id()) as u8
+    }
+
+    /// Returns true once everything queued by [`JoybusPort::send_frame`] including the stop bit has been transmitted.
+    ///
+    /// After writing the stop bit the PIO program jumps straight back into the read routine,
+    /// so transmission is complete once the state machine is no longer executing the write routine.
+    pub fn is_send_complete(&self) -> bool {
+        self.tx.is_empty() && (self.sm.instruction_address() as u8) < WRITE_ADDRESS
+    }
+
+    /// Blocks until everything queued by [`JoybusPort::send_frame`] including the stop bit has been transmitted.
+    pub fn flush(&mut self) {
+        while !self.is_send_complete() {}
+    }
+
+    /// Restart the state machine into the read routine, discarding any partially received byte.
+    ///
+    /// To avoid losing a frame that is on its way in, this first waits for the bus to be idle for [`BUS_IDLE_US`],
+    /// then confirms the line is still high and restarts in a single critical section.
+    /// A restart takes a handful of cycles, well within the first microsecond of a bit,
+    /// so a frame starting right after the check will still be read correctly.
+    ///
+    /// If the line never goes idle, e.g. because the other end is unplugged, this gives up waiting after
+    /// [`BUS_IDLE_GIVE_UP_US`] and restarts anyway.
+    pub fn restart_for_read(&mut self, timer: &Timer) {
+        let start = timer.get_counter();
+        let mut high_since = start;
+        loop {
+            let now = timer.get_counter();
+            if self.data_pin.as_input().is_low().unwrap() {
+                high_since = now;
+            }
+            let idle = now.checked_duration_since(high_since).unwrap().ticks() >= BUS_IDLE_US;
+            let gave_up = now.checked_duration_since(start).unwrap().ticks() > BUS_IDLE_GIVE_UP_US;
+
+            if gave_up {
+                warn!("joybus: bus never went idle, restarting anyway");
+            }
+            if idle || gave_up {
+                let restarted = cortex_m::interrupt::free(|_| {
+                    // a frame may have started since we last sampled the line
+                    if gave_up || self.data_pin.as_input().is_high().unwrap() {
+                        self.restart_at(0);
+                        true
+                    } else {
+                        false
+                    }
+                });
+                if restarted {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Restart the state machine into the write routine, discarding anything queued or partially received.
+    pub fn restart_for_write(&mut self) {
+        self.restart_at(WRITE_ADDRESS);
+    }
+
+    /// Clear the FIFOs and any partially shifted bits, then continue execution from `address`.
+    /// Restarting into the read routine also loads the idle timeout.
+    fn restart_at(&mut self, address: u8) {
+        self.sm.clear_fifos();
+        self.sm.restart();
+        self.take_frame_end();
+        self.in_frame = false;
+        if address == 0 {
+            // the pull stalls the state machine until the word is there, so it can't run off with it in the write routine.
+            // a restart empties the OSR again, so the write routine still pulls its first byte afterwards
+            self.sm.exec_instruction(Instruction {
+                operands: InstructionOperands::PULL {
+                    if_empty: false,
+                    block: true,
+                },
+                delay: 0,
+                side_set: None,
+            });
+            self.tx.write(self.idle_timeout << 9);
+        }
+        self.jump(address);
+    }
+
+    /// Continue execution from `address` without touching the FIFOs or shift registers.
+    pub(crate) fn jump(&mut self, address: u8) {
+        self.sm.exec_instruction(Instruction {
+            operands: InstructionOperands::JMP {
+                condition: pio::JmpCondition::Always,
+                address,
+            },
+            delay: 0,
+            side_set: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tx_stall_flags() {
+        // SM1 stalled on its TX FIFO, alongside an RX stall on SM0 and SM3 and a TX overflow on SM2
+        let fdebug = 1 << 25 | 1 << 0 | 1 << 3 | 1 << 18;
+        assert!(tx_stalled(fdebug, 1));
+        assert!(!tx_stalled(fdebug, 0));
+        assert!(!tx_stalled(fdebug, 2));
+        assert!(!tx_stalled(fdebug, 3));
+        assert_eq!(tx_stall_flag(3), 0x0800_0000);
+    }
+}
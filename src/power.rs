@@ -0,0 +1,62 @@
+use embedded_hal::digital::InputPin;
+
+/// Tracks whether the console is powered via a pin connected to the 5V (or 3.3V) line of the controller cable.
+///
+/// The pin must be level shifted or divided down to 3.3V.
+pub struct PowerSense<P> {
+    pin: P,
+    powered: bool,
+    debounce_samples: u8,
+    /// How many consecutive samples have disagreed with `powered`.
+    disagreeing: u8,
+}
+
+/// A change in console power reported by [`PowerSense::update`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerEvent {
+    PoweredOn,
+    PoweredOff,
+}
+
+impl<P: InputPin> PowerSense<P> {
+    /// `debounce_samples` is how many consecutive calls to [`PowerSense::update`] must see the new level before a change is reported.
+    pub fn new(mut pin: P, debounce_samples: u8) -> PowerSense<P> {
+        let powered = pin.is_high().unwrap();
+        PowerSense {
+            pin,
+            powered,
+            debounce_samples,
+            disagreeing: 0,
+        }
+    }
+
+    /// Whether the console was powered as of the last [`PowerSense::update`].
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Sample the pin, returning an event if the debounced power state changed.
+    pub fn update(&mut self) -> Option<PowerEvent> {
+        if self.pin.is_high().unwrap() == self.powered {
+            self.disagreeing = 0;
+            return None;
+        }
+
+        self.disagreeing += 1;
+        if self.disagreeing < self.debounce_samples {
+            return None;
+        }
+
+        self.disagreeing = 0;
+        self.powered = !self.powered;
+        Some(if self.powered {
+            PowerEvent::PoweredOn
+        } else {
+            PowerEvent::PoweredOff
+        })
+    }
+
+    pub fn free(self) -> P {
+        self.pin
+    }
+}
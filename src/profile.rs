@@ -0,0 +1,269 @@
+//! Stored profiles that can be switched between at runtime by holding a button combo, without a PC.
+//!
+//! A [`Profile`] bundles the button and stick mapping from [`crate::layers`] with the stick shaping from [`crate::curve`].
+//! [`ProfileSwitcher`] holds `N` of them, and each can be given a combo that makes it the active profile
+//! once all of its buttons, and no others, have been held for [`ProfileSwitcher::set_hold_polls`] polls:
+//!
+//! ```ignore
+//! let mut profiles = ProfileSwitcher::load(&mut storage, PROFILES_OFFSET)?;
+//! profiles.set_combo(0, &[Button::Start, Button::DpadLeft]);
+//! profiles.set_combo(1, &[Button::Start, Button::DpadRight]);
+//! profiles.set_on_switch(Some(|profile| LED.blink(profile as u8 + 1)));
+//! controller.respond_to_poll(&timer, &mut delay, profiles.apply(&input));
+//! ```
+//!
+//! The buttons of a combo are still sent to the console while held, so pick combos that are harmless in game.
+//! The active profile is saved along with the profiles, so it survives a power cycle.
+
+use crate::curve::{Shaping, SHAPING_LEN};
+use crate::layers::{Layers, LAYERS_LEN};
+use crate::remap::Button;
+use crate::GamecubeInput;
+
+/// The length of [`Profile::to_bytes`].
+pub const PROFILE_LEN: usize = 1 + LAYERS_LEN + SHAPING_LEN;
+
+/// Bumped whenever the layout of [`Profile::to_bytes`] changes, so old data is rejected instead of misread.
+const PROFILE_VERSION: u8 = 1;
+
+/// The default number of polls a combo is held for before switching, about a second at typical polling rates.
+pub const DEFAULT_HOLD_POLLS: u16 = 60;
+
+/// Everything that is switched between by a [`ProfileSwitcher`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Profile {
+    pub layers: Layers,
+    pub shaping: Shaping,
+}
+
+impl Profile {
+    /// Shape the sticks of `input` then map it through the layers.
+    pub fn apply(&mut self, input: &GamecubeInput) -> GamecubeInput {
+        self.layers.apply(&self.shaping.apply(input))
+    }
+
+    /// Serialize for persisting, e.g. through a [`crate::storage::WearLevelled`].
+    pub fn to_bytes(&self) -> [u8; PROFILE_LEN] {
+        let mut bytes = [0; PROFILE_LEN];
+        bytes[0] = PROFILE_VERSION;
+        bytes[1..1 + LAYERS_LEN].copy_from_slice(&self.layers.to_bytes());
+        bytes[1 + LAYERS_LEN..].copy_from_slice(&self.shaping.to_bytes());
+        bytes
+    }
+
+    /// Deserialize what was written by [`Profile::to_bytes`].
+    /// Returns None if `bytes` is from a different version or is invalid, e.g. erased flash.
+    pub fn from_bytes(bytes: &[u8; PROFILE_LEN]) -> Option<Profile> {
+        if bytes[0] != PROFILE_VERSION {
+            return None;
+        }
+        Some(Profile {
+            layers: Layers::from_bytes(bytes[1..1 + LAYERS_LEN].try_into().unwrap())?,
+            shaping: Shaping::from_bytes(bytes[1 + LAYERS_LEN..].try_into().unwrap())?,
+        })
+    }
+}
+
+/// `N` profiles and the combos that switch between them, see the [module docs](self).
+///
+/// This holds the state of the combos and the active profile's layers, so [`ProfileSwitcher::apply`] must be called for every poll.
+#[derive(Debug, Clone, Copy)]
+pub struct ProfileSwitcher<const N: usize> {
+    profiles: [Profile; N],
+    /// Bits in the order of [`Button::ALL`] of the combo for each profile, 0 if it has none.
+    combos: [u16; N],
+    active: usize,
+    hold_polls: u16,
+    /// The profile whose combo is currently held and for how many polls.
+    held: Option<(usize, u16)>,
+    on_switch: Option<fn(usize)>,
+}
+
+impl<const N: usize> ProfileSwitcher<N> {
+    /// Start with `profiles[active]` active and no combos set.
+    pub const fn new(profiles: [Profile; N], active: usize) -> ProfileSwitcher<N> {
+        assert!(active < N, "active profile out of range");
+        ProfileSwitcher {
+            profiles,
+            combos: [0; N],
+            active,
+            hold_polls: DEFAULT_HOLD_POLLS,
+            held: None,
+            on_switch: None,
+        }
+    }
+
+    /// Switch to `profile` after `buttons` are held, or never switch to it by combo if `buttons` is empty.
+    pub fn set_combo(&mut self, profile: usize, buttons: &[Button]) {
+        self.combos[profile] = buttons
+            .iter()
+            .fold(0, |combo, button| combo | 1 << *button as u16);
+    }
+
+    /// How many consecutive polls a combo must be held for before switching, [`DEFAULT_HOLD_POLLS`] by default.
+    pub fn set_hold_polls(&mut self, polls: u16) {
+        self.hold_polls = polls;
+    }
+
+    /// Call the function with the index of the new profile whenever a combo switches profile,
+    /// e.g. to flash an LED or pulse rumble as an acknowledgment.
+    /// It runs inside [`ProfileSwitcher::apply`], so must be fast enough to still respond to the poll in time.
+    pub fn set_on_switch(&mut self, on_switch: Option<fn(usize)>) {
+        self.on_switch = on_switch;
+    }
+
+    /// The index of the active profile.
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Make `profiles[active]` the active profile, without calling the switch callback.
+    pub fn set_active(&mut self, active: usize) {
+        assert!(active < N, "active profile out of range");
+        self.active = active;
+        self.profiles[active].layers.reset();
+    }
+
+    pub fn profile(&mut self, profile: usize) -> &mut Profile {
+        &mut self.profiles[profile]
+    }
+
+    /// Check the combos against the buttons held in `input`, then apply the active profile to it.
+    /// The combo is checked first, so the poll that completes a combo is already sent with the new profile.
+    pub fn apply(&mut self, input: &GamecubeInput) -> GamecubeInput {
+        let mut held = 0;
+        for (i, button) in Button::ALL.into_iter().enumerate() {
+            if button.is_pressed(input) {
+                held |= 1 << i;
+            }
+        }
+
+        let matching = self
+            .combos
+            .iter()
+            .position(|combo| *combo != 0 && *combo == held);
+        self.held = match (matching, self.held) {
+            (Some(profile), Some((held_profile, polls))) if profile == held_profile => {
+                Some((profile, polls.saturating_add(1)))
+            }
+            (Some(profile), _) => Some((profile, 1)),
+            (None, _) => None,
+        };
+        // switch exactly once per press of the combo
+        if let Some((profile, polls)) = self.held {
+            if polls == self.hold_polls.max(1) {
+                debug!("joybus: switching to profile {}", profile);
+                self.set_active(profile);
+                if let Some(on_switch) = self.on_switch {
+                    on_switch(profile);
+                }
+            }
+        }
+
+        self.profiles[self.active].apply(input)
+    }
+
+    /// Load the profiles and the active profile from `storage` at `offset`,
+    /// any profile that has not been saved falls back to [`Profile::default`].
+    #[cfg(feature = "storage")]
+    pub fn load<S: crate::storage::ReadStorage>(
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<ProfileSwitcher<N>, S::Error> {
+        let mut active = [0];
+        storage.read(offset, &mut active)?;
+        let mut profiles = [Profile::default(); N];
+        for (i, profile) in profiles.iter_mut().enumerate() {
+            let mut bytes = [0; PROFILE_LEN];
+            storage.read(offset + 1 + (i * PROFILE_LEN) as u32, &mut bytes)?;
+            *profile = Profile::from_bytes(&bytes).unwrap_or_default();
+        }
+        let active = match active[0] as usize {
+            active if active < N => active,
+            _ => 0,
+        };
+        Ok(ProfileSwitcher::new(profiles, active))
+    }
+
+    /// Save the profiles and the active profile to `storage` at `offset`, using 1 + `N` * [`PROFILE_LEN`] bytes.
+    #[cfg(feature = "storage")]
+    pub fn save<S: crate::storage::Storage>(
+        &self,
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<(), S::Error> {
+        storage.write(offset, &[self.active as u8])?;
+        for (i, profile) in self.profiles.iter().enumerate() {
+            storage.write(offset + 1 + (i * PROFILE_LEN) as u32, &profile.to_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    fn held(buttons: &[Button]) -> GamecubeInput {
+        let mut input = GamecubeInput::NEUTRAL;
+        for button in buttons {
+            button.set_pressed(&mut input, true);
+        }
+        input
+    }
+
+    static SWITCHES: AtomicU32 = AtomicU32::new(0);
+
+    #[test]
+    fn combo() {
+        let mut profiles = ProfileSwitcher::new([Profile::default(); 3], 0);
+        profiles.set_combo(1, &[Button::Start, Button::X]);
+        profiles.set_combo(2, &[Button::Start, Button::Y]);
+        profiles.set_hold_polls(3);
+        profiles.set_on_switch(Some(|_| {
+            SWITCHES.fetch_add(1, Ordering::Relaxed);
+        }));
+
+        let combo = held(&[Button::Start, Button::X]);
+        let extra = held(&[Button::Start, Button::X, Button::A]);
+        // interrupted by an extra button, so the count starts over
+        for input in [&combo, &combo, &extra, &combo, &combo] {
+            profiles.apply(input);
+            assert_eq!(profiles.active(), 0);
+        }
+        // held on, the switch only happens once
+        for _ in 0..5 {
+            profiles.apply(&combo);
+            assert_eq!(profiles.active(), 1);
+        }
+        assert_eq!(SWITCHES.load(Ordering::Relaxed), 1);
+
+        profiles.set_hold_polls(0);
+        profiles.apply(&held(&[Button::Start, Button::Y]));
+        assert_eq!(profiles.active(), 2);
+        profiles.apply(&GamecubeInput::NEUTRAL);
+        assert_eq!(profiles.active(), 2);
+        assert_eq!(SWITCHES.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn no_combo() {
+        let mut profiles = ProfileSwitcher::new([Profile::default(); 2], 1);
+        profiles.set_hold_polls(1);
+        profiles.set_combo(0, &[]);
+        for input in [GamecubeInput::NEUTRAL, held(&Button::ALL)] {
+            profiles.apply(&input);
+            assert_eq!(profiles.active(), 1);
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        let profile = Profile::default();
+        let bytes = profile.to_bytes();
+        assert_eq!(bytes[0], PROFILE_VERSION);
+        assert_eq!(Profile::from_bytes(&bytes), Some(profile));
+        assert_eq!(Profile::from_bytes(&[0xFF; PROFILE_LEN]), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! Named constants for the bytes and timings of the joybus wire protocol.
+//!
+//! The device, host and detection code all use these, so they always agree on what goes over the wire.
+//! Opcodes are the first byte of a command, lengths are in bytes excluding the stop bit,
+//! and button masks are for the two button bytes at the start of a poll response.
+
+use crate::BITRATE;
+
+// Gamecube and N64 opcodes
+
+/// Asks for the device identifier. The N64 calls this the info command.
+pub const CMD_PROBE: u8 = 0x00;
+/// Asks for the current inputs of a gamecube controller, followed by the poll mode and rumble bytes.
+pub const CMD_POLL: u8 = 0x40;
+/// Asks a gamecube controller for the neutral positions of its sticks and triggers.
+pub const CMD_ORIGIN: u8 = 0x41;
+/// Asks a gamecube controller to recalibrate and respond with its new origin.
+pub const CMD_RECALIBRATE: u8 = 0x42;
+/// Resets the device, which then responds with its identifier like a probe.
+pub const CMD_RESET: u8 = 0xFF;
+/// Asks for the current inputs of an N64 controller.
+pub const CMD_N64_POLL: u8 = 0x01;
+/// Reads a block from an N64 controller pak, followed by the 2 byte address.
+pub const CMD_PAK_READ: u8 = 0x02;
+/// Writes a block to an N64 controller pak, followed by the 2 byte address and the block.
+pub const CMD_PAK_WRITE: u8 = 0x03;
+/// Asks a gamecube keyboard for the held keys, followed by 2 bytes.
+pub const CMD_KEYBOARD_POLL: u8 = 0x54;
+
+// Frame lengths
+
+/// A gamecube poll: the opcode, the poll mode and the rumble byte.
+pub const POLL_COMMAND_LEN: usize = 3;
+/// The response to a probe or reset: 2 identifier bytes and a status byte.
+pub const ID_RESPONSE_LEN: usize = 3;
+/// The response to a gamecube poll, see [`crate::report`].
+pub const POLL_RESPONSE_LEN: usize = 8;
+/// The response to an origin or recalibrate: a mode 0 poll response followed by 2 reserved bytes.
+pub const ORIGIN_RESPONSE_LEN: usize = 10;
+/// The response to an N64 poll: 2 button bytes then the stick x and y.
+pub const N64_POLL_RESPONSE_LEN: usize = 4;
+/// The size of an N64 pak block.
+pub const PAK_BLOCK_LEN: usize = 32;
+/// The response to a pak read: the block followed by its CRC.
+pub const PAK_READ_RESPONSE_LEN: usize = PAK_BLOCK_LEN + 1;
+/// The response to a keyboard poll: a counter, 3 reserved bytes, 3 keys and a checksum.
+pub const KEYBOARD_POLL_RESPONSE_LEN: usize = 8;
+
+// Device identifiers, the first 2 bytes of the response to a probe
+
+pub const GAMECUBE_CONTROLLER_ID: [u8; 2] = [0x09, 0x00];
+pub const N64_CONTROLLER_ID: [u8; 2] = [0x05, 0x00];
+pub const KEYBOARD_ID: [u8; 2] = [0x08, 0x20];
+/// A WaveBird receiver with a controller paired: wireless, receiving and without rumble.
+pub const WAVEBIRD_ID: [u8; 2] = [0xE9, 0x00];
+/// A gamecube controller without a rumble motor.
+pub const NO_RUMBLE_CONTROLLER_ID: [u8; 2] = [0x29, 0x00];
+pub const STEERING_WHEEL_ID: [u8; 2] = [0x08, 0x00];
+
+// Timing
+
+/// The length of a single bit on the wire.
+pub const BIT_US: u64 = 4;
+/// The length of a byte on the wire.
+pub const BYTE_US: u64 = 8 * BIT_US;
+/// How long OEM controllers wait after the stop bit of a command before starting their response.
+pub const REPLY_DELAY_US: u32 = 4;
+
+// Poll response buttons1
+
+pub const BUTTONS1_A: u8 = 0b0000_0001;
+pub const BUTTONS1_B: u8 = 0b0000_0010;
+pub const BUTTONS1_X: u8 = 0b0000_0100;
+pub const BUTTONS1_Y: u8 = 0b0000_1000;
+pub const BUTTONS1_START: u8 = 0b0001_0000;
+/// Set by the controller when it wants the console to read its origin again.
+pub const BUTTONS1_ORIGIN_REQUEST: u8 = 0b0010_0000;
+/// Set by the controller once an error has occurred, until the console resets it.
+pub const BUTTONS1_ERROR_LATCH: u8 = 0b0100_0000;
+/// Set by the controller while it is in an error state.
+pub const BUTTONS1_ERROR: u8 = 0b1000_0000;
+/// The status flags of buttons1, every other bit is a button.
+pub const BUTTONS1_STATUS_MASK: u8 =
+    BUTTONS1_ORIGIN_REQUEST | BUTTONS1_ERROR_LATCH | BUTTONS1_ERROR;
+
+// Poll response buttons2
+
+pub const BUTTONS2_DPAD_LEFT: u8 = 0b0000_0001;
+pub const BUTTONS2_DPAD_RIGHT: u8 = 0b0000_0010;
+pub const BUTTONS2_DPAD_DOWN: u8 = 0b0000_0100;
+pub const BUTTONS2_DPAD_UP: u8 = 0b0000_1000;
+pub const BUTTONS2_Z: u8 = 0b0001_0000;
+pub const BUTTONS2_R: u8 = 0b0010_0000;
+pub const BUTTONS2_L: u8 = 0b0100_0000;
+/// Always set by OEM controllers, some consoles and adapters ignore responses without it.
+/// This is the flag telling the console the controller's origin is in use.
+pub const BUTTONS2_ALWAYS_SET: u8 = 0b1000_0000;
+
+// N64 poll response buttons1
+
+pub const N64_BUTTONS1_DPAD_RIGHT: u8 = 0b0000_0001;
+pub const N64_BUTTONS1_DPAD_LEFT: u8 = 0b0000_0010;
+pub const N64_BUTTONS1_DPAD_DOWN: u8 = 0b0000_0100;
+pub const N64_BUTTONS1_DPAD_UP: u8 = 0b0000_1000;
+pub const N64_BUTTONS1_START: u8 = 0b0001_0000;
+pub const N64_BUTTONS1_Z: u8 = 0b0010_0000;
+pub const N64_BUTTONS1_B: u8 = 0b0100_0000;
+pub const N64_BUTTONS1_A: u8 = 0b1000_0000;
+
+// N64 poll response buttons2
+
+pub const N64_BUTTONS2_C_RIGHT: u8 = 0b0000_0001;
+pub const N64_BUTTONS2_C_LEFT: u8 = 0b0000_0010;
+pub const N64_BUTTONS2_C_DOWN: u8 = 0b0000_0100;
+pub const N64_BUTTONS2_C_UP: u8 = 0b0000_1000;
+pub const N64_BUTTONS2_R: u8 = 0b0001_0000;
+pub const N64_BUTTONS2_L: u8 = 0b0010_0000;
+/// Set instead of [`N64_BUTTONS1_START`] while L, R and Start are held together, the combo that re-zeroes the stick.
+pub const N64_BUTTONS2_RESET: u8 = 0b1000_0000;
+
+const _: () = assert!(BIT_US * BITRATE as u64 == 1_000_000);
@@ -0,0 +1,311 @@
+//! Records the reports sent in response to polls so a session can be dumped later for analysis or replay.
+//!
+//! Consecutive polls answered with the same report are stored as a single run, so a recording grows with how often
+//! the inputs change rather than with how often the console polls.
+//!
+//! The format is [`MAGIC`], then the [`crate::rp2040_hal::Timer`] microsecond timestamp of the first poll as a u64 little endian,
+//! so a recording can be lined up with other event streams,
+//! followed by records of [`RECORD_LEN`] bytes, ending with a record of 0 polls:
+//! * polls in the run: u16 little endian
+//! * microseconds since the start of the previous run: u32 little endian, 0 for the first run
+//! * the report: 8 bytes
+//!
+//! ```ignore
+//! let mut recorder = Recorder::new(storage);
+//! loop {
+//!     controller.respond_to_poll(&timer, &mut delay, input);
+//!     recorder.record(timer.get_counter().ticks(), &controller.last_report())?;
+//! }
+//! ```
+
+use crate::storage::Storage;
+
+/// The first 4 bytes of a recording.
+pub const MAGIC: [u8; 4] = *b"JBR2";
+
+/// The size of [`MAGIC`] and the timestamp that follows it.
+const HEADER_LEN: usize = MAGIC.len() + 8;
+
+/// The size of a single run in a recording.
+pub const RECORD_LEN: usize = 14;
+
+/// Runs are written to storage in batches of this many bytes, to keep the number of writes down.
+const BUFFER_LEN: usize = RECORD_LEN * 16;
+
+/// A run longer than this is split in two.
+const MAX_RUN_POLLS: u16 = u16::MAX - 1;
+
+/// Why a [`Recorder`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordError<E> {
+    /// The storage is out of space.
+    Full,
+    /// Writing to the storage failed.
+    Storage(E),
+}
+
+/// A report that was sent in response to `polls` consecutive polls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordedRun {
+    /// Microseconds from the start of the recording until the first poll of this run.
+    pub start_us: u64,
+    /// The timestamp passed to [`Recorder::record`] for the first poll of this run.
+    pub timestamp_us: u64,
+    pub polls: u16,
+    pub report: [u8; 8],
+}
+
+/// Streams the reports sent in response to polls to `S`, see the [module docs](self) for the format.
+/// `S` would normally be a region of flash set aside for recordings, not a [`crate::storage::WearLevelled`].
+///
+/// Runs are buffered in RAM and written in batches, so [`Recorder::record`] only occasionally touches storage.
+/// It should still be called after the response has been sent rather than while the console is waiting for it.
+pub struct Recorder<S: Storage> {
+    storage: S,
+    /// Where the buffer will be written to.
+    offset: u32,
+    buffer: [u8; BUFFER_LEN],
+    buffered: usize,
+    run: Option<RecordedRun>,
+    first_poll_us: Option<u64>,
+    last_run_start_us: u64,
+}
+
+impl<S: Storage> Recorder<S> {
+    /// Start a recording at the beginning of `storage`.
+    pub fn new(storage: S) -> Recorder<S> {
+        let mut buffer = [0; BUFFER_LEN];
+        buffer[..MAGIC.len()].copy_from_slice(&MAGIC);
+        Recorder {
+            storage,
+            offset: 0,
+            buffer,
+            buffered: HEADER_LEN,
+            run: None,
+            first_poll_us: None,
+            last_run_start_us: 0,
+        }
+    }
+
+    /// Record that `report` was sent in response to a poll at `timestamp_us`.
+    pub fn record(
+        &mut self,
+        timestamp_us: u64,
+        report: &[u8; 8],
+    ) -> Result<(), RecordError<S::Error>> {
+        let first_poll_us = match self.first_poll_us {
+            Some(first_poll_us) => first_poll_us,
+            None => {
+                // the header is still in the buffer, nothing is written until a run has been pushed
+                self.buffer[MAGIC.len()..HEADER_LEN].copy_from_slice(&timestamp_us.to_le_bytes());
+                *self.first_poll_us.insert(timestamp_us)
+            }
+        };
+        if let Some(run) = &mut self.run {
+            if run.report == *report && run.polls < MAX_RUN_POLLS {
+                run.polls += 1;
+                return Ok(());
+            }
+        }
+        if let Some(run) = self.run.take() {
+            self.push_run(&run)?;
+        }
+        self.run = Some(RecordedRun {
+            start_us: timestamp_us.saturating_sub(first_poll_us),
+            timestamp_us,
+            polls: 1,
+            report: *report,
+        });
+        Ok(())
+    }
+
+    /// Write out everything recorded so far and the end marker.
+    /// Returns the storage and the length of the recording in bytes.
+    pub fn finish(mut self) -> Result<(S, u32), RecordError<S::Error>> {
+        if let Some(run) = self.run.take() {
+            self.push_run(&run)?;
+        }
+        if self.buffered + 2 > BUFFER_LEN {
+            self.write_buffer()?;
+        }
+        self.buffer[self.buffered..self.buffered + 2].copy_from_slice(&0u16.to_le_bytes());
+        self.buffered += 2;
+        self.write_buffer()?;
+        Ok((self.storage, self.offset))
+    }
+
+    fn push_run(&mut self, run: &RecordedRun) -> Result<(), RecordError<S::Error>> {
+        if self.buffered + RECORD_LEN > BUFFER_LEN {
+            self.write_buffer()?;
+        }
+        let delta_us = run.start_us - self.last_run_start_us;
+        self.last_run_start_us = run.start_us;
+
+        let record = &mut self.buffer[self.buffered..self.buffered + RECORD_LEN];
+        record[0..2].copy_from_slice(&run.polls.to_le_bytes());
+        record[2..6].copy_from_slice(&u32::try_from(delta_us).unwrap_or(u32::MAX).to_le_bytes());
+        record[6..14].copy_from_slice(&run.report);
+        self.buffered += RECORD_LEN;
+        Ok(())
+    }
+
+    fn write_buffer(&mut self) -> Result<(), RecordError<S::Error>> {
+        if self.offset as usize + self.buffered > self.storage.capacity() {
+            return Err(RecordError::Full);
+        }
+        self.storage
+            .write(self.offset, &self.buffer[..self.buffered])
+            .map_err(RecordError::Storage)?;
+        self.offset += self.buffered as u32;
+        self.buffered = 0;
+        Ok(())
+    }
+}
+
+/// Iterate over the runs of a recording read back from storage.
+/// Returns None if `recording` doesn't start with [`MAGIC`].
+///
+/// Iteration stops at the end marker, at erased flash, or at the end of `recording`.
+pub fn runs(recording: &[u8]) -> Option<Runs<'_>> {
+    let rest = recording.strip_prefix(&MAGIC)?;
+    let (first_poll_us, rest) = rest.split_first_chunk::<8>()?;
+    Some(Runs {
+        rest,
+        first_poll_us: u64::from_le_bytes(*first_poll_us),
+        start_us: 0,
+    })
+}
+
+/// Returned by [`runs`].
+pub struct Runs<'a> {
+    rest: &'a [u8],
+    first_poll_us: u64,
+    start_us: u64,
+}
+
+impl Runs<'_> {
+    /// The timestamp of the first poll in the recording.
+    pub fn first_poll_us(&self) -> u64 {
+        self.first_poll_us
+    }
+}
+
+impl Iterator for Runs<'_> {
+    type Item = RecordedRun;
+
+    fn next(&mut self) -> Option<RecordedRun> {
+        let (record, rest) = self.rest.split_first_chunk::<RECORD_LEN>()?;
+        let polls = u16::from_le_bytes([record[0], record[1]]);
+        if polls == 0 || polls == u16::MAX {
+            return None;
+        }
+        self.rest = rest;
+        let delta_us = u32::from_le_bytes([record[2], record[3], record[4], record[5]]);
+        self.start_us += delta_us as u64;
+        Some(RecordedRun {
+            start_us: self.start_us,
+            timestamp_us: self.first_poll_us + self.start_us,
+            polls,
+            report: record[6..14].try_into().unwrap(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ReadStorage;
+    use core::convert::Infallible;
+
+    /// Storage in RAM that starts out erased.
+    struct Ram<const N: usize>([u8; N]);
+
+    impl<const N: usize> ReadStorage for Ram<N> {
+        type Error = Infallible;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Infallible> {
+            bytes.copy_from_slice(&self.0[offset as usize..offset as usize + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            N
+        }
+    }
+
+    impl<const N: usize> Storage for Ram<N> {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Infallible> {
+            self.0[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record() {
+        let mut recorder = Recorder::new(Ram([0xFF; 512]));
+        let a = [1; 8];
+        let b = [2; 8];
+        for (timestamp_us, report) in [(1_000, &a), (2_000, &a), (3_000, &b), (3_000, &a)] {
+            recorder.record(timestamp_us, report).unwrap();
+        }
+        let (Ram(bytes), len) = recorder.finish().unwrap();
+        assert_eq!(len as usize, HEADER_LEN + 3 * RECORD_LEN + 2);
+        assert_eq!(bytes[..HEADER_LEN], *b"JBR2\xE8\x03\0\0\0\0\0\0");
+        assert_eq!(bytes[HEADER_LEN..HEADER_LEN + 6], [2, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            bytes[HEADER_LEN + RECORD_LEN..][..6],
+            [1, 0, 0xD0, 0x07, 0, 0]
+        );
+
+        let mut runs = runs(&bytes).unwrap();
+        assert_eq!(runs.first_poll_us(), 1_000);
+        let expected = [(0, 2, a), (2_000, 1, b), (2_000, 1, a)];
+        for (start_us, polls, report) in expected {
+            assert_eq!(
+                runs.next(),
+                Some(RecordedRun {
+                    start_us,
+                    timestamp_us: 1_000 + start_us,
+                    polls,
+                    report
+                })
+            );
+        }
+        assert_eq!(runs.next(), None);
+    }
+
+    #[test]
+    fn empty() {
+        let (Ram(bytes), len) = Recorder::new(Ram([0xFF; 64])).finish().unwrap();
+        assert_eq!(len as usize, HEADER_LEN + 2);
+        assert_eq!(runs(&bytes).unwrap().next(), None);
+        assert!(runs(&[]).is_none());
+        assert!(runs(&[0xFF; 64]).is_none());
+        assert!(runs(&MAGIC).is_none());
+        // nothing after the header, or erased flash, is the end of the recording
+        assert_eq!(runs(&bytes[..HEADER_LEN]).unwrap().next(), None);
+        let mut erased = [0xFF; 64];
+        erased[..HEADER_LEN].copy_from_slice(&bytes[..HEADER_LEN]);
+        assert_eq!(runs(&erased).unwrap().next(), None);
+    }
+
+    #[test]
+    fn long_run() {
+        let mut recorder = Recorder::new(Ram([0xFF; 64]));
+        for _ in 0..MAX_RUN_POLLS as u32 + 1 {
+            recorder.record(0, &[0; 8]).unwrap();
+        }
+        let (Ram(bytes), _) = recorder.finish().unwrap();
+        let polls = runs(&bytes).unwrap().map(|run| run.polls);
+        assert!(polls.eq([MAX_RUN_POLLS, 1]));
+    }
+
+    #[test]
+    fn full() {
+        let mut recorder = Recorder::new(Ram([0xFF; 2 * BUFFER_LEN]));
+        let error = (0..).find_map(|i| recorder.record(i, &[i as u8; 8]).err());
+        assert_eq!(error, Some(RecordError::Full));
+        // the header and 15 runs, then 16 runs, but not another 16
+        assert_eq!(recorder.offset as usize, HEADER_LEN + 31 * RECORD_LEN);
+    }
+}
@@ -0,0 +1,431 @@
+//! Runtime button and stick remapping, applied to a [`GamecubeInput`] right before it is encoded.
+//!
+//! ```ignore
+//! let mut remap = Remap::IDENTITY;
+//! remap.set_button(Button::X, Some(Button::Z));
+//! remap.set_axis(Axis::StickY, AxisSource { axis: Axis::StickY, inverted: true });
+//! controller.respond_to_poll(&timer, &mut delay, remap.apply(&input));
+//! ```
+
+use crate::GamecubeInput;
+
+/// A digital button of a gamecube controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Start,
+    A,
+    B,
+    X,
+    Y,
+    Z,
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+    L,
+    R,
+}
+
+impl Button {
+    pub const ALL: [Button; 12] = [
+        Button::Start,
+        Button::A,
+        Button::B,
+        Button::X,
+        Button::Y,
+        Button::Z,
+        Button::DpadUp,
+        Button::DpadDown,
+        Button::DpadLeft,
+        Button::DpadRight,
+        Button::L,
+        Button::R,
+    ];
+
+    pub fn is_pressed(self, input: &GamecubeInput) -> bool {
+        match self {
+            Button::Start => input.start,
+            Button::A => input.a,
+            Button::B => input.b,
+            Button::X => input.x,
+            Button::Y => input.y,
+            Button::Z => input.z,
+            Button::DpadUp => input.dpad_up,
+            Button::DpadDown => input.dpad_down,
+            Button::DpadLeft => input.dpad_left,
+            Button::DpadRight => input.dpad_right,
+            Button::L => input.l_digital,
+            Button::R => input.r_digital,
+        }
+    }
+
+    pub(crate) fn set_pressed(self, input: &mut GamecubeInput, pressed: bool) {
+        *self.pressed_mut(input) = pressed;
+    }
+
+    fn pressed_mut(self, input: &mut GamecubeInput) -> &mut bool {
+        match self {
+            Button::Start => &mut input.start,
+            Button::A => &mut input.a,
+            Button::B => &mut input.b,
+            Button::X => &mut input.x,
+            Button::Y => &mut input.y,
+            Button::Z => &mut input.z,
+            Button::DpadUp => &mut input.dpad_up,
+            Button::DpadDown => &mut input.dpad_down,
+            Button::DpadLeft => &mut input.dpad_left,
+            Button::DpadRight => &mut input.dpad_right,
+            Button::L => &mut input.l_digital,
+            Button::R => &mut input.r_digital,
+        }
+    }
+}
+
+/// A stick axis of a gamecube controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    StickX,
+    StickY,
+    CStickX,
+    CStickY,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 4] = [Axis::StickX, Axis::StickY, Axis::CStickX, Axis::CStickY];
+
+    pub fn value(self, input: &GamecubeInput) -> u8 {
+        match self {
+            Axis::StickX => input.stick_x,
+            Axis::StickY => input.stick_y,
+            Axis::CStickX => input.cstick_x,
+            Axis::CStickY => input.cstick_y,
+        }
+    }
+
+    pub(crate) fn value_mut(self, input: &mut GamecubeInput) -> &mut u8 {
+        match self {
+            Axis::StickX => &mut input.stick_x,
+            Axis::StickY => &mut input.stick_y,
+            Axis::CStickX => &mut input.cstick_x,
+            Axis::CStickY => &mut input.cstick_y,
+        }
+    }
+}
+
+/// Where a remapped axis takes its value from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AxisSource {
+    pub axis: Axis,
+    /// Mirror the value around center.
+    pub inverted: bool,
+}
+
+/// The length of [`Remap::to_bytes`].
+pub const REMAP_LEN: usize = 17;
+
+/// Bumped whenever the layout of [`Remap::to_bytes`] changes, so old data is rejected instead of misread.
+const REMAP_VERSION: u8 = 1;
+
+/// Which report button each button is sent as and which axis each stick axis takes its value from.
+///
+/// Several buttons may be sent as the same button, which is then pressed if any of them are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Remap {
+    /// Indexed in the order of [`Button::ALL`], None disables the button.
+    buttons: [Option<Button>; 12],
+    /// Indexed in the order of [`Axis::ALL`].
+    axes: [AxisSource; 4],
+}
+
+impl Remap {
+    /// Every button and axis sent as itself.
+    pub const IDENTITY: Remap = Remap {
+        buttons: [
+            Some(Button::Start),
+            Some(Button::A),
+            Some(Button::B),
+            Some(Button::X),
+            Some(Button::Y),
+            Some(Button::Z),
+            Some(Button::DpadUp),
+            Some(Button::DpadDown),
+            Some(Button::DpadLeft),
+            Some(Button::DpadRight),
+            Some(Button::L),
+            Some(Button::R),
+        ],
+        axes: [
+            AxisSource {
+                axis: Axis::StickX,
+                inverted: false,
+            },
+            AxisSource {
+                axis: Axis::StickY,
+                inverted: false,
+            },
+            AxisSource {
+                axis: Axis::CStickX,
+                inverted: false,
+            },
+            AxisSource {
+                axis: Axis::CStickY,
+                inverted: false,
+            },
+        ],
+    };
+
+    /// Send `button` as `target`, or never send it if `target` is None.
+    pub fn set_button(&mut self, button: Button, target: Option<Button>) {
+        self.buttons[button as usize] = target;
+    }
+
+    pub fn button(&self, button: Button) -> Option<Button> {
+        self.buttons[button as usize]
+    }
+
+    /// Set where `axis` takes its value from.
+    pub fn set_axis(&mut self, axis: Axis, source: AxisSource) {
+        self.axes[axis as usize] = source;
+    }
+
+    pub fn axis(&self, axis: Axis) -> AxisSource {
+        self.axes[axis as usize]
+    }
+
+    /// Apply the mapping to `input`, the triggers are passed through unchanged.
+    pub fn apply(&self, input: &GamecubeInput) -> GamecubeInput {
+        let mut output = *input;
+        for button in Button::ALL {
+            *button.pressed_mut(&mut output) = false;
+        }
+        for (button, target) in Button::ALL.iter().zip(self.buttons) {
+            if let Some(target) = target {
+                *target.pressed_mut(&mut output) |= button.is_pressed(input);
+            }
+        }
+        for (axis, source) in Axis::ALL.iter().zip(self.axes) {
+            let value = source.axis.value(input);
+            *axis.value_mut(&mut output) = if source.inverted {
+                // mirror around 128, the center of the axis
+                (256 - value as u16).min(255) as u8
+            } else {
+                value
+            };
+        }
+        output
+    }
+
+    /// Serialize for persisting, e.g. through a [`crate::storage::WearLevelled`].
+    pub fn to_bytes(&self) -> [u8; REMAP_LEN] {
+        let mut bytes = [0; REMAP_LEN];
+        bytes[0] = REMAP_VERSION;
+        for (byte, target) in bytes[1..13].iter_mut().zip(self.buttons) {
+            *byte = target.map(|target| target as u8).unwrap_or(0xFF);
+        }
+        for (byte, source) in bytes[13..].iter_mut().zip(self.axes) {
+            *byte = source.axis as u8 | if source.inverted { 0x80 } else { 0 };
+        }
+        bytes
+    }
+
+    /// Deserialize what was written by [`Remap::to_bytes`].
+    /// Returns None if `bytes` is from a different version or is invalid, e.g. erased flash.
+    pub fn from_bytes(bytes: &[u8; REMAP_LEN]) -> Option<Remap> {
+        if bytes[0] != REMAP_VERSION {
+            return None;
+        }
+        let mut remap = Remap::IDENTITY;
+        for (target, byte) in remap.buttons.iter_mut().zip(&bytes[1..13]) {
+            *target = match byte {
+                0xFF => None,
+                index => Some(*Button::ALL.get(*index as usize)?),
+            };
+        }
+        for (source, byte) in remap.axes.iter_mut().zip(&bytes[13..]) {
+            *source = AxisSource {
+                axis: *Axis::ALL.get((byte & 0x7F) as usize)?,
+                inverted: byte & 0x80 != 0,
+            };
+        }
+        Some(remap)
+    }
+
+    /// Load from `storage` at `offset`, falling back to [`Remap::IDENTITY`] if nothing valid has been saved.
+    #[cfg(feature = "storage")]
+    pub fn load<S: crate::storage::ReadStorage>(
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<Remap, S::Error> {
+        let mut bytes = [0; REMAP_LEN];
+        storage.read(offset, &mut bytes)?;
+        Ok(Remap::from_bytes(&bytes).unwrap_or(Remap::IDENTITY))
+    }
+
+    /// Save to `storage` at `offset`, using [`REMAP_LEN`] bytes.
+    #[cfg(feature = "storage")]
+    pub fn save<S: crate::storage::Storage>(
+        &self,
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<(), S::Error> {
+        storage.write(offset, &self.to_bytes())
+    }
+}
+
+impl Default for Remap {
+    fn default() -> Self {
+        Remap::IDENTITY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRESSED: GamecubeInput = GamecubeInput {
+        x: true,
+        dpad_up: true,
+        stick_x: 0,
+        stick_y: 255,
+        cstick_x: 1,
+        cstick_y: 128,
+        l_analog: 200,
+        ..GamecubeInput::NEUTRAL
+    };
+
+    #[test]
+    fn identity() {
+        assert_eq!(Remap::IDENTITY.apply(&PRESSED), PRESSED);
+    }
+
+    #[test]
+    fn buttons() {
+        let mut remap = Remap::IDENTITY;
+        remap.set_button(Button::X, Some(Button::Z));
+        remap.set_button(Button::Z, Some(Button::X));
+        remap.set_button(Button::DpadUp, Some(Button::Z));
+        remap.set_button(Button::A, None);
+        let output = remap.apply(&GamecubeInput { a: true, ..PRESSED });
+        assert_eq!(
+            output,
+            GamecubeInput {
+                z: true,
+                stick_x: 0,
+                stick_y: 255,
+                cstick_x: 1,
+                cstick_y: 128,
+                l_analog: 200,
+                ..GamecubeInput::NEUTRAL
+            }
+        );
+    }
+
+    #[test]
+    fn inverted_axes() {
+        let mut remap = Remap::IDENTITY;
+        for axis in Axis::ALL {
+            remap.set_axis(
+                axis,
+                AxisSource {
+                    axis,
+                    inverted: true,
+                },
+            );
+        }
+        let output = remap.apply(&PRESSED);
+        // 0 has no mirror image on the axis, so saturates at 255
+        assert_eq!(
+            (
+                output.stick_x,
+                output.stick_y,
+                output.cstick_x,
+                output.cstick_y
+            ),
+            (255, 1, 255, 128)
+        );
+    }
+
+    #[test]
+    fn axis_from_another() {
+        let mut remap = Remap::IDENTITY;
+        remap.set_axis(
+            Axis::StickX,
+            AxisSource {
+                axis: Axis::CStickX,
+                inverted: false,
+            },
+        );
+        assert_eq!(remap.apply(&PRESSED).stick_x, 1);
+        assert_eq!(remap.apply(&PRESSED).cstick_x, 1);
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(
+            Remap::IDENTITY.to_bytes(),
+            [
+                REMAP_VERSION,
+                0,
+                1,
+                2,
+                3,
+                4,
+                5,
+                6,
+                7,
+                8,
+                9,
+                10,
+                11,
+                0,
+                1,
+                2,
+                3
+            ]
+        );
+
+        let mut remap = Remap::IDENTITY;
+        remap.set_button(Button::Start, None);
+        remap.set_button(Button::R, Some(Button::L));
+        remap.set_axis(
+            Axis::CStickY,
+            AxisSource {
+                axis: Axis::StickY,
+                inverted: true,
+            },
+        );
+        let bytes = remap.to_bytes();
+        assert_eq!(
+            bytes,
+            [
+                REMAP_VERSION,
+                0xFF,
+                1,
+                2,
+                3,
+                4,
+                5,
+                6,
+                7,
+                8,
+                9,
+                10,
+                10,
+                0,
+                1,
+                2,
+                0x81
+            ]
+        );
+        assert_eq!(Remap::from_bytes(&bytes), Some(remap));
+
+        // erased flash
+        assert_eq!(Remap::from_bytes(&[0xFF; REMAP_LEN]), None);
+        let mut bad_button = bytes;
+        bad_button[1] = 12;
+        assert_eq!(Remap::from_bytes(&bad_button), None);
+        let mut bad_axis = bytes;
+        bad_axis[13] = 4;
+        assert_eq!(Remap::from_bytes(&bad_axis), None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Playing back the inputs of a Slippi replay through a [`crate::GamecubeController`],
+//! so real matches can be replayed on hardware for testing monitors, capture cards and the rest of a capture chain.
+//!
+//! Parsing `.slp` files is left to host side tooling, which extracts one frame of inputs per game frame
+//! into a stream of [`FRAME_LEN`] byte frames that can be embedded in the firmware with `include_bytes!`:
+//! * physical buttons: u16 big endian, exactly as stored in the pre-frame update of the replay
+//! * stick x, stick y, c-stick x, c-stick y, L analog, R analog: u8 each, as raw gamecube values centered at 128
+//!
+//! ```ignore
+//! static MATCH: &[u8] = include_bytes!("match.frames");
+//! let mut player = ReplayPlayer::new(MATCH).unwrap();
+//! while !player.is_finished() {
+//!     controller.poll_blocking(&timer, &mut delay, || player.next_input());
+//! }
+//! ```
+
+use crate::GamecubeInput;
+
+/// The size of a single frame in the stream.
+pub const FRAME_LEN: usize = 8;
+
+/// The stream passed to [`ReplayPlayer::new`] can't be played back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The stream is not a whole number of frames. Contains its length in bytes.
+    Truncated { len: usize },
+}
+
+/// Decode a single frame of the stream.
+///
+/// Slippi's physical buttons have the same layout as the first two bytes of a poll report,
+/// so the frame is decoded as a report with the always set bit filled in.
+pub fn decode_frame(frame: &[u8; FRAME_LEN]) -> GamecubeInput {
+    GamecubeInput::from_report(&[
+        frame[0],
+        frame[1] | 0b1000_0000,
+        frame[2],
+        frame[3],
+        frame[4],
+        frame[5],
+        frame[6],
+        frame[7],
+    ])
+}
+
+/// Steps through a frame stream one poll at a time, see the [module docs](self) for the format.
+#[derive(Debug, Clone)]
+pub struct ReplayPlayer<'a> {
+    frames: &'a [u8],
+    frame: usize,
+    polls_per_frame: u8,
+    /// Polls answered with the current frame so far.
+    polls: u8,
+}
+
+impl<'a> ReplayPlayer<'a> {
+    pub fn new(frames: &'a [u8]) -> Result<ReplayPlayer<'a>, ReplayError> {
+        if frames.len() % FRAME_LEN != 0 {
+            return Err(ReplayError::Truncated { len: frames.len() });
+        }
+        Ok(ReplayPlayer {
+            frames,
+            frame: 0,
+            polls_per_frame: 1,
+            polls: 0,
+        })
+    }
+
+    /// How many polls each frame is held for, 1 by default.
+    /// Melee polls once per frame, but some polling drivers and mods poll several times per frame.
+    /// 0 is treated as 1.
+    pub fn set_polls_per_frame(&mut self, polls_per_frame: u8) {
+        self.polls_per_frame = polls_per_frame.max(1);
+    }
+
+    /// The input to answer the current poll with, advancing to the next frame once it has been held long enough.
+    /// Once every frame has been played this returns [`GamecubeInput::NEUTRAL`].
+    pub fn next_input(&mut self) -> GamecubeInput {
+        let Some(input) = self.current() else {
+            return GamecubeInput::NEUTRAL;
+        };
+        self.polls += 1;
+        if self.polls >= self.polls_per_frame {
+            self.polls = 0;
+            self.frame += 1;
+        }
+        input
+    }
+
+    /// The input of the current frame without advancing, None once finished.
+    pub fn current(&self) -> Option<GamecubeInput> {
+        let start = self.frame * FRAME_LEN;
+        let frame = self.frames.get(start..start + FRAME_LEN)?;
+        Some(decode_frame(frame.try_into().unwrap()))
+    }
+
+    /// Index of the frame that will be played next.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Total number of frames in the stream.
+    pub fn frame_count(&self) -> usize {
+        self.frames.len() / FRAME_LEN
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.frame_count()
+    }
+
+    /// Jump to `frame`, e.g. to skip the countdown at the start of a match.
+    pub fn seek(&mut self, frame: usize) {
+        self.frame = frame.min(self.frame_count());
+        self.polls = 0;
+    }
+}
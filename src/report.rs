@@ -0,0 +1,521 @@
+//! Layouts of the last 4 bytes of a poll response, which depend on the mode byte of the poll command.
+//!
+//! The first 4 bytes are always buttons1, buttons2, stick x and stick y.
+//! The remaining analog values are C-stick x/y, L/R triggers and the analog A/B buttons,
+//! which modes 0, 1 and 2 squeeze into 4 bytes by sending some of them as 4 bit nibbles:
+//!
+//! | mode       | byte 4        | byte 5        | byte 6        | byte 7      |
+//! |------------|---------------|---------------|---------------|-------------|
+//! | 0, 5, 6, 7 | cstick x      | cstick y      | L 4 \| R 4    | A 4 \| B 4  |
+//! | 1          | cx 4 \| cy 4  | L             | R             | A 4 \| B 4  |
+//! | 2          | cx 4 \| cy 4  | L 4 \| R 4    | A             | B           |
+//! | 3          | cstick x      | cstick y      | L             | R           |
+//! | 4          | cstick x      | cstick y      | A             | B           |
+//!
+//! `x 4 | y 4` means the high nibble of x goes in the high nibble of the byte and the high nibble of y in the low nibble.
+
+use crate::protocol::{
+    BUTTONS1_A, BUTTONS1_B, BUTTONS1_START, BUTTONS1_X, BUTTONS1_Y, BUTTONS2_ALWAYS_SET,
+    BUTTONS2_DPAD_DOWN, BUTTONS2_DPAD_LEFT, BUTTONS2_DPAD_RIGHT, BUTTONS2_DPAD_UP, BUTTONS2_L,
+    BUTTONS2_R, BUTTONS2_Z,
+};
+
+/// Pack the most significant nibble of `high` and of `low` into a single byte, `high` first.
+pub const fn pack_nibbles(high: u8, low: u8) -> u8 {
+    (high & 0xF0) | (low >> 4)
+}
+
+/// The inverse of [`pack_nibbles`], each nibble is returned scaled back up to the full 0-255 range
+/// by placing it in the high nibble, matching what the console does.
+pub const fn unpack_nibbles(byte: u8) -> (u8, u8) {
+    (byte & 0xF0, byte << 4)
+}
+
+/// The analog values that share the last 4 bytes of a poll response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AnalogValues {
+    pub cstick_x: u8,
+    pub cstick_y: u8,
+    pub l_analog: u8,
+    pub r_analog: u8,
+    pub a_analog: u8,
+    pub b_analog: u8,
+}
+
+/// Encode `values` into the last 4 bytes of a poll response for poll `mode`.
+/// Values that the mode doesn't send are dropped, values sent as nibbles lose their low 4 bits.
+pub const fn encode_analog(mode: u8, values: &AnalogValues) -> [u8; 4] {
+    let v = values;
+    match mode {
+        1 => [
+            pack_nibbles(v.cstick_x, v.cstick_y),
+            v.l_analog,
+            v.r_analog,
+            pack_nibbles(v.a_analog, v.b_analog),
+        ],
+        2 => [
+            pack_nibbles(v.cstick_x, v.cstick_y),
+            pack_nibbles(v.l_analog, v.r_analog),
+            v.a_analog,
+            v.b_analog,
+        ],
+        3 => [v.cstick_x, v.cstick_y, v.l_analog, v.r_analog],
+        4 => [v.cstick_x, v.cstick_y, v.a_analog, v.b_analog],
+        // 0 and the undocumented 5, 6 and 7
+        _ => [
+            v.cstick_x,
+            v.cstick_y,
+            pack_nibbles(v.l_analog, v.r_analog),
+            pack_nibbles(v.a_analog, v.b_analog),
+        ],
+    }
+}
+
+/// Decode the last 4 bytes of a poll response sent for poll `mode`, for use in host mode.
+/// Values that the mode doesn't send are returned as 0.
+pub const fn decode_analog(mode: u8, bytes: &[u8; 4]) -> AnalogValues {
+    match mode {
+        1 => {
+            let (cstick_x, cstick_y) = unpack_nibbles(bytes[0]);
+            let (a_analog, b_analog) = unpack_nibbles(bytes[3]);
+            AnalogValues {
+                cstick_x,
+                cstick_y,
+                l_analog: bytes[1],
+                r_analog: bytes[2],
+                a_analog,
+                b_analog,
+            }
+        }
+        2 => {
+            let (cstick_x, cstick_y) = unpack_nibbles(bytes[0]);
+            let (l_analog, r_analog) = unpack_nibbles(bytes[1]);
+            AnalogValues {
+                cstick_x,
+                cstick_y,
+                l_analog,
+                r_analog,
+                a_analog: bytes[2],
+                b_analog: bytes[3],
+            }
+        }
+        3 => AnalogValues {
+            cstick_x: bytes[0],
+            cstick_y: bytes[1],
+            l_analog: bytes[2],
+            r_analog: bytes[3],
+            a_analog: 0,
+            b_analog: 0,
+        },
+        4 => AnalogValues {
+            cstick_x: bytes[0],
+            cstick_y: bytes[1],
+            l_analog: 0,
+            r_analog: 0,
+            a_analog: bytes[2],
+            b_analog: bytes[3],
+        },
+        _ => {
+            let (l_analog, r_analog) = unpack_nibbles(bytes[2]);
+            let (a_analog, b_analog) = unpack_nibbles(bytes[3]);
+            AnalogValues {
+                cstick_x: bytes[0],
+                cstick_y: bytes[1],
+                l_analog,
+                r_analog,
+                a_analog,
+                b_analog,
+            }
+        }
+    }
+}
+
+/// The digital buttons sent in the first two bytes of every poll response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Buttons {
+    pub start: bool,
+    pub a: bool,
+    pub b: bool,
+    pub x: bool,
+    pub y: bool,
+    pub z: bool,
+    pub dpad_up: bool,
+    pub dpad_down: bool,
+    pub dpad_left: bool,
+    pub dpad_right: bool,
+    pub l_digital: bool,
+    pub r_digital: bool,
+}
+
+impl Buttons {
+    /// Encode as buttons1 and buttons2, including the bit in buttons2 that is always set.
+    pub const fn encode(&self) -> [u8; 2] {
+        #[rustfmt::skip]
+        let buttons1 =
+              if self.a     { BUTTONS1_A } else { 0 }
+            | if self.b     { BUTTONS1_B } else { 0 }
+            | if self.x     { BUTTONS1_X } else { 0 }
+            | if self.y     { BUTTONS1_Y } else { 0 }
+            | if self.start { BUTTONS1_START } else { 0 };
+
+        #[rustfmt::skip]
+        let buttons2 = BUTTONS2_ALWAYS_SET
+            | if self.dpad_left  { BUTTONS2_DPAD_LEFT } else { 0 }
+            | if self.dpad_right { BUTTONS2_DPAD_RIGHT } else { 0 }
+            | if self.dpad_down  { BUTTONS2_DPAD_DOWN } else { 0 }
+            | if self.dpad_up    { BUTTONS2_DPAD_UP } else { 0 }
+            | if self.z          { BUTTONS2_Z } else { 0 }
+            | if self.r_digital  { BUTTONS2_R } else { 0 }
+            | if self.l_digital  { BUTTONS2_L } else { 0 };
+
+        [buttons1, buttons2]
+    }
+
+    /// Decode buttons1 and buttons2, ignoring the status bits.
+    pub const fn decode(bytes: [u8; 2]) -> Buttons {
+        let [buttons1, buttons2] = bytes;
+        Buttons {
+            a: buttons1 & BUTTONS1_A != 0,
+            b: buttons1 & BUTTONS1_B != 0,
+            x: buttons1 & BUTTONS1_X != 0,
+            y: buttons1 & BUTTONS1_Y != 0,
+            start: buttons1 & BUTTONS1_START != 0,
+            dpad_left: buttons2 & BUTTONS2_DPAD_LEFT != 0,
+            dpad_right: buttons2 & BUTTONS2_DPAD_RIGHT != 0,
+            dpad_down: buttons2 & BUTTONS2_DPAD_DOWN != 0,
+            dpad_up: buttons2 & BUTTONS2_DPAD_UP != 0,
+            z: buttons2 & BUTTONS2_Z != 0,
+            r_digital: buttons2 & BUTTONS2_R != 0,
+            l_digital: buttons2 & BUTTONS2_L != 0,
+        }
+    }
+
+    /// Buttons pressed in either `self` or `other`.
+    pub const fn union(&self, other: &Buttons) -> Buttons {
+        let [a1, a2] = self.encode();
+        let [b1, b2] = other.encode();
+        Buttons::decode([a1 | b1, a2 | b2])
+    }
+
+    /// Replace the buttons set in `mask` with their state in `values`, leaving the rest as they are.
+    pub const fn overridden(&self, mask: &Buttons, values: &Buttons) -> Buttons {
+        let [s1, s2] = self.encode();
+        let [m1, m2] = mask.encode();
+        let [v1, v2] = values.encode();
+        Buttons::decode([(s1 & !m1) | (v1 & m1), (s2 & !m2) | (v2 & m2)])
+    }
+}
+
+/// Defines a poll report struct for a mode whose last 4 bytes can be expressed with [`AnalogValues`].
+macro_rules! poll_report {
+    (
+        $(#[$meta:meta])*
+        $name:ident, $mode:literal, { $($field:ident),* }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct $name {
+            pub buttons: Buttons,
+            pub stick_x: u8,
+            pub stick_y: u8,
+            $(pub $field: u8,)*
+        }
+
+        impl $name {
+            /// The poll mode byte this report is sent in response to.
+            pub const MODE: u8 = $mode;
+
+            pub const fn encode(&self) -> [u8; 8] {
+                let [buttons1, buttons2] = self.buttons.encode();
+                let mut values = ANALOG_ZERO;
+                $(values.$field = self.$field;)*
+                let analog = encode_analog($mode, &values);
+                [
+                    buttons1,
+                    buttons2,
+                    self.stick_x,
+                    self.stick_y,
+                    analog[0],
+                    analog[1],
+                    analog[2],
+                    analog[3],
+                ]
+            }
+
+            pub const fn decode(report: &[u8; 8]) -> $name {
+                let analog = decode_analog($mode, &[report[4], report[5], report[6], report[7]]);
+                $name {
+                    buttons: Buttons::decode([report[0], report[1]]),
+                    stick_x: report[2],
+                    stick_y: report[3],
+                    $($field: analog.$field,)*
+                }
+            }
+        }
+    };
+}
+
+const ANALOG_ZERO: AnalogValues = AnalogValues {
+    cstick_x: 0,
+    cstick_y: 0,
+    l_analog: 0,
+    r_analog: 0,
+    a_analog: 0,
+    b_analog: 0,
+};
+
+poll_report!(
+    /// Mode 0 poll response, the triggers and analog A/B are sent as nibbles.
+    PollReportMode0, 0, { cstick_x, cstick_y, l_analog, r_analog, a_analog, b_analog }
+);
+poll_report!(
+    /// Mode 1 poll response, the C-stick and analog A/B are sent as nibbles.
+    PollReportMode1, 1, { cstick_x, cstick_y, l_analog, r_analog, a_analog, b_analog }
+);
+poll_report!(
+    /// Mode 2 poll response, the C-stick and triggers are sent as nibbles.
+    PollReportMode2, 2, { cstick_x, cstick_y, l_analog, r_analog, a_analog, b_analog }
+);
+poll_report!(
+    /// Mode 3 poll response, the mode used by nearly every game. Everything is sent at full precision but there is no analog A/B.
+    PollReportMode3, 3, { cstick_x, cstick_y, l_analog, r_analog }
+);
+poll_report!(
+    /// Mode 4 poll response, analog A/B are sent instead of the triggers.
+    PollReportMode4, 4, { cstick_x, cstick_y, a_analog, b_analog }
+);
+
+/// A poll response decoded according to the mode it was requested with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollReport {
+    /// Modes 0, 5, 6 and 7 share the same layout.
+    Mode0(PollReportMode0),
+    Mode1(PollReportMode1),
+    Mode2(PollReportMode2),
+    Mode3(PollReportMode3),
+    Mode4(PollReportMode4),
+}
+
+impl PollReport {
+    pub const fn decode(mode: u8, report: &[u8; 8]) -> PollReport {
+        match mode {
+            1 => PollReport::Mode1(PollReportMode1::decode(report)),
+            2 => PollReport::Mode2(PollReportMode2::decode(report)),
+            3 => PollReport::Mode3(PollReportMode3::decode(report)),
+            4 => PollReport::Mode4(PollReportMode4::decode(report)),
+            _ => PollReport::Mode0(PollReportMode0::decode(report)),
+        }
+    }
+
+    pub const fn encode(&self) -> [u8; 8] {
+        match self {
+            PollReport::Mode0(report) => report.encode(),
+            PollReport::Mode1(report) => report.encode(),
+            PollReport::Mode2(report) => report.encode(),
+            PollReport::Mode3(report) => report.encode(),
+            PollReport::Mode4(report) => report.encode(),
+        }
+    }
+}
+
+// Compile time checks of the wire format, so refactors of the encoding can't silently change what is sent.
+// The helpers live inside the block, rustc 1.84 doesn't see uses from an anonymous const as uses of outside items.
+const _: () = {
+    const fn bytes_eq<const N: usize>(a: &[u8; N], b: &[u8; N]) -> bool {
+        let mut i = 0;
+        while i < N {
+            if a[i] != b[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    const BUTTONS_NONE: Buttons = Buttons::decode([0, 0]);
+
+    // No buttons pressed still sets the always set bit in buttons2.
+    assert!(bytes_eq(&BUTTONS_NONE.encode(), &[0, 0b1000_0000]));
+
+    /// Assert that pressing only `$field` encodes as `$bytes` and decodes back to only `$field`.
+    macro_rules! assert_button_bit {
+        ($field:ident, $bytes:expr) => {
+            let mut buttons = BUTTONS_NONE;
+            buttons.$field = true;
+            assert!(bytes_eq(&buttons.encode(), &$bytes));
+            let decoded = Buttons::decode($bytes);
+            assert!(decoded.$field);
+            assert!(bytes_eq(&decoded.encode(), &$bytes));
+        };
+    }
+
+    assert_button_bit!(a, [0b0000_0001, 0b1000_0000]);
+    assert_button_bit!(b, [0b0000_0010, 0b1000_0000]);
+    assert_button_bit!(x, [0b0000_0100, 0b1000_0000]);
+    assert_button_bit!(y, [0b0000_1000, 0b1000_0000]);
+    assert_button_bit!(start, [0b0001_0000, 0b1000_0000]);
+    assert_button_bit!(dpad_left, [0, 0b1000_0001]);
+    assert_button_bit!(dpad_right, [0, 0b1000_0010]);
+    assert_button_bit!(dpad_down, [0, 0b1000_0100]);
+    assert_button_bit!(dpad_up, [0, 0b1000_1000]);
+    assert_button_bit!(z, [0, 0b1001_0000]);
+    assert_button_bit!(r_digital, [0, 0b1010_0000]);
+    assert_button_bit!(l_digital, [0, 0b1100_0000]);
+
+    const ANALOG_DISTINCT: AnalogValues = AnalogValues {
+        cstick_x: 0x1A,
+        cstick_y: 0x2B,
+        l_analog: 0x3C,
+        r_analog: 0x4D,
+        a_analog: 0x5E,
+        b_analog: 0x6F,
+    };
+
+    // The byte positions of each analog value, and which nibble they keep when squeezed.
+    assert!(bytes_eq(
+        &encode_analog(0, &ANALOG_DISTINCT),
+        &[0x1A, 0x2B, 0x34, 0x56]
+    ));
+    assert!(bytes_eq(
+        &encode_analog(1, &ANALOG_DISTINCT),
+        &[0x12, 0x3C, 0x4D, 0x56]
+    ));
+    assert!(bytes_eq(
+        &encode_analog(2, &ANALOG_DISTINCT),
+        &[0x12, 0x34, 0x5E, 0x6F]
+    ));
+    assert!(bytes_eq(
+        &encode_analog(3, &ANALOG_DISTINCT),
+        &[0x1A, 0x2B, 0x3C, 0x4D]
+    ));
+    assert!(bytes_eq(
+        &encode_analog(4, &ANALOG_DISTINCT),
+        &[0x1A, 0x2B, 0x5E, 0x6F]
+    ));
+
+    // Every report is 8 bytes: buttons1, buttons2, stick x, stick y, then the mode specific analog values.
+    assert!(bytes_eq(
+        &PollReportMode3 {
+            buttons: BUTTONS_NONE,
+            stick_x: 0x11,
+            stick_y: 0x22,
+            cstick_x: 0x1A,
+            cstick_y: 0x2B,
+            l_analog: 0x3C,
+            r_analog: 0x4D,
+        }
+        .encode(),
+        &[0, 0b1000_0000, 0x11, 0x22, 0x1A, 0x2B, 0x3C, 0x4D]
+    ));
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Every value distinct in both nibbles, so any swapped or dropped nibble shows up.
+    const VALUES: AnalogValues = AnalogValues {
+        cstick_x: 0xA1,
+        cstick_y: 0xB2,
+        l_analog: 0xC3,
+        r_analog: 0xD4,
+        a_analog: 0xE5,
+        b_analog: 0xF6,
+    };
+
+    fn analog_values() -> impl Strategy<Value = AnalogValues> {
+        any::<[u8; 6]>().prop_map(|v| AnalogValues {
+            cstick_x: v[0],
+            cstick_y: v[1],
+            l_analog: v[2],
+            r_analog: v[3],
+            a_analog: v[4],
+            b_analog: v[5],
+        })
+    }
+
+    #[test]
+    fn nibble_order() {
+        assert_eq!(pack_nibbles(0xAB, 0xCD), 0xAC);
+        assert_eq!(pack_nibbles(0x0F, 0xF0), 0x0F);
+        assert_eq!(unpack_nibbles(0xAC), (0xA0, 0xC0));
+        assert_eq!(unpack_nibbles(0x0F), (0x00, 0xF0));
+    }
+
+    #[test]
+    fn mode_1() {
+        let bytes = encode_analog(1, &VALUES);
+        assert_eq!(bytes, [0xAB, 0xC3, 0xD4, 0xEF]);
+        assert_eq!(
+            decode_analog(1, &bytes),
+            AnalogValues {
+                cstick_x: 0xA0,
+                cstick_y: 0xB0,
+                l_analog: 0xC3,
+                r_analog: 0xD4,
+                a_analog: 0xE0,
+                b_analog: 0xF0,
+            }
+        );
+    }
+
+    #[test]
+    fn mode_2() {
+        let bytes = encode_analog(2, &VALUES);
+        assert_eq!(bytes, [0xAB, 0xCD, 0xE5, 0xF6]);
+        assert_eq!(
+            decode_analog(2, &bytes),
+            AnalogValues {
+                cstick_x: 0xA0,
+                cstick_y: 0xB0,
+                l_analog: 0xC0,
+                r_analog: 0xD0,
+                a_analog: 0xE5,
+                b_analog: 0xF6,
+            }
+        );
+    }
+
+    #[test]
+    fn mode_4() {
+        let bytes = encode_analog(4, &VALUES);
+        assert_eq!(bytes, [0xA1, 0xB2, 0xE5, 0xF6]);
+        assert_eq!(
+            decode_analog(4, &bytes),
+            AnalogValues {
+                l_analog: 0,
+                r_analog: 0,
+                ..VALUES
+            }
+        );
+    }
+
+    /// The last 4 bytes of a poll response as Dolphin lays them out in `CSIDevice_GCController::MapPadStatus`,
+    /// where modes above 4 fall back to mode 0.
+    #[test]
+    fn dolphin_layout() {
+        let expected = [
+            [0xA1, 0xB2, 0xCD, 0xEF],
+            [0xAB, 0xC3, 0xD4, 0xEF],
+            [0xAB, 0xCD, 0xE5, 0xF6],
+            [0xA1, 0xB2, 0xC3, 0xD4],
+            [0xA1, 0xB2, 0xE5, 0xF6],
+            [0xA1, 0xB2, 0xCD, 0xEF],
+            [0xA1, 0xB2, 0xCD, 0xEF],
+            [0xA1, 0xB2, 0xCD, 0xEF],
+        ];
+        for (mode, expected) in expected.iter().enumerate() {
+            assert_eq!(&encode_analog(mode as u8, &VALUES), expected, "mode {mode}");
+        }
+    }
+
+    proptest! {
+        /// Decoding keeps exactly the bits the mode sends.
+        #[test]
+        fn round_trip(mode in 0..8u8, values in analog_values()) {
+            let decoded = decode_analog(mode, &encode_analog(mode, &values));
+            prop_assert_eq!(encode_analog(mode, &decoded), encode_analog(mode, &values));
+        }
+    }
+}
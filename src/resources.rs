@@ -0,0 +1,171 @@
+//! Compile time checks that several joybus ports don't share a pin or a PIO state machine.
+//!
+//! Every port holds the HAL's singleton for its pin and state machine, so safe code can't hand the same one to two ports.
+//! That stops holding once a pin or state machine is conjured with `unsafe`, e.g. pins made with `Pins::new` on a stolen
+//! `IO_BANK0` in board support code, and two ports on the same pin or state machine don't fail loudly:
+//! both keep running and each sees half of the other's frames.
+//!
+//! Firmware with several ports can list their types with [`assert_distinct_ports!`](crate::assert_distinct_ports)
+//! to fail the build if any two of them overlap:
+//!
+//! ```ignore
+//! type Console = JoybusPort<PIO0, Gpio28>;
+//! type Passthrough = JoybusPort<PIO0, Gpio27, SM1>;
+//! type N64 = JoybusPort<PIO1, Gpio26>;
+//!
+//! joybus_pio::assert_distinct_ports!(Console, Passthrough, N64);
+//! ```
+//!
+//! Ports created with [`crate::JoybusPort::new`] use SM0 and [`crate::JoybusPort::new_pair`] uses SM0 and SM1,
+//! so list the [`crate::JoybusPort`] type each controller or host is built from.
+//! Only pins known at compile time can be checked, a port on a `DynPinId` doesn't implement [`PortResources`].
+
+use crate::rp2040_hal::{
+    gpio::bank0::*,
+    pac::{PIO0, PIO1},
+    pio::{PIOExt, StateMachineIndex, SM0, SM1, SM2, SM3},
+};
+use crate::{JoybusPin, JoybusPort};
+
+/// A PIO block with its index known at compile time.
+pub trait KnownPio: PIOExt {
+    const INDEX: u8;
+}
+
+impl KnownPio for PIO0 {
+    const INDEX: u8 = 0;
+}
+
+impl KnownPio for PIO1 {
+    const INDEX: u8 = 1;
+}
+
+/// A state machine with its index known at compile time.
+pub trait KnownStateMachine: StateMachineIndex {
+    const INDEX: u8;
+}
+
+impl KnownStateMachine for SM0 {
+    const INDEX: u8 = 0;
+}
+
+impl KnownStateMachine for SM1 {
+    const INDEX: u8 = 1;
+}
+
+impl KnownStateMachine for SM2 {
+    const INDEX: u8 = 2;
+}
+
+impl KnownStateMachine for SM3 {
+    const INDEX: u8 = 3;
+}
+
+/// A bank 0 pin with its number known at compile time.
+pub trait KnownPin<P: PIOExt>: JoybusPin<P> {
+    const NUM: u8;
+}
+
+macro_rules! known_pins {
+    ($($pin:ident = $num:literal),*) => {
+        $(
+            impl<P: PIOExt> KnownPin<P> for $pin
+            where
+                $pin: JoybusPin<P>,
+            {
+                const NUM: u8 = $num;
+            }
+        )*
+    };
+}
+
+known_pins!(
+    Gpio0 = 0,
+    Gpio1 = 1,
+    Gpio2 = 2,
+    Gpio3 = 3,
+    Gpio4 = 4,
+    Gpio5 = 5,
+    Gpio6 = 6,
+    Gpio7 = 7,
+    Gpio8 = 8,
+    Gpio9 = 9,
+    Gpio10 = 10,
+    Gpio11 = 11,
+    Gpio12 = 12,
+    Gpio13 = 13,
+    Gpio14 = 14,
+    Gpio15 = 15,
+    Gpio16 = 16,
+    Gpio17 = 17,
+    Gpio18 = 18,
+    Gpio19 = 19,
+    Gpio20 = 20,
+    Gpio21 = 21,
+    Gpio22 = 22,
+    Gpio23 = 23,
+    Gpio24 = 24,
+    Gpio25 = 25,
+    Gpio26 = 26,
+    Gpio27 = 27,
+    Gpio28 = 28,
+    Gpio29 = 29
+);
+
+/// The hardware a port runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resources {
+    pub pio: u8,
+    pub state_machine: u8,
+    pub pin: u8,
+}
+
+/// A port type whose [`Resources`] are known at compile time.
+pub trait PortResources {
+    const RESOURCES: Resources;
+}
+
+impl<P: KnownPio, I: KnownPin<P>, S: KnownStateMachine> PortResources for JoybusPort<P, I, S> {
+    const RESOURCES: Resources = Resources {
+        pio: P::INDEX,
+        state_machine: S::INDEX,
+        pin: I::NUM,
+    };
+}
+
+/// Panics, which fails the build when evaluated in a const, if any two of `ports` share a pin or a state machine.
+/// Used by [`assert_distinct_ports!`](crate::assert_distinct_ports).
+pub const fn assert_distinct(ports: &[Resources]) {
+    let mut i = 0;
+    while i < ports.len() {
+        let mut j = i + 1;
+        while j < ports.len() {
+            if ports[i].pin == ports[j].pin {
+                panic!("two joybus ports use the same pin");
+            }
+            if ports[i].pio == ports[j].pio && ports[i].state_machine == ports[j].state_machine {
+                panic!("two joybus ports use the same PIO state machine");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+/// Fail the build if any two of the listed [`crate::JoybusPort`] types share a pin or a PIO state machine,
+/// see [`crate::resources`].
+#[macro_export]
+macro_rules! assert_distinct_ports {
+    ($($port:ty),+ $(,)?) => {
+        const _: () = $crate::resources::assert_distinct(&[
+            $(<$port as $crate::resources::PortResources>::RESOURCES),+
+        ]);
+    };
+}
+
+// The layout from the module docs: a pair sharing PIO0 and a port on PIO1.
+assert_distinct_ports!(
+    JoybusPort<PIO0, Gpio28>,
+    JoybusPort<PIO0, Gpio27, SM1>,
+    JoybusPort<PIO1, Gpio26>
+);
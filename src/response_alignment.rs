@@ -0,0 +1,72 @@
+/// Starts each response a fixed number of cycles after the command it answers, see [`crate::GamecubeController::set_response_alignment`].
+///
+/// How long software takes to start a response varies from command to command, with interrupts, flash cache misses
+/// and whatever the caller does before responding, which is what `JitterProbe` measures with the `jitter` feature.
+/// A rig measuring latency from the response edge sees all of that on top of what it is measuring.
+/// With an alignment set, [`crate::GamecubeController::send`] spins until `offset_cycles` have passed since the final byte
+/// of the command was received and only then starts the response, so that variance becomes a constant delay instead.
+/// What is left is a few cycles of the spin loop and the fixed time taken to restart the state machine and turn the line around.
+///
+/// The offset has to cover the worst case, so pick one a little over the slowest response measured by `JitterProbe`
+/// with [`crate::JoybusConfig::reply_delay_us`] included, and keep it to a few microseconds,
+/// since every microsecond added here is taken from the time the console allows for the response to start.
+/// A response that is already past the offset is sent straight away and counted by [`ResponseAlignment::late`].
+///
+/// The cycles are counted from when the final byte is read from the RX FIFO, not from when it arrived.
+/// The blocking and async methods of [`crate::GamecubeController`] read it as soon as the state machine pushes it,
+/// [`crate::fast_path`] responds from its interrupt handler and isn't aligned.
+#[derive(Debug, Clone, Copy)]
+pub struct ResponseAlignment {
+    cycle_count: fn() -> u32,
+    offset_cycles: u32,
+    /// When the most recent byte was received, taken by the response to it.
+    command_end: Option<u32>,
+    aligned: u32,
+    late: u32,
+}
+
+impl ResponseAlignment {
+    /// `cycle_count` must return an incrementing counter, wrapping at `u32::MAX`, such as the counter given to `JitterProbe`.
+    /// `offset_cycles` is counted in the same cycles.
+    pub const fn new(cycle_count: fn() -> u32, offset_cycles: u32) -> ResponseAlignment {
+        ResponseAlignment {
+            cycle_count,
+            offset_cycles,
+            command_end: None,
+            aligned: 0,
+            late: 0,
+        }
+    }
+
+    pub fn offset_cycles(&self) -> u32 {
+        self.offset_cycles
+    }
+
+    /// Responses that were held back to start at the offset.
+    pub fn aligned(&self) -> u32 {
+        self.aligned
+    }
+
+    /// Responses that were already past the offset, which means the offset is too short.
+    pub fn late(&self) -> u32 {
+        self.late
+    }
+
+    /// Called whenever a byte is received, only the last byte before a response is kept.
+    pub(crate) fn byte_received(&mut self) {
+        self.command_end = Some((self.cycle_count)());
+    }
+
+    /// Called right before a response is started.
+    pub(crate) fn wait(&mut self) {
+        let Some(start) = self.command_end.take() else {
+            return;
+        };
+        if (self.cycle_count)().wrapping_sub(start) > self.offset_cycles {
+            self.late = self.late.saturating_add(1);
+            return;
+        }
+        while (self.cycle_count)().wrapping_sub(start) < self.offset_cycles {}
+        self.aligned = self.aligned.saturating_add(1);
+    }
+}
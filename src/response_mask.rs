@@ -0,0 +1,84 @@
+use cortex_m::peripheral::NVIC;
+
+use crate::rp2040_hal::pac::Interrupt;
+
+/// Interrupts held off while a response is on its way out, see [`crate::GamecubeController::set_response_mask`].
+///
+/// A console only waits a few microseconds for a response to start, and once it has started the TX FIFO must be topped up
+/// every 32us or the frame is abandoned. A USB interrupt handler, e.g. `usb-device` polling or defmt-over-USB flushing a log,
+/// can easily run for longer than that, and the response breaks only when the two happen to line up.
+///
+/// Masking every interrupt with a critical section would fix that, but also holds off the timer and anything else
+/// the firmware relies on. Instead the controller masks only the interrupts in this set, in the NVIC, for the response window:
+/// * from the first byte of a poll until its response is in the TX FIFO, at most around 200us,
+/// * and while the response to any other command is queued.
+///
+/// If a poll is never responded to, e.g. because the caller bailed out after [`crate::GamecubeController::wait_for_poll_start`],
+/// the window instead closes when the controller next starts waiting for a poll, or when it is dropped.
+///
+/// Anything that fires in the meantime stays pending and runs as soon as the window closes.
+/// USB copes with that easily, the host retries a NAKed transfer within the same 1ms frame.
+/// Interrupts that were disabled to begin with are left disabled.
+///
+/// Only the blocking methods of [`crate::GamecubeController`] apply the mask. [`crate::fast_path`] responds from the PIO interrupt,
+/// which should instead be given a higher priority than the USB interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseMask {
+    irqs: u32,
+}
+
+impl ResponseMask {
+    /// The USB controller interrupt, which covers `usb-device` and logging over USB.
+    pub const USB: ResponseMask = ResponseMask::NONE.with(Interrupt::USBCTRL_IRQ);
+
+    pub const NONE: ResponseMask = ResponseMask { irqs: 0 };
+
+    /// Also mask `irq`.
+    pub const fn with(self, irq: Interrupt) -> ResponseMask {
+        ResponseMask {
+            irqs: self.irqs | 1 << irq as u32,
+        }
+    }
+
+    /// Mask the interrupts in the set that are enabled, returning them for [`ResponseMask::restore`].
+    fn mask(&self) -> u32 {
+        // Safety: only the interrupts in the set are touched, and ISER and ICER only change the bits that are written as 1.
+        unsafe {
+            let nvic = &*NVIC::PTR;
+            let enabled = nvic.iser[0].read() & self.irqs;
+            nvic.icer[0].write(enabled);
+            enabled
+        }
+    }
+
+    /// Enable the interrupts returned by [`ResponseMask::mask`] again.
+    fn restore(masked: u32) {
+        if masked != 0 {
+            // Safety: these were enabled before being masked, so enabling them again can't break a critical section.
+            unsafe { (*NVIC::PTR).iser[0].write(masked) };
+        }
+    }
+}
+
+/// The interrupts masked for the response in progress, enabled again by [`MaskedIrqs::restore`] or when dropped,
+/// so a controller dropped in the middle of a response window doesn't leave them masked.
+#[derive(Debug, Default)]
+pub(crate) struct MaskedIrqs(u32);
+
+impl MaskedIrqs {
+    /// Mask the interrupts of `mask` that are enabled, on top of any already masked.
+    pub(crate) fn add(&mut self, mask: &ResponseMask) {
+        self.0 |= mask.mask();
+    }
+
+    pub(crate) fn restore(&mut self) {
+        ResponseMask::restore(self.0);
+        self.0 = 0;
+    }
+}
+
+impl Drop for MaskedIrqs {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
@@ -0,0 +1,88 @@
+//! A common interface over everything that can be attached to a [`crate::JoybusPort`],
+//! so firmware driving several ports can hold them in one array and service them in turn.
+//!
+//! Devices answer the console with whatever was last set on them, e.g. [`crate::GamecubeController::set_input`],
+//! and hosts poll the controller and keep the response, e.g. [`crate::GamecubeHost::last_report`].
+//!
+//! ```ignore
+//! let mut roles: [&mut dyn JoybusRole; 2] = [&mut controller, &mut host];
+//! loop {
+//!     for role in &mut roles {
+//!         role.service(&timer, &mut delay, 1_000);
+//!     }
+//! }
+//! ```
+
+use cortex_m::delay::Delay;
+
+use crate::rp2040_hal::Timer;
+
+/// Something attached to a joybus port, either a device answering a console or a host polling a device.
+pub trait JoybusRole {
+    /// Handle a single exchange on the bus.
+    ///
+    /// A device waits up to `timeout_us` microseconds for the console to poll it, handling any other commands along the way.
+    /// A host polls its device straight away and ignores `timeout_us`, the response timeout is fixed by the protocol.
+    fn service(&mut self, timer: &Timer, delay: &mut Delay, timeout_us: u64) -> ServiceOutcome;
+
+    /// Whether the other end of the bus answered the most recent [`JoybusRole::service`].
+    fn state(&self) -> RoleState;
+
+    fn stats(&self) -> RoleStats;
+}
+
+/// Returned by [`JoybusRole::service`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceOutcome {
+    /// A device answered a poll, or a host received a poll response.
+    Exchanged,
+    /// The other end of the bus didn't respond in time.
+    Timeout,
+}
+
+/// Returned by [`JoybusRole::state`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoleState {
+    /// Nothing has been exchanged yet or the most recent exchange timed out.
+    #[default]
+    Disconnected,
+    Connected,
+}
+
+/// Counts of the outcomes of [`JoybusRole::service`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoleStats {
+    pub exchanges: u32,
+    pub timeouts: u32,
+}
+
+/// Tracks the state and stats of a [`JoybusRole`] implementation.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RoleTracker {
+    pub state: RoleState,
+    pub stats: RoleStats,
+}
+
+impl RoleTracker {
+    pub const fn new() -> RoleTracker {
+        RoleTracker {
+            state: RoleState::Disconnected,
+            stats: RoleStats {
+                exchanges: 0,
+                timeouts: 0,
+            },
+        }
+    }
+
+    pub fn record(&mut self, exchanged: bool) -> ServiceOutcome {
+        if exchanged {
+            self.state = RoleState::Connected;
+            self.stats.exchanges = self.stats.exchanges.saturating_add(1);
+            ServiceOutcome::Exchanged
+        } else {
+            self.state = RoleState::Disconnected;
+            self.stats.timeouts = self.stats.timeouts.saturating_add(1);
+            ServiceOutcome::Timeout
+        }
+    }
+}
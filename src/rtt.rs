@@ -0,0 +1,77 @@
+//! Streaming timing events over RTT, for timing analysis with probe-rs without a logic analyzer.
+//!
+//! Every event is [`EVENT_LEN`] bytes:
+//! * the [`TraceEvent`]
+//! * a byte of data, see each event
+//! * a cycle timestamp: u32 little endian, wrapping
+//!
+//! Like [`crate::JitterProbe`] the cycle source is provided by the user, since the Cortex-M0+ has no cycle counter.
+//! Give the trace a channel of its own in `NoBlockSkip` mode, the default, so an event either fits in the buffer
+//! or is dropped whole and counted in [`RttTrace::dropped`], and never blocks the response.
+//!
+//! ```ignore
+//! let channels = rtt_init! { up: { 0: { size: 1024, name: "joybus" } } };
+//! controller.set_rtt_trace(Some(RttTrace::new(channels.up.0, read_pwm_counter)));
+//! ```
+//! The channel can then be saved to a file with probe-rs and each event decoded from its 6 bytes.
+
+use rtt_target::UpChannel;
+
+/// The size of a single event.
+pub const EVENT_LEN: usize = 6;
+
+/// The first byte of every event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TraceEvent {
+    /// A byte was read from the RX FIFO, the data is the byte.
+    ByteReceived = 1,
+    /// The first byte of a response was written to the TX FIFO, the data is that byte.
+    ResponseStarted = 2,
+    /// Every byte of a response was written to the TX FIFO, the data is the length of the response.
+    /// The last byte and the stop bit are still being transmitted.
+    ResponseQueued = 3,
+}
+
+/// Writes [`TraceEvent`]s to an RTT up channel, see [`crate::GamecubeController::set_rtt_trace`].
+pub struct RttTrace {
+    channel: UpChannel,
+    cycle_count: fn() -> u32,
+    dropped: u32,
+}
+
+impl RttTrace {
+    /// `cycle_count` must return an incrementing counter, wrapping at `u32::MAX`.
+    pub fn new(channel: UpChannel, cycle_count: fn() -> u32) -> RttTrace {
+        RttTrace {
+            channel,
+            cycle_count,
+            dropped: 0,
+        }
+    }
+
+    /// Events that were dropped because the channel's buffer was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Returns the channel so it can be reused.
+    pub fn free(self) -> UpChannel {
+        self.channel
+    }
+
+    pub(crate) fn event(&mut self, event: TraceEvent, data: u8) {
+        let cycles = (self.cycle_count)().to_le_bytes();
+        let bytes = [
+            event as u8,
+            data,
+            cycles[0],
+            cycles[1],
+            cycles[2],
+            cycles[3],
+        ];
+        if self.channel.write(&bytes) < EVENT_LEN {
+            self.dropped = self.dropped.saturating_add(1);
+        }
+    }
+}
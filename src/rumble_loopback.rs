@@ -0,0 +1,126 @@
+//! A host mode test that toggles rumble in polls and checks that the controller's motor follows,
+//! for validating rumble implemented on the device side of this crate.
+//!
+//! The test can't see the motor itself, so it reads a measurement hook after each change settles,
+//! usually the current drawn through the controller's rumble supply measured with an ADC and a shunt resistor.
+//! A measurement at or above [`RumbleLoopbackConfig::threshold`] counts as rumbling.
+//!
+//! ```ignore
+//! let mut host = GamecubeHost::new(port);
+//! let report = rumble_loopback::run(&mut host, &timer, RumbleLoopbackConfig::default(), || {
+//!     adc.read(&mut shunt_pin).unwrap()
+//! });
+//! assert!(report.passed(), "{:?}", report);
+//! ```
+
+use crate::rp2040_hal::{
+    pio::{PIOExt, StateMachineIndex},
+    Timer,
+};
+use crate::{GamecubeHost, JoybusPin};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RumbleLoopbackConfig {
+    /// How many times to turn rumble on and back off.
+    pub cycles: u32,
+    /// Time between polls in microseconds.
+    pub poll_interval_us: u64,
+    /// Polls sent after each change before measuring, to let the motor spin up or down.
+    pub settle_polls: u32,
+    /// Measurements at or above this count as rumbling, in whatever unit the measurement hook returns.
+    pub threshold: u32,
+}
+
+impl Default for RumbleLoopbackConfig {
+    fn default() -> Self {
+        RumbleLoopbackConfig {
+            cycles: 10,
+            poll_interval_us: 1_000,
+            settle_polls: 100,
+            threshold: 1,
+        }
+    }
+}
+
+/// The result of [`run`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RumbleLoopbackReport {
+    pub cycles: u32,
+    /// Cycles where the measurement stayed below the threshold with rumble on.
+    pub missed_on: u32,
+    /// Cycles where the measurement stayed at or above the threshold with rumble off.
+    pub missed_off: u32,
+    /// The lowest measurement with rumble on.
+    pub min_on: Option<u32>,
+    /// The highest measurement with rumble off.
+    pub max_off: Option<u32>,
+    /// Polls that got no response or a short one, e.g. because the motor browned out the controller.
+    pub failed_polls: u32,
+    /// Responses that were missing the bit in buttons2 that is always set.
+    pub malformed: u32,
+}
+
+impl RumbleLoopbackReport {
+    /// Returns true if the motor followed every change and every poll was answered properly.
+    pub fn passed(&self) -> bool {
+        self.missed_on == 0 && self.missed_off == 0 && self.failed_polls == 0 && self.malformed == 0
+    }
+}
+
+/// Toggle rumble on the controller connected to `host` as configured by `config`, reading `measure` after each change settles.
+///
+/// The controller is left with rumble off.
+pub fn run<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+    host: &mut GamecubeHost<P, I, S>,
+    timer: &Timer,
+    config: RumbleLoopbackConfig,
+    mut measure: impl FnMut() -> u32,
+) -> RumbleLoopbackReport {
+    let mut report = RumbleLoopbackReport::default();
+    let mut last_poll = None;
+    for _ in 0..config.cycles {
+        for rumble in [true, false] {
+            for _ in 0..config.settle_polls.max(1) {
+                if let Some(last_poll) = last_poll {
+                    while timer
+                        .get_counter()
+                        .checked_duration_since(last_poll)
+                        .unwrap()
+                        .ticks()
+                        < config.poll_interval_us
+                    {}
+                }
+                last_poll = Some(timer.get_counter());
+                match host.poll(timer, 3, rumble) {
+                    Ok(response) if response[1] & 0b1000_0000 == 0 => report.malformed += 1,
+                    Ok(_) => {}
+                    Err(_) => report.failed_polls += 1,
+                }
+            }
+
+            let measurement = measure();
+            let rumbling = measurement >= config.threshold;
+            if rumble {
+                report.min_on = Some(
+                    report
+                        .min_on
+                        .map_or(measurement, |min| min.min(measurement)),
+                );
+                if !rumbling {
+                    report.missed_on += 1;
+                }
+            } else {
+                report.max_off = Some(
+                    report
+                        .max_off
+                        .map_or(measurement, |max| max.max(measurement)),
+                );
+                if rumbling {
+                    report.missed_off += 1;
+                }
+            }
+        }
+        report.cycles += 1;
+    }
+    report
+}
@@ -0,0 +1,121 @@
+//! Sampling inputs at a fixed rate of their own, independent of when the console polls.
+//!
+//! Sampling only when a poll arrives ties input latency and debouncing to the game's poll rate,
+//! which can be as slow as once per frame.
+//! [`Sampler`] instead samples an [`InputSource`] at a configurable rate, e.g. 4kHz, and debounces the buttons,
+//! so polls are always answered with the freshest processed sample.
+//!
+//! Call [`Sampler::service`] from the main loop as often as possible, and hand each new sample to the poll responder,
+//! e.g. through an [`crate::InputCell`] read from an interrupt:
+//!
+//! ```ignore
+//! let mut sampler = Sampler::new(|| read_buttons_and_sticks(), 250, 4);
+//! loop {
+//!     if sampler.service(&timer) {
+//!         INPUTS.store(&sampler.latest());
+//!     }
+//! }
+//! ```
+
+use crate::remap::Button;
+use crate::rp2040_hal::{fugit::MicrosDurationU64, timer::Instant, Timer};
+use crate::GamecubeInput;
+
+/// Anything that can read the current inputs.
+pub trait InputSource {
+    fn sample(&mut self) -> GamecubeInput;
+}
+
+impl<F: FnMut() -> GamecubeInput> InputSource for F {
+    fn sample(&mut self) -> GamecubeInput {
+        self()
+    }
+}
+
+/// Samples an [`InputSource`] at a fixed interval, see the [module docs](self).
+pub struct Sampler<S: InputSource> {
+    source: S,
+    interval_us: u64,
+    debounce_samples: u8,
+    next_sample: Option<Instant>,
+    latest: GamecubeInput,
+    /// Indexed in the order of [`Button::ALL`], how many consecutive samples have disagreed with the debounced button.
+    disagreeing: [u8; 12],
+    samples: u32,
+    late_samples: u32,
+}
+
+impl<S: InputSource> Sampler<S> {
+    /// Sample `source` every `interval_us` microseconds, e.g. 250 for 4kHz.
+    /// `debounce_samples` is how many consecutive samples must see a button change before it is reported, 1 disables debouncing.
+    pub fn new(source: S, interval_us: u64, debounce_samples: u8) -> Sampler<S> {
+        Sampler {
+            source,
+            interval_us,
+            debounce_samples,
+            next_sample: None,
+            latest: GamecubeInput::NEUTRAL,
+            disagreeing: [0; 12],
+            samples: 0,
+            late_samples: 0,
+        }
+    }
+
+    /// Take a sample if one is due, returning true if [`Sampler::latest`] was updated.
+    pub fn service(&mut self, timer: &Timer) -> bool {
+        let now = timer.get_counter();
+        let interval = MicrosDurationU64::micros(self.interval_us);
+        match self.next_sample {
+            Some(next_sample) if now < next_sample => return false,
+            // a whole interval behind, e.g. because the main loop was busy, so skip the missed samples instead of bursting through them
+            Some(next_sample) if now >= next_sample + interval => {
+                self.late_samples += 1;
+                self.next_sample = Some(now + interval);
+            }
+            Some(next_sample) => self.next_sample = Some(next_sample + interval),
+            None => self.next_sample = Some(now + interval),
+        }
+
+        let sample = self.source.sample();
+        self.debounce(&sample);
+        self.samples += 1;
+        true
+    }
+
+    /// The freshest processed sample: analog values as sampled and buttons debounced.
+    pub fn latest(&self) -> GamecubeInput {
+        self.latest
+    }
+
+    /// How many samples have been taken.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// How many times sampling fell a whole interval behind, which means [`Sampler::service`] isn't called often enough.
+    pub fn late_samples(&self) -> u32 {
+        self.late_samples
+    }
+
+    pub fn free(self) -> S {
+        self.source
+    }
+
+    fn debounce(&mut self, sample: &GamecubeInput) {
+        let mut latest = *sample;
+        for (button, disagreeing) in Button::ALL.into_iter().zip(&mut self.disagreeing) {
+            let debounced = button.is_pressed(&self.latest);
+            if button.is_pressed(sample) == debounced {
+                *disagreeing = 0;
+            } else {
+                *disagreeing += 1;
+                if *disagreeing >= self.debounce_samples {
+                    *disagreeing = 0;
+                } else {
+                    button.set_pressed(&mut latest, debounced);
+                }
+            }
+        }
+        self.latest = latest;
+    }
+}
@@ -0,0 +1,247 @@
+//! An optional pass over a [`GamecubeInput`] that removes values that upset consoles and adapters.
+//!
+//! Apply it right before responding, after any remapping or modifiers:
+//!
+//! ```ignore
+//! controller.respond_to_poll(&timer, &mut delay, Sanitize::DEFAULT.apply(&input));
+//! ```
+//!
+//! The rules, each of which can be turned off:
+//!
+//! | Rule | Default | Why |
+//! |------|---------|-----|
+//! | [`Sanitize::stick_min`] and [`Sanitize::stick_max`] | 1 to 255 | A stick at 0 is one step further from center than the same stick at 255, which breaks code that mirrors axes around 128. |
+//! | [`Sanitize::trigger_max`] | 255 | Some adapters and games treat analog triggers above what an OEM controller can produce as a fully pressed digital trigger. Lower it to keep the two apart. |
+//! | [`Sanitize::block_origin_reset`] | on | Holding X, Y and Start for 3 seconds makes the console and most adapters recalibrate the controller using its current sticks and triggers. Start is released while X and Y are held. |
+//!
+//! The status bits of the report, including the one that asks the console to request the origin again,
+//! are never taken from a [`GamecubeInput`] so don't need sanitizing.
+//! Every rule is checked at compile time at the bottom of this file.
+
+use crate::GamecubeInput;
+
+/// Which rules to apply, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sanitize {
+    /// The lowest value any stick axis is sent as.
+    /// If it is above [`Sanitize::stick_max`], axes below it are sent as `stick_min` and every other axis as `stick_max`.
+    pub stick_min: u8,
+    /// The highest value any stick axis is sent as.
+    pub stick_max: u8,
+    /// The highest value either analog trigger is sent as.
+    pub trigger_max: u8,
+    /// Release Start while X and Y are held, so the console never sees the recalibration combo.
+    pub block_origin_reset: bool,
+}
+
+impl Sanitize {
+    /// Sticks kept symmetric around center and the recalibration combo blocked, triggers untouched.
+    pub const DEFAULT: Sanitize = Sanitize {
+        stick_min: 1,
+        stick_max: 255,
+        trigger_max: 255,
+        block_origin_reset: true,
+    };
+
+    /// Every rule turned off, inputs pass through unchanged.
+    pub const NONE: Sanitize = Sanitize {
+        stick_min: 0,
+        stick_max: 255,
+        trigger_max: 255,
+        block_origin_reset: false,
+    };
+
+    pub const fn apply(&self, input: &GamecubeInput) -> GamecubeInput {
+        let mut output = *input;
+        output.stick_x = clamp(output.stick_x, self.stick_min, self.stick_max);
+        output.stick_y = clamp(output.stick_y, self.stick_min, self.stick_max);
+        output.cstick_x = clamp(output.cstick_x, self.stick_min, self.stick_max);
+        output.cstick_y = clamp(output.cstick_y, self.stick_min, self.stick_max);
+        output.l_analog = clamp(output.l_analog, 0, self.trigger_max);
+        output.r_analog = clamp(output.r_analog, 0, self.trigger_max);
+        if self.block_origin_reset && output.x && output.y {
+            output.start = false;
+        }
+        output
+    }
+}
+
+impl Default for Sanitize {
+    fn default() -> Self {
+        Sanitize::DEFAULT
+    }
+}
+
+const fn clamp(value: u8, min: u8, max: u8) -> u8 {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}
+
+// Check each rule of the table in the module docs.
+const _: () = {
+    let mut input = GamecubeInput::NEUTRAL;
+    input.stick_x = 0;
+    input.cstick_y = 0;
+    let output = Sanitize::DEFAULT.apply(&input);
+    assert!(output.stick_x == 1 && output.cstick_y == 1);
+    let output = Sanitize::NONE.apply(&input);
+    assert!(output.stick_x == 0 && output.cstick_y == 0);
+};
+const _: () = {
+    let mut input = GamecubeInput::NEUTRAL;
+    input.l_analog = 255;
+    let sanitize = Sanitize {
+        trigger_max: 200,
+        ..Sanitize::DEFAULT
+    };
+    assert!(sanitize.apply(&input).l_analog == 200);
+    assert!(Sanitize::DEFAULT.apply(&input).l_analog == 255);
+};
+const _: () = {
+    let mut input = GamecubeInput::NEUTRAL;
+    input.x = true;
+    input.y = true;
+    input.start = true;
+    let output = Sanitize::DEFAULT.apply(&input);
+    assert!(output.x && output.y && !output.start);
+    assert!(Sanitize::NONE.apply(&input).start);
+    input.y = false;
+    assert!(Sanitize::DEFAULT.apply(&input).start);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_axes(value: u8) -> GamecubeInput {
+        GamecubeInput {
+            stick_x: value,
+            stick_y: value,
+            cstick_x: value,
+            cstick_y: value,
+            ..GamecubeInput::NEUTRAL
+        }
+    }
+
+    fn axes(input: &GamecubeInput) -> [u8; 4] {
+        [input.stick_x, input.stick_y, input.cstick_x, input.cstick_y]
+    }
+
+    #[test]
+    fn default_stick_limits() {
+        assert_eq!(axes(&Sanitize::DEFAULT.apply(&with_axes(0))), [1; 4]);
+        assert_eq!(axes(&Sanitize::DEFAULT.apply(&with_axes(1))), [1; 4]);
+        assert_eq!(axes(&Sanitize::DEFAULT.apply(&with_axes(128))), [128; 4]);
+        assert_eq!(axes(&Sanitize::DEFAULT.apply(&with_axes(255))), [255; 4]);
+    }
+
+    #[test]
+    fn cstick_x() {
+        let input = GamecubeInput {
+            cstick_x: 0,
+            ..GamecubeInput::NEUTRAL
+        };
+        let output = Sanitize::DEFAULT.apply(&input);
+        assert_eq!(output.cstick_x, 1);
+        assert_eq!(output.cstick_y, 128);
+        assert_eq!(Sanitize::NONE.apply(&input).cstick_x, 0);
+    }
+
+    #[test]
+    fn custom_stick_limits() {
+        let sanitize = Sanitize {
+            stick_min: 28,
+            stick_max: 228,
+            ..Sanitize::DEFAULT
+        };
+        assert_eq!(axes(&sanitize.apply(&with_axes(0))), [28; 4]);
+        assert_eq!(axes(&sanitize.apply(&with_axes(27))), [28; 4]);
+        assert_eq!(axes(&sanitize.apply(&with_axes(28))), [28; 4]);
+        assert_eq!(axes(&sanitize.apply(&with_axes(100))), [100; 4]);
+        assert_eq!(axes(&sanitize.apply(&with_axes(228))), [228; 4]);
+        assert_eq!(axes(&sanitize.apply(&with_axes(255))), [228; 4]);
+    }
+
+    #[test]
+    fn stick_min_above_stick_max() {
+        let sanitize = Sanitize {
+            stick_min: 200,
+            stick_max: 100,
+            ..Sanitize::DEFAULT
+        };
+        assert_eq!(axes(&sanitize.apply(&with_axes(50))), [200; 4]);
+        assert_eq!(axes(&sanitize.apply(&with_axes(150))), [200; 4]);
+        assert_eq!(axes(&sanitize.apply(&with_axes(200))), [100; 4]);
+        assert_eq!(axes(&sanitize.apply(&with_axes(250))), [100; 4]);
+    }
+
+    #[test]
+    fn trigger_max() {
+        let input = GamecubeInput {
+            l_analog: 255,
+            r_analog: 255,
+            ..GamecubeInput::NEUTRAL
+        };
+        let sanitize = Sanitize {
+            trigger_max: 200,
+            ..Sanitize::DEFAULT
+        };
+        let output = sanitize.apply(&input);
+        assert_eq!((output.l_analog, output.r_analog), (200, 200));
+        let output = Sanitize::DEFAULT.apply(&input);
+        assert_eq!((output.l_analog, output.r_analog), (255, 255));
+
+        let input = GamecubeInput {
+            r_analog: 150,
+            ..GamecubeInput::NEUTRAL
+        };
+        assert_eq!(sanitize.apply(&input).r_analog, 150);
+    }
+
+    #[test]
+    fn origin_reset_combo() {
+        let combo = GamecubeInput {
+            x: true,
+            y: true,
+            start: true,
+            ..GamecubeInput::NEUTRAL
+        };
+        let output = Sanitize::DEFAULT.apply(&combo);
+        assert!(output.x && output.y && !output.start);
+        assert_eq!(Sanitize::NONE.apply(&combo), combo);
+
+        // X and Y without Start are left alone
+        let held = GamecubeInput {
+            start: false,
+            ..combo
+        };
+        assert_eq!(Sanitize::DEFAULT.apply(&held), held);
+
+        // Start with only one of X and Y is left alone
+        for input in [
+            GamecubeInput { x: false, ..combo },
+            GamecubeInput { y: false, ..combo },
+        ] {
+            assert_eq!(Sanitize::DEFAULT.apply(&input), input);
+        }
+    }
+
+    #[test]
+    fn none_passes_through() {
+        let input = GamecubeInput {
+            x: true,
+            y: true,
+            start: true,
+            stick_x: 0,
+            cstick_y: 0,
+            l_analog: 255,
+            ..GamecubeInput::NEUTRAL
+        };
+        assert_eq!(Sanitize::NONE.apply(&input), input);
+    }
+}
@@ -0,0 +1,254 @@
+//! Answering several controllers that share a PIO block, whichever response is due first.
+//!
+//! A 4 port adapter runs a [`GamecubeController`] on each state machine of a single PIO block.
+//! Servicing them one after the other with the blocking methods lets one port that is busy receiving or sending
+//! hold up another port whose console is already waiting for its response.
+//!
+//! Instead each controller is turned into a [`ScheduledController`] with [`GamecubeController::into_scheduled`],
+//! and [`PollScheduler::service`] is called from the main loop as often as possible.
+//! It never waits on a single port: it drains every RX FIFO, then runs the work that is due in order of deadline,
+//! either starting a response once its reply delay has passed or topping up the TX FIFO of a response in progress.
+//!
+//! ```ignore
+//! let mut port0 = controller0.into_scheduled();
+//! let mut port1 = controller1.into_scheduled();
+//! let mut scheduler = PollScheduler::new();
+//! loop {
+//!     port0.set_input(&read_input(0));
+//!     port1.set_input(&read_input(1));
+//!     scheduler.service(&timer, &mut [&mut port0, &mut port1]);
+//! }
+//! ```
+//!
+//! Apart from resyncing after an unknown command nothing blocks, so each call only takes a few microseconds per port,
+//! but the main loop must come back around well within a byte, 32us, for responses to start on time.
+//! The jitter probe, RTT trace and fault injector of a controller are not used by the scheduled path.
+
+use crate::protocol::{BIT_US, BYTE_US};
+use crate::rp2040_hal::{
+    fugit::MicrosDurationU64,
+    gpio::bank0::Gpio28,
+    pac::PIO0,
+    pio::{PIOExt, StateMachineIndex, SM0},
+    timer::Instant,
+    Timer,
+};
+use crate::{
+    ConfigSource, ControllerStats, FsmAction, GamecubeController, GamecubeInput, JoybusConfig,
+    JoybusPin, Response, FRAME_GAP_US,
+};
+
+/// Work run this long after its deadline is counted as late by [`PollScheduler`].
+pub const LATE_US: u64 = BIT_US;
+
+/// A port that [`PollScheduler`] can service.
+pub trait Scheduled {
+    /// Receive whatever has arrived without blocking, returning when [`Scheduled::run`] is next due, or None if there is nothing to do.
+    fn deadline(&mut self, timer: &Timer) -> Option<Instant>;
+
+    /// Run the work that is due without blocking, e.g. start a response or top up the TX FIFO.
+    fn run(&mut self, timer: &Timer);
+}
+
+/// Runs the work of several ports in order of deadline, see the [module docs](self).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PollScheduler {
+    late: u32,
+    worst_late_us: u64,
+}
+
+impl PollScheduler {
+    pub const fn new() -> PollScheduler {
+        PollScheduler {
+            late: 0,
+            worst_late_us: 0,
+        }
+    }
+
+    /// Receive on every port, then run all work that is due, earliest deadline first.
+    /// Returns how many ports had work run.
+    pub fn service<const N: usize>(
+        &mut self,
+        timer: &Timer,
+        ports: &mut [&mut dyn Scheduled; N],
+    ) -> usize {
+        let mut deadlines = [None; N];
+        for (deadline, port) in deadlines.iter_mut().zip(ports.iter_mut()) {
+            *deadline = port.deadline(timer);
+        }
+
+        let mut ran = 0;
+        loop {
+            let now = timer.get_counter();
+            let earliest = deadlines
+                .iter()
+                .enumerate()
+                .filter_map(|(i, deadline)| Some((i, (*deadline)?)))
+                .filter(|(_, deadline)| *deadline <= now)
+                .min_by_key(|(_, deadline)| *deadline);
+            let Some((i, deadline)) = earliest else {
+                return ran;
+            };
+
+            let late_us = now.checked_duration_since(deadline).unwrap().ticks();
+            if late_us > LATE_US {
+                self.late = self.late.saturating_add(1);
+            }
+            self.worst_late_us = self.worst_late_us.max(late_us);
+
+            ports[i].run(timer);
+            deadlines[i] = None;
+            ran += 1;
+        }
+    }
+
+    /// How many times work was run more than [`LATE_US`] after its deadline,
+    /// which means [`PollScheduler::service`] isn't called often enough.
+    pub fn late(&self) -> u32 {
+        self.late
+    }
+
+    /// The furthest past its deadline any work has been run.
+    pub fn worst_late_us(&self) -> u64 {
+        self.worst_late_us
+    }
+}
+
+/// A response waiting for its reply delay or part way into the TX FIFO.
+struct Pending {
+    response: Response,
+    queued: usize,
+    due: Instant,
+}
+
+/// A [`GamecubeController`] serviced by a [`PollScheduler`], see the [module docs](self).
+pub struct ScheduledController<
+    P: PIOExt = PIO0,
+    I: JoybusPin<P> = Gpio28,
+    S: StateMachineIndex = SM0,
+    C: ConfigSource = JoybusConfig,
+> {
+    controller: GamecubeController<P, I, S, C>,
+    pending: Option<Pending>,
+    /// When the most recent byte was received, to time out a command that stops part way.
+    last_byte: Option<Instant>,
+    rumble: bool,
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource>
+    GamecubeController<P, I, S, C>
+{
+    /// Answer commands from a [`PollScheduler`] instead of the blocking methods, see [`crate::scheduler`].
+    pub fn into_scheduled(self) -> ScheduledController<P, I, S, C> {
+        ScheduledController {
+            controller: self,
+            pending: None,
+            last_byte: None,
+            rumble: false,
+        }
+    }
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource>
+    ScheduledController<P, I, S, C>
+{
+    /// Set the input that polls are responded to with.
+    pub fn set_input(&mut self, input: &GamecubeInput) {
+        self.controller.set_input(input);
+    }
+
+    /// Whether the console asked for rumble in the most recent poll.
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    /// The report sent in response to the most recent poll.
+    pub fn last_report(&self) -> [u8; 8] {
+        self.controller.last_report()
+    }
+
+    pub fn stats(&self) -> ControllerStats {
+        self.controller.stats()
+    }
+
+    /// Finish any response in progress, then return the controller.
+    pub fn free(mut self, timer: &Timer) -> GamecubeController<P, I, S, C> {
+        while self.pending.is_some() {
+            self.run(timer);
+        }
+        self.controller
+    }
+
+    /// Feed every byte waiting in the RX FIFO to the controller, making any response it calls for pending.
+    fn receive(&mut self, timer: &Timer) {
+        let controller = &mut self.controller;
+        while let Some(byte) = controller.port.try_recv_byte() {
+            let now = timer.get_counter();
+            self.last_byte = Some(now);
+            let due = now + MicrosDurationU64::micros(controller.config().reply_delay_us as u64);
+            let response = match controller.fsm.on_byte(byte) {
+                FsmAction::Wait | FsmAction::PollStarted => continue,
+                FsmAction::RespondPoll { mode, rumble } => {
+                    controller.record_poll(timer, mode, rumble);
+                    self.rumble = rumble;
+                    controller.last_report = controller.next_report;
+                    Some(Response::new(&controller.next_report))
+                }
+                action => controller.handle_action(action, timer),
+            };
+            self.pending = response.map(|response| Pending {
+                response,
+                queued: 0,
+                due,
+            });
+            return;
+        }
+
+        // a command that stopped part way, e.g. because the console was unplugged mid command
+        let now = timer.get_counter();
+        let stalled = self.last_byte.is_some_and(|last_byte| {
+            now.checked_duration_since(last_byte)
+                .is_some_and(|since| since.ticks() > FRAME_GAP_US)
+        });
+        if !controller.fsm.is_idle() && stalled {
+            let action = controller.fsm.on_timeout();
+            controller.handle_action(action, timer);
+        }
+    }
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource> Scheduled
+    for ScheduledController<P, I, S, C>
+{
+    fn deadline(&mut self, timer: &Timer) -> Option<Instant> {
+        if self.pending.is_none() {
+            self.receive(timer);
+        }
+        self.pending.as_ref().map(|pending| pending.due)
+    }
+
+    fn run(&mut self, timer: &Timer) {
+        let Some(pending) = &mut self.pending else {
+            return;
+        };
+        let port = &mut self.controller.port;
+        if pending.queued == 0 && !port.try_start_frame() {
+            return;
+        }
+        let bytes = pending.response.as_bytes();
+        match port.queue_frame(bytes, pending.queued) {
+            Some(queued) if queued == bytes.len() => self.pending = None,
+            Some(queued) => {
+                pending.queued = queued;
+                // a FIFO slot frees up every byte
+                pending.due = timer.get_counter() + MicrosDurationU64::micros(BYTE_US);
+            }
+            None => {
+                let stats = &mut self.controller.stats;
+                stats.tx_underruns = stats.tx_underruns.saturating_add(1);
+                self.controller.fsm.reset();
+                self.pending = None;
+            }
+        }
+    }
+}
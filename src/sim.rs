@@ -0,0 +1,240 @@
+//! A software model of the joybus wire format, independent of the PIO program.
+//!
+//! The line is modelled as one sample per microsecond, `true` meaning the line is high.
+//! Each bit takes 4us and starts with the line pulled low:
+//! * a 0 bit is 3us low followed by 1us high
+//! * a 1 bit is 1us low followed by 3us high
+//!
+//! Every frame ends with a single 1 stop bit.
+//!
+//! This is intended for host side tooling and for checking the PIO implementation against.
+
+use std::vec::Vec;
+
+/// Encode `bytes` as the line samples of a single frame, including the trailing stop bit.
+/// The line is left high after the frame.
+pub fn encode_frame(bytes: &[u8]) -> Vec<bool> {
+    let mut samples = Vec::with_capacity((bytes.len() * 8 + 1) * 4 + 1);
+    for byte in bytes {
+        for i in (0..8).rev() {
+            encode_bit(&mut samples, byte & (1 << i) != 0);
+        }
+    }
+    // stop bit
+    encode_bit(&mut samples, true);
+    samples
+}
+
+fn encode_bit(samples: &mut Vec<bool>, bit: bool) {
+    if bit {
+        samples.extend_from_slice(&[false, true, true, true]);
+    } else {
+        samples.extend_from_slice(&[false, false, false, true]);
+    }
+}
+
+/// Model the PIO write routine sending TX FIFO entries, each a byte in the top 8 bits followed by a stop bit flag,
+/// written to the FIFO at the given microsecond after the routine is started.
+///
+/// Returns the line samples and whether the routine stalled on an empty FIFO between bytes,
+/// the underrun that [`crate::JoybusPort`] watches FDEBUG.TXSTALL for.
+/// The routine also stalls waiting for the first entry, the port clears the flag once that is pulled so it isn't counted here.
+/// Like the port, the frame is abandoned at an underrun, so the samples end without a stop bit.
+pub fn write_routine(words: &[(usize, u32)]) -> (Vec<bool>, bool) {
+    let mut samples = Vec::new();
+    for (i, (written_at, word)) in words.iter().enumerate() {
+        if i == 0 {
+            samples.resize(*written_at, true);
+        } else if *written_at > samples.len() {
+            return (samples, true);
+        }
+        let value = (word >> 24) as u8;
+        for i in (0..8).rev() {
+            encode_bit(&mut samples, value & (1 << i) != 0);
+        }
+        if word & (1 << 23) != 0 {
+            encode_bit(&mut samples, true);
+            return (samples, false);
+        }
+    }
+    (samples, !words.is_empty())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The line never went low.
+    Empty,
+    /// The line was low for longer than any valid bit allows, starting at the given sample.
+    LowTooLong { sample: usize },
+    /// The number of bits was not a whole number of bytes plus a stop bit.
+    /// Contains the number of bits that were received.
+    BitCount { bits: usize },
+    /// The final bit was a 0 instead of a stop bit.
+    MissingStopBit,
+}
+
+/// Decode the line samples of a single frame back into bytes, checking for the stop bit.
+///
+/// Bits are classified by how long the line stays low after each falling edge,
+/// anything under 2us is a 1 and anything from 2us to 4us is a 0.
+pub fn decode_frame(samples: &[bool]) -> Result<Vec<u8>, DecodeError> {
+    let mut bits = Vec::new();
+    let mut i = 0;
+    while i < samples.len() {
+        if samples[i] {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < samples.len() && !samples[i] {
+            i += 1;
+        }
+        let low = i - start;
+        if low > 4 {
+            return Err(DecodeError::LowTooLong { sample: start });
+        }
+        // 1us low is a 1, 3us low is a 0, split the difference.
+        bits.push(low < 2);
+    }
+
+    if bits.is_empty() {
+        return Err(DecodeError::Empty);
+    }
+    if bits.len() % 8 != 1 {
+        return Err(DecodeError::BitCount { bits: bits.len() });
+    }
+    if !bits[bits.len() - 1] {
+        return Err(DecodeError::MissingStopBit);
+    }
+
+    Ok(bits[..bits.len() - 1]
+        .chunks(8)
+        .map(|chunk| chunk.iter().fold(0, |byte, bit| (byte << 1) | *bit as u8))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port::tx_word;
+    use crate::{FsmAction, GamecubeInput, ProtocolFsm};
+    use proptest::prelude::*;
+
+    fn gamecube_input() -> impl Strategy<Value = GamecubeInput> {
+        (any::<[bool; 12]>(), any::<[u8; 6]>()).prop_map(|(buttons, axes)| GamecubeInput {
+            start: buttons[0],
+            a: buttons[1],
+            b: buttons[2],
+            x: buttons[3],
+            y: buttons[4],
+            z: buttons[5],
+            dpad_up: buttons[6],
+            dpad_down: buttons[7],
+            dpad_left: buttons[8],
+            dpad_right: buttons[9],
+            l_digital: buttons[10],
+            r_digital: buttons[11],
+            stick_x: axes[0],
+            stick_y: axes[1],
+            cstick_x: axes[2],
+            cstick_y: axes[3],
+            l_analog: axes[4],
+            r_analog: axes[5],
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn frame_round_trip(bytes in proptest::collection::vec(any::<u8>(), 1..64)) {
+            prop_assert_eq!(decode_frame(&encode_frame(&bytes)), Ok(bytes));
+        }
+
+        #[test]
+        fn input_round_trip(input in gamecube_input()) {
+            let report = input.create_report();
+            let decoded = decode_frame(&encode_frame(&report)).unwrap();
+            let decoded: [u8; 8] = decoded.try_into().unwrap();
+            prop_assert_eq!(GamecubeInput::from_report(&decoded), input);
+        }
+
+        /// Any byte stream leaves the FSM in a consistent state: only the two argument bytes of a poll are waited on,
+        /// and every complete command returns it to idle.
+        #[test]
+        fn fsm_random_stream(stream in proptest::collection::vec(any::<u8>(), 0..256)) {
+            let mut fsm = ProtocolFsm::new();
+            let mut poll_args = 0;
+            for byte in stream {
+                let action = fsm.on_byte(byte);
+                if poll_args > 0 {
+                    poll_args -= 1;
+                    if poll_args == 0 {
+                        prop_assert!(
+                            matches!(action, FsmAction::RespondPoll { .. }),
+                            "expected a poll response, got {:?}",
+                            action
+                        );
+                    } else {
+                        prop_assert_eq!(action, FsmAction::Wait);
+                    }
+                } else if action == FsmAction::PollStarted {
+                    poll_args = 2;
+                } else {
+                    prop_assert!(
+                        !matches!(action, FsmAction::Wait | FsmAction::RespondPoll { .. }),
+                        "unexpected {:?} outside of a poll",
+                        action
+                    );
+                }
+                prop_assert_eq!(fsm.is_idle(), poll_args == 0);
+            }
+        }
+    }
+
+    #[test]
+    fn write_routine_underrun() {
+        let report = [0x00, 0x80, 0x80, 0x80, 0x80, 0x80, 0x1F, 0x1F];
+        let word = |i: usize| tx_word(report[i], i == report.len() - 1);
+
+        // refilled twice as fast as bytes go out
+        let on_time: Vec<_> = (0..report.len()).map(|i| (i * 16, word(i))).collect();
+        let (samples, stalled) = write_routine(&on_time);
+        assert!(!stalled);
+        assert_eq!(decode_frame(&samples), Ok(report.to_vec()));
+
+        // the sixth byte is written long after the fifth is out, the console sees five bytes without a stop bit
+        let mut late = on_time.clone();
+        late[5].0 = 200;
+        let (samples, stalled) = write_routine(&late);
+        assert!(stalled);
+        assert_eq!(
+            decode_frame(&samples),
+            Err(DecodeError::BitCount { bits: 40 })
+        );
+
+        // every byte queued without a stop bit
+        let (_, stalled) = write_routine(&[(0, tx_word(0x41, false))]);
+        assert!(stalled);
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(decode_frame(&[true; 8]), Err(DecodeError::Empty));
+        assert_eq!(
+            decode_frame(&[true, false, false, false, false, false, true]),
+            Err(DecodeError::LowTooLong { sample: 1 })
+        );
+
+        let mut truncated = encode_frame(&[0x40]);
+        truncated.drain(..4);
+        assert_eq!(
+            decode_frame(&truncated),
+            Err(DecodeError::BitCount { bits: 8 })
+        );
+
+        let mut no_stop = encode_frame(&[0x40, 0x03]);
+        no_stop.truncate(no_stop.len() - 4);
+        no_stop.extend_from_slice(&[false, false, false, true]);
+        assert_eq!(decode_frame(&no_stop), Err(DecodeError::MissingStopBit));
+    }
+}
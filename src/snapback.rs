@@ -0,0 +1,221 @@
+//! Suppressing snapback, the oscillation of an analog stick around center after it is released from the edge.
+//!
+//! A released stick is pulled back by its spring fast enough to overshoot center,
+//! and the overshoot can be read as a brief input in the opposite direction, e.g. a dash back in Melee.
+//! [`SnapbackFilter`] limits how fast each axis can move toward or across center,
+//! while moving away from center is never slowed down so flicks stay instant.
+//!
+//! Filter the raw samples before they are shaped by [`crate::curve`], for every poll:
+//!
+//! ```ignore
+//! let mut snapback = SnapbackFilter::new();
+//! snapback.set_filter(Axis::StickX, AxisFilter::VelocityLimit(24));
+//! snapback.set_filter(Axis::StickY, AxisFilter::VelocityLimit(24));
+//! let input = shaping.apply(&snapback.apply(&input));
+//! ```
+//!
+//! The filters are saved alongside the calibration through [`SnapbackFilter::save`].
+
+use crate::remap::Axis;
+use crate::GamecubeInput;
+
+/// How an axis is filtered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AxisFilter {
+    /// Samples are passed through unchanged.
+    #[default]
+    Off,
+    /// While returning toward or crossing center the axis moves at most this far per sample,
+    /// lower values suppress more snapback but delay releasing the stick by up to 128 / the value samples.
+    VelocityLimit(u8),
+}
+
+/// The length of [`SnapbackFilter::to_bytes`].
+pub const SNAPBACK_LEN: usize = 1 + 2 * Axis::ALL.len();
+
+/// Bumped whenever the layout of [`SnapbackFilter::to_bytes`] changes, so old data is rejected instead of misread.
+const SNAPBACK_VERSION: u8 = 1;
+
+/// A filter for each stick axis, see the [module docs](self).
+///
+/// This holds the previous output of each axis, so [`SnapbackFilter::apply`] must be called for every sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapbackFilter {
+    /// Indexed in the order of [`Axis::ALL`].
+    filters: [AxisFilter; 4],
+    /// The previous output of each axis as a distance from center.
+    previous: [i16; 4],
+}
+
+impl SnapbackFilter {
+    /// Every axis unfiltered until set with [`SnapbackFilter::set_filter`].
+    pub const fn new() -> SnapbackFilter {
+        SnapbackFilter {
+            filters: [AxisFilter::Off; 4],
+            previous: [0; 4],
+        }
+    }
+
+    pub fn set_filter(&mut self, axis: Axis, filter: AxisFilter) {
+        self.filters[axis as usize] = filter;
+    }
+
+    pub fn filter(&self, axis: Axis) -> AxisFilter {
+        self.filters[axis as usize]
+    }
+
+    /// Forget the previous samples, e.g. after the sticks were not sampled for a while.
+    pub fn reset(&mut self) {
+        self.previous = [0; 4];
+    }
+
+    /// Filter the stick axes of `input`, everything else is passed through unchanged.
+    pub fn apply(&mut self, input: &GamecubeInput) -> GamecubeInput {
+        let mut output = *input;
+        for (i, axis) in Axis::ALL.into_iter().enumerate() {
+            let value = axis.value(input) as i16 - 128;
+            let previous = self.previous[i];
+            let filtered = match self.filters[i] {
+                AxisFilter::VelocityLimit(max_step) if returning(previous, value) => {
+                    // a limit of 0 would hold the axis away from center forever
+                    let max_step = max_step.max(1) as i16;
+                    value.clamp(previous - max_step, previous + max_step)
+                }
+                _ => value,
+            };
+            self.previous[i] = filtered;
+            *axis.value_mut(&mut output) = (filtered + 128) as u8;
+        }
+        output
+    }
+
+    /// Serialize the filters for persisting, e.g. through a [`crate::storage::WearLevelled`].
+    /// The previous samples are not included.
+    pub fn to_bytes(&self) -> [u8; SNAPBACK_LEN] {
+        let mut bytes = [0; SNAPBACK_LEN];
+        bytes[0] = SNAPBACK_VERSION;
+        for (chunk, filter) in bytes[1..].chunks_exact_mut(2).zip(self.filters) {
+            match filter {
+                AxisFilter::Off => chunk[0] = 0,
+                AxisFilter::VelocityLimit(max_step) => {
+                    chunk[0] = 1;
+                    chunk[1] = max_step;
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Deserialize what was written by [`SnapbackFilter::to_bytes`].
+    /// Returns None if `bytes` is from a different version or is invalid, e.g. erased flash.
+    pub fn from_bytes(bytes: &[u8; SNAPBACK_LEN]) -> Option<SnapbackFilter> {
+        if bytes[0] != SNAPBACK_VERSION {
+            return None;
+        }
+        let mut snapback = SnapbackFilter::new();
+        for (filter, chunk) in snapback.filters.iter_mut().zip(bytes[1..].chunks_exact(2)) {
+            *filter = match chunk[0] {
+                0 => AxisFilter::Off,
+                1 => AxisFilter::VelocityLimit(chunk[1]),
+                _ => return None,
+            };
+        }
+        Some(snapback)
+    }
+
+    /// Load from `storage` at `offset`, falling back to [`SnapbackFilter::default`] if nothing valid has been saved.
+    #[cfg(feature = "storage")]
+    pub fn load<S: crate::storage::ReadStorage>(
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<SnapbackFilter, S::Error> {
+        let mut bytes = [0; SNAPBACK_LEN];
+        storage.read(offset, &mut bytes)?;
+        Ok(SnapbackFilter::from_bytes(&bytes).unwrap_or_default())
+    }
+
+    /// Save to `storage` at `offset`, using [`SNAPBACK_LEN`] bytes.
+    #[cfg(feature = "storage")]
+    pub fn save<S: crate::storage::Storage>(
+        &self,
+        storage: &mut S,
+        offset: u32,
+    ) -> Result<(), S::Error> {
+        storage.write(offset, &self.to_bytes())
+    }
+}
+
+impl Default for SnapbackFilter {
+    fn default() -> Self {
+        SnapbackFilter::new()
+    }
+}
+
+/// Returns true if an axis moving from `previous` to `value` is returning toward center or crossing it,
+/// both measured as distances from center.
+fn returning(previous: i16, value: i16) -> bool {
+    previous.signum() * value.signum() < 0 || value.abs() < previous.abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `samples` of the stick x axis through `snapback`, returning the outputs.
+    fn run<const N: usize>(snapback: &mut SnapbackFilter, samples: [u8; N]) -> [u8; N] {
+        samples.map(|stick_x| {
+            let input = GamecubeInput {
+                stick_x,
+                ..GamecubeInput::NEUTRAL
+            };
+            snapback.apply(&input).stick_x
+        })
+    }
+
+    #[test]
+    fn release_from_edge() {
+        let mut snapback = SnapbackFilter::new();
+        snapback.set_filter(Axis::StickX, AxisFilter::VelocityLimit(32));
+        // moving away from center is instant, returning is limited to 32 per sample
+        assert_eq!(
+            run(&mut snapback, [255, 128, 128, 128, 128, 128]),
+            [255, 223, 191, 159, 128, 128]
+        );
+        // an overshoot past center is limited the same way
+        assert_eq!(run(&mut snapback, [255, 60, 60]), [255, 223, 191]);
+    }
+
+    #[test]
+    fn extremes() {
+        let mut snapback = SnapbackFilter::new();
+        snapback.set_filter(Axis::StickX, AxisFilter::VelocityLimit(255));
+        assert_eq!(run(&mut snapback, [0, 255, 0]), [0, 255, 0]);
+
+        // a limit of 0 still returns to center
+        snapback.set_filter(Axis::StickX, AxisFilter::VelocityLimit(0));
+        snapback.reset();
+        assert_eq!(run(&mut snapback, [0, 128, 128]), [0, 1, 2]);
+    }
+
+    #[test]
+    fn off_passes_through() {
+        let mut snapback = SnapbackFilter::new();
+        snapback.set_filter(Axis::CStickY, AxisFilter::VelocityLimit(1));
+        assert_eq!(run(&mut snapback, [255, 0, 128]), [255, 0, 128]);
+    }
+
+    #[test]
+    fn bytes() {
+        let mut snapback = SnapbackFilter::new();
+        snapback.set_filter(Axis::StickY, AxisFilter::VelocityLimit(24));
+        let bytes = snapback.to_bytes();
+        assert_eq!(bytes, [SNAPBACK_VERSION, 0, 0, 1, 24, 0, 0, 0, 0]);
+        assert_eq!(SnapbackFilter::from_bytes(&bytes), Some(snapback));
+
+        // erased flash
+        assert_eq!(SnapbackFilter::from_bytes(&[0xFF; SNAPBACK_LEN]), None);
+        let mut bad_filter = bytes;
+        bad_filter[5] = 2;
+        assert_eq!(SnapbackFilter::from_bytes(&bad_filter), None);
+    }
+}
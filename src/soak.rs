@@ -0,0 +1,161 @@
+//! A long running host mode test that polls a controller for hours and keeps statistics of anything that went wrong.
+//!
+//! This is useful for validating DIY controllers, including ones running this crate's device mode on a second board.
+//! Call [`Soak::step`] from the main loop, which allows reporting progress from [`Soak::stats`] while it runs,
+//! or [`Soak::run`] to block until it is done.
+
+use crate::rp2040_hal::{
+    pio::{PIOExt, StateMachineIndex},
+    timer::Instant,
+    Timer,
+};
+use crate::{GamecubeHost, HostError, JoybusPin};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoakConfig {
+    /// Time between polls in microseconds, a console polls roughly every 1000 to 16000us depending on the game.
+    pub poll_interval_us: u64,
+    /// How long to run for in microseconds.
+    pub duration_us: u64,
+    /// Read the origin every this many polls to check for drift.
+    pub origin_interval_polls: u32,
+}
+
+impl Default for SoakConfig {
+    fn default() -> Self {
+        SoakConfig {
+            poll_interval_us: 1_000,
+            duration_us: 60 * 60 * 1_000_000,
+            origin_interval_polls: 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SoakStats {
+    /// Microseconds since the first step.
+    pub elapsed_us: u64,
+    pub polls: u32,
+    pub origin_reads: u32,
+    /// Commands that got no response at all.
+    pub timeouts: u32,
+    /// Responses that ended early.
+    pub short_responses: u32,
+    /// Responses of the right length that were missing the bit in buttons2 that is always set.
+    pub malformed: u32,
+    /// The largest difference of any stick or trigger between the first origin read and any later one.
+    pub max_origin_drift: u8,
+    /// The slowest response, see [`GamecubeHost::last_response_us`].
+    pub max_response_us: u64,
+}
+
+pub struct Soak {
+    config: SoakConfig,
+    stats: SoakStats,
+    start: Option<Instant>,
+    last_poll: Option<Instant>,
+    first_origin: Option<[u8; 10]>,
+    polls_since_origin: u32,
+}
+
+impl Soak {
+    pub fn new(config: SoakConfig) -> Soak {
+        Soak {
+            config,
+            stats: SoakStats::default(),
+            start: None,
+            last_poll: None,
+            first_origin: None,
+            polls_since_origin: 0,
+        }
+    }
+
+    pub fn stats(&self) -> SoakStats {
+        self.stats
+    }
+
+    /// Poll the controller if it is due, returns false once the configured duration has passed.
+    pub fn step<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+        &mut self,
+        host: &mut GamecubeHost<P, I, S>,
+        timer: &Timer,
+    ) -> bool {
+        let now = timer.get_counter();
+        let start = *self.start.get_or_insert(now);
+        self.stats.elapsed_us = now.checked_duration_since(start).unwrap().ticks();
+        if self.stats.elapsed_us >= self.config.duration_us {
+            return false;
+        }
+        if let Some(last_poll) = self.last_poll {
+            if now.checked_duration_since(last_poll).unwrap().ticks() < self.config.poll_interval_us
+            {
+                return true;
+            }
+        }
+        self.last_poll = Some(now);
+
+        if self.first_origin.is_none()
+            || self.polls_since_origin >= self.config.origin_interval_polls
+        {
+            self.polls_since_origin = 0;
+            if let Some(origin) = self.record(host, |host| host.origin(timer)) {
+                self.stats.origin_reads += 1;
+                self.check_origin(origin);
+            }
+        }
+
+        self.polls_since_origin += 1;
+        if let Some(report) = self.record(host, |host| host.poll(timer, 3, false)) {
+            self.stats.polls += 1;
+            if report[1] & 0b1000_0000 == 0 {
+                self.stats.malformed += 1;
+            }
+        }
+        true
+    }
+
+    /// Poll the controller until the configured duration has passed.
+    pub fn run<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+        mut self,
+        host: &mut GamecubeHost<P, I, S>,
+        timer: &Timer,
+    ) -> SoakStats {
+        while self.step(host, timer) {}
+        self.stats
+    }
+
+    /// Run a single transaction, recording its failure or response time.
+    fn record<T, P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex>(
+        &mut self,
+        host: &mut GamecubeHost<P, I, S>,
+        transaction: impl FnOnce(&mut GamecubeHost<P, I, S>) -> Result<T, HostError>,
+    ) -> Option<T> {
+        match transaction(host) {
+            Ok(response) => {
+                let response_us = host.last_response_us().unwrap_or(0);
+                self.stats.max_response_us = self.stats.max_response_us.max(response_us);
+                Some(response)
+            }
+            Err(HostError::Timeout) => {
+                self.stats.timeouts += 1;
+                None
+            }
+            Err(HostError::ShortResponse { .. }) => {
+                self.stats.short_responses += 1;
+                None
+            }
+        }
+    }
+
+    fn check_origin(&mut self, origin: [u8; 10]) {
+        if origin[1] & 0b1000_0000 == 0 {
+            self.stats.malformed += 1;
+            return;
+        }
+        let first = *self.first_origin.get_or_insert(origin);
+        // sticks and triggers
+        for (a, b) in first[2..8].iter().zip(&origin[2..8]) {
+            self.stats.max_origin_drift = self.stats.max_origin_drift.max(a.abs_diff(*b));
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Splitting a [`GamecubeController`] into halves that run in different execution contexts.
+//!
+//! [`GamecubeController::split`] returns a [`Listener`] and a [`Responder`]:
+//! * The listener reads the RX FIFO and classifies commands with a [`ProtocolFsm`]. It never blocks and needs no timer,
+//!   so it can run from the RX FIFO not empty interrupt enabled with [`Listener::enable_interrupt`].
+//! * The responder owns the state machine, the TX FIFO and the report sent in response to polls,
+//!   and carries out each [`FsmAction`] the listener returns.
+//!
+//! Both halves are `Send`, so each can be moved into the context it runs in:
+//!
+//! ```ignore
+//! let (listener, responder) = controller.split();
+//! listener.enable_interrupt(PioIRQ::Irq0);
+//!
+//! #[interrupt]
+//! fn PIO0_IRQ_0() {
+//!     while let Some(action) = listener.poll() {
+//!         if action == FsmAction::PollStarted {
+//!             responder.set_input(&INPUTS.load());
+//!         }
+//!         // pend a lower priority interrupt that calls responder.respond(&timer, &mut delay, action)
+//!     }
+//! }
+//! ```
+//!
+//! The console expects a response within a few microseconds of a command ending, see [`crate::POLL_BUDGET_US`],
+//! so the responder must run as soon as the listener returns an action.
+
+use core::marker::PhantomData;
+
+use cortex_m::delay::Delay;
+
+use crate::port::{irq_inte_alias, FRAME_END_MARKER};
+use crate::rp2040_hal::{
+    gpio::bank0::Gpio28,
+    pac::PIO0,
+    pio::{PIOExt, PioIRQ, StateMachineIndex, SM0},
+    Timer,
+};
+use crate::{
+    ConfigSource, ControllerStats, FsmAction, GamecubeController, GamecubeInput, JoybusConfig,
+    JoybusPin, ProtocolFsm,
+};
+
+/// Reads and classifies commands from the console, see the [module docs](self).
+pub struct Listener<P: PIOExt = PIO0, S: StateMachineIndex = SM0> {
+    fsm: ProtocolFsm,
+    _sm: PhantomData<(P, S)>,
+}
+
+/// Responds to the commands classified by a [`Listener`], see the [module docs](self).
+pub struct Responder<
+    P: PIOExt = PIO0,
+    I: JoybusPin<P> = Gpio28,
+    S: StateMachineIndex = SM0,
+    C: ConfigSource = JoybusConfig,
+> {
+    controller: GamecubeController<P, I, S, C>,
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource>
+    GamecubeController<P, I, S, C>
+{
+    /// Split into a [`Listener`] and a [`Responder`], see [`crate::split`].
+    /// Any partially received command is carried over to the listener.
+    pub fn split(self) -> (Listener<P, S>, Responder<P, I, S, C>) {
+        let listener = Listener {
+            fsm: self.fsm.clone(),
+            _sm: PhantomData,
+        };
+        (listener, Responder { controller: self })
+    }
+}
+
+impl<P: PIOExt, S: StateMachineIndex> Listener<P, S> {
+    /// Process the bytes waiting in the RX FIFO until one completes a command or starts a poll,
+    /// returning what the [`Responder`] should do about it.
+    /// Returns None once the FIFO is empty, any further bytes are processed by the next call.
+    pub fn poll(&mut self) -> Option<FsmAction> {
+        while let Some(word) = self.read() {
+            if word == FRAME_END_MARKER {
+                // the frame ended before the command was complete, so the command can never be completed
+                if !self.fsm.is_idle() {
+                    debug!("joybus: abandoning a truncated command");
+                    self.fsm.reset();
+                }
+                continue;
+            }
+            match self.fsm.on_byte(word as u8) {
+                FsmAction::Wait => {}
+                action => return Some(action),
+            }
+        }
+        None
+    }
+
+    /// Returns true if no command is partially received.
+    pub fn is_idle(&self) -> bool {
+        self.fsm.is_idle()
+    }
+
+    /// Enable the RX FIFO not empty interrupt of this listener's state machine on `irq`, call [`Listener::poll`] from its handler.
+    pub fn enable_interrupt(&self, irq: PioIRQ) {
+        // Safety: only this state machine's bit is set, through the atomic set alias so nothing else in the register can be lost.
+        unsafe { irq_inte_alias::<P>(irq, 0x2000).write_volatile(1 << S::id()) };
+    }
+
+    pub fn disable_interrupt(&self, irq: PioIRQ) {
+        // Safety: only this state machine's bit is cleared, through the atomic clear alias so nothing else in the register can be lost.
+        unsafe { irq_inte_alias::<P>(irq, 0x3000).write_volatile(1 << S::id()) };
+    }
+
+    /// The next entry of the RX FIFO, if any.
+    fn read(&mut self) -> Option<u32> {
+        let registers = crate::port::registers::<P>();
+        if registers.fstat().read().rxempty().bits() & (1 << S::id()) != 0 {
+            return None;
+        }
+        // The responder never reads the RX FIFO, so the listener is its only reader.
+        Some(registers.rxf(S::id()).read().bits())
+    }
+}
+
+impl<P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex, C: ConfigSource> Responder<P, I, S, C> {
+    /// Carry out an `action` returned by [`Listener::poll`].
+    ///
+    /// Polls are responded to with the input most recently set with [`Responder::set_input`],
+    /// other commands the same way as [`GamecubeController`] does.
+    pub fn respond(&mut self, timer: &Timer, delay: &mut Delay, action: FsmAction) {
+        let controller = &mut self.controller;
+        match action {
+            FsmAction::PollStarted => {
+                #[cfg(debug_assertions)]
+                controller.start_poll_budget(timer);
+            }
+            FsmAction::RespondPoll { mode, rumble } => {
+                #[cfg(debug_assertions)]
+                controller.end_poll_budget(timer);
+                controller.record_poll(timer, mode, rumble);
+                delay.delay_us(controller.config().reply_delay_us);
+                let report = controller.next_report;
+                controller.send(&report);
+                controller.last_report = report;
+            }
+            action => controller.perform(action, timer, delay),
+        }
+    }
+
+    /// Set the input that polls are responded to with.
+    pub fn set_input(&mut self, input: &GamecubeInput) {
+        self.controller.set_input(input);
+    }
+
+    /// The report sent in response to the most recent poll.
+    pub fn last_report(&self) -> [u8; 8] {
+        self.controller.last_report()
+    }
+
+    pub fn stats(&self) -> ControllerStats {
+        self.controller.stats()
+    }
+
+    /// Returns true once the most recent response including its stop bit has been transmitted.
+    pub fn is_send_complete(&self) -> bool {
+        self.controller.is_send_complete()
+    }
+
+    /// Put the halves back together, carrying over any partially received command from the listener.
+    pub fn join(self, listener: Listener<P, S>) -> GamecubeController<P, I, S, C> {
+        let mut controller = self.controller;
+        controller.fsm = listener.fsm;
+        controller
+    }
+}
+
+// Both halves have to be movable into the interrupt handler or executor they run in.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<Listener>();
+    assert_send::<Responder>();
+};
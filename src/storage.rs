@@ -0,0 +1,321 @@
+//! Persistence shared by every feature that saves data.
+//!
+//! Everything that persists data takes a [`Storage`], the trait from the `embedded-storage` crate,
+//! so a single implementation for the board's flash serves all of them.
+//! Small data that is rewritten often, such as calibration or profiles, should be stored through [`WearLevelled`]
+//! so that each write doesn't erase the same flash sector.
+//! Append only data such as [`crate::recording`] can write to flash directly.
+
+use embedded_storage::nor_flash::NorFlash;
+pub use embedded_storage::{ReadStorage, Storage};
+
+/// [`WearLevelled`] writes to flash through a buffer of this size, so the flash's write size can't be larger than this.
+const SCRATCH_LEN: usize = 256;
+
+/// Each slot starts with its sequence number and ends with the inverted sequence number,
+/// a slot is only valid if both are intact, which detects writes that were interrupted by a power loss.
+const HEADER_LEN: usize = 4;
+const FOOTER_LEN: usize = 4;
+
+/// Why a [`WearLevelled`] read or write failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WearLevelledError<E> {
+    /// The access goes past the end of the `N` bytes of storage.
+    OutOfBounds,
+    Flash(E),
+}
+
+/// Presents `N` bytes of storage that are spread across all of the flash region `F` to avoid wearing out any one sector.
+///
+/// The region is divided into slots, each large enough for `N` bytes plus a small header.
+/// Every write stores a full copy of the data in the next slot, only erasing a sector once every slot in it has been used,
+/// so a region of `s` sectors that each fit `k` slots takes `s * k` writes per erase of each sector.
+/// The data is kept in RAM so reads never touch the flash.
+///
+/// `F` must cover at least 2 erase sectors so that the newest copy is never erased,
+/// must have a read size of 1 and a write size of at most 256, both of which are true of the RP2040's flash.
+pub struct WearLevelled<F: NorFlash, const N: usize> {
+    flash: F,
+    data: [u8; N],
+    slot_len: usize,
+    slots_per_sector: usize,
+    slots: usize,
+    /// The slot holding the current copy, None if nothing valid was found in the flash.
+    current: Option<usize>,
+    sequence: u32,
+}
+
+impl<F: NorFlash, const N: usize> WearLevelled<F, N> {
+    /// Scan `flash` for the newest valid copy of the data.
+    /// If there is none the data starts out as all 0xFF, the same as erased flash.
+    pub fn new(flash: F) -> Result<WearLevelled<F, N>, F::Error> {
+        assert!(F::READ_SIZE == 1, "flash read size must be 1");
+        assert!(
+            F::WRITE_SIZE <= SCRATCH_LEN && SCRATCH_LEN % F::WRITE_SIZE == 0,
+            "flash write size must divide 256"
+        );
+        let slot_len = (HEADER_LEN + N + FOOTER_LEN).next_multiple_of(F::WRITE_SIZE);
+        assert!(
+            slot_len <= F::ERASE_SIZE,
+            "N doesn't fit in an erase sector"
+        );
+        let sectors = flash.capacity() / F::ERASE_SIZE;
+        assert!(
+            sectors >= 2,
+            "flash region must cover at least 2 erase sectors"
+        );
+        let slots_per_sector = F::ERASE_SIZE / slot_len;
+
+        let mut storage = WearLevelled {
+            flash,
+            data: [0xFF; N],
+            slot_len,
+            slots_per_sector,
+            slots: sectors * slots_per_sector,
+            current: None,
+            sequence: 0,
+        };
+
+        for slot in 0..storage.slots {
+            let offset = storage.slot_offset(slot);
+            let mut header = [0; HEADER_LEN];
+            let mut footer = [0; FOOTER_LEN];
+            storage.flash.read(offset, &mut header)?;
+            storage
+                .flash
+                .read(offset + (HEADER_LEN + N) as u32, &mut footer)?;
+            let sequence = u32::from_le_bytes(header);
+            let valid = sequence != u32::MAX && u32::from_le_bytes(footer) == !sequence;
+            if valid && (storage.current.is_none() || sequence > storage.sequence) {
+                storage.current = Some(slot);
+                storage.sequence = sequence;
+            }
+        }
+        if let Some(slot) = storage.current {
+            let offset = storage.slot_offset(slot) + HEADER_LEN as u32;
+            storage.flash.read(offset, &mut storage.data)?;
+        }
+        Ok(storage)
+    }
+
+    /// Returns the flash region so it can be reused.
+    pub fn free(self) -> F {
+        self.flash
+    }
+
+    /// The current data, without copying it out through [`ReadStorage::read`].
+    pub fn data(&self) -> &[u8; N] {
+        &self.data
+    }
+
+    fn slot_offset(&self, slot: usize) -> u32 {
+        let sector = slot / self.slots_per_sector;
+        let index = slot % self.slots_per_sector;
+        (sector * F::ERASE_SIZE + index * self.slot_len) as u32
+    }
+
+    /// Write a copy of the data to the next slot, erasing its sector first if it is the first slot in it.
+    fn commit(&mut self) -> Result<(), F::Error> {
+        let slot = self
+            .current
+            .map(|slot| (slot + 1) % self.slots)
+            .unwrap_or(0);
+        let offset = self.slot_offset(slot);
+        if slot % self.slots_per_sector == 0 {
+            self.flash.erase(offset, offset + F::ERASE_SIZE as u32)?;
+        }
+
+        let sequence = if self.current.is_some() {
+            self.sequence + 1
+        } else {
+            0
+        };
+        let header = sequence.to_le_bytes();
+        let footer = (!sequence).to_le_bytes();
+        let byte_at = |i: usize| {
+            if i < HEADER_LEN {
+                header[i]
+            } else if i < HEADER_LEN + N {
+                self.data[i - HEADER_LEN]
+            } else if i < HEADER_LEN + N + FOOTER_LEN {
+                footer[i - HEADER_LEN - N]
+            } else {
+                0xFF
+            }
+        };
+
+        let mut scratch = [0; SCRATCH_LEN];
+        let mut written = 0;
+        while written < self.slot_len {
+            let len = SCRATCH_LEN.min(self.slot_len - written);
+            for (i, byte) in scratch[..len].iter_mut().enumerate() {
+                *byte = byte_at(written + i);
+            }
+            self.flash.write(offset + written as u32, &scratch[..len])?;
+            written += len;
+        }
+
+        self.current = Some(slot);
+        self.sequence = sequence;
+        Ok(())
+    }
+}
+
+impl<F: NorFlash, const N: usize> ReadStorage for WearLevelled<F, N> {
+    type Error = WearLevelledError<F::Error>;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let data = self
+            .data
+            .get(offset as usize..offset as usize + bytes.len())
+            .ok_or(WearLevelledError::OutOfBounds)?;
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<F: NorFlash, const N: usize> Storage for WearLevelled<F, N> {
+    /// Writes that don't change the data are skipped, so saving unchanged settings causes no wear.
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let data = self
+            .data
+            .get_mut(offset as usize..offset as usize + bytes.len())
+            .ok_or(WearLevelledError::OutOfBounds)?;
+        if data == bytes {
+            return Ok(());
+        }
+        data.copy_from_slice(bytes);
+        self.commit().map_err(WearLevelledError::Flash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_storage::nor_flash::{ErrorType, ReadNorFlash};
+
+    const ERASE_SIZE: usize = 64;
+
+    /// Two sectors of flash in RAM, which like real flash can only clear bits until erased.
+    struct RamFlash {
+        bytes: [u8; 2 * ERASE_SIZE],
+        erases: [u32; 2],
+    }
+
+    impl RamFlash {
+        fn new() -> RamFlash {
+            RamFlash {
+                bytes: [0xFF; 2 * ERASE_SIZE],
+                erases: [0; 2],
+            }
+        }
+    }
+
+    impl ErrorType for RamFlash {
+        type Error = Infallible;
+    }
+
+    impl ReadNorFlash for RamFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Infallible> {
+            bytes.copy_from_slice(&self.bytes[offset as usize..offset as usize + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = ERASE_SIZE;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Infallible> {
+            assert_eq!(from as usize % ERASE_SIZE, 0);
+            assert_eq!(to - from, ERASE_SIZE as u32);
+            self.bytes[from as usize..to as usize].fill(0xFF);
+            self.erases[from as usize / ERASE_SIZE] += 1;
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Infallible> {
+            assert_eq!(offset as usize % Self::WRITE_SIZE, 0);
+            assert_eq!(bytes.len() % Self::WRITE_SIZE, 0);
+            for (old, new) in self.bytes[offset as usize..].iter_mut().zip(bytes) {
+                assert_eq!(*old & new, *new, "writing to flash that wasn't erased");
+                *old = *new;
+            }
+            Ok(())
+        }
+    }
+
+    /// 8 bytes, so each slot is 16 bytes and each sector holds 4.
+    type Settings = WearLevelled<RamFlash, 8>;
+
+    #[test]
+    fn erased() {
+        let mut storage = Settings::new(RamFlash::new()).unwrap();
+        assert_eq!(storage.data(), &[0xFF; 8]);
+        assert_eq!(storage.capacity(), 8);
+        let mut bytes = [0; 4];
+        assert_eq!(
+            storage.read(6, &mut bytes),
+            Err(WearLevelledError::OutOfBounds)
+        );
+        assert_eq!(storage.write(8, &[0]), Err(WearLevelledError::OutOfBounds));
+        storage.read(4, &mut bytes).unwrap();
+        assert_eq!(bytes, [0xFF; 4]);
+        storage.write(8, &[]).unwrap();
+        assert_eq!(storage.free().erases, [0, 0]);
+    }
+
+    #[test]
+    fn wrap_around() {
+        let mut flash = RamFlash::new();
+        // every slot is used twice, and a bit more
+        for i in 0..20u8 {
+            let mut storage = Settings::new(flash).unwrap();
+            if i > 0 {
+                assert_eq!(storage.data()[..2], [i - 1, !(i - 1)]);
+            }
+            storage.write(0, &[i, !i]).unwrap();
+            assert_eq!(storage.data()[..3], [i, !i, 0xFF]);
+            flash = storage.free();
+        }
+        assert_eq!(flash.erases, [3, 2]);
+        // slot 3 of the first sector holds the newest copy, sequence 19
+        assert_eq!(
+            flash.bytes[48..60],
+            [19, 0, 0, 0, 19, !19, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(flash.bytes[60..64], (!19u32).to_le_bytes());
+    }
+
+    #[test]
+    fn unchanged_writes_skipped() {
+        let mut storage = Settings::new(RamFlash::new()).unwrap();
+        storage.write(0, &[1, 2, 3]).unwrap();
+        let written = storage.flash.bytes;
+        storage.write(1, &[2, 3]).unwrap();
+        assert_eq!(storage.flash.bytes, written);
+    }
+
+    #[test]
+    fn interrupted_write() {
+        let mut storage = Settings::new(RamFlash::new()).unwrap();
+        storage.write(0, &[1]).unwrap();
+        storage.write(0, &[2]).unwrap();
+        let mut flash = storage.free();
+        // lose power before the footer of the second copy was written
+        flash.bytes[16 + 12..32].fill(0xFF);
+        let storage = Settings::new(flash).unwrap();
+        assert_eq!(storage.data()[0], 1);
+    }
+}
@@ -0,0 +1,27 @@
+use crate::rp2040_hal::{
+    gpio::{FunctionSioOutput, Pin, PinId, PullType},
+    pac::SIO,
+};
+
+/// Toggles a GPIO every time a poll is answered, see [`crate::GamecubeController::set_poll_strobe`].
+///
+/// This gives an oscilloscope or latency tester something to trigger on, and lets LED animations lock to the console's polling.
+/// The toggle is a single write to the SIO, so it costs a couple of cycles on the poll path.
+#[derive(Debug)]
+pub struct PollStrobe {
+    mask: u32,
+}
+
+impl PollStrobe {
+    /// Takes ownership of `pin` so nothing else can drive it.
+    pub fn new<I: PinId, R: PullType>(pin: Pin<I, FunctionSioOutput, R>) -> PollStrobe {
+        PollStrobe {
+            mask: 1 << pin.id().num,
+        }
+    }
+
+    pub(crate) fn toggle(&self) {
+        // Safety: GPIO_OUT_XOR only affects the bits that are set, and we own the only pin in the mask.
+        unsafe { (*SIO::ptr()).gpio_out_xor().write(|w| w.bits(self.mask)) };
+    }
+}
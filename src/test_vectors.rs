@@ -0,0 +1,190 @@
+//! Known good command and response byte sequences for the joybus protocol.
+//!
+//! These describe the bytes of each frame without the stop bit, the `sim` module can turn them into line samples.
+//! They are checked against this crate's own responses at compile time
+//! and can be used by downstream adapters and host implementations to validate their own integrations.
+
+/// A single command sent by the host and the response sent by the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exchange {
+    pub command: &'static [u8],
+    pub response: &'static [u8],
+}
+
+/// Probe of a standard gamecube controller.
+pub const GAMECUBE_PROBE: Exchange = Exchange {
+    command: &[0x00],
+    response: &[0x09, 0x00, 0x03],
+};
+
+/// Reset of a standard gamecube controller, answered the same as a probe.
+pub const GAMECUBE_RESET: Exchange = Exchange {
+    command: &[0xFF],
+    response: &[0x09, 0x00, 0x03],
+};
+
+/// Origin of a gamecube controller with centered sticks and released triggers.
+pub const GAMECUBE_ORIGIN: Exchange = Exchange {
+    command: &[0x41],
+    response: &[0x00, 0x80, 128, 128, 128, 128, 0, 0, 0, 0],
+};
+
+/// Mode 3 poll with rumble off, answered with no buttons pressed, centered sticks and released triggers.
+pub const GAMECUBE_POLL_MODE3_NEUTRAL: Exchange = Exchange {
+    command: &[0x40, 0x03, 0x00],
+    response: &[0x00, 0x80, 128, 128, 128, 128, 0, 0],
+};
+
+/// Mode 3 poll with rumble on, answered with A and Z held,
+/// the stick fully right, the C-stick fully down and L fully pressed.
+pub const GAMECUBE_POLL_MODE3_INPUTS: Exchange = Exchange {
+    command: &[0x40, 0x03, 0x01],
+    response: &[0x01, 0x90, 255, 128, 128, 0, 255, 0],
+};
+
+/// Probe of a gamecube ASCII keyboard.
+pub const KEYBOARD_PROBE: Exchange = Exchange {
+    command: &[0x00],
+    response: &[0x08, 0x20, 0x00],
+};
+
+/// Info of an N64 controller with nothing in its accessory slot.
+pub const N64_INFO: Exchange = Exchange {
+    command: &[0x00],
+    response: &[0x05, 0x00, 0x02],
+};
+
+/// Info of an N64 controller with an accessory such as a controller pak inserted.
+pub const N64_INFO_WITH_PAK: Exchange = Exchange {
+    command: &[0x00],
+    response: &[0x05, 0x00, 0x01],
+};
+
+/// Everything a standard gamecube controller is expected to answer.
+pub const GAMECUBE_CONTROLLER: &[Exchange] = &[
+    GAMECUBE_PROBE,
+    GAMECUBE_RESET,
+    GAMECUBE_ORIGIN,
+    GAMECUBE_POLL_MODE3_NEUTRAL,
+    GAMECUBE_POLL_MODE3_INPUTS,
+];
+
+// Check the vectors against what GamecubeController actually sends.
+// The helper lives inside the block, rustc 1.84 doesn't see uses from an anonymous const as uses of outside items.
+const _: () = {
+    const fn bytes_eq(a: &[u8], b: &[u8]) -> bool {
+        if a.len() != b.len() {
+            return false;
+        }
+        let mut i = 0;
+        while i < a.len() {
+            if a[i] != b[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    assert!(bytes_eq(GAMECUBE_PROBE.response, &crate::ID_RESPONSE));
+    assert!(bytes_eq(GAMECUBE_ORIGIN.response, &crate::ORIGIN_RESPONSE));
+    assert!(bytes_eq(
+        GAMECUBE_POLL_MODE3_NEUTRAL.response,
+        &crate::GamecubeInput::NEUTRAL.to_mode3().encode()
+    ));
+
+    let mut input = crate::GamecubeInput::NEUTRAL;
+    input.a = true;
+    input.z = true;
+    input.stick_x = 255;
+    input.cstick_y = 0;
+    input.l_analog = 255;
+    assert!(bytes_eq(
+        GAMECUBE_POLL_MODE3_INPUTS.response,
+        &input.to_mode3().encode()
+    ));
+
+    #[cfg(feature = "keyboard")]
+    assert!(bytes_eq(
+        KEYBOARD_PROBE.response,
+        &crate::keyboard::KEYBOARD_ID_RESPONSE
+    ));
+    #[cfg(feature = "n64")]
+    assert!(bytes_eq(N64_INFO.response, &crate::n64::N64_ID_RESPONSE));
+    #[cfg(feature = "n64")]
+    assert!(bytes_eq(
+        N64_INFO_WITH_PAK.response,
+        &[
+            crate::protocol::N64_CONTROLLER_ID[0],
+            crate::protocol::N64_CONTROLLER_ID[1],
+            crate::pak::PAK_INSERTED
+        ]
+    ));
+    assert!(bytes_eq(GAMECUBE_RESET.response, &crate::ID_RESPONSE));
+};
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::sim::{decode_frame, encode_frame};
+    use crate::{FsmAction, GamecubeInput, ProtocolFsm};
+
+    const ALL: &[Exchange] = &[
+        GAMECUBE_PROBE,
+        GAMECUBE_RESET,
+        GAMECUBE_ORIGIN,
+        GAMECUBE_POLL_MODE3_NEUTRAL,
+        GAMECUBE_POLL_MODE3_INPUTS,
+        KEYBOARD_PROBE,
+        N64_INFO,
+        N64_INFO_WITH_PAK,
+    ];
+
+    #[test]
+    fn wire_round_trip() {
+        for exchange in ALL {
+            for frame in [exchange.command, exchange.response] {
+                assert_eq!(decode_frame(&encode_frame(frame)).as_deref(), Ok(frame));
+            }
+        }
+    }
+
+    #[test]
+    fn gamecube_commands() {
+        let expected = [
+            FsmAction::RespondId,
+            FsmAction::Reset,
+            FsmAction::RespondOrigin,
+            FsmAction::RespondPoll {
+                mode: 3,
+                rumble: false,
+            },
+            FsmAction::RespondPoll {
+                mode: 3,
+                rumble: true,
+            },
+        ];
+        for (exchange, expected) in GAMECUBE_CONTROLLER.iter().zip(expected) {
+            let mut fsm = ProtocolFsm::new();
+            let action = exchange
+                .command
+                .iter()
+                .map(|byte| fsm.on_byte(*byte))
+                .last();
+            assert_eq!(action, Some(expected), "{:?}", exchange);
+            assert!(fsm.is_idle());
+        }
+    }
+
+    #[test]
+    fn gamecube_poll_responses() {
+        for exchange in [GAMECUBE_POLL_MODE3_NEUTRAL, GAMECUBE_POLL_MODE3_INPUTS] {
+            let report: [u8; 8] = exchange.response.try_into().unwrap();
+            assert_eq!(GamecubeInput::from_report(&report).create_report(), report);
+        }
+        assert_eq!(
+            GamecubeInput::from_report(&GAMECUBE_POLL_MODE3_NEUTRAL.response.try_into().unwrap()),
+            GamecubeInput::NEUTRAL
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Typing text into a game's on-screen keyboard by pressing buttons, for kiosks and automation.
+//!
+//! A [`KeyboardLayout`] describes the on-screen keyboard as rows of characters with the cursor starting on one of them.
+//! [`TextEntry`] then steps the cursor to each character of the text with the d-pad and presses A,
+//! one input per poll like [`crate::replay::ReplayPlayer`]:
+//!
+//! ```ignore
+//! const LAYOUT: KeyboardLayout = KeyboardLayout::new(&["ABCDEFGHIJ", "KLMNOPQRST", "UVWXYZ .-!"]);
+//! let mut entry = TextEntry::new(LAYOUT, "HELLO")?;
+//! while !entry.is_finished() {
+//!     controller.poll_blocking(&timer, &mut delay, || entry.next_input());
+//! }
+//! ```
+//!
+//! Every button is held for a few polls and then released for a few polls, so that menus which act on presses
+//! rather than held buttons see each one. Games that read the keyboard once per frame need
+//! [`TextEntry::set_timing`] to cover at least a frame each way.
+
+use crate::remap::Button;
+use crate::GamecubeInput;
+
+/// An on-screen keyboard, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardLayout<'a> {
+    rows: &'a [&'a str],
+    /// Row and column the cursor is on when the keyboard opens.
+    start: (usize, usize),
+    /// Whether moving off one edge comes back in on the opposite edge.
+    wrap: bool,
+}
+
+impl<'a> KeyboardLayout<'a> {
+    /// Every row must have the same number of characters, pad short rows with a character that isn't typed, e.g. `'\0'`.
+    /// The cursor starts in the top left and doesn't wrap around.
+    pub const fn new(rows: &'a [&'a str]) -> KeyboardLayout<'a> {
+        KeyboardLayout {
+            rows,
+            start: (0, 0),
+            wrap: false,
+        }
+    }
+
+    pub const fn with_start(self, row: usize, column: usize) -> KeyboardLayout<'a> {
+        KeyboardLayout {
+            start: (row, column),
+            ..self
+        }
+    }
+
+    /// Take the shorter way around when moving off one edge comes back in on the opposite edge.
+    pub const fn with_wrap(self, wrap: bool) -> KeyboardLayout<'a> {
+        KeyboardLayout { wrap, ..self }
+    }
+
+    /// The row and column of `c`.
+    fn position(&self, c: char) -> Option<(usize, usize)> {
+        self.rows
+            .iter()
+            .enumerate()
+            .find_map(|(row, keys)| Some((row, keys.chars().position(|key| key == c)?)))
+    }
+
+    fn columns(&self) -> usize {
+        self.rows.first().map_or(0, |keys| keys.chars().count())
+    }
+}
+
+/// The text passed to [`TextEntry::new`] can't be typed on the layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEntryError {
+    /// The rows of the layout have different lengths. Contains the first row that doesn't match the first.
+    RaggedLayout { row: usize },
+    /// The start position is outside the layout.
+    StartOutOfBounds,
+    /// The character at byte offset `index` of the text isn't on the layout.
+    NotInLayout { index: usize, c: char },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Pressing { button: Button, polls: u8 },
+    Releasing { polls: u8 },
+}
+
+/// Types text on a [`KeyboardLayout`] one poll at a time, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct TextEntry<'a> {
+    layout: KeyboardLayout<'a>,
+    text: &'a str,
+    /// Byte offset into `text` of the next character to type.
+    next: usize,
+    cursor: (usize, usize),
+    phase: Option<Phase>,
+    hold_polls: u8,
+    release_polls: u8,
+}
+
+impl<'a> TextEntry<'a> {
+    /// Type `text` on `layout`, starting with the cursor at the layout's start position.
+    pub fn new(layout: KeyboardLayout<'a>, text: &'a str) -> Result<TextEntry<'a>, TextEntryError> {
+        let columns = layout.columns();
+        if let Some(row) = layout
+            .rows
+            .iter()
+            .position(|keys| keys.chars().count() != columns)
+        {
+            return Err(TextEntryError::RaggedLayout { row });
+        }
+        if layout.start.0 >= layout.rows.len() || layout.start.1 >= columns {
+            return Err(TextEntryError::StartOutOfBounds);
+        }
+        if let Some((index, c)) = text
+            .char_indices()
+            .find(|(_, c)| layout.position(*c).is_none())
+        {
+            return Err(TextEntryError::NotInLayout { index, c });
+        }
+        Ok(TextEntry {
+            layout,
+            text,
+            next: 0,
+            cursor: layout.start,
+            phase: None,
+            hold_polls: 2,
+            release_polls: 2,
+        })
+    }
+
+    /// How many polls each button is held for and then released for, 2 each by default. 0 is treated as 1.
+    pub fn set_timing(&mut self, hold_polls: u8, release_polls: u8) {
+        self.hold_polls = hold_polls.max(1);
+        self.release_polls = release_polls.max(1);
+    }
+
+    /// The input to answer the current poll with.
+    /// Once every character has been typed this returns [`GamecubeInput::NEUTRAL`].
+    pub fn next_input(&mut self) -> GamecubeInput {
+        let mut input = GamecubeInput::NEUTRAL;
+        let phase = match self.phase {
+            Some(phase) => phase,
+            None => match self.next_button() {
+                Some(button) => Phase::Pressing {
+                    button,
+                    polls: self.hold_polls,
+                },
+                None => return input,
+            },
+        };
+        self.phase = match phase {
+            Phase::Pressing { button, polls } => {
+                button.set_pressed(&mut input, true);
+                if polls > 1 {
+                    Some(Phase::Pressing {
+                        button,
+                        polls: polls - 1,
+                    })
+                } else {
+                    self.pressed(button);
+                    Some(Phase::Releasing {
+                        polls: self.release_polls,
+                    })
+                }
+            }
+            Phase::Releasing { polls } => {
+                (polls > 1).then_some(Phase::Releasing { polls: polls - 1 })
+            }
+        };
+        input
+    }
+
+    /// Whether every character has been typed and the final A press released.
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.text.len() && self.phase.is_none()
+    }
+
+    /// The text that has been typed so far.
+    pub fn typed(&self) -> &'a str {
+        &self.text[..self.next]
+    }
+
+    /// The button that moves the cursor towards the next character, or A once it is there.
+    fn next_button(&self) -> Option<Button> {
+        let c = self.text[self.next..].chars().next()?;
+        // checked by new
+        let (row, column) = self.layout.position(c).unwrap();
+        let (cursor_row, cursor_column) = self.cursor;
+        let button = if row != cursor_row {
+            if self.forwards(cursor_row, row, self.layout.rows.len()) {
+                Button::DpadDown
+            } else {
+                Button::DpadUp
+            }
+        } else if column != cursor_column {
+            if self.forwards(cursor_column, column, self.layout.columns()) {
+                Button::DpadRight
+            } else {
+                Button::DpadLeft
+            }
+        } else {
+            Button::A
+        };
+        Some(button)
+    }
+
+    /// Whether moving from `from` to `to` along an axis of `len` keys is shortest by counting up.
+    fn forwards(&self, from: usize, to: usize, len: usize) -> bool {
+        if self.layout.wrap {
+            (to + len - from) % len <= len / 2
+        } else {
+            to > from
+        }
+    }
+
+    /// Move the cursor or the text along once `button` has been pressed.
+    fn pressed(&mut self, button: Button) {
+        let rows = self.layout.rows.len();
+        let columns = self.layout.columns();
+        let (row, column) = &mut self.cursor;
+        match button {
+            Button::DpadDown => *row = (*row + 1) % rows,
+            Button::DpadUp => *row = (*row + rows - 1) % rows,
+            Button::DpadRight => *column = (*column + 1) % columns,
+            Button::DpadLeft => *column = (*column + columns - 1) % columns,
+            _ => {
+                let c = self.text[self.next..].chars().next().unwrap();
+                self.next += c.len_utf8();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `entry` to the end with each button held and released for a single poll, returning the buttons pressed.
+    fn presses<const N: usize>(mut entry: TextEntry) -> ([Option<Button>; N], usize) {
+        entry.set_timing(0, 0);
+        let mut buttons = [None; N];
+        let mut count = 0;
+        while !entry.is_finished() {
+            let input = entry.next_input();
+            buttons[count] = Button::ALL
+                .into_iter()
+                .find(|button| button.is_pressed(&input));
+            assert!(buttons[count].is_some(), "nothing pressed on poll {count}");
+            assert_eq!(entry.next_input(), GamecubeInput::NEUTRAL);
+            count += 1;
+        }
+        assert_eq!(entry.next_input(), GamecubeInput::NEUTRAL);
+        (buttons, count)
+    }
+
+    const GRID: KeyboardLayout = KeyboardLayout::new(&["ABC", "DEF"]);
+
+    #[test]
+    fn moves() {
+        use Button::*;
+        let (buttons, count) = presses::<8>(TextEntry::new(GRID, "FA").unwrap());
+        assert_eq!(
+            buttons[..count],
+            [DpadDown, DpadRight, DpadRight, A, DpadUp, DpadLeft, DpadLeft, A].map(Some)
+        );
+
+        let (buttons, count) = presses::<4>(TextEntry::new(GRID.with_start(1, 1), "EE").unwrap());
+        assert_eq!(buttons[..count], [Some(A), Some(A)]);
+    }
+
+    #[test]
+    fn wrap() {
+        use Button::*;
+        let row = KeyboardLayout::new(&["ABCDE"]);
+        let (buttons, count) = presses::<8>(TextEntry::new(row, "E").unwrap());
+        assert_eq!(
+            buttons[..count],
+            [DpadRight, DpadRight, DpadRight, DpadRight, A].map(Some)
+        );
+        let (buttons, count) = presses::<8>(TextEntry::new(row.with_wrap(true), "EC").unwrap());
+        assert_eq!(
+            buttons[..count],
+            [DpadLeft, A, DpadLeft, DpadLeft, A].map(Some)
+        );
+    }
+
+    #[test]
+    fn timing() {
+        let mut entry = TextEntry::new(GRID, "A").unwrap();
+        let mut pressed = [false; 4];
+        for pressed in &mut pressed {
+            assert!(!entry.is_finished());
+            *pressed = entry.next_input().a;
+        }
+        assert_eq!(pressed, [true, true, false, false]);
+        assert!(entry.is_finished());
+        assert_eq!(entry.typed(), "A");
+
+        let mut entry = TextEntry::new(GRID, "A").unwrap();
+        entry.set_timing(3, 1);
+        let pressed = [(); 4].map(|_| entry.next_input().a);
+        assert_eq!(pressed, [true, true, true, false]);
+        assert!(entry.is_finished());
+    }
+
+    #[test]
+    fn empty() {
+        let mut entry = TextEntry::new(GRID, "").unwrap();
+        assert!(entry.is_finished());
+        assert_eq!(entry.next_input(), GamecubeInput::NEUTRAL);
+        assert_eq!(entry.typed(), "");
+    }
+
+    #[test]
+    fn multi_byte() {
+        use Button::*;
+        let layout = KeyboardLayout::new(&["AÉ", "ñ\0"]);
+        let (buttons, count) = presses::<8>(TextEntry::new(layout, "ÉñA").unwrap());
+        assert_eq!(
+            buttons[..count],
+            [DpadRight, A, DpadDown, DpadLeft, A, DpadUp, A].map(Some)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let ragged = KeyboardLayout::new(&["AB", "CD", "E"]);
+        assert_eq!(
+            TextEntry::new(ragged, "A").unwrap_err(),
+            TextEntryError::RaggedLayout { row: 2 }
+        );
+        assert_eq!(
+            TextEntry::new(GRID.with_start(2, 0), "A").unwrap_err(),
+            TextEntryError::StartOutOfBounds
+        );
+        assert_eq!(
+            TextEntry::new(GRID.with_start(0, 3), "A").unwrap_err(),
+            TextEntryError::StartOutOfBounds
+        );
+        assert_eq!(
+            TextEntry::new(GRID, "Aé").unwrap_err(),
+            TextEntryError::NotInLayout { index: 1, c: 'é' }
+        );
+        assert_eq!(
+            TextEntry::new(KeyboardLayout::new(&[]), "").unwrap_err(),
+            TextEntryError::StartOutOfBounds
+        );
+    }
+}
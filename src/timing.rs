@@ -0,0 +1,120 @@
+//! Timing parameters of the joybus PIO program.
+
+/// PIO cycles spent on the initial low period of every bit.
+pub const T1: u32 = 10;
+/// PIO cycles spent on the data period of every bit.
+pub const T2: u32 = 20;
+/// PIO cycles spent on the final high period of every bit.
+pub const T3: u32 = 10;
+
+/// PIO cycles that make up a single bit on the wire.
+pub const CYCLES_PER_BIT: u32 = T1 + T2 + T3;
+
+/// Joybus bitrate in bits per second, 4us per bit.
+pub const BITRATE: u32 = 250_000;
+
+/// Calculates the PIO clock divisor needed to run [`CYCLES_PER_BIT`] cycles per bit at `bitrate`
+/// from a system clock of `sys_hz`.
+///
+/// Returned as the integer and fractional (1/256ths) parts expected by `clock_divisor_fixed_point`,
+/// rounded to the nearest representable divisor.
+/// The integer part saturates at `u16::MAX`.
+pub const fn clock_divisor(sys_hz: u32, bitrate: u32) -> (u16, u8) {
+    let cycles_per_second = CYCLES_PER_BIT as u64 * bitrate as u64;
+    let divisor_256ths = (sys_hz as u64 * 256 + cycles_per_second / 2) / cycles_per_second;
+    let int = divisor_256ths >> 8;
+    if int > u16::MAX as u64 {
+        (u16::MAX, 0)
+    } else {
+        (int as u16, (divisor_256ths & 0xFF) as u8)
+    }
+}
+
+/// The slowest system clock that can run the PIO program at [`BITRATE`], since the PIO clock divisor can't go below 1.
+pub const MIN_SYSTEM_CLOCK_HZ: u32 = CYCLES_PER_BIT * BITRATE;
+
+/// The system clock can not be used to run the joybus protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockError {
+    /// The system clock is too slow to produce the required bit timing,
+    /// `sys_hz` must be at least `min_hz`.
+    TooSlow { sys_hz: u32, min_hz: u32 },
+}
+
+impl core::fmt::Display for ClockError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ClockError::TooSlow { sys_hz, min_hz } => write!(
+                f,
+                "system clock of {sys_hz}Hz is too slow for joybus, at least {min_hz}Hz is required"
+            ),
+        }
+    }
+}
+
+/// Same as [`clock_divisor`] for the joybus [`BITRATE`] but returns an error instead of a divisor that can't produce the bit timing.
+///
+/// 48MHz, the default 125MHz, 133MHz and overclocks of 200MHz+ are all fine.
+pub const fn checked_clock_divisor(sys_hz: u32) -> Result<(u16, u8), ClockError> {
+    if sys_hz < MIN_SYSTEM_CLOCK_HZ {
+        return Err(ClockError::TooSlow {
+            sys_hz,
+            min_hz: MIN_SYSTEM_CLOCK_HZ,
+        });
+    }
+    Ok(clock_divisor(sys_hz, BITRATE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_divisor_default_clock() {
+        // 125MHz / 10MHz = 12.5
+        assert_eq!(clock_divisor(125_000_000, BITRATE), (12, 128));
+    }
+
+    #[test]
+    fn clock_divisor_edges() {
+        assert_eq!(clock_divisor(MIN_SYSTEM_CLOCK_HZ, BITRATE), (1, 0));
+        assert_eq!(clock_divisor(0, BITRATE), (0, 0));
+        assert_eq!(clock_divisor(u32::MAX, BITRATE), (429, 127));
+        // the integer part saturates
+        assert_eq!(clock_divisor(4_000_000_000, 1), (u16::MAX, 0));
+    }
+
+    #[test]
+    fn clock_divisor_rounds_to_nearest() {
+        // either side of halfway between a divisor of 1 and 1 + 1/256
+        assert_eq!(clock_divisor(10_019_531, BITRATE), (1, 0));
+        assert_eq!(clock_divisor(10_019_532, BITRATE), (1, 1));
+    }
+
+    #[test]
+    fn checked_clock_divisor_common_clocks() {
+        assert_eq!(checked_clock_divisor(48_000_000), Ok((4, 205)));
+        assert_eq!(checked_clock_divisor(125_000_000), Ok((12, 128)));
+        assert_eq!(checked_clock_divisor(133_000_000), Ok((13, 77)));
+        assert_eq!(checked_clock_divisor(200_000_000), Ok((20, 0)));
+    }
+
+    #[test]
+    fn checked_clock_divisor_too_slow() {
+        assert_eq!(checked_clock_divisor(MIN_SYSTEM_CLOCK_HZ), Ok((1, 0)));
+        assert_eq!(
+            checked_clock_divisor(MIN_SYSTEM_CLOCK_HZ - 1),
+            Err(ClockError::TooSlow {
+                sys_hz: MIN_SYSTEM_CLOCK_HZ - 1,
+                min_hz: MIN_SYSTEM_CLOCK_HZ
+            })
+        );
+        assert_eq!(
+            checked_clock_divisor(0),
+            Err(ClockError::TooSlow {
+                sys_hz: 0,
+                min_hz: MIN_SYSTEM_CLOCK_HZ
+            })
+        );
+    }
+}
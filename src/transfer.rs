@@ -0,0 +1,413 @@
+//! Receiving firmware images and other large blobs from a homebrew console app over the controller cable.
+//!
+//! The console sends fixed size [`COMMAND_LEN`] byte frames starting with [`TRANSFER_COMMAND`], an opcode that
+//! no console or game uses, and [`TransferDevice`] answers each one with a [`RESPONSE_LEN`] byte status.
+//! It also answers probes as a standard controller, so the app can find it like any other controller.
+//!
+//! Command frames, with multi byte values little endian:
+//! * [`TRANSFER_COMMAND`]
+//! * [`TransferOp`]
+//! * offset: u32, where in the image the payload goes
+//! * payload: [`CHUNK_LEN`] bytes
+//! * CRC-16/CCITT-FALSE of the op, offset and payload, big endian
+//!
+//! Response frames:
+//! * [`TransferStatus`]
+//! * the offset of the next chunk the device expects: u32
+//!
+//! A transfer is a [`TransferOp::Begin`] whose payload holds the image length and CRC-32 as u32s,
+//! then [`TransferOp::Data`] for every chunk in order, then [`TransferOp::Finish`].
+//! Chunks are acknowledged as soon as they arrive and written to storage afterwards,
+//! the device doesn't respond while writing, so the app should retry a command that times out.
+//! [`TransferOp::Status`] reports the state of the transfer without changing it.
+//!
+//! ```ignore
+//! let mut device = TransferDevice::new(port, firmware_region);
+//! loop {
+//!     if let Some(TransferEvent::Complete { len }) = device.respond(&timer, &mut delay, 1_000_000) {
+//!         mark_update_ready(len);
+//!     }
+//! }
+//! ```
+
+use cortex_m::delay::Delay;
+
+use crate::protocol::{CMD_PROBE, CMD_RESET, REPLY_DELAY_US};
+use crate::rp2040_hal::{
+    gpio::bank0::Gpio28,
+    pac::PIO0,
+    pio::{PIOExt, StateMachineIndex, SM0},
+    Timer,
+};
+use crate::storage::Storage;
+use crate::{JoybusPin, JoybusPort, FRAME_GAP_US, ID_RESPONSE};
+
+/// The opcode that starts every transfer command.
+pub const TRANSFER_COMMAND: u8 = 0xE0;
+
+/// Bytes of the image carried by each command.
+pub const CHUNK_LEN: usize = 32;
+
+/// The size of a command frame, including the opcode.
+pub const COMMAND_LEN: usize = 8 + CHUNK_LEN;
+
+/// The size of a response frame.
+pub const RESPONSE_LEN: usize = 5;
+
+/// The second byte of a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TransferOp {
+    /// Start a new transfer, abandoning any transfer in progress.
+    Begin = 1,
+    Data = 2,
+    /// Check the image against the CRC-32 sent with [`TransferOp::Begin`].
+    Finish = 3,
+    Status = 4,
+}
+
+impl TransferOp {
+    fn from_u8(value: u8) -> Option<TransferOp> {
+        Some(match value {
+            1 => TransferOp::Begin,
+            2 => TransferOp::Data,
+            3 => TransferOp::Finish,
+            4 => TransferOp::Status,
+            _ => return None,
+        })
+    }
+}
+
+/// The first byte of a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TransferStatus {
+    /// No transfer has been started.
+    Idle = 0,
+    /// The command was accepted and the transfer is in progress.
+    Ok = 1,
+    /// The command's CRC-16 didn't match, it was ignored.
+    BadCrc = 2,
+    /// The command was for a different offset than expected, it was ignored. The response holds the expected offset.
+    BadOffset = 3,
+    /// The image is larger than the storage.
+    TooLarge = 4,
+    /// The received image doesn't match the CRC-32 sent with [`TransferOp::Begin`].
+    BadImage = 5,
+    /// The whole image was received and written.
+    Complete = 6,
+    /// Writing to the storage failed, the transfer has to be started again.
+    StorageError = 7,
+    /// The op wasn't recognized.
+    UnknownOp = 8,
+}
+
+/// Returned by [`TransferDevice::respond`] when a transfer changes state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferEvent {
+    Started {
+        len: u32,
+    },
+    /// A chunk was written to storage.
+    Chunk {
+        offset: u32,
+    },
+    /// The whole image was received, written and matches its CRC-32.
+    Complete {
+        len: u32,
+    },
+    /// The transfer failed and has to be started again.
+    Failed(TransferStatus),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    Receiving {
+        len: u32,
+        crc: u32,
+        /// CRC-32 of everything received so far, before the final inversion.
+        running_crc: u32,
+        next_offset: u32,
+    },
+    Complete {
+        len: u32,
+    },
+    Failed(TransferStatus),
+}
+
+/// Build a command frame, for the console side app or for testing from host mode.
+pub fn encode_command(op: TransferOp, offset: u32, payload: &[u8; CHUNK_LEN]) -> [u8; COMMAND_LEN] {
+    let mut frame = [0; COMMAND_LEN];
+    frame[0] = TRANSFER_COMMAND;
+    frame[1] = op as u8;
+    frame[2..6].copy_from_slice(&offset.to_le_bytes());
+    frame[6..6 + CHUNK_LEN].copy_from_slice(payload);
+    let crc = crc16(&frame[1..6 + CHUNK_LEN]);
+    frame[6 + CHUNK_LEN..].copy_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+/// CRC-16/CCITT-FALSE, protecting each command.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Feed `bytes` into a CRC-32 (IEEE) that started at `0xFFFF_FFFF`, invert the result once everything has been fed in.
+fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// CRC-32 (IEEE) of a whole image, as sent with [`TransferOp::Begin`].
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !crc32_update(0xFFFF_FFFF, bytes)
+}
+
+/// Receives transfers from the console into `T`, see the [module docs](self).
+pub struct TransferDevice<
+    T: Storage,
+    P: PIOExt = PIO0,
+    I: JoybusPin<P> = Gpio28,
+    S: StateMachineIndex = SM0,
+> {
+    port: JoybusPort<P, I, S>,
+    storage: T,
+    state: State,
+}
+
+impl<T: Storage, P: PIOExt, I: JoybusPin<P>, S: StateMachineIndex> TransferDevice<T, P, I, S> {
+    pub fn new(mut port: JoybusPort<P, I, S>, storage: T) -> TransferDevice<T, P, I, S> {
+        port.jump(0);
+        TransferDevice {
+            port,
+            storage,
+            state: State::Idle,
+        }
+    }
+
+    /// Returns the [`JoybusPort`] and storage so they can be reused.
+    pub fn free(self) -> (JoybusPort<P, I, S>, T) {
+        (self.port, self.storage)
+    }
+
+    /// Wait up to `timeout_us` microseconds for a command and respond to it.
+    /// Returns what happened to the transfer, or None if nothing changed.
+    pub fn respond(
+        &mut self,
+        timer: &Timer,
+        delay: &mut Delay,
+        timeout_us: u64,
+    ) -> Option<TransferEvent> {
+        match self.port.recv_byte(timer, timeout_us)? {
+            CMD_PROBE | CMD_RESET => {
+                delay.delay_us(REPLY_DELAY_US);
+                self.port.send_frame(&ID_RESPONSE);
+                None
+            }
+            TRANSFER_COMMAND => self.handle_command(timer, delay),
+            _ => {
+                debug!("joybus: resyncing");
+                self.port.restart_for_read(timer);
+                None
+            }
+        }
+    }
+
+    fn handle_command(&mut self, timer: &Timer, delay: &mut Delay) -> Option<TransferEvent> {
+        let mut frame = [0; COMMAND_LEN - 1];
+        if self.port.recv_frame(timer, &mut frame, FRAME_GAP_US) != Some(frame.len()) {
+            self.port.restart_for_read(timer);
+            return None;
+        }
+        let (body, crc) = frame.split_at(frame.len() - 2);
+        if crc16(body) != u16::from_be_bytes([crc[0], crc[1]]) {
+            self.send_status(delay, TransferStatus::BadCrc);
+            return None;
+        }
+        let offset = u32::from_le_bytes(body[1..5].try_into().unwrap());
+        let payload: &[u8; CHUNK_LEN] = body[5..].try_into().unwrap();
+
+        match TransferOp::from_u8(body[0]) {
+            Some(TransferOp::Begin) => {
+                let len = u32::from_le_bytes(payload[0..4].try_into().unwrap());
+                let crc = u32::from_le_bytes(payload[4..8].try_into().unwrap());
+                if len as usize > self.storage.capacity() {
+                    return self.fail(delay, TransferStatus::TooLarge);
+                }
+                self.state = State::Receiving {
+                    len,
+                    crc,
+                    running_crc: 0xFFFF_FFFF,
+                    next_offset: 0,
+                };
+                self.send_status(delay, TransferStatus::Ok);
+                Some(TransferEvent::Started { len })
+            }
+            Some(TransferOp::Data) => {
+                let State::Receiving {
+                    len,
+                    running_crc,
+                    next_offset,
+                    ..
+                } = &mut self.state
+                else {
+                    self.send_status(delay, self.status());
+                    return None;
+                };
+                if offset != *next_offset || offset >= *len {
+                    self.send_status(delay, TransferStatus::BadOffset);
+                    return None;
+                }
+                let chunk = &payload[..(*len - offset).min(CHUNK_LEN as u32) as usize];
+                *running_crc = crc32_update(*running_crc, chunk);
+                *next_offset += chunk.len() as u32;
+                self.send_status(delay, TransferStatus::Ok);
+
+                // the console retries whatever it sends while this is writing
+                if self.storage.write(offset, chunk).is_err() {
+                    warn!("joybus: transfer failed to write at {}", offset);
+                    self.state = State::Failed(TransferStatus::StorageError);
+                    return Some(TransferEvent::Failed(TransferStatus::StorageError));
+                }
+                Some(TransferEvent::Chunk { offset })
+            }
+            Some(TransferOp::Finish) => {
+                let State::Receiving {
+                    len,
+                    crc,
+                    running_crc,
+                    next_offset,
+                } = self.state
+                else {
+                    self.send_status(delay, self.status());
+                    return None;
+                };
+                if next_offset != len {
+                    self.send_status(delay, TransferStatus::BadOffset);
+                    return None;
+                }
+                if !running_crc != crc {
+                    return self.fail(delay, TransferStatus::BadImage);
+                }
+                self.state = State::Complete { len };
+                self.send_status(delay, TransferStatus::Complete);
+                Some(TransferEvent::Complete { len })
+            }
+            Some(TransferOp::Status) => {
+                self.send_status(delay, self.status());
+                None
+            }
+            None => {
+                self.send_status(delay, TransferStatus::UnknownOp);
+                None
+            }
+        }
+    }
+
+    fn fail(&mut self, delay: &mut Delay, status: TransferStatus) -> Option<TransferEvent> {
+        debug!("joybus: transfer failed {:?}", status);
+        self.state = State::Failed(status);
+        self.send_status(delay, status);
+        Some(TransferEvent::Failed(status))
+    }
+
+    /// The status of the transfer as a whole, sent in response to [`TransferOp::Status`].
+    fn status(&self) -> TransferStatus {
+        match self.state {
+            State::Idle => TransferStatus::Idle,
+            State::Receiving { .. } => TransferStatus::Ok,
+            State::Complete { .. } => TransferStatus::Complete,
+            State::Failed(status) => status,
+        }
+    }
+
+    fn send_status(&mut self, delay: &mut Delay, status: TransferStatus) {
+        let next_offset = match self.state {
+            State::Receiving { next_offset, .. } => next_offset,
+            State::Complete { len } => len,
+            State::Idle | State::Failed(_) => 0,
+        };
+        let mut response = [status as u8, 0, 0, 0, 0];
+        response[1..].copy_from_slice(&next_offset.to_le_bytes());
+        delay.delay_us(REPLY_DELAY_US);
+        self.port.send_frame(&response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_check_values() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(&[]), 0xFFFF);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn crc32_in_chunks() {
+        let mut image = [0; 3 * CHUNK_LEN + 5];
+        for (i, byte) in image.iter_mut().enumerate() {
+            *byte = (i * 7) as u8;
+        }
+        let running = image.chunks(CHUNK_LEN).fold(0xFFFF_FFFF, crc32_update);
+        assert_eq!(!running, crc32(&image));
+    }
+
+    #[test]
+    fn command() {
+        let mut payload = [0; CHUNK_LEN];
+        payload[0] = 0xAA;
+        payload[CHUNK_LEN - 1] = 0x55;
+        let frame = encode_command(TransferOp::Data, 0x0403_0201, &payload);
+        assert_eq!(
+            frame[..7],
+            [TRANSFER_COMMAND, 2, 0x01, 0x02, 0x03, 0x04, 0xAA]
+        );
+        assert_eq!(frame[6 + CHUNK_LEN - 1], 0x55);
+        // a CRC-16/CCITT-FALSE followed by its big endian CRC always comes out to 0
+        assert_eq!(crc16(&frame[1..]), 0);
+
+        let frame = encode_command(TransferOp::Status, 0, &[0; CHUNK_LEN]);
+        assert_eq!(frame[1], 4);
+        assert_eq!(crc16(&frame[1..]), 0);
+    }
+
+    #[test]
+    fn ops() {
+        for op in [
+            TransferOp::Begin,
+            TransferOp::Data,
+            TransferOp::Finish,
+            TransferOp::Status,
+        ] {
+            assert_eq!(TransferOp::from_u8(op as u8), Some(op));
+        }
+        assert_eq!(TransferOp::from_u8(0), None);
+        assert_eq!(TransferOp::from_u8(5), None);
+        assert_eq!(TransferOp::from_u8(0xFF), None);
+    }
+}
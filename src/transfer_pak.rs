@@ -0,0 +1,199 @@
+//! An emulated Transfer Pak, which gives the N64 access to a Game Boy cartridge, see [`TransferPak`].
+//!
+//! The console controls the pak through registers that each fill a 4KB region of the pak address space,
+//! every byte of a block read from a register holds its value:
+//!
+//! | Address | Register |
+//! |---------|----------|
+//! | `0x8000` | Power, write [`POWER_ON`] or [`POWER_OFF`]. Reads [`POWER_ON`] while powered, so the pak can be told apart from a rumble pak. |
+//! | `0xA000` | The bank of the cartridge mapped at `0xC000`, 0 to 3. |
+//! | `0xB000` | Write 1 to enable cartridge access or 0 to disable it. Reads the [status](TransferPak::status). |
+//! | `0xC000` to `0xFFFF` | A 16KB window into the cartridge address space, starting at bank × `0x4000`. |
+//!
+//! The cartridge behind the window is provided by implementing [`GbCartridge`],
+//! e.g. to pass accesses through to a real cartridge connected to spare pins, or to serve a ROM image from flash.
+//!
+//! ```ignore
+//! let mut controller = N64Controller::new_with_pak(port, Some(TransferPak::new(Some(RomCart::new(GAME_ROM)))));
+//! ```
+
+use crate::pak::{Pak, PAK_BLOCK_LEN};
+
+/// Written to the power register to power the cartridge, read back from it while powered.
+pub const POWER_ON: u8 = 0x84;
+
+/// Written to the power register to cut power to the cartridge.
+pub const POWER_OFF: u8 = 0xFE;
+
+/// Status bit set while cartridge access is enabled.
+pub const STATUS_ACCESS: u8 = 0x01;
+/// Status bit set on the first status read after the cartridge is powered.
+pub const STATUS_WAS_RESET: u8 = 0x04;
+/// Status bit set while no cartridge is inserted.
+pub const STATUS_REMOVED: u8 = 0x40;
+/// Status bit set while the cartridge is powered.
+pub const STATUS_POWERED: u8 = 0x80;
+
+const POWER_REGISTER: u16 = 0x8000;
+const BANK_REGISTER: u16 = 0xA000;
+const STATUS_REGISTER: u16 = 0xB000;
+const WINDOW: u16 = 0xC000;
+
+/// The size of the cartridge address space mapped into the window by each bank.
+pub const BANK_LEN: u16 = 0x4000;
+
+/// The Game Boy cartridge inserted into a [`TransferPak`].
+///
+/// Addresses are in the Game Boy's own address space, ROM at `0x0000` to `0x7FFF` and cartridge RAM at `0xA000` to `0xBFFF`.
+/// Like [`Pak`], both methods are called within the response window of a command so must be fast.
+pub trait GbCartridge {
+    /// Fill `data` with the 32 bytes starting at `address`.
+    fn read(&mut self, address: u16, data: &mut [u8; PAK_BLOCK_LEN]);
+
+    /// Write the 32 bytes of `data` one after the other starting at `address`.
+    /// Games switch memory banks by writing to the ROM area, which a real cartridge's mapper sees as a write of each byte.
+    fn write(&mut self, address: u16, data: &[u8; PAK_BLOCK_LEN]);
+}
+
+/// A Transfer Pak holding a cartridge of type `C`, insert it into an N64 controller with [`crate::n64::N64Controller::insert_pak`].
+pub struct TransferPak<C: GbCartridge> {
+    cart: Option<C>,
+    powered: bool,
+    access: bool,
+    bank: u8,
+    was_reset: bool,
+}
+
+impl<C: GbCartridge> TransferPak<C> {
+    pub const fn new(cart: Option<C>) -> TransferPak<C> {
+        TransferPak {
+            cart,
+            powered: false,
+            access: false,
+            bank: 0,
+            was_reset: false,
+        }
+    }
+
+    /// Insert `cart`, returning the cartridge it replaced.
+    pub fn insert_cart(&mut self, cart: C) -> Option<C> {
+        self.cart.replace(cart)
+    }
+
+    /// Remove the cartridge, cutting off access to it like pulling it out of a real pak would.
+    pub fn remove_cart(&mut self) -> Option<C> {
+        self.access = false;
+        self.cart.take()
+    }
+
+    /// The inserted cartridge.
+    pub fn cart(&mut self) -> Option<&mut C> {
+        self.cart.as_mut()
+    }
+
+    /// The value of the status register, made up of the `STATUS_` bits.
+    pub fn status(&self) -> u8 {
+        let mut status = 0;
+        if self.access {
+            status |= STATUS_ACCESS;
+        }
+        if self.was_reset {
+            status |= STATUS_WAS_RESET;
+        }
+        if self.cart.is_none() {
+            status |= STATUS_REMOVED;
+        }
+        if self.powered {
+            status |= STATUS_POWERED;
+        }
+        status
+    }
+
+    /// The cartridge address that `address` in the window maps to with the current bank.
+    fn cart_address(&self, address: u16) -> u16 {
+        self.bank as u16 * BANK_LEN + (address - WINDOW)
+    }
+}
+
+impl<C: GbCartridge> Pak for TransferPak<C> {
+    fn read(&mut self, address: u16, data: &mut [u8; PAK_BLOCK_LEN]) {
+        let value = match address {
+            WINDOW.. => {
+                let cart_address = self.cart_address(address);
+                match &mut self.cart {
+                    Some(cart) if self.access => cart.read(cart_address, data),
+                    _ => *data = [0; PAK_BLOCK_LEN],
+                }
+                return;
+            }
+            STATUS_REGISTER.. => {
+                let status = self.status();
+                self.was_reset = false;
+                status
+            }
+            BANK_REGISTER.. => self.bank,
+            POWER_REGISTER.. if self.powered => POWER_ON,
+            _ => 0,
+        };
+        *data = [value; PAK_BLOCK_LEN];
+    }
+
+    fn write(&mut self, address: u16, data: &[u8; PAK_BLOCK_LEN]) {
+        // registers are written with every byte of the block set to the value
+        let value = data[0];
+        match address {
+            WINDOW.. => {
+                let cart_address = self.cart_address(address);
+                if let Some(cart) = &mut self.cart {
+                    if self.access {
+                        cart.write(cart_address, data);
+                    }
+                }
+            }
+            STATUS_REGISTER.. => {
+                self.access = self.powered && value & 1 != 0 && self.cart.is_some()
+            }
+            BANK_REGISTER.. => self.bank = value & 3,
+            POWER_REGISTER.. => match value {
+                POWER_ON => {
+                    debug!("joybus: transfer pak powered on");
+                    self.powered = true;
+                    self.was_reset = true;
+                }
+                POWER_OFF => {
+                    self.powered = false;
+                    self.access = false;
+                }
+                _ => {}
+            },
+            // below 0x8000 is unused
+            _ => {}
+        }
+    }
+}
+
+/// A cartridge that serves a ROM image with no mapper or RAM, e.g. a 32KB game or one bank of a larger dump.
+pub struct RomCart<'a> {
+    rom: &'a [u8],
+}
+
+impl<'a> RomCart<'a> {
+    pub const fn new(rom: &'a [u8]) -> RomCart<'a> {
+        RomCart { rom }
+    }
+}
+
+impl GbCartridge for RomCart<'_> {
+    fn read(&mut self, address: u16, data: &mut [u8; PAK_BLOCK_LEN]) {
+        for (i, byte) in data.iter_mut().enumerate() {
+            let address = address as usize + i;
+            // anything outside the ROM area or the image is open bus, which reads high
+            *byte = match address {
+                0..0x8000 => self.rom.get(address).copied().unwrap_or(0xFF),
+                _ => 0xFF,
+            };
+        }
+    }
+
+    fn write(&mut self, _address: u16, _data: &[u8; PAK_BLOCK_LEN]) {}
+}
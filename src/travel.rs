@@ -0,0 +1,179 @@
+//! Travel time for sticks synthesized from buttons, as used by box style controllers.
+//!
+//! A stick driven by buttons jumps straight from one coordinate to another,
+//! which no physical stick can do, some games and tournament rulesets treat it differently from a real stick.
+//! [`TravelTime`] moves each stick to its new coordinate in a straight line over a configurable number of polls,
+//! sending the intermediate coordinates on the way.
+//!
+//! Apply it last, after [`crate::modifiers`], for every poll:
+//!
+//! ```ignore
+//! let mut travel = TravelTime::new(2, 2);
+//! let input = travel.apply(&modifiers.apply(&input, &held));
+//! controller.respond_to_poll(&timer, &mut delay, input);
+//! ```
+
+use crate::GamecubeInput;
+
+/// Moves each stick to its new coordinate over a number of polls, see the [module docs](self).
+///
+/// This holds the position of each stick, so [`TravelTime::apply`] must be called for every poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TravelTime {
+    stick: Travel,
+    cstick: Travel,
+}
+
+impl TravelTime {
+    /// Move the main stick over `stick_polls` polls and the c-stick over `cstick_polls` polls.
+    /// 0 or 1 moves a stick instantly.
+    pub const fn new(stick_polls: u8, cstick_polls: u8) -> TravelTime {
+        TravelTime {
+            stick: Travel::new(stick_polls),
+            cstick: Travel::new(cstick_polls),
+        }
+    }
+
+    pub fn set_polls(&mut self, stick_polls: u8, cstick_polls: u8) {
+        self.stick.polls = stick_polls;
+        self.cstick.polls = cstick_polls;
+    }
+
+    /// The number of polls the main stick and c-stick take to reach a new coordinate.
+    pub fn polls(&self) -> (u8, u8) {
+        (self.stick.polls, self.cstick.polls)
+    }
+
+    /// Returns true while either stick is between coordinates.
+    pub fn is_moving(&self) -> bool {
+        self.stick.is_moving() || self.cstick.is_moving()
+    }
+
+    /// Move both sticks of `input` one poll further toward their coordinates, everything else is passed through unchanged.
+    pub fn apply(&mut self, input: &GamecubeInput) -> GamecubeInput {
+        let mut output = *input;
+        (output.stick_x, output.stick_y) = self.stick.step((input.stick_x, input.stick_y));
+        (output.cstick_x, output.cstick_y) = self.cstick.step((input.cstick_x, input.cstick_y));
+        output
+    }
+}
+
+impl Default for TravelTime {
+    /// Both sticks move instantly.
+    fn default() -> Self {
+        TravelTime::new(0, 0)
+    }
+}
+
+/// The travel of a single stick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Travel {
+    polls: u8,
+    from: (u8, u8),
+    to: (u8, u8),
+    /// The number of polls since the stick started moving from `from` to `to`.
+    elapsed: u8,
+}
+
+impl Travel {
+    const fn new(polls: u8) -> Travel {
+        Travel {
+            polls,
+            from: (128, 128),
+            to: (128, 128),
+            elapsed: 0,
+        }
+    }
+
+    fn is_moving(&self) -> bool {
+        self.elapsed < self.polls
+    }
+
+    /// The coordinate sent for the current poll.
+    fn position(&self) -> (u8, u8) {
+        if !self.is_moving() {
+            return self.to;
+        }
+        let interpolate = |from: u8, to: u8| {
+            let distance = to as i32 - from as i32;
+            (from as i32 + distance * self.elapsed as i32 / self.polls as i32) as u8
+        };
+        (
+            interpolate(self.from.0, self.to.0),
+            interpolate(self.from.1, self.to.1),
+        )
+    }
+
+    /// Advance by one poll toward `target`, restarting from the current position if the target changed.
+    fn step(&mut self, target: (u8, u8)) -> (u8, u8) {
+        if target != self.to {
+            self.from = self.position();
+            self.to = target;
+            self.elapsed = 0;
+        }
+        self.elapsed = self.elapsed.saturating_add(1);
+        self.position()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stick(x: u8, y: u8) -> GamecubeInput {
+        GamecubeInput {
+            stick_x: x,
+            stick_y: y,
+            cstick_x: x,
+            cstick_y: y,
+            ..GamecubeInput::NEUTRAL
+        }
+    }
+
+    #[test]
+    fn straight_line() {
+        let mut travel = TravelTime::new(4, 0);
+        let mut positions = [(0, 0); 5];
+        for position in positions.iter_mut() {
+            let output = travel.apply(&stick(255, 0));
+            // the c-stick moves instantly
+            assert_eq!((output.cstick_x, output.cstick_y), (255, 0));
+            *position = (output.stick_x, output.stick_y);
+        }
+        assert_eq!(
+            positions,
+            [(159, 96), (191, 64), (223, 32), (255, 0), (255, 0)]
+        );
+        assert!(!travel.is_moving());
+    }
+
+    #[test]
+    fn retarget_from_current_position() {
+        let mut travel = TravelTime::new(4, 4);
+        travel.apply(&stick(255, 128));
+        assert_eq!(travel.apply(&stick(255, 128)).stick_x, 191);
+        assert!(travel.is_moving());
+        assert_eq!(travel.apply(&stick(0, 128)).stick_x, 144);
+    }
+
+    #[test]
+    fn instant() {
+        for polls in [0, 1] {
+            let mut travel = TravelTime::new(polls, polls);
+            assert_eq!(travel.apply(&stick(0, 255)), stick(0, 255));
+            assert!(!travel.is_moving());
+        }
+    }
+
+    #[test]
+    fn longest_travel() {
+        let mut travel = TravelTime::new(255, 255);
+        travel.apply(&stick(0, 0));
+        let mut output = stick(0, 0);
+        for _ in 0..300 {
+            output = travel.apply(&stick(255, 255));
+        }
+        assert_eq!(output, stick(255, 255));
+        assert!(!travel.is_moving());
+    }
+}
@@ -0,0 +1,205 @@
+//! Deriving the digital L and R clicks from the analog triggers, and the analog triggers from digital buttons.
+//!
+//! An OEM trigger clicks near the end of its travel, and the analog value stays high while it is clicked.
+//! Triggers read through an ADC have no click switch, so [`Triggers`] clicks them once the analog value reaches a threshold,
+//! with hysteresis so a trigger resting near the threshold doesn't chatter.
+//! The other way around, triggers that are only buttons, as on box style controllers, can be sent with an analog value while pressed.
+//!
+//! ```ignore
+//! let mut triggers = Triggers::new(TriggerConfig::OEM, TriggerConfig::OEM);
+//! controller.respond_to_poll(&timer, &mut delay, triggers.apply(&input));
+//! ```
+
+use crate::GamecubeInput;
+
+/// How a single trigger is derived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TriggerConfig {
+    /// Click the trigger once its analog value reaches this, or None to only click it from the digital input.
+    pub press: Option<u8>,
+    /// Once clicked by `press`, keep the trigger clicked until its analog value falls below this.
+    /// Values above `press` are treated as `press`, so there is no hysteresis.
+    pub release: u8,
+    /// While the digital input is pressed send at least this analog value, or None to leave the analog value alone.
+    pub pressed_analog: Option<u8>,
+}
+
+impl TriggerConfig {
+    /// Click near the end of travel like an OEM controller.
+    pub const OEM: TriggerConfig = TriggerConfig {
+        press: Some(230),
+        release: 215,
+        pressed_analog: None,
+    };
+
+    /// A trigger that is only a button, sent fully pressed while held.
+    pub const BUTTON: TriggerConfig = TriggerConfig {
+        press: None,
+        release: 0,
+        pressed_analog: Some(255),
+    };
+
+    /// The trigger is passed through unchanged.
+    pub const NONE: TriggerConfig = TriggerConfig {
+        press: None,
+        release: 0,
+        pressed_analog: None,
+    };
+}
+
+impl Default for TriggerConfig {
+    fn default() -> Self {
+        TriggerConfig::NONE
+    }
+}
+
+/// Derives both triggers, see the [module docs](self).
+///
+/// This holds whether each trigger is clicked for the hysteresis, so [`Triggers::apply`] must be called for every poll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Triggers {
+    l: Trigger,
+    r: Trigger,
+}
+
+impl Triggers {
+    pub const fn new(l: TriggerConfig, r: TriggerConfig) -> Triggers {
+        Triggers {
+            l: Trigger::new(l),
+            r: Trigger::new(r),
+        }
+    }
+
+    pub fn set_config(&mut self, l: TriggerConfig, r: TriggerConfig) {
+        self.l = Trigger::new(l);
+        self.r = Trigger::new(r);
+    }
+
+    /// The config of the left and right trigger.
+    pub fn config(&self) -> (TriggerConfig, TriggerConfig) {
+        (self.l.config, self.r.config)
+    }
+
+    /// Derive the triggers of `input`, everything else is passed through unchanged.
+    /// The digital click is pressed if either the digital input is pressed or the analog value clicked it.
+    pub fn apply(&mut self, input: &GamecubeInput) -> GamecubeInput {
+        let mut output = *input;
+        (output.l_digital, output.l_analog) = self.l.apply(input.l_digital, input.l_analog);
+        (output.r_digital, output.r_analog) = self.r.apply(input.r_digital, input.r_analog);
+        output
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Trigger {
+    config: TriggerConfig,
+    clicked: bool,
+}
+
+impl Trigger {
+    const fn new(config: TriggerConfig) -> Trigger {
+        Trigger {
+            config,
+            clicked: false,
+        }
+    }
+
+    fn apply(&mut self, digital: bool, analog: u8) -> (bool, u8) {
+        self.clicked = match self.config.press {
+            Some(press) if self.clicked => analog >= self.config.release.min(press),
+            Some(press) => analog >= press,
+            None => false,
+        };
+        let analog = match self.config.pressed_analog {
+            Some(pressed_analog) if digital => analog.max(pressed_analog),
+            _ => analog,
+        };
+        (digital || self.clicked, analog)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the left trigger through `samples` of its digital and analog input, returning the outputs.
+    fn run<const N: usize>(config: TriggerConfig, samples: [(bool, u8); N]) -> [(bool, u8); N] {
+        let mut triggers = Triggers::new(config, TriggerConfig::NONE);
+        samples.map(|(l_digital, l_analog)| {
+            let output = triggers.apply(&GamecubeInput {
+                l_digital,
+                l_analog,
+                ..GamecubeInput::NEUTRAL
+            });
+            (output.l_digital, output.l_analog)
+        })
+    }
+
+    #[test]
+    fn hysteresis() {
+        let clicks = run(
+            TriggerConfig::OEM,
+            [
+                (false, 229),
+                (false, 230),
+                (false, 215),
+                (false, 214),
+                (false, 255),
+            ],
+        )
+        .map(|(clicked, _)| clicked);
+        assert_eq!(clicks, [false, true, true, false, true]);
+    }
+
+    #[test]
+    fn release_above_press() {
+        let config = TriggerConfig {
+            press: Some(100),
+            release: 200,
+            pressed_analog: None,
+        };
+        let clicks = run(config, [(false, 100), (false, 99)]).map(|(clicked, _)| clicked);
+        assert_eq!(clicks, [true, false]);
+    }
+
+    #[test]
+    fn thresholds_at_the_ends() {
+        let always = TriggerConfig {
+            press: Some(0),
+            ..TriggerConfig::NONE
+        };
+        assert_eq!(run(always, [(false, 0)]), [(true, 0)]);
+        let full = TriggerConfig {
+            press: Some(255),
+            release: 255,
+            pressed_analog: None,
+        };
+        assert_eq!(
+            run(full, [(false, 254), (false, 255), (false, 254)]),
+            [(false, 254), (true, 255), (false, 254)]
+        );
+    }
+
+    #[test]
+    fn button() {
+        assert_eq!(
+            run(TriggerConfig::BUTTON, [(true, 0), (false, 30), (true, 30)]),
+            [(true, 255), (false, 30), (true, 255)]
+        );
+        let light = TriggerConfig {
+            pressed_analog: Some(49),
+            ..TriggerConfig::NONE
+        };
+        // the analog value is only ever raised
+        assert_eq!(
+            run(light, [(true, 10), (true, 200)]),
+            [(true, 49), (true, 200)]
+        );
+    }
+
+    #[test]
+    fn none_passes_through() {
+        let samples = [(false, 255), (true, 0), (false, 128)];
+        assert_eq!(run(TriggerConfig::NONE, samples), samples);
+    }
+}
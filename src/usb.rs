@@ -0,0 +1,290 @@
+//! Glue for turning an RP2040 into a gamecube controller to USB adapter.
+//!
+//! [`UsbBridge`] polls a controller with a [`GamecubeHost`] and presents it to the USB host as a generic HID gamepad,
+//! which works with any OS or game without drivers.
+//! [`GamepadHid`] is a regular [`UsbClass`] so it can be combined with other classes such as a serial port in a composite device.
+
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, Request, RequestType};
+use usb_device::device::UsbDevice;
+
+use crate::host::{GamecubeHost, RumbleCommand};
+use crate::rp2040_hal::{timer::Instant, Timer};
+use crate::GamecubeInput;
+
+const HID_CLASS: u8 = 0x03;
+const DESCRIPTOR_TYPE_HID: u8 = 0x21;
+const DESCRIPTOR_TYPE_REPORT: u8 = 0x22;
+const REQUEST_GET_REPORT: u8 = 0x01;
+const REQUEST_SET_IDLE: u8 = 0x0A;
+
+/// 16 buttons followed by 6 axes of 8 bits each.
+#[rustfmt::skip]
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01,       // Usage Page (Generic Desktop)
+    0x09, 0x05,       // Usage (Game Pad)
+    0xA1, 0x01,       // Collection (Application)
+    0x05, 0x09,       //   Usage Page (Button)
+    0x19, 0x01,       //   Usage Minimum (1)
+    0x29, 0x10,       //   Usage Maximum (16)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x25, 0x01,       //   Logical Maximum (1)
+    0x75, 0x01,       //   Report Size (1)
+    0x95, 0x10,       //   Report Count (16)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0x05, 0x01,       //   Usage Page (Generic Desktop)
+    0x09, 0x30,       //   Usage (X)
+    0x09, 0x31,       //   Usage (Y)
+    0x09, 0x33,       //   Usage (Rx)
+    0x09, 0x34,       //   Usage (Ry)
+    0x09, 0x32,       //   Usage (Z)
+    0x09, 0x35,       //   Usage (Rz)
+    0x15, 0x00,       //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08,       //   Report Size (8)
+    0x95, 0x06,       //   Report Count (6)
+    0x81, 0x02,       //   Input (Data, Variable, Absolute)
+    0xC0,             // End Collection
+];
+
+/// Encode `input` as a [`GamepadHid`] report.
+///
+/// Buttons 1 to 12 are A, B, X, Y, Z, L, R, Start, then dpad up, down, left and right.
+/// The axes are the stick, the C-stick and then the L and R triggers.
+/// The stick Y axes are flipped since HID Y axes point down.
+pub fn gamepad_report(input: &GamecubeInput) -> [u8; 8] {
+    let buttons = [
+        input.a,
+        input.b,
+        input.x,
+        input.y,
+        input.z,
+        input.l_digital,
+        input.r_digital,
+        input.start,
+        input.dpad_up,
+        input.dpad_down,
+        input.dpad_left,
+        input.dpad_right,
+    ]
+    .iter()
+    .enumerate()
+    .fold(0u16, |bits, (i, pressed)| bits | ((*pressed as u16) << i))
+    .to_le_bytes();
+
+    [
+        buttons[0],
+        buttons[1],
+        input.stick_x,
+        255 - input.stick_y,
+        input.cstick_x,
+        255 - input.cstick_y,
+        input.l_analog,
+        input.r_analog,
+    ]
+}
+
+/// A USB HID gamepad interface sending [`gamepad_report`]s.
+pub struct GamepadHid<'a, B: UsbBus> {
+    interface: InterfaceNumber,
+    endpoint: EndpointIn<'a, B>,
+    report: [u8; 8],
+    /// The report has changed since it was last accepted by the endpoint.
+    pending: bool,
+}
+
+impl<'a, B: UsbBus> GamepadHid<'a, B> {
+    /// Allocates an interface and an interrupt endpoint polled every `interval_ms`.
+    pub fn new(alloc: &'a UsbBusAllocator<B>, interval_ms: u8) -> GamepadHid<'a, B> {
+        GamepadHid {
+            interface: alloc.interface(),
+            endpoint: alloc.interrupt(8, interval_ms),
+            report: gamepad_report(&GamecubeInput::NEUTRAL),
+            pending: true,
+        }
+    }
+
+    /// Set the inputs to report, they are sent as soon as the USB host asks for them.
+    pub fn set_input(&mut self, input: &GamecubeInput) {
+        let report = gamepad_report(input);
+        if report != self.report {
+            self.report = report;
+            self.pending = true;
+        }
+        self.write_pending();
+    }
+
+    fn write_pending(&mut self) {
+        if self.pending {
+            match self.endpoint.write(&self.report) {
+                Ok(_) => self.pending = false,
+                // The previous report hasn't been collected yet, try again later.
+                Err(UsbError::WouldBlock) => {}
+                Err(_err) => {
+                    debug!("joybus: failed to write HID report {:?}", _err);
+                }
+            }
+        }
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for GamepadHid<'_, B> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(self.interface, HID_CLASS, 0, 0)?;
+        let [length_low, length_high] = (REPORT_DESCRIPTOR.len() as u16).to_le_bytes();
+        writer.write(
+            DESCRIPTOR_TYPE_HID,
+            &[
+                0x11, // bcdHID 1.11
+                0x01,
+                0x00, // country code
+                0x01, // number of class descriptors
+                DESCRIPTOR_TYPE_REPORT,
+                length_low,
+                length_high,
+            ],
+        )?;
+        writer.endpoint(&self.endpoint)?;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.pending = true;
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.endpoint.address() {
+            self.write_pending();
+        }
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let request = *xfer.request();
+        if request.recipient != Recipient::Interface
+            || request.index != u8::from(self.interface) as u16
+        {
+            return;
+        }
+
+        match (request.request_type, request.request) {
+            (RequestType::Standard, Request::GET_DESCRIPTOR)
+                if (request.value >> 8) as u8 == DESCRIPTOR_TYPE_REPORT =>
+            {
+                xfer.accept_with_static(REPORT_DESCRIPTOR).ok();
+            }
+            (RequestType::Class, REQUEST_GET_REPORT) => {
+                xfer.accept_with(&self.report).ok();
+            }
+            _ => {}
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let request = *xfer.request();
+        if request.recipient == Recipient::Interface
+            && request.index == u8::from(self.interface) as u16
+            && request.request_type == RequestType::Class
+            && request.request == REQUEST_SET_IDLE
+        {
+            // Reports are only sent on change anyway.
+            xfer.accept().ok();
+        }
+    }
+}
+
+/// Polls a gamecube controller and forwards its inputs to a [`GamepadHid`].
+///
+/// Call [`UsbBridge::update`] from the main loop as often as possible.
+/// The controller is polled at most once per poll interval, 1ms by default to match the USB frame rate,
+/// and a HID report is only sent when the inputs change.
+/// If the controller stops responding it is probed again every poll interval until it comes back.
+/// Polls go through [`GamecubeHost::poll_scheduled`], so leave the host's [`crate::HostQuirks::poll_mode`] at 3.
+pub struct UsbBridge<'a, B: UsbBus> {
+    host: GamecubeHost,
+    hid: GamepadHid<'a, B>,
+    poll_interval_us: u64,
+    last_poll: Option<Instant>,
+    connected: bool,
+}
+
+impl<'a, B: UsbBus> UsbBridge<'a, B> {
+    pub fn new(host: GamecubeHost, alloc: &'a UsbBusAllocator<B>) -> UsbBridge<'a, B> {
+        UsbBridge {
+            host,
+            hid: GamepadHid::new(alloc, 1),
+            poll_interval_us: 1_000,
+            last_poll: None,
+            connected: false,
+        }
+    }
+
+    /// How often to poll the controller, in microseconds.
+    pub fn set_poll_interval_us(&mut self, poll_interval_us: u64) {
+        self.poll_interval_us = poll_interval_us;
+    }
+
+    /// Turn the controller's rumble motor on or off, applied on the next poll.
+    /// Turning it off brakes the motor first, see [`RumbleCommand`].
+    pub fn set_rumble(&mut self, rumble: bool) {
+        self.host.set_rumble(if rumble {
+            RumbleCommand::Start
+        } else {
+            RumbleCommand::Stop
+        });
+    }
+
+    /// Returns true if the controller responded to the most recent poll.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// The HID class, for passing to [`UsbDevice::poll`] along with any other classes of a composite device.
+    /// When using this, call [`UsbBridge::update_controller`] instead of [`UsbBridge::update`].
+    pub fn hid_mut(&mut self) -> &mut GamepadHid<'a, B> {
+        &mut self.hid
+    }
+
+    /// Service the USB device and poll the controller if it is due.
+    pub fn update(&mut self, device: &mut UsbDevice<'a, B>, timer: &Timer) {
+        device.poll(&mut [&mut self.hid]);
+        self.update_controller(timer);
+    }
+
+    /// Poll the controller if it is due and forward its inputs, without servicing the USB device.
+    pub fn update_controller(&mut self, timer: &Timer) {
+        let now = timer.get_counter();
+        if let Some(last_poll) = self.last_poll {
+            let elapsed = now.checked_duration_since(last_poll).unwrap().ticks();
+            if elapsed < self.poll_interval_us {
+                return;
+            }
+        }
+        self.last_poll = Some(now);
+
+        if !self.connected {
+            // A controller needs to be probed and have its origin read before it will respond to polls.
+            self.connected = self.host.probe(timer).is_ok() && self.host.origin(timer).is_ok();
+            if !self.connected {
+                return;
+            }
+            debug!("joybus: controller connected");
+        }
+
+        match self.host.poll_scheduled(timer) {
+            Ok(report) => self.hid.set_input(&GamecubeInput::from_report(&report)),
+            Err(_err) => {
+                debug!("joybus: controller disconnected {:?}", _err);
+                self.connected = false;
+                self.hid.set_input(&GamecubeInput::NEUTRAL);
+            }
+        }
+    }
+
+    /// Returns the [`GamecubeHost`] so it can be reused.
+    pub fn free(self) -> GamecubeHost {
+        self.host
+    }
+}